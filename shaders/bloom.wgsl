@@ -0,0 +1,39 @@
+@group(0) @binding(0) var source_texture: texture_2d<f32>;
+@group(0) @binding(1) var source_sampler: sampler;
+
+struct Bloom {
+    // Unit axis to blur along
+    direction: vec2<f32>,
+    threshold: f32,
+    // Spread of the blur in texels
+    radius: f32
+}
+@group(1) @binding(0) var<uniform> bloom: Bloom;
+
+// Keeps only what is brighter than the threshold
+@fragment
+fn frag_bright(in: Fragment) -> @location(0) vec4<f32> {
+    let color = textureSample(source_texture, source_sampler, in.uv).rgb;
+    let brightness = max(color.r, max(color.g, color.b));
+    let weight = max(brightness - bloom.threshold, 0.0) / max(brightness, 0.0001);
+
+    return vec4<f32>(color * weight, 1.0);
+}
+
+// Separable gaussian, run once per axis
+@fragment
+fn frag_blur(in: Fragment) -> @location(0) vec4<f32> {
+    var weights = array<f32, 5>(0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+
+    let texel = 1.0 / vec2<f32>(textureDimensions(source_texture));
+    let step = bloom.direction * texel * bloom.radius / 4.0;
+
+    var color = textureSample(source_texture, source_sampler, in.uv).rgb * weights[0];
+    for (var i = 1; i < 5; i++) {
+        let offset = step * f32(i);
+        color += textureSample(source_texture, source_sampler, in.uv + offset).rgb * weights[i];
+        color += textureSample(source_texture, source_sampler, in.uv - offset).rgb * weights[i];
+    }
+
+    return vec4<f32>(color, 1.0);
+}
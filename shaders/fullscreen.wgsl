@@ -0,0 +1,18 @@
+// Prepended to the shaders of fullscreen passes, see `Graphics::load_fullscreen_shader`
+
+struct Fragment {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) uv: vec2<f32>
+}
+
+// Single triangle covering the whole viewport
+@vertex
+fn vert_main(@builtin(vertex_index) index: u32) -> Fragment {
+    var out: Fragment;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+
+    out.pos = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+
+    return out;
+}
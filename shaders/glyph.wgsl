@@ -1,6 +1,12 @@
 @group(0) @binding(0) var atlas_texture: texture_2d<f32>;
 @group(0) @binding(1) var atlas_sampler: sampler; 
 
+struct Camera {
+    view_proj: mat4x4<f32>
+}
+// Only bound by the world space pipeline
+@group(1) @binding(0) var<uniform> camera: Camera;
+
 struct Glyph {
     @builtin(vertex_index) index: u32,
     
@@ -51,8 +57,27 @@ fn vert_main(in: Glyph) -> Fragment {
     return out;
 }
 
+// Positions and scales in world units
+@vertex
+fn vert_world(in: Glyph) -> Fragment {
+    var out: Fragment;
+    let mesh = generate_quad(in.index);
+    let world = mesh * in.scale + in.pos.xy;
+
+    out.pos = camera.view_proj * vec4<f32>(world, in.pos.z, 1.0);
+    out.uv = glyph_uv(mesh, in.codepoint);
+    out.color = in.color;
+
+    return out;
+}
+
 
 @fragment 
 fn frag_main(in: Fragment) -> @location(0) vec4<f32> {
-    return in.color * textureSample(atlas_texture, atlas_sampler, in.uv.xy);
+    let color = in.color * textureSample(atlas_texture, atlas_sampler, in.uv.xy);
+    // Fully transparent texels would otherwise still write depth
+    if color.a <= 0.0 {
+        discard;
+    }
+    return color;
 }
\ No newline at end of file
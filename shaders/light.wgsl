@@ -0,0 +1,88 @@
+// Normals of the game layer, read per pixel
+@group(0) @binding(0) var normal_texture: texture_2d<f32>;
+@group(0) @binding(1) var normal_sampler: sampler;
+
+struct Camera {
+    view_proj: mat4x4<f32>,
+}
+@group(1) @binding(0) var<uniform> camera: Camera;
+
+struct Light {
+    @builtin(vertex_index) index: u32,
+    @location(0) pos: vec2<f32>,
+    @location(1) radius: f32,
+    @location(2) falloff: f32,
+    // Already scaled by the intensity
+    @location(3) color: vec4<f32>,
+    @location(4) direction: vec2<f32>,
+    // x: cosine where the cone ends, y: cosine where it reaches full
+    // strength, z: height above the scene
+    @location(5) cone: vec3<f32>,
+}
+
+struct Fragment {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) world: vec2<f32>,
+    @location(1) @interpolate(flat) center: vec2<f32>,
+    @location(2) @interpolate(flat) radius: f32,
+    @location(3) @interpolate(flat) falloff: f32,
+    @location(4) @interpolate(flat) color: vec4<f32>,
+    @location(5) @interpolate(flat) direction: vec2<f32>,
+    @location(6) @interpolate(flat) cone: vec3<f32>,
+}
+
+fn generate_quad(index: u32) -> vec2<f32> {
+    switch(index) {
+        case 0u { return vec2<f32>( 1.0,  1.0); }
+        case 1u { return vec2<f32>(-1.0, -1.0); }
+        case 2u { return vec2<f32>( 1.0, -1.0); }
+        case 3u { return vec2<f32>( 1.0,  1.0); }
+        case 4u { return vec2<f32>(-1.0,  1.0); }
+        default { return vec2<f32>(-1.0, -1.0); }
+    }
+}
+
+@vertex
+fn vert_light(in: Light) -> Fragment {
+    var out: Fragment;
+    let world = in.pos + generate_quad(in.index) * in.radius;
+
+    out.pos = camera.view_proj * vec4<f32>(world, 0.0, 1.0);
+    out.world = world;
+    out.center = in.pos;
+    out.radius = in.radius;
+    out.falloff = in.falloff;
+    out.color = in.color;
+    out.direction = in.direction;
+    out.cone = in.cone;
+
+    return out;
+}
+
+@fragment
+fn frag_light(in: Fragment) -> @location(0) vec4<f32> {
+    let offset = in.center - in.world;
+    let distance = length(offset);
+    var attenuation = pow(1.0 - clamp(distance / in.radius, 0.0, 1.0), in.falloff);
+
+    // Point lights use a cone wider than the full circle
+    let facing = dot(-offset / max(distance, 0.0001), in.direction);
+    attenuation *= smoothstep(in.cone.x, in.cone.y, facing);
+
+    let normal = textureLoad(normal_texture, vec2<i32>(in.pos.xy), 0).xyz * 2.0 - 1.0;
+    let to_light = normalize(vec3<f32>(offset, in.cone.z));
+    let diffuse = max(dot(normalize(normal), to_light), 0.0);
+
+    return vec4<f32>(in.color.rgb * attenuation * diffuse, 1.0);
+}
+
+// Shadow volumes only mark the stencil
+@vertex
+fn vert_shadow(@location(0) pos: vec2<f32>) -> @builtin(position) vec4<f32> {
+    return camera.view_proj * vec4<f32>(pos, 0.0, 1.0);
+}
+
+@fragment
+fn frag_shadow() -> @location(0) vec4<f32> {
+    return vec4<f32>(0.0);
+}
@@ -0,0 +1,17 @@
+@group(0) @binding(0) var scene_texture: texture_2d<f32>;
+@group(0) @binding(1) var scene_sampler: sampler;
+
+@group(1) @binding(0) var light_texture: texture_2d<f32>;
+@group(1) @binding(1) var light_sampler: sampler;
+
+struct Ambient {
+    color: vec4<f32>
+}
+@group(2) @binding(0) var<uniform> ambient: Ambient;
+
+@fragment
+fn frag_main(in: Fragment) -> @location(0) vec4<f32> {
+    let color = textureSample(scene_texture, scene_sampler, in.uv);
+    let light = textureSample(light_texture, light_sampler, in.uv).rgb + ambient.color.rgb;
+    return vec4<f32>(color.rgb * light, color.a);
+}
@@ -0,0 +1,57 @@
+@group(0) @binding(0) var scene_texture: texture_2d<f32>;
+@group(0) @binding(1) var scene_sampler: sampler;
+
+@group(1) @binding(0) var bloom_texture: texture_2d<f32>;
+@group(1) @binding(1) var bloom_sampler: sampler;
+
+// Disabled effects have their strengths zeroed
+struct Post {
+    // x: bloom intensity, y: aberration in pixels, z: grading strength
+    effects: vec4<f32>,
+    // x: vignette strength, y: vignette radius, z: scanline strength, w: curvature
+    screen: vec4<f32>
+}
+@group(2) @binding(0) var<uniform> post: Post;
+@group(2) @binding(1) var lut_texture: texture_3d<f32>;
+@group(2) @binding(2) var lut_sampler: sampler;
+
+// Barrel distortion of a CRT screen
+fn curve(uv: vec2<f32>, amount: f32) -> vec2<f32> {
+    let centered = uv * 2.0 - 1.0;
+    let bent = centered * (1.0 + centered.yx * centered.yx * amount);
+    return bent * 0.5 + 0.5;
+}
+
+fn grade(color: vec3<f32>) -> vec3<f32> {
+    let size = f32(textureDimensions(lut_texture).x);
+    // Sample texel centres, so the ends of the range map exactly
+    let coord = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0)) * ((size - 1.0) / size) + 0.5 / size;
+    return textureSample(lut_texture, lut_sampler, coord).rgb;
+}
+
+@fragment
+fn frag_main(in: Fragment) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(scene_texture));
+    let uv = curve(in.uv, post.screen.w);
+
+    // Colour fringes growing towards the edges
+    let shift = (uv - 0.5) * 2.0 * post.effects.y / size;
+    let center = textureSample(scene_texture, scene_sampler, uv);
+    let red = textureSample(scene_texture, scene_sampler, uv + shift).r;
+    let blue = textureSample(scene_texture, scene_sampler, uv - shift).b;
+    var color = vec3<f32>(red, center.g, blue);
+
+    color += textureSample(bloom_texture, bloom_sampler, uv).rgb * post.effects.x;
+    color = mix(color, grade(color), post.effects.z);
+
+    // Darken every other row of pixels
+    let line = step(0.5, fract(in.pos.y * 0.5));
+    color *= 1.0 - post.screen.z * line;
+
+    let distance = length(uv - 0.5) * 1.4142135;
+    color *= 1.0 - post.screen.x * smoothstep(post.screen.y, 1.0, distance);
+
+    // Black outside the curved screen
+    let inside = all(uv >= vec2<f32>(0.0)) && all(uv <= vec2<f32>(1.0));
+    return vec4<f32>(select(vec3<f32>(0.0), color, inside), center.a);
+}
@@ -0,0 +1,29 @@
+struct Camera {
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0) var<uniform> camera: Camera;
+
+struct Vertex {
+    @location(0) pos: vec3<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+struct Fragment {
+    @builtin(position) pos: vec4<f32>,
+    @location(0) color: vec4<f32>,
+}
+
+// Positions in world units, with the layer depth in z
+@vertex
+fn vert_main(in: Vertex) -> Fragment {
+    var out: Fragment;
+    out.pos = camera.view_proj * vec4<f32>(in.pos.xy, in.pos.z, 1.0);
+    out.color = in.color;
+    return out;
+}
+
+// Antialiasing comes from the faded edges tessellated around each shape
+@fragment
+fn frag_main(in: Fragment) -> @location(0) vec4<f32> {
+    return in.color;
+}
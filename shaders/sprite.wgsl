@@ -1,3 +1,15 @@
+@group(0) @binding(0) var atlas_texture: texture_2d<f32>;
+@group(0) @binding(1) var atlas_sampler: sampler;
+
+struct Camera {
+    view_proj: mat4x4<f32>,
+}
+@group(1) @binding(0) var<uniform> camera: Camera;
+
+// Only bound by the normal pipeline, shares the atlas layout
+@group(2) @binding(0) var normal_texture: texture_2d<f32>;
+@group(2) @binding(1) var normal_sampler: sampler;
+
 struct Sprite {
     @builtin(vertex_index) index: u32,
     @location(0) pos: vec3<f32>,
@@ -5,12 +17,20 @@ struct Sprite {
     @location(2) color: vec4<f32>,
     @location(3) uv_a: vec2<f32>,
     @location(4) uv_b: vec2<f32>,
+    // Radians counter-clockwise around the pivot
+    @location(5) rotation: f32,
+    // In the quad's own [-1, 1] space, where `pos` sits
+    @location(6) pivot: vec2<f32>,
+    // Bit 0 flips horizontally, bit 1 vertically
+    @location(7) flip: u32,
 }
 
 struct Fragment {
     @builtin(position) pos: vec4<f32>,
     @location(0) color: vec4<f32>,
-    @location(1) uv: vec2<f32>
+    @location(1) uv: vec2<f32>,
+    // Columns of the sprite's rotation and flip, to turn its normals with it
+    @location(2) @interpolate(flat) basis: vec4<f32>,
 }
 
 fn generate_quad(index: u32) -> vec2<f32> {
@@ -31,11 +51,59 @@ fn generate_quad(index: u32) -> vec2<f32> {
 @vertex
 fn vert_main(in: Sprite) -> Fragment {
     var out: Fragment;
-    
+    let mesh = generate_quad(in.index);
+
+    // Scale is the half size of the sprite in world units
+    let local = (mesh - in.pivot) * in.scale;
+    let c = cos(in.rotation);
+    let s = sin(in.rotation);
+    let world = in.pos.xy + vec2<f32>(c * local.x - s * local.y, s * local.x + c * local.y);
+    out.pos = camera.view_proj * vec4<f32>(world, in.pos.z, 1.0);
+    out.color = in.color;
+
+    let flip = vec2<f32>(
+        select(1.0, -1.0, (in.flip & 1u) != 0u),
+        select(1.0, -1.0, (in.flip & 2u) != 0u),
+    );
+    out.basis = vec4<f32>(c * flip.x, s * flip.x, -s * flip.y, c * flip.y);
+
+    // Atlas v grows downwards while the quad's y grows upwards
+    let texel = mesh * flip;
+    let t = vec2<f32>(0.5 + 0.5 * texel.x, 0.5 - 0.5 * texel.y);
+    out.uv = mix(in.uv_a, in.uv_b, t);
+
     return out;
 }
 
+fn shade(in: Fragment) -> vec4<f32> {
+    let color = in.color * textureSample(atlas_texture, atlas_sampler, in.uv);
+    // Fully transparent texels would otherwise still write depth
+    if color.a <= 0.0 {
+        discard;
+    }
+    return color;
+}
+
 @fragment 
 fn frag_main(in: Fragment) -> @location(0) vec4<f32> {
-    return vec4<f32>(0.0, 1.0, 0.0, 1.0);
+    return shade(in);
+}
+
+// For the additive and multiply blend modes
+@fragment
+fn frag_premultiplied(in: Fragment) -> @location(0) vec4<f32> {
+    let color = shade(in);
+    return vec4<f32>(color.rgb * color.a, color.a);
+}
+
+// Writes the sprite's normal map instead of its colour, for lighting
+@fragment
+fn frag_normal(in: Fragment) -> @location(0) vec4<f32> {
+    let alpha = in.color.a * textureSample(atlas_texture, atlas_sampler, in.uv).a;
+    let normal = textureSample(normal_texture, normal_sampler, in.uv).rgb * 2.0 - 1.0;
+    if alpha <= 0.0 {
+        discard;
+    }
+    let turned = mat2x2<f32>(in.basis.xy, in.basis.zw) * normal.xy;
+    return vec4<f32>(vec3<f32>(turned, normal.z) * 0.5 + 0.5, 1.0);
 } 
@@ -0,0 +1,362 @@
+use crate::nvec::*;
+use crate::sprite::{Sprite, UVRect};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlayMode {
+    // Holds the last frame once done
+    Once,
+    Loop,
+    // Forwards then backwards, without repeating the end frames
+    PingPong,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub rect: UVRect,
+    // Seconds
+    pub duration: f32,
+    // Reported once each time the frame is entered
+    pub event: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Clip {
+    pub name: String,
+    pub frames: Vec<Frame>,
+    pub mode: PlayMode,
+}
+impl Clip {
+    pub fn new(name: &str, frames: Vec<Frame>) -> Self {
+        assert!(!frames.is_empty(), "Clip {name} has no frames");
+        Self {
+            name: name.to_string(),
+            frames,
+            mode: PlayMode::Loop,
+        }
+    }
+
+    // Frames of equal length from a sprite sheet grid, see `UVRect::grid`
+    pub fn grid(
+        name: &str,
+        origin: Vec2<f32>,
+        size: Vec2<f32>,
+        count: usize,
+        columns: usize,
+        duration: f32,
+    ) -> Self {
+        let frames = UVRect::grid(origin, size, count, columns)
+            .into_iter()
+            .map(|rect| Frame {
+                rect,
+                duration,
+                event: None,
+            })
+            .collect();
+        Self::new(name, frames)
+    }
+
+    pub fn mode(mut self, mode: PlayMode) -> Self {
+        self.mode = mode;
+        self
+    }
+    pub fn event(mut self, frame: usize, event: &str) -> Self {
+        self.frames[frame].event = Some(event.to_string());
+        self
+    }
+    pub fn durations(mut self, durations: &[f32]) -> Self {
+        for (frame, duration) in self.frames.iter_mut().zip(durations) {
+            frame.duration = *duration;
+        }
+        self
+    }
+
+    pub fn length(&self) -> f32 {
+        self.frames.iter().map(|frame| frame.duration).sum()
+    }
+}
+
+// Plays one clip at a time and writes its current frame into sprites
+pub struct Animator {
+    clips: Vec<Clip>,
+    current: usize,
+    frame: usize,
+    // Time spent on the current frame
+    time: f32,
+    // Only false while a ping-pong clip plays backwards
+    forward: bool,
+    finished: bool,
+    pub speed: f32,
+    events: Vec<String>,
+}
+impl Animator {
+    pub fn new(clips: Vec<Clip>) -> Self {
+        assert!(!clips.is_empty(), "Animator needs at least one clip");
+        let mut animator = Self {
+            clips,
+            current: 0,
+            frame: 0,
+            time: 0.0,
+            forward: true,
+            finished: false,
+            speed: 1.0,
+            events: Vec::new(),
+        };
+        animator.enter_frame(0);
+        animator
+    }
+
+    pub fn clip(&self) -> &Clip {
+        &self.clips[self.current]
+    }
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+    pub fn rect(&self) -> UVRect {
+        self.clip().frames[self.frame].rect
+    }
+    // Only `PlayMode::Once` clips finish
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    // Switches clip, keeping the current one going if it is already playing
+    pub fn play(&mut self, name: &str) {
+        if self.clip().name == name {
+            return;
+        }
+        match self.clips.iter().position(|clip| clip.name == name) {
+            Some(index) => {
+                self.current = index;
+                self.restart();
+            }
+            None => eprintln!("Warning: No animation clip named {name}"),
+        }
+    }
+    // Switches clip, starting over even if it is already playing
+    pub fn play_from_start(&mut self, name: &str) {
+        if self.clip().name == name {
+            self.restart();
+        } else {
+            self.play(name);
+        }
+    }
+    pub fn restart(&mut self) {
+        self.time = 0.0;
+        self.forward = true;
+        self.finished = false;
+        self.enter_frame(0);
+    }
+
+    fn enter_frame(&mut self, frame: usize) {
+        self.frame = frame;
+        if let Some(event) = &self.clips[self.current].frames[frame].event {
+            self.events.push(event.clone());
+        }
+    }
+
+    fn next_frame(&self) -> Option<(usize, bool)> {
+        let clip = self.clip();
+        let last = clip.frames.len() - 1;
+        match clip.mode {
+            PlayMode::Once if self.frame == last => None,
+            PlayMode::Once => Some((self.frame + 1, true)),
+            PlayMode::Loop => Some(((self.frame + 1) % clip.frames.len(), true)),
+            PlayMode::PingPong if last == 0 => Some((0, true)),
+            PlayMode::PingPong if self.forward && self.frame == last => Some((last - 1, false)),
+            PlayMode::PingPong if !self.forward && self.frame == 0 => Some((1, true)),
+            PlayMode::PingPong if self.forward => Some((self.frame + 1, true)),
+            PlayMode::PingPong => Some((self.frame - 1, false)),
+        }
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        if self.finished {
+            return;
+        }
+        self.time += dt * self.speed;
+
+        // Several frames can pass in one tick when they are short
+        loop {
+            let duration = self.clip().frames[self.frame].duration;
+            if self.time < duration {
+                break;
+            }
+            match self.next_frame() {
+                Some((frame, forward)) => {
+                    self.time -= duration;
+                    self.forward = forward;
+                    self.enter_frame(frame);
+                }
+                None => {
+                    self.time = duration;
+                    self.finished = true;
+                    break;
+                }
+            }
+            // Zero length clips would spin forever
+            if self.clip().length() <= 0.0 {
+                break;
+            }
+        }
+    }
+
+    // Events from frames entered since the last call, in order
+    pub fn drain_events(&mut self) -> std::vec::Drain<'_, String> {
+        self.events.drain(..)
+    }
+
+    pub fn apply(&self, sprite: &mut Sprite) {
+        sprite.rect = self.rect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Four frames a quarter of a second each, with an event on the first
+    fn clip(name: &str, mode: PlayMode) -> Clip {
+        Clip::grid(name, vec2(0.0, 0.0), vec2(0.25, 1.0), 4, 4, 0.25)
+            .mode(mode)
+            .event(0, &format!("{name} start"))
+    }
+    fn frames(animator: &mut Animator, dt: f32, steps: usize) -> Vec<usize> {
+        (0..steps)
+            .map(|_| {
+                animator.update(dt);
+                animator.frame()
+            })
+            .collect()
+    }
+    fn events(animator: &mut Animator) -> Vec<String> {
+        animator.drain_events().collect()
+    }
+
+    #[test]
+    fn once_holds_the_last_frame() {
+        let mut animator = Animator::new(vec![clip("cast", PlayMode::Once)]);
+        assert_eq!(frames(&mut animator, 0.25, 5), [1, 2, 3, 3, 3]);
+        assert!(animator.is_finished());
+        assert_eq!(animator.rect(), animator.clip().frames[3].rect);
+
+        animator.restart();
+        assert_eq!(animator.frame(), 0);
+        assert!(!animator.is_finished());
+    }
+
+    #[test]
+    fn loop_wraps_around() {
+        let mut animator = Animator::new(vec![clip("walk", PlayMode::Loop)]);
+        assert_eq!(frames(&mut animator, 0.25, 6), [1, 2, 3, 0, 1, 2]);
+        assert!(!animator.is_finished());
+    }
+
+    #[test]
+    fn ping_pong_does_not_repeat_the_ends() {
+        let mut animator = Animator::new(vec![clip("idle", PlayMode::PingPong)]);
+        assert_eq!(frames(&mut animator, 0.25, 8), [1, 2, 3, 2, 1, 0, 1, 2]);
+
+        let single = Clip::grid("still", vec2(0.0, 0.0), vec2(1.0, 1.0), 1, 1, 0.25)
+            .mode(PlayMode::PingPong);
+        let mut animator = Animator::new(vec![single]);
+        assert_eq!(frames(&mut animator, 0.25, 3), [0, 0, 0]);
+    }
+
+    #[test]
+    fn large_steps_pass_several_frames() {
+        let mut animator = Animator::new(vec![clip("walk", PlayMode::Loop)]);
+        assert_eq!(frames(&mut animator, 0.6, 3), [2, 0, 3]);
+        animator.update(0.45);
+        assert_eq!(animator.frame(), 1);
+
+        let mut animator = Animator::new(vec![clip("idle", PlayMode::PingPong)]);
+        animator.update(1.0);
+        assert_eq!(animator.frame(), 2);
+
+        let mut animator = Animator::new(vec![clip("cast", PlayMode::Once)]);
+        animator.update(100.0);
+        assert_eq!(animator.frame(), 3);
+        assert!(animator.is_finished());
+    }
+
+    #[test]
+    fn frames_keep_their_own_durations() {
+        let clip = clip("walk", PlayMode::Loop).durations(&[0.5, 0.125, 0.125, 0.25]);
+        assert_eq!(clip.length(), 1.0);
+        let mut animator = Animator::new(vec![clip]);
+        assert_eq!(frames(&mut animator, 0.125, 9), [0, 0, 0, 1, 2, 3, 3, 0, 0]);
+
+        // Speed scales time on every frame
+        animator.speed = 4.0;
+        animator.update(0.1);
+        assert_eq!(animator.frame(), 1);
+    }
+
+    #[test]
+    fn zero_length_clips_do_not_hang() {
+        let clip = clip("blink", PlayMode::Loop).durations(&[0.0; 4]);
+        let mut animator = Animator::new(vec![clip]);
+        animator.update(1.0);
+        assert!(animator.frame() < 4);
+    }
+
+    #[test]
+    fn events_are_reported_each_time_a_frame_is_entered() {
+        let walk = clip("walk", PlayMode::Loop).event(2, "step");
+        let mut animator = Animator::new(vec![walk, clip("cast", PlayMode::Once)]);
+        assert_eq!(events(&mut animator), ["walk start"]);
+        assert!(events(&mut animator).is_empty());
+
+        // One big step reports everything passed, in order
+        animator.update(1.0);
+        assert_eq!(events(&mut animator), ["step", "walk start"]);
+
+        animator.update(0.5);
+        animator.update(0.5);
+        assert_eq!(events(&mut animator), ["step", "walk start"]);
+    }
+
+    #[test]
+    fn play_switches_clips() {
+        let mut animator = Animator::new(vec![
+            clip("walk", PlayMode::Loop),
+            clip("cast", PlayMode::Once),
+        ]);
+        animator.update(0.5);
+        animator.drain_events();
+
+        // Already playing, so it carries on
+        animator.play("walk");
+        assert_eq!(animator.frame(), 2);
+        assert!(events(&mut animator).is_empty());
+
+        animator.play("cast");
+        assert_eq!(animator.clip().name, "cast");
+        assert_eq!(animator.frame(), 0);
+        assert_eq!(events(&mut animator), ["cast start"]);
+
+        // Unknown names leave the clip alone
+        animator.play("dance");
+        assert_eq!(animator.clip().name, "cast");
+    }
+
+    #[test]
+    fn play_from_start_reports_the_first_frame_once() {
+        let mut animator = Animator::new(vec![
+            clip("walk", PlayMode::Loop),
+            clip("cast", PlayMode::Once),
+        ]);
+        animator.drain_events();
+
+        animator.play_from_start("cast");
+        assert_eq!(events(&mut animator), ["cast start"]);
+
+        animator.update(1.0);
+        assert!(animator.is_finished());
+        animator.drain_events();
+        animator.play_from_start("cast");
+        assert_eq!(animator.frame(), 0);
+        assert!(!animator.is_finished());
+        assert_eq!(events(&mut animator), ["cast start"]);
+    }
+}
@@ -0,0 +1,1025 @@
+use std::io::Read;
+
+use crate::animation::{Clip, Frame as ClipFrame, PlayMode};
+use crate::color::Color;
+use crate::geometry::Rect;
+use crate::nvec::*;
+use crate::sprite::UVRect;
+
+// Reader for .ase/.aseprite files, see
+// https://github.com/aseprite/aseprite/blob/main/docs/ase-file-specs.md
+// Only the parts the game uses are kept: layers, cels, tags, slices and the
+// palette. Tilemaps and user data are skipped
+
+// Indexed pixels are a single byte, so no more entries can be used
+const PALETTE_SIZE: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorDepth {
+    Rgba,
+    Grayscale,
+    Indexed,
+}
+impl ColorDepth {
+    fn bytes(self) -> usize {
+        match self {
+            ColorDepth::Rgba => 4,
+            ColorDepth::Grayscale => 2,
+            ColorDepth::Indexed => 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Layer {
+    pub name: String,
+    pub visible: bool,
+    pub group: bool,
+    // Nesting depth, children follow their group with one more level
+    pub level: u16,
+    pub opacity: u8,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cel {
+    pub layer: usize,
+    pub pos: Vec2<i32>,
+    pub opacity: u8,
+    pub size: Vec2<u32>,
+    // Converted to RGBA8
+    pub pixels: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    // Seconds
+    pub duration: f32,
+    pub cels: Vec<Cel>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Forward,
+    Reverse,
+    PingPong,
+    PingPongReverse,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tag {
+    pub name: String,
+    // Inclusive
+    pub from: usize,
+    pub to: usize,
+    pub direction: Direction,
+    // Zero plays forever
+    pub repeat: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SliceKey {
+    // The key applies from this frame until the next key
+    pub frame: usize,
+    // In pixels from the top-left of the canvas
+    pub bounds: Rect,
+    // Nine-slice centre, relative to `bounds`
+    pub center: Option<Rect>,
+    pub pivot: Option<Vec2<f32>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Slice {
+    pub name: String,
+    pub keys: Vec<SliceKey>,
+}
+impl Slice {
+    pub fn key(&self, frame: usize) -> Option<&SliceKey> {
+        self.keys.iter().rev().find(|key| key.frame <= frame)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Aseprite {
+    pub size: Vec2<u32>,
+    pub depth: ColorDepth,
+    pub layers: Vec<Layer>,
+    pub frames: Vec<Frame>,
+    pub tags: Vec<Tag>,
+    pub slices: Vec<Slice>,
+    pub palette: Vec<Color>,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> Option<&'a [u8]> {
+        if count > self.data.len() {
+            return None;
+        }
+        let (head, tail) = self.data.split_at(count);
+        self.data = tail;
+        Some(head)
+    }
+    fn skip(&mut self, count: usize) -> Option<()> {
+        self.bytes(count).map(|_| ())
+    }
+    fn byte(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+    fn word(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.bytes(2)?.try_into().ok()?))
+    }
+    fn short(&mut self) -> Option<i16> {
+        Some(i16::from_le_bytes(self.bytes(2)?.try_into().ok()?))
+    }
+    fn dword(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+    fn long(&mut self) -> Option<i32> {
+        Some(i32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+    fn string(&mut self) -> Option<String> {
+        let length = self.word()? as usize;
+        Some(String::from_utf8_lossy(self.bytes(length)?).into_owned())
+    }
+}
+
+// Cels reference palette entries, so pixels are converted once the whole
+// frame has been read
+struct RawCel {
+    layer: usize,
+    pos: Vec2<i32>,
+    opacity: u8,
+    content: RawContent,
+}
+enum RawContent {
+    Pixels(Vec2<u32>, Vec<u8>),
+    Linked(usize),
+}
+
+impl Aseprite {
+    pub fn load(path: &str) -> Self {
+        let data = std::fs::read(path).unwrap_or_else(|_| panic!("Cannot read {path}"));
+        Self::parse(&data).unwrap_or_else(|| panic!("Invalid Aseprite file {path}"))
+    }
+
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let mut header = Reader {
+            data: data.get(..128)?,
+        };
+        header.dword()?;
+        if header.word()? != 0xA5E0 {
+            return None;
+        }
+        let frame_count = header.word()? as usize;
+        let size = vec2(header.word()? as u32, header.word()? as u32);
+        let depth = match header.word()? {
+            32 => ColorDepth::Rgba,
+            16 => ColorDepth::Grayscale,
+            8 => ColorDepth::Indexed,
+            _ => return None,
+        };
+        header.skip(4 + 2 + 8)?;
+        let transparent = header.byte()?;
+
+        let mut ase = Self {
+            size,
+            depth,
+            layers: Vec::new(),
+            frames: Vec::with_capacity(frame_count),
+            tags: Vec::new(),
+            slices: Vec::new(),
+            palette: Vec::new(),
+        };
+        let mut raw_frames = Vec::with_capacity(frame_count);
+        let mut has_new_palette = false;
+
+        let mut reader = Reader {
+            data: data.get(128..)?,
+        };
+        for _ in 0..frame_count {
+            let length = reader.dword()? as usize;
+            let mut frame = Reader {
+                data: reader.bytes(length.checked_sub(4)?)?,
+            };
+            if frame.word()? != 0xF1FA {
+                return None;
+            }
+            let old_chunks = frame.word()? as usize;
+            let duration = frame.word()? as f32 / 1000.0;
+            frame.skip(2)?;
+            let chunks = match frame.dword()? as usize {
+                0 => old_chunks,
+                count => count,
+            };
+
+            let mut cels = Vec::new();
+            for _ in 0..chunks {
+                let length = frame.dword()? as usize;
+                let kind = frame.word()?;
+                let mut chunk = Reader {
+                    data: frame.bytes(length.checked_sub(6)?)?,
+                };
+                match kind {
+                    0x0004 if !has_new_palette => ase.read_old_palette(&mut chunk)?,
+                    0x2004 => ase.layers.push(Self::read_layer(&mut chunk)?),
+                    0x2005 => {
+                        if let Some(cel) = Self::read_cel(&mut chunk, depth)? {
+                            cels.push(cel);
+                        }
+                    }
+                    0x2018 => ase.tags = Self::read_tags(&mut chunk)?,
+                    0x2019 => {
+                        has_new_palette = true;
+                        ase.read_palette(&mut chunk)?;
+                    }
+                    0x2022 => ase.slices.push(Self::read_slice(&mut chunk)?),
+                    _ => {}
+                }
+            }
+            raw_frames.push((duration, cels));
+        }
+
+        for (index, (duration, cels)) in raw_frames.iter().enumerate() {
+            let cels = cels
+                .iter()
+                .filter_map(|cel| {
+                    let (size, pixels) = match &cel.content {
+                        RawContent::Pixels(size, pixels) => (*size, pixels),
+                        RawContent::Linked(frame) if *frame < index => {
+                            let linked = raw_frames[*frame].1.iter().find(|other| {
+                                other.layer == cel.layer
+                                    && matches!(other.content, RawContent::Pixels(..))
+                            })?;
+                            match &linked.content {
+                                RawContent::Pixels(size, pixels) => (*size, pixels),
+                                RawContent::Linked(_) => return None,
+                            }
+                        }
+                        RawContent::Linked(_) => return None,
+                    };
+                    Some(Cel {
+                        layer: cel.layer,
+                        pos: cel.pos,
+                        opacity: cel.opacity,
+                        size,
+                        pixels: ase.to_rgba(pixels, transparent),
+                    })
+                })
+                .collect();
+            ase.frames.push(Frame {
+                duration: *duration,
+                cels,
+            });
+        }
+        Some(ase)
+    }
+
+    fn read_layer(chunk: &mut Reader) -> Option<Layer> {
+        let flags = chunk.word()?;
+        let kind = chunk.word()?;
+        let level = chunk.word()?;
+        chunk.skip(2 + 2 + 2)?;
+        let opacity = chunk.byte()?;
+        chunk.skip(3)?;
+        Some(Layer {
+            name: chunk.string()?,
+            // Reference layers are only a drawing aid
+            visible: flags & 1 != 0 && flags & 64 == 0,
+            group: kind == 1,
+            level,
+            opacity,
+        })
+    }
+
+    fn read_cel(chunk: &mut Reader, depth: ColorDepth) -> Option<Option<RawCel>> {
+        let layer = chunk.word()? as usize;
+        let pos = vec2(chunk.short()? as i32, chunk.short()? as i32);
+        let opacity = chunk.byte()?;
+        let kind = chunk.word()?;
+        chunk.skip(2 + 5)?;
+
+        let content = match kind {
+            0 | 2 => {
+                let size = vec2(chunk.word()? as u32, chunk.word()? as u32);
+                let length = (size.x * size.y) as usize * depth.bytes();
+                let pixels = if kind == 0 {
+                    chunk.bytes(length)?.to_vec()
+                } else {
+                    let mut pixels = Vec::with_capacity(length);
+                    flate2::read::ZlibDecoder::new(chunk.data)
+                        .read_to_end(&mut pixels)
+                        .ok()?;
+                    pixels
+                };
+                if pixels.len() < length {
+                    return None;
+                }
+                RawContent::Pixels(size, pixels)
+            }
+            1 => RawContent::Linked(chunk.word()? as usize),
+            // Tilemaps are not supported
+            _ => return Some(None),
+        };
+        Some(Some(RawCel {
+            layer,
+            pos,
+            opacity,
+            content,
+        }))
+    }
+
+    fn read_tags(chunk: &mut Reader) -> Option<Vec<Tag>> {
+        let count = chunk.word()?;
+        chunk.skip(8)?;
+        (0..count)
+            .map(|_| {
+                let from = chunk.word()? as usize;
+                let to = chunk.word()? as usize;
+                let direction = match chunk.byte()? {
+                    1 => Direction::Reverse,
+                    2 => Direction::PingPong,
+                    3 => Direction::PingPongReverse,
+                    _ => Direction::Forward,
+                };
+                let repeat = chunk.word()?;
+                chunk.skip(6 + 3 + 1)?;
+                Some(Tag {
+                    name: chunk.string()?,
+                    from,
+                    to,
+                    direction,
+                    repeat,
+                })
+            })
+            .collect()
+    }
+
+    fn read_palette(&mut self, chunk: &mut Reader) -> Option<()> {
+        let size = chunk.dword()? as usize;
+        let first = chunk.dword()? as usize;
+        let last = chunk.dword()? as usize;
+        chunk.skip(8)?;
+
+        self.palette
+            .resize(size.min(PALETTE_SIZE), Color::TRANSPARENT);
+        for index in first..=last {
+            let flags = chunk.word()?;
+            let [r, g, b, a] = [chunk.byte()?, chunk.byte()?, chunk.byte()?, chunk.byte()?];
+            if flags & 1 != 0 {
+                chunk.string()?;
+            }
+            if let Some(entry) = self.palette.get_mut(index) {
+                *entry = Color::from_srgb8(r, g, b, a);
+            }
+        }
+        Some(())
+    }
+
+    // Written by older versions alongside the new chunk, only used without it
+    fn read_old_palette(&mut self, chunk: &mut Reader) -> Option<()> {
+        let packets = chunk.word()?;
+        let mut index = 0;
+        for _ in 0..packets {
+            index += chunk.byte()? as usize;
+            let count = match chunk.byte()? {
+                0 => 256,
+                count => count as usize,
+            };
+            for _ in 0..count {
+                let [r, g, b] = [chunk.byte()?, chunk.byte()?, chunk.byte()?];
+                if index >= PALETTE_SIZE {
+                    continue;
+                }
+                if self.palette.len() <= index {
+                    self.palette.resize(index + 1, Color::TRANSPARENT);
+                }
+                self.palette[index] = Color::from_srgb8(r, g, b, 255);
+                index += 1;
+            }
+        }
+        Some(())
+    }
+
+    fn read_slice(chunk: &mut Reader) -> Option<Slice> {
+        let count = chunk.dword()?;
+        let flags = chunk.dword()?;
+        chunk.skip(4)?;
+        let name = chunk.string()?;
+
+        let rect = |chunk: &mut Reader| {
+            let pos = vec2(chunk.long()? as f32, chunk.long()? as f32);
+            let size = vec2(chunk.dword()? as f32, chunk.dword()? as f32);
+            Some(Rect::sized(pos, size))
+        };
+        let keys = (0..count)
+            .map(|_| {
+                let frame = chunk.dword()? as usize;
+                let bounds = rect(chunk)?;
+                let center = match flags & 1 {
+                    0 => None,
+                    _ => Some(rect(chunk)?),
+                };
+                let pivot = match flags & 2 {
+                    0 => None,
+                    _ => Some(vec2(chunk.long()? as f32, chunk.long()? as f32)),
+                };
+                Some(SliceKey {
+                    frame,
+                    bounds,
+                    center,
+                    pivot,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Slice { name, keys })
+    }
+
+    fn to_rgba(&self, pixels: &[u8], transparent: u8) -> Vec<u8> {
+        match self.depth {
+            ColorDepth::Rgba => pixels.to_vec(),
+            ColorDepth::Grayscale => pixels
+                .chunks_exact(2)
+                .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            ColorDepth::Indexed => pixels
+                .iter()
+                .flat_map(|index| {
+                    let color = self.palette.get(*index as usize);
+                    match color {
+                        Some(color) if *index != transparent => color.to_srgb8(),
+                        _ => [0, 0, 0, 0],
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    // Layers hidden themselves or inside a hidden group
+    fn shown_layers(&self) -> Vec<bool> {
+        let mut hidden_below: Option<u16> = None;
+        self.layers
+            .iter()
+            .map(|layer| {
+                if hidden_below.is_some_and(|level| layer.level <= level) {
+                    hidden_below = None;
+                }
+                if hidden_below.is_some() {
+                    return false;
+                }
+                if !layer.visible {
+                    hidden_below = Some(layer.level);
+                }
+                layer.visible && !layer.group
+            })
+            .collect()
+    }
+
+    // Visible layers blended bottom to top, as Aseprite shows the frame.
+    // Every layer uses normal blending
+    pub fn frame_image(&self, frame: usize) -> image::RgbaImage {
+        let mut image = image::RgbaImage::new(self.size.x, self.size.y);
+        let shown = self.shown_layers();
+
+        let mut cels: Vec<&Cel> = self.frames[frame]
+            .cels
+            .iter()
+            .filter(|cel| shown.get(cel.layer).copied().unwrap_or(false))
+            .collect();
+        cels.sort_by_key(|cel| cel.layer);
+
+        for cel in cels {
+            let layer_opacity = self.layers[cel.layer].opacity as f32 / 255.0;
+            let opacity = cel.opacity as f32 / 255.0 * layer_opacity;
+            for y in 0..cel.size.y {
+                for x in 0..cel.size.x {
+                    let target = cel.pos + vec2(x as i32, y as i32);
+                    if target.x < 0
+                        || target.y < 0
+                        || target.x >= self.size.x as i32
+                        || target.y >= self.size.y as i32
+                    {
+                        continue;
+                    }
+
+                    let offset = ((y * cel.size.x + x) * 4) as usize;
+                    let src = &cel.pixels[offset..offset + 4];
+                    let alpha = src[3] as f32 / 255.0 * opacity;
+                    if alpha <= 0.0 {
+                        continue;
+                    }
+                    let dst = image.get_pixel_mut(target.x as u32, target.y as u32);
+                    let dst_alpha = dst[3] as f32 / 255.0;
+                    let out_alpha = alpha + dst_alpha * (1.0 - alpha);
+                    for channel in 0..3 {
+                        let value = (src[channel] as f32 * alpha
+                            + dst[channel] as f32 * dst_alpha * (1.0 - alpha))
+                            / out_alpha;
+                        dst[channel] = value.round() as u8;
+                    }
+                    dst[3] = (out_alpha * 255.0).round() as u8;
+                }
+            }
+        }
+        image
+    }
+
+    // All frames packed into a grid, with the UV rect of each
+    pub fn sheet(&self) -> (image::RgbaImage, Vec<UVRect>) {
+        let count = self.frames.len().max(1);
+        let columns = (count as f32).sqrt().ceil() as u32;
+        let rows = (count as u32).div_ceil(columns);
+
+        let mut sheet = image::RgbaImage::new(self.size.x * columns, self.size.y * rows);
+        for frame in 0..self.frames.len() {
+            let cell = vec2(frame as u32 % columns, frame as u32 / columns);
+            let origin = cell * self.size;
+            image::imageops::replace(
+                &mut sheet,
+                &self.frame_image(frame),
+                origin.x as i64,
+                origin.y as i64,
+            );
+        }
+
+        let size = vec2(1.0 / columns as f32, 1.0 / rows as f32);
+        let rects = UVRect::grid(vec2(0.0, 0.0), size, self.frames.len(), columns as usize);
+        (sheet, rects)
+    }
+
+    // One clip per tag, taking its frames from `rects` as given by `sheet`.
+    // Without tags the whole file becomes a single looping clip named after
+    // the first layer, and a file without frames has no clips at all
+    pub fn clips(&self, rects: &[UVRect]) -> Vec<Clip> {
+        let frame = |index: usize| ClipFrame {
+            rect: rects[index],
+            duration: self.frames[index].duration,
+            event: None,
+        };
+
+        if self.frames.is_empty() {
+            return Vec::new();
+        }
+        if self.tags.is_empty() {
+            let name = self.layers.first().map_or("default", |layer| &layer.name);
+            let frames = (0..self.frames.len()).map(frame).collect();
+            return vec![Clip::new(name, frames)];
+        }
+
+        self.tags
+            .iter()
+            .filter(|tag| tag.from <= tag.to && tag.to < self.frames.len())
+            .map(|tag| {
+                let mut frames: Vec<ClipFrame> = (tag.from..=tag.to).map(frame).collect();
+                if matches!(
+                    tag.direction,
+                    Direction::Reverse | Direction::PingPongReverse
+                ) {
+                    frames.reverse();
+                }
+                let mode = match tag.direction {
+                    _ if tag.repeat == 1 => PlayMode::Once,
+                    Direction::Forward | Direction::Reverse => PlayMode::Loop,
+                    Direction::PingPong | Direction::PingPongReverse => PlayMode::PingPong,
+                };
+                Clip::new(&tag.name, frames).mode(mode)
+            })
+            .collect()
+    }
+
+    pub fn slice(&self, name: &str) -> Option<&Slice> {
+        self.slices.iter().find(|slice| slice.name == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn basic_font() {
+        let ase = Aseprite::load("assets/BasicFont.ase");
+        assert_eq!(ase.size, vec2(160, 176));
+        assert_eq!(ase.depth, ColorDepth::Grayscale);
+        assert_eq!(ase.frames.len(), 1);
+        assert!(ase.tags.is_empty());
+        assert!(ase.slices.is_empty());
+
+        assert_eq!(ase.layers.len(), 1);
+        let layer = &ase.layers[0];
+        assert_eq!(layer.name, "Layer 1");
+        assert!(layer.visible && !layer.group);
+        assert_eq!(layer.opacity, 255);
+
+        let frame = &ase.frames[0];
+        assert_eq!(frame.duration, 0.1);
+        assert_eq!(frame.cels.len(), 1);
+        let cel = &frame.cels[0];
+        assert_eq!(cel.layer, 0);
+        assert_eq!(cel.pos, vec2(0, 44));
+        assert_eq!(cel.size, vec2(158, 132));
+        // Decompressed and widened from grey and alpha to RGBA
+        assert_eq!(cel.pixels.len(), 158 * 132 * 4);
+        for pixel in cel.pixels.chunks_exact(4) {
+            assert!(pixel == [0, 0, 0, 0] || pixel == [255, 255, 255, 255]);
+        }
+
+        let image = ase.frame_image(0);
+        assert_eq!(image.dimensions(), (160, 176));
+        assert_eq!(image.get_pixel(0, 0).0, [0, 0, 0, 0]);
+        assert_eq!(image.get_pixel(13, 44).0, [255, 255, 255, 255]);
+        // Nothing is drawn above the cel
+        assert!((0..44).all(|y| (0..160).all(|x| image.get_pixel(x, y)[3] == 0)));
+    }
+
+    #[test]
+    fn untagged_clip() {
+        let ase = Aseprite::load("assets/BasicFont.ase");
+        let (sheet, rects) = ase.sheet();
+        assert_eq!(sheet.dimensions(), (160, 176));
+        assert_eq!(rects.len(), 1);
+
+        let clips = ase.clips(&rects);
+        assert_eq!(clips.len(), 1);
+        assert_eq!(clips[0].name, "Layer 1");
+        assert_eq!(clips[0].mode, PlayMode::Loop);
+        assert_eq!(clips[0].frames.len(), 1);
+    }
+
+    #[test]
+    fn no_frames_no_clips() {
+        let ase = Aseprite {
+            size: vec2(8, 8),
+            depth: ColorDepth::Rgba,
+            layers: Vec::new(),
+            frames: Vec::new(),
+            tags: Vec::new(),
+            slices: Vec::new(),
+            palette: Vec::new(),
+        };
+        let (_, rects) = ase.sheet();
+        assert!(rects.is_empty());
+        assert!(ase.clips(&rects).is_empty());
+    }
+
+    #[test]
+    fn rejects_bad_header() {
+        let data = std::fs::read("assets/BasicFont.ase").unwrap();
+        assert!(Aseprite::parse(&data[..64]).is_none());
+
+        let mut wrong_magic = data.clone();
+        wrong_magic[4] = 0;
+        assert!(Aseprite::parse(&wrong_magic).is_none());
+
+        // Cut off part way through the frame
+        assert!(Aseprite::parse(&data[..data.len() - 16]).is_none());
+    }
+
+    // Builds files in memory, laid out as the spec describes
+    #[derive(Default)]
+    struct Writer(Vec<u8>);
+    impl Writer {
+        fn byte(mut self, value: u8) -> Self {
+            self.0.push(value);
+            self
+        }
+        fn bytes(mut self, values: &[u8]) -> Self {
+            self.0.extend_from_slice(values);
+            self
+        }
+        fn word(self, value: u16) -> Self {
+            self.bytes(&value.to_le_bytes())
+        }
+        fn dword(self, value: u32) -> Self {
+            self.bytes(&value.to_le_bytes())
+        }
+        fn long(self, value: i32) -> Self {
+            self.bytes(&value.to_le_bytes())
+        }
+        fn string(self, value: &str) -> Self {
+            self.word(value.len() as u16).bytes(value.as_bytes())
+        }
+        fn zeros(self, count: usize) -> Self {
+            self.bytes(&vec![0; count])
+        }
+    }
+
+    fn file(size: Vec2<u16>, depth: u16, transparent: u8, frames: &[Vec<u8>]) -> Vec<u8> {
+        let body: Vec<u8> = frames.concat();
+        let header = Writer::default()
+            .dword(128 + body.len() as u32)
+            .word(0xA5E0)
+            .word(frames.len() as u16)
+            .word(size.x)
+            .word(size.y)
+            .word(depth)
+            .dword(1)
+            .word(100)
+            .zeros(8)
+            .byte(transparent)
+            .zeros(3)
+            .word(0)
+            .zeros(128 - 34);
+        [header.0, body].concat()
+    }
+    fn frame(duration: u16, chunks: &[Vec<u8>]) -> Vec<u8> {
+        let body: Vec<u8> = chunks.concat();
+        Writer::default()
+            .dword(16 + body.len() as u32)
+            .word(0xF1FA)
+            .word(chunks.len() as u16)
+            .word(duration)
+            .zeros(2)
+            .dword(chunks.len() as u32)
+            .bytes(&body)
+            .0
+    }
+    fn chunk(kind: u16, body: Writer) -> Vec<u8> {
+        Writer::default()
+            .dword(6 + body.0.len() as u32)
+            .word(kind)
+            .bytes(&body.0)
+            .0
+    }
+    fn layer(name: &str) -> Vec<u8> {
+        let body = Writer::default()
+            .word(1)
+            .word(0)
+            .word(0)
+            .zeros(6)
+            .byte(255)
+            .zeros(3)
+            .string(name);
+        chunk(0x2004, body)
+    }
+    fn cel_header(x: i16, kind: u16) -> Writer {
+        Writer::default()
+            .word(0)
+            .bytes(&x.to_le_bytes())
+            .word(0)
+            .byte(255)
+            .word(kind)
+            .zeros(7)
+    }
+    fn raw_cel(x: i16, size: Vec2<u16>, pixels: &[u8]) -> Vec<u8> {
+        let body = cel_header(x, 0).word(size.x).word(size.y).bytes(pixels);
+        chunk(0x2005, body)
+    }
+    fn compressed_cel(x: i16, size: Vec2<u16>, pixels: &[u8]) -> Vec<u8> {
+        use std::io::Write;
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), Default::default());
+        encoder.write_all(pixels).unwrap();
+        let data = encoder.finish().unwrap();
+        let body = cel_header(x, 2).word(size.x).word(size.y).bytes(&data);
+        chunk(0x2005, body)
+    }
+    fn linked_cel(x: i16, frame: u16) -> Vec<u8> {
+        chunk(0x2005, cel_header(x, 1).word(frame))
+    }
+    // (from, to, direction, repeat, name)
+    fn tags(tags: &[(u16, u16, u8, u16, &str)]) -> Vec<u8> {
+        let mut body = Writer::default().word(tags.len() as u16).zeros(8);
+        for (from, to, direction, repeat, name) in tags {
+            body = body
+                .word(*from)
+                .word(*to)
+                .byte(*direction)
+                .word(*repeat)
+                .zeros(10)
+                .string(name);
+        }
+        chunk(0x2018, body)
+    }
+    // Entries from `first`, named entries have the name flag set
+    fn palette(size: u32, first: u32, entries: &[([u8; 4], Option<&str>)]) -> Vec<u8> {
+        let last = first + entries.len() as u32 - 1;
+        let mut body = Writer::default()
+            .dword(size)
+            .dword(first)
+            .dword(last)
+            .zeros(8);
+        for (rgba, name) in entries {
+            body = body.word(name.is_some() as u16).bytes(rgba);
+            if let Some(name) = name {
+                body = body.string(name);
+            }
+        }
+        chunk(0x2019, body)
+    }
+    // (entries to skip, colours)
+    fn old_palette(packets: &[(u8, &[[u8; 3]])]) -> Vec<u8> {
+        let mut body = Writer::default().word(packets.len() as u16);
+        for (skip, colors) in packets {
+            body = body.byte(*skip).byte(colors.len() as u8);
+            for color in colors.iter() {
+                body = body.bytes(color);
+            }
+        }
+        chunk(0x0004, body)
+    }
+    // Keys of (frame, bounds), every key with the same centre and pivot
+    fn slice(
+        name: &str,
+        keys: &[(u32, [i32; 4])],
+        center: Option<[i32; 4]>,
+        pivot: Option<[i32; 2]>,
+    ) -> Vec<u8> {
+        let flags = center.is_some() as u32 | (pivot.is_some() as u32) << 1;
+        let mut body = Writer::default()
+            .dword(keys.len() as u32)
+            .dword(flags)
+            .dword(0)
+            .string(name);
+        let rect = |body: Writer, [x, y, w, h]: [i32; 4]| {
+            body.long(x).long(y).dword(w as u32).dword(h as u32)
+        };
+        for (frame, bounds) in keys {
+            body = rect(body.dword(*frame), *bounds);
+            if let Some(center) = center {
+                body = rect(body, center);
+            }
+            if let Some([x, y]) = pivot {
+                body = body.long(x).long(y);
+            }
+        }
+        chunk(0x2022, body)
+    }
+
+    // Four frames of a 2x1 RGBA sprite, two of them reusing earlier cels
+    fn tagged() -> Aseprite {
+        let red = [255, 0, 0, 255, 255, 0, 0, 128];
+        let blue = [0, 0, 255, 255, 0, 0, 255, 255];
+        let data = file(
+            vec2(2, 1),
+            32,
+            0,
+            &[
+                frame(
+                    100,
+                    &[
+                        layer("Body"),
+                        compressed_cel(0, vec2(2, 1), &red),
+                        tags(&[
+                            (0, 3, 0, 0, "walk"),
+                            (1, 2, 0, 1, "cast"),
+                            (0, 2, 3, 0, "hover"),
+                            (1, 3, 1, 0, "back"),
+                            (2, 9, 0, 0, "broken"),
+                        ]),
+                        slice(
+                            "Frame",
+                            &[(0, [0, 0, 2, 1]), (2, [1, 0, 1, 1])],
+                            Some([1, 0, 1, 1]),
+                            Some([1, 0]),
+                        ),
+                        slice("Hitbox", &[(0, [0, 0, 1, 1])], None, None),
+                    ],
+                ),
+                frame(200, &[raw_cel(1, vec2(2, 1), &blue)]),
+                frame(100, &[linked_cel(0, 0)]),
+                // Links only point backwards, so this one is dropped
+                frame(50, &[linked_cel(0, 3)]),
+            ],
+        );
+        Aseprite::parse(&data).unwrap()
+    }
+
+    #[test]
+    fn frames_and_linked_cels() {
+        let ase = tagged();
+        assert_eq!(ase.size, vec2(2, 1));
+        assert_eq!(ase.depth, ColorDepth::Rgba);
+        assert_eq!(ase.layers[0].name, "Body");
+        let durations: Vec<f32> = ase.frames.iter().map(|frame| frame.duration).collect();
+        assert_eq!(durations, [0.1, 0.2, 0.1, 0.05]);
+
+        assert_eq!(
+            ase.frames[0].cels[0].pixels,
+            [255, 0, 0, 255, 255, 0, 0, 128]
+        );
+        assert_eq!(ase.frames[1].cels[0].pos, vec2(1, 0));
+        // Linked cels share the pixels but keep their own position
+        assert_eq!(ase.frames[2].cels[0].pixels, ase.frames[0].cels[0].pixels);
+        assert_eq!(ase.frame_image(2), ase.frame_image(0));
+        assert!(ase.frames[3].cels.is_empty());
+
+        // Moved one pixel right, so half of it is off the canvas
+        let image = ase.frame_image(1);
+        assert_eq!(image.get_pixel(0, 0).0, [0, 0, 0, 0]);
+        assert_eq!(image.get_pixel(1, 0).0, [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn tags_become_clips() {
+        let ase = tagged();
+        let (sheet, rects) = ase.sheet();
+        assert_eq!(sheet.dimensions(), (4, 2));
+        let clips = ase.clips(&rects);
+
+        let names: Vec<&str> = clips.iter().map(|clip| clip.name.as_str()).collect();
+        assert_eq!(names, ["walk", "cast", "hover", "back"]);
+        let frames = |clip: &Clip| -> Vec<usize> {
+            clip.frames
+                .iter()
+                .map(|frame| rects.iter().position(|rect| *rect == frame.rect).unwrap())
+                .collect()
+        };
+
+        assert_eq!(clips[0].mode, PlayMode::Loop);
+        assert_eq!(frames(&clips[0]), [0, 1, 2, 3]);
+        assert_eq!(clips[0].frames[3].duration, 0.05);
+
+        // Playing a tag once holds its last frame
+        assert_eq!(clips[1].mode, PlayMode::Once);
+        assert_eq!(frames(&clips[1]), [1, 2]);
+        assert_eq!(clips[1].length(), 0.3);
+
+        assert_eq!(clips[2].mode, PlayMode::PingPong);
+        assert_eq!(frames(&clips[2]), [2, 1, 0]);
+        assert_eq!(clips[2].frames[1].duration, 0.2);
+
+        assert_eq!(clips[3].mode, PlayMode::Loop);
+        assert_eq!(frames(&clips[3]), [3, 2, 1]);
+    }
+
+    #[test]
+    fn slices_and_their_keys() {
+        let ase = tagged();
+        let frame = ase.slice("Frame").unwrap();
+        assert_eq!(frame.keys.len(), 2);
+        let key = frame.key(1).unwrap();
+        assert_eq!(key.frame, 0);
+        assert_eq!(key.bounds, Rect::sized(vec2(0.0, 0.0), vec2(2.0, 1.0)));
+        assert_eq!(
+            key.center,
+            Some(Rect::sized(vec2(1.0, 0.0), vec2(1.0, 1.0)))
+        );
+        assert_eq!(key.pivot, Some(vec2(1.0, 0.0)));
+        assert_eq!(frame.key(3).unwrap().bounds.min, vec2(1.0, 0.0));
+
+        let hitbox = ase.slice("Hitbox").unwrap();
+        assert_eq!(hitbox.keys[0].center, None);
+        assert_eq!(hitbox.keys[0].pivot, None);
+        assert!(ase.slice("Missing").is_none());
+    }
+
+    fn indexed(palettes: Vec<Vec<u8>>) -> Aseprite {
+        let chunks = [
+            palettes,
+            vec![layer("Ink"), raw_cel(0, vec2(4, 1), &[0, 1, 2, 5])],
+        ];
+        let data = file(vec2(4, 1), 8, 0, &[frame(100, &chunks.concat())]);
+        Aseprite::parse(&data).unwrap()
+    }
+
+    #[test]
+    fn indexed_with_the_new_palette() {
+        // Older versions also write the old chunk, which must not override
+        let ase = indexed(vec![
+            palette(
+                3,
+                0,
+                &[
+                    ([9, 9, 9, 255], None),
+                    ([255, 0, 0, 255], Some("Blood")),
+                    ([0, 255, 0, 128], None),
+                ],
+            ),
+            old_palette(&[(0, &[[1, 1, 1], [2, 2, 2], [3, 3, 3]])]),
+        ]);
+        assert_eq!(ase.depth, ColorDepth::Indexed);
+        assert_eq!(ase.palette.len(), 3);
+        // The transparent index is see-through whatever its colour, and
+        // indices past the palette are too
+        assert_eq!(
+            ase.frames[0].cels[0].pixels,
+            [0, 0, 0, 0, 255, 0, 0, 255, 0, 255, 0, 128, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn indexed_with_the_old_palette() {
+        let ase = indexed(vec![old_palette(&[
+            (0, &[[0, 0, 0], [10, 20, 30]]),
+            (3, &[[255, 255, 255]]),
+        ])]);
+        assert_eq!(ase.palette.len(), 6);
+        assert_eq!(ase.palette[3], Color::TRANSPARENT);
+        assert_eq!(
+            ase.frames[0].cels[0].pixels,
+            [0, 0, 0, 0, 10, 20, 30, 255, 0, 0, 0, 0, 255, 255, 255, 255]
+        );
+    }
+
+    #[test]
+    fn palettes_stop_at_256_entries() {
+        let ase = indexed(vec![palette(100000, 0, &[([1, 2, 3, 255], None)])]);
+        assert_eq!(ase.palette.len(), 256);
+
+        let colors = [[7, 7, 7]; 4];
+        let ase = indexed(vec![old_palette(&[(200, &colors), (50, &colors)])]);
+        assert_eq!(ase.palette.len(), 256);
+        assert_eq!(ase.palette[255].to_srgb8(), [7, 7, 7, 255]);
+    }
+}
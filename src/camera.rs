@@ -0,0 +1,340 @@
+use crate::geometry::Rect;
+use crate::graphics::Graphics;
+use crate::input::ScreenToWorld;
+use crate::nvec::*;
+
+// World units are pixels at zoom 1, with y pointing up
+pub struct Camera2D {
+    pub position: Vec2<f32>,
+    pub zoom: f32,
+    pub rotation: f32,
+    // Size of the render target in physical pixels
+    pub viewport: Vec2<f32>,
+
+    // Higher is snappier, roughly the inverse of the catch-up time in seconds
+    pub follow_speed: f32,
+    // Area the view is kept inside of, if any
+    pub bounds: Option<Rect>,
+
+    pub shake_strength: f32,
+    pub shake_decay: f32,
+    trauma: f32,
+    time: f32,
+}
+
+impl Camera2D {
+    pub fn new(viewport: Vec2<f32>) -> Self {
+        Self {
+            position: vec2(0.0, 0.0),
+            zoom: 1.0,
+            rotation: 0.0,
+            viewport,
+            follow_speed: 8.0,
+            bounds: None,
+            shake_strength: 8.0,
+            shake_decay: 1.5,
+            trauma: 0.0,
+            time: 0.0,
+        }
+    }
+
+    // Half of the visible area, in world units
+    pub fn half_extent(&self) -> Vec2<f32> {
+        self.viewport / (2.0 * self.zoom)
+    }
+    // World space bounding box of what is drawn, including rotation and shake
+    pub fn visible(&self) -> Rect {
+        let to_world = |ndc| match self.view().inverse() {
+            Some(inverse) => inverse.transform_point(ndc),
+            None => self.position,
+        };
+        let corners = [
+            vec2(-1.0, -1.0),
+            vec2(1.0, -1.0),
+            vec2(1.0, 1.0),
+            vec2(-1.0, 1.0),
+        ]
+        .map(to_world);
+        corners
+            .iter()
+            .fold(Rect::from_corners(corners[0], corners[0]), |rect, p| {
+                rect.union(&Rect::from_corners(*p, *p))
+            })
+    }
+
+    // Adds screen shake, in [0, 1], accumulating with any shake already running
+    pub fn shake(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount).min(1.0);
+    }
+
+    fn shake_offset(&self) -> Vec2<f32> {
+        // Deterministic noise, so replays shake identically
+        let strength = self.shake_strength * self.trauma * self.trauma;
+        let t = self.time;
+        let x = (t * 37.0).sin() * 0.6 + (t * 71.0 + 1.3).sin() * 0.4;
+        let y = (t * 43.0 + 2.1).sin() * 0.6 + (t * 67.0 + 0.7).sin() * 0.4;
+        vec2(x, y) * strength
+    }
+
+    // Moves smoothly towards the target, independent of frame rate
+    pub fn follow(&mut self, target: Vec2<f32>, dt: f32) {
+        let t = 1.0 - (-self.follow_speed * dt).exp();
+        self.position = self.position.lerp(target, t);
+        self.clamp_to_bounds();
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.time += dt;
+        self.trauma = (self.trauma - self.shake_decay * dt).max(0.0);
+        self.clamp_to_bounds();
+    }
+
+    fn clamp_to_bounds(&mut self) {
+        let Some(bounds) = self.bounds else {
+            return;
+        };
+        // Collapses onto the centre along any axis where the view is larger
+        // than the bounds
+        let inner = bounds.inflate(-self.half_extent());
+        self.position = self.position.clamp(inner.min, inner.max);
+    }
+
+    // What is drawn, shaking included
+    pub fn view(&self) -> Mat3<f32> {
+        self.view_around(self.position + self.shake_offset())
+    }
+    // The view without shake, so picking and anything placed with
+    // `world_to_screen` hold still while the picture shakes
+    fn stable_view(&self) -> Mat3<f32> {
+        self.view_around(self.position)
+    }
+    fn view_around(&self, centre: Vec2<f32>) -> Mat3<f32> {
+        let half = self.half_extent();
+        Mat3::scale(vec2(1.0 / half.x, 1.0 / half.y))
+            * Mat3::rotation(-self.rotation)
+            * Mat3::translation(-centre)
+    }
+    pub fn view_proj(&self) -> Mat4<f32> {
+        self.view().into()
+    }
+
+    pub fn world_to_ndc(&self, world: Vec2<f32>) -> Vec2<f32> {
+        self.stable_view().transform_point(world)
+    }
+
+    // Physical pixels from the top-left of the viewport
+    pub fn screen_to_world(&self, pixels: Vec2<f32>) -> Vec2<f32> {
+        let ndc = vec2(
+            2.0 * pixels.x / self.viewport.x - 1.0,
+            1.0 - 2.0 * pixels.y / self.viewport.y,
+        );
+        self.ndc_to_world(ndc)
+    }
+    pub fn world_to_screen(&self, world: Vec2<f32>) -> Vec2<f32> {
+        let ndc = self.world_to_ndc(world);
+        vec2(
+            0.5 * (ndc.x + 1.0) * self.viewport.x,
+            0.5 * (1.0 - ndc.y) * self.viewport.y,
+        )
+    }
+}
+
+impl ScreenToWorld for Camera2D {
+    fn ndc_to_world(&self, ndc: Vec2<f32>) -> Vec2<f32> {
+        match self.stable_view().inverse() {
+            Some(inverse) => inverse.transform_point(ndc),
+            None => self.position,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+struct CameraUniform {
+    view_proj: Mat4<f32>,
+}
+
+pub struct CameraBuffer {
+    buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+}
+impl CameraBuffer {
+    pub fn layout(gfx: &Graphics) -> wgpu::BindGroupLayout {
+        gfx.device
+            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Camera.BindGroupLayout"),
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+            })
+    }
+
+    pub fn new(gfx: &Graphics) -> Self {
+        let buffer = gfx.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Camera.Buffer"),
+            size: std::mem::size_of::<CameraUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = gfx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Camera.BindGroup"),
+            layout: &Self::layout(gfx),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+
+        Self { buffer, bind_group }
+    }
+
+    pub fn upload(&self, gfx: &Graphics, camera: &Camera2D) {
+        self.upload_matrix(gfx, camera.view_proj());
+    }
+    // For fixed spaces, such as the identity for drawing in clip space
+    pub fn upload_matrix(&self, gfx: &Graphics, view_proj: Mat4<f32>) {
+        let uniform = CameraUniform { view_proj };
+        gfx.queue
+            .write_buffer(&self.buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn near(a: Vec2<f32>, b: Vec2<f32>) -> bool {
+        (a - b).length() < 1e-3
+    }
+
+    fn camera() -> Camera2D {
+        let mut camera = Camera2D::new(vec2(640.0, 360.0));
+        camera.position = vec2(100.0, -50.0);
+        camera.zoom = 2.0;
+        camera
+    }
+
+    #[test]
+    fn screen_corners_and_centre() {
+        let camera = camera();
+        // y is down on screen and up in the world
+        assert!(near(
+            camera.screen_to_world(vec2(320.0, 180.0)),
+            vec2(100.0, -50.0)
+        ));
+        assert!(near(
+            camera.screen_to_world(vec2(0.0, 0.0)),
+            vec2(-60.0, 40.0)
+        ));
+        assert!(near(
+            camera.screen_to_world(vec2(640.0, 360.0)),
+            vec2(260.0, -140.0)
+        ));
+        assert!(near(
+            camera.world_to_screen(vec2(260.0, 40.0)),
+            vec2(640.0, 0.0)
+        ));
+    }
+
+    #[test]
+    fn screen_world_round_trip() {
+        let mut camera = camera();
+        for rotation in [0.0, 0.3, -2.0] {
+            camera.rotation = rotation;
+            for pixels in [vec2(0.0, 0.0), vec2(17.0, 300.0), vec2(640.0, 1.0)] {
+                let world = camera.screen_to_world(pixels);
+                assert!(near(camera.world_to_screen(world), pixels));
+            }
+            let world = vec2(-30.0, 12.5);
+            assert!(near(
+                camera.screen_to_world(camera.world_to_screen(world)),
+                world
+            ));
+            assert!(near(camera.ndc_to_world(camera.world_to_ndc(world)), world));
+        }
+    }
+
+    #[test]
+    fn shake_moves_the_picture_not_the_picking() {
+        let mut camera = camera();
+        let still = camera.view();
+        let pointer = camera.screen_to_world(vec2(10.0, 20.0));
+
+        camera.shake(1.0);
+        camera.update(0.1);
+        assert!(camera.view() != still);
+        assert!(near(camera.screen_to_world(vec2(10.0, 20.0)), pointer));
+        assert!(near(camera.world_to_screen(pointer), vec2(10.0, 20.0)));
+
+        // Shaking wears off
+        for _ in 0..10 {
+            camera.update(0.1);
+        }
+        assert!(near(
+            camera.view().transform_point(pointer),
+            still.transform_point(pointer)
+        ));
+    }
+
+    #[test]
+    fn visible_area() {
+        let mut camera = camera();
+        let visible = camera.visible();
+        assert!(near(visible.min, vec2(-60.0, -140.0)));
+        assert!(near(visible.max, vec2(260.0, 40.0)));
+
+        // A quarter turn swaps the extents around the same centre
+        camera.rotation = std::f32::consts::FRAC_PI_2;
+        let visible = camera.visible();
+        assert!(near(visible.center(), vec2(100.0, -50.0)));
+        assert!(near(visible.size(), vec2(180.0, 320.0)));
+    }
+
+    #[test]
+    fn bounds_clamp_the_view() {
+        let mut camera = Camera2D::new(vec2(200.0, 100.0));
+        camera.bounds = Some(Rect::from_corners(vec2(0.0, 0.0), vec2(1000.0, 500.0)));
+
+        camera.update(0.0);
+        assert_eq!(camera.position, vec2(100.0, 50.0));
+
+        camera.follow(vec2(2000.0, 2000.0), 10.0);
+        assert!(near(camera.position, vec2(900.0, 450.0)));
+        assert!(camera.bounds.unwrap().contains_rect(&camera.visible()));
+
+        camera.follow(vec2(500.0, 200.0), 10.0);
+        assert!(near(camera.position, vec2(500.0, 200.0)));
+    }
+
+    #[test]
+    fn bounds_smaller_than_the_view_centre_it() {
+        let mut camera = Camera2D::new(vec2(200.0, 100.0));
+        camera.bounds = Some(Rect::from_corners(vec2(0.0, 0.0), vec2(1000.0, 60.0)));
+        camera.follow(vec2(800.0, -300.0), 10.0);
+        assert!(near(camera.position, vec2(800.0, 30.0)));
+
+        // Zooming out far enough does the same on the other axis
+        camera.zoom = 0.1;
+        camera.update(0.0);
+        assert!(near(camera.position, vec2(500.0, 30.0)));
+    }
+
+    #[test]
+    fn follow_is_frame_rate_independent() {
+        let mut once = camera();
+        let mut split = camera();
+        once.follow(vec2(0.0, 0.0), 0.2);
+        for _ in 0..4 {
+            split.follow(vec2(0.0, 0.0), 0.05);
+        }
+        assert!(near(once.position, split.position));
+    }
+}
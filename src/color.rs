@@ -0,0 +1,425 @@
+use crate::nvec::*;
+
+// Linear RGBA, straight (non-premultiplied) alpha unless stated otherwise.
+// Shares its layout with Vec4<f32> so it can go straight into vertex buffers
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, bytemuck::Zeroable, bytemuck::Pod)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+pub const fn rgba(r: f32, g: f32, b: f32, a: f32) -> Color {
+    Color { r, g, b, a }
+}
+pub const fn rgb(r: f32, g: f32, b: f32) -> Color {
+    Color { r, g, b, a: 1.0 }
+}
+
+impl Color {
+    pub const TRANSPARENT: Color = rgba(0.0, 0.0, 0.0, 0.0);
+    pub const BLACK: Color = rgb(0.0, 0.0, 0.0);
+    pub const WHITE: Color = rgb(1.0, 1.0, 1.0);
+    pub const GREY: Color = rgb(0.5, 0.5, 0.5);
+    pub const RED: Color = rgb(1.0, 0.0, 0.0);
+    pub const GREEN: Color = rgb(0.0, 1.0, 0.0);
+    pub const BLUE: Color = rgb(0.0, 0.0, 1.0);
+    pub const YELLOW: Color = rgb(1.0, 1.0, 0.0);
+    pub const CYAN: Color = rgb(0.0, 1.0, 1.0);
+    pub const MAGENTA: Color = rgb(1.0, 0.0, 1.0);
+
+    // Geomagika palette
+    pub const ARCANE: Color = rgb(0.6, 0.3, 0.8);
+    pub const MANA: Color = rgb(0.2, 0.4, 0.8);
+    pub const EMBER: Color = rgb(0.9, 0.2, 0.3);
+
+    pub const NAMED: &'static [(&'static str, Color)] = &[
+        ("transparent", Self::TRANSPARENT),
+        ("black", Self::BLACK),
+        ("white", Self::WHITE),
+        ("grey", Self::GREY),
+        ("red", Self::RED),
+        ("green", Self::GREEN),
+        ("blue", Self::BLUE),
+        ("yellow", Self::YELLOW),
+        ("cyan", Self::CYAN),
+        ("magenta", Self::MAGENTA),
+        ("arcane", Self::ARCANE),
+        ("mana", Self::MANA),
+        ("ember", Self::EMBER),
+    ];
+
+    pub fn named(name: &str) -> Option<Color> {
+        Self::NAMED
+            .iter()
+            .find(|(other, _)| other.eq_ignore_ascii_case(name))
+            .map(|(_, color)| *color)
+    }
+
+    pub fn with_alpha(self, a: f32) -> Color {
+        Color { a, ..self }
+    }
+
+    fn map_rgb(self, f: impl Fn(f32) -> f32) -> Color {
+        rgba(f(self.r), f(self.g), f(self.b), self.a)
+    }
+
+    pub fn srgb_to_linear(value: f32) -> f32 {
+        if value <= 0.04045 {
+            value / 12.92
+        } else {
+            ((value + 0.055) / 1.055).powf(2.4)
+        }
+    }
+    pub fn linear_to_srgb(value: f32) -> f32 {
+        if value <= 0.0031308 {
+            value * 12.92
+        } else {
+            1.055 * value.powf(1.0 / 2.4) - 0.055
+        }
+    }
+
+    // Interprets the channels as sRGB encoded
+    pub fn from_srgb(srgb: Color) -> Color {
+        srgb.map_rgb(Self::srgb_to_linear)
+    }
+    pub fn to_srgb(self) -> Color {
+        self.map_rgb(Self::linear_to_srgb)
+    }
+
+    pub fn from_srgb8(r: u8, g: u8, b: u8, a: u8) -> Color {
+        let unit = |v: u8| v as f32 / 255.0;
+        Self::from_srgb(rgba(unit(r), unit(g), unit(b), unit(a)))
+    }
+    pub fn to_srgb8(self) -> [u8; 4] {
+        let srgb = self.to_srgb();
+        let byte = |v: f32| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+        [byte(srgb.r), byte(srgb.g), byte(srgb.b), byte(srgb.a)]
+    }
+
+    // Accepts #RGB, #RGBA, #RRGGBB and #RRGGBBAA, with or without the '#'
+    pub fn from_hex(hex: &str) -> Option<Color> {
+        let hex = hex.strip_prefix('#').unwrap_or(hex);
+        if !hex.is_ascii() {
+            return None;
+        }
+        let digit = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).ok();
+        let pair = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+
+        let [r, g, b, a] = match hex.len() {
+            3 | 4 => {
+                let mut channels = [255; 4];
+                for (i, channel) in channels.iter_mut().enumerate().take(hex.len()) {
+                    *channel = digit(i)? * 17;
+                }
+                channels
+            }
+            6 | 8 => {
+                let mut channels = [255; 4];
+                for (i, channel) in channels.iter_mut().enumerate().take(hex.len() / 2) {
+                    *channel = pair(2 * i)?;
+                }
+                channels
+            }
+            _ => return None,
+        };
+        Some(Self::from_srgb8(r, g, b, a))
+    }
+    pub fn to_hex(self) -> String {
+        let [r, g, b, a] = self.to_srgb8();
+        if a == 255 {
+            format!("#{r:02x}{g:02x}{b:02x}")
+        } else {
+            format!("#{r:02x}{g:02x}{b:02x}{a:02x}")
+        }
+    }
+
+    // Hue in degrees, saturation and value in [0, 1]. Operates on the
+    // channels as stored, so convert with `to_srgb` first for perceptual edits
+    pub fn from_hsv(hue: f32, saturation: f32, value: f32, alpha: f32) -> Color {
+        let chroma = value * saturation;
+        Self::from_hue(hue, chroma, value - chroma, alpha)
+    }
+    pub fn to_hsv(self) -> (f32, f32, f32) {
+        let (hue, max, min) = self.hue();
+        let saturation = if max > 0.0 { (max - min) / max } else { 0.0 };
+        (hue, saturation, max)
+    }
+
+    pub fn from_hsl(hue: f32, saturation: f32, lightness: f32, alpha: f32) -> Color {
+        let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+        Self::from_hue(hue, chroma, lightness - chroma / 2.0, alpha)
+    }
+    pub fn to_hsl(self) -> (f32, f32, f32) {
+        let (hue, max, min) = self.hue();
+        let lightness = (max + min) / 2.0;
+        let saturation = if max == min {
+            0.0
+        } else {
+            (max - min) / (1.0 - (2.0 * lightness - 1.0).abs())
+        };
+        (hue, saturation, lightness)
+    }
+
+    fn from_hue(hue: f32, chroma: f32, offset: f32, alpha: f32) -> Color {
+        let h = hue.rem_euclid(360.0) / 60.0;
+        let x = chroma * (1.0 - (h % 2.0 - 1.0).abs());
+        let (r, g, b) = match h as u32 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        rgba(r + offset, g + offset, b + offset, alpha)
+    }
+    // Returns (hue in degrees, max channel, min channel)
+    fn hue(self) -> (f32, f32, f32) {
+        let max = self.r.max(self.g).max(self.b);
+        let min = self.r.min(self.g).min(self.b);
+        let delta = max - min;
+
+        let hue = if delta == 0.0 {
+            0.0
+        } else if max == self.r {
+            60.0 * ((self.g - self.b) / delta).rem_euclid(6.0)
+        } else if max == self.g {
+            60.0 * ((self.b - self.r) / delta + 2.0)
+        } else {
+            60.0 * ((self.r - self.g) / delta + 4.0)
+        };
+        (hue, max, min)
+    }
+
+    pub fn premultiply(self) -> Color {
+        rgba(self.r * self.a, self.g * self.a, self.b * self.a, self.a)
+    }
+    pub fn unpremultiply(self) -> Color {
+        if self.a == 0.0 {
+            return Self::TRANSPARENT;
+        }
+        rgba(self.r / self.a, self.g / self.a, self.b / self.a, self.a)
+    }
+
+    pub fn lerp(self, rhs: Color, t: f32) -> Color {
+        Vec4::from(self).lerp(rhs.into(), t).into()
+    }
+    // Porter-Duff "over", with self drawn on top of `below`
+    pub fn over(self, below: Color) -> Color {
+        let a = self.a + below.a * (1.0 - self.a);
+        if a == 0.0 {
+            return Self::TRANSPARENT;
+        }
+        let mix = |top: f32, bottom: f32| (top * self.a + bottom * below.a * (1.0 - self.a)) / a;
+        rgba(
+            mix(self.r, below.r),
+            mix(self.g, below.g),
+            mix(self.b, below.b),
+            a,
+        )
+    }
+    pub fn multiply(self, rhs: Color) -> Color {
+        rgba(
+            self.r * rhs.r,
+            self.g * rhs.g,
+            self.b * rhs.b,
+            self.a * rhs.a,
+        )
+    }
+    pub fn add_rgb(self, rhs: Color) -> Color {
+        rgba(self.r + rhs.r, self.g + rhs.g, self.b + rhs.b, self.a)
+    }
+
+    // Accepts a palette name, a hex string, [r, g, b(, a)] or {r, g, b(, a)}
+    // with linear channels in [0, 1]
+    pub fn from_json(value: &json::JsonValue) -> Option<Color> {
+        if let Some(text) = value.as_str() {
+            return Self::named(text).or_else(|| Self::from_hex(text));
+        }
+        if value.is_array() {
+            let channels: Vec<f32> = value.members().map(|v| v.as_f32()).collect::<Option<_>>()?;
+            return match channels[..] {
+                [r, g, b] => Some(rgb(r, g, b)),
+                [r, g, b, a] => Some(rgba(r, g, b, a)),
+                _ => None,
+            };
+        }
+        if value.is_object() {
+            return Some(rgba(
+                value["r"].as_f32()?,
+                value["g"].as_f32()?,
+                value["b"].as_f32()?,
+                value["a"].as_f32().unwrap_or(1.0),
+            ));
+        }
+        None
+    }
+    pub fn to_json(self) -> json::JsonValue {
+        json::array![self.r, self.g, self.b, self.a]
+    }
+}
+
+impl From<Vec4<f32>> for Color {
+    fn from(v: Vec4<f32>) -> Self {
+        rgba(v.x, v.y, v.z, v.w)
+    }
+}
+impl From<Color> for Vec4<f32> {
+    fn from(c: Color) -> Self {
+        vec4(c.r, c.g, c.b, c.a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn near(a: Color, b: Color) -> bool {
+        let d = Vec4::from(a) - Vec4::from(b);
+        d.x.abs() < 1e-4 && d.y.abs() < 1e-4 && d.z.abs() < 1e-4 && d.w.abs() < 1e-4
+    }
+
+    #[test]
+    fn hex_forms() {
+        assert_eq!(Color::from_hex("#fff"), Some(Color::WHITE));
+        assert_eq!(Color::from_hex("f00"), Some(Color::RED));
+        assert_eq!(
+            Color::from_hex("#0f08").map(|c| c.to_srgb8()),
+            Some([0, 255, 0, 136])
+        );
+        assert_eq!(Color::from_hex("#0000ff"), Some(Color::BLUE));
+        assert_eq!(
+            Color::from_hex("#ffffff80").map(|c| c.to_srgb8()),
+            Some([255, 255, 255, 128])
+        );
+        assert_eq!(Color::from_hex("#336699").unwrap().to_hex(), "#336699");
+        assert_eq!(Color::from_hex("#33669980").unwrap().to_hex(), "#33669980");
+
+        assert_eq!(Color::from_hex(""), None);
+        assert_eq!(Color::from_hex("#ff"), None);
+        assert_eq!(Color::from_hex("#fffff"), None);
+        assert_eq!(Color::from_hex("#ggg"), None);
+        assert_eq!(Color::from_hex("#ffé"), None);
+        assert_eq!(Color::from_hex("#+ff"), None);
+    }
+
+    #[test]
+    fn srgb_round_trip() {
+        for i in 0..=255u8 {
+            let c = Color::from_srgb8(i, 255 - i, i / 2, 255);
+            assert_eq!(c.to_srgb8(), [i, 255 - i, i / 2, 255]);
+        }
+        for i in 0..=100 {
+            let v = i as f32 / 100.0;
+            assert!((Color::srgb_to_linear(Color::linear_to_srgb(v)) - v).abs() < 1e-5);
+        }
+        // Mid grey in sRGB is much darker in linear light, alpha is untouched
+        let grey = Color::from_srgb(rgba(0.5, 0.5, 0.5, 0.5));
+        assert!((grey.r - 0.2140).abs() < 1e-3);
+        assert_eq!(grey.a, 0.5);
+    }
+
+    #[test]
+    fn hsv() {
+        assert!(near(Color::from_hsv(0.0, 1.0, 1.0, 1.0), Color::RED));
+        assert!(near(Color::from_hsv(120.0, 1.0, 1.0, 1.0), Color::GREEN));
+        assert!(near(
+            Color::from_hsv(240.0, 1.0, 1.0, 0.5),
+            Color::BLUE.with_alpha(0.5)
+        ));
+        assert!(near(Color::from_hsv(-300.0, 1.0, 1.0, 1.0), Color::YELLOW));
+        assert!(near(Color::from_hsv(90.0, 0.0, 0.5, 1.0), Color::GREY));
+
+        for color in [Color::ARCANE, Color::MANA, Color::EMBER, rgb(0.1, 0.7, 0.4)] {
+            let (h, s, v) = color.to_hsv();
+            assert!(near(Color::from_hsv(h, s, v, 1.0), color));
+        }
+        assert_eq!(Color::CYAN.to_hsv(), (180.0, 1.0, 1.0));
+        assert_eq!(Color::BLACK.to_hsv(), (0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn hsl() {
+        assert!(near(Color::from_hsl(0.0, 1.0, 0.5, 1.0), Color::RED));
+        assert!(near(Color::from_hsl(300.0, 1.0, 0.5, 1.0), Color::MAGENTA));
+        assert!(near(Color::from_hsl(0.0, 0.0, 1.0, 1.0), Color::WHITE));
+        assert!(near(Color::from_hsl(200.0, 1.0, 0.0, 1.0), Color::BLACK));
+        assert!(near(
+            Color::from_hsl(240.0, 1.0, 0.75, 1.0),
+            rgb(0.5, 0.5, 1.0)
+        ));
+
+        for color in [Color::ARCANE, Color::MANA, Color::EMBER, rgb(0.1, 0.7, 0.4)] {
+            let (h, s, l) = color.to_hsl();
+            assert!(near(Color::from_hsl(h, s, l, 1.0), color));
+        }
+    }
+
+    #[test]
+    fn premultiplied_alpha() {
+        let c = rgba(0.8, 0.4, 0.2, 0.5);
+        assert_eq!(c.premultiply(), rgba(0.4, 0.2, 0.1, 0.5));
+        assert!(near(c.premultiply().unpremultiply(), c));
+        assert_eq!(
+            Color::RED.with_alpha(0.0).unpremultiply(),
+            Color::TRANSPARENT
+        );
+    }
+
+    #[test]
+    fn over_composites() {
+        // Opaque on top hides what is below, transparent on top shows it
+        assert_eq!(Color::RED.over(Color::BLUE), Color::RED);
+        assert_eq!(Color::TRANSPARENT.over(Color::BLUE), Color::BLUE);
+        assert_eq!(
+            Color::TRANSPARENT.over(Color::TRANSPARENT),
+            Color::TRANSPARENT
+        );
+
+        assert!(near(
+            Color::RED.with_alpha(0.5).over(Color::BLUE),
+            rgb(0.5, 0.0, 0.5)
+        ));
+        let both = Color::RED.with_alpha(0.5).over(Color::BLUE.with_alpha(0.5));
+        assert!(near(both, rgba(2.0 / 3.0, 0.0, 1.0 / 3.0, 0.75)));
+
+        // Matches the premultiplied form of the same operator
+        let (top, below) = (rgba(0.9, 0.5, 0.1, 0.3), rgba(0.2, 0.6, 0.8, 0.6));
+        let expected =
+            Vec4::from(top.premultiply()) + Vec4::from(below.premultiply()) * (1.0 - top.a);
+        assert!(near(top.over(below).premultiply(), expected.into()));
+    }
+
+    #[test]
+    fn json() {
+        let parse = |text: &str| Color::from_json(&json::parse(text).unwrap());
+        assert_eq!(parse(r#""Arcane""#), Some(Color::ARCANE));
+        assert_eq!(parse(r##""#0000ff""##), Some(Color::BLUE));
+        assert_eq!(parse("[0.1, 0.2, 0.3]"), Some(rgb(0.1, 0.2, 0.3)));
+        assert_eq!(
+            parse("[0.1, 0.2, 0.3, 0.4]"),
+            Some(rgba(0.1, 0.2, 0.3, 0.4))
+        );
+        assert_eq!(
+            parse(r#"{"r": 1, "g": 0.5, "b": 0}"#),
+            Some(rgb(1.0, 0.5, 0.0))
+        );
+        assert_eq!(
+            parse(r#"{"r": 1, "g": 0.5, "b": 0, "a": 0.25}"#),
+            Some(rgba(1.0, 0.5, 0.0, 0.25))
+        );
+
+        assert_eq!(parse(r#""nope""#), None);
+        assert_eq!(parse("[0.1, 0.2]"), None);
+        assert_eq!(parse(r#"[0.1, "x", 0.3]"#), None);
+        assert_eq!(parse(r#"{"r": 1, "g": 0.5}"#), None);
+        assert_eq!(parse("3"), None);
+
+        let color = rgba(0.25, 0.5, 0.75, 1.0);
+        assert_eq!(color.to_json().dump(), "[0.25,0.5,0.75,1]");
+        assert_eq!(Color::from_json(&color.to_json()), Some(color));
+    }
+}
@@ -0,0 +1,665 @@
+use std::collections::HashMap;
+
+use crate::input::ButtonState;
+use crate::nvec::*;
+
+use winit::event::ElementState;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GamepadId(pub usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GamepadButton {
+    South,
+    East,
+    North,
+    West,
+    LeftBumper,
+    RightBumper,
+    Select,
+    Start,
+    Mode,
+    LeftStick,
+    RightStick,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+impl GamepadButton {
+    pub const ALL: [GamepadButton; 15] = [
+        Self::South,
+        Self::East,
+        Self::North,
+        Self::West,
+        Self::LeftBumper,
+        Self::RightBumper,
+        Self::Select,
+        Self::Start,
+        Self::Mode,
+        Self::LeftStick,
+        Self::RightStick,
+        Self::DPadUp,
+        Self::DPadDown,
+        Self::DPadLeft,
+        Self::DPadRight,
+    ];
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    LeftTrigger,
+    RightTrigger,
+}
+impl GamepadAxis {
+    pub const ALL: [GamepadAxis; 6] = [
+        Self::LeftStickX,
+        Self::LeftStickY,
+        Self::RightStickX,
+        Self::RightStickY,
+        Self::LeftTrigger,
+        Self::RightTrigger,
+    ];
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GamepadEvent {
+    Connected(GamepadId),
+    Disconnected(GamepadId),
+    Button(GamepadId, GamepadButton, ElementState),
+    Axis(GamepadId, GamepadAxis, f32),
+}
+
+pub trait GamepadBackend {
+    fn poll(&mut self, events: &mut Vec<GamepadEvent>);
+}
+
+// Backend used when no controller support is compiled in
+pub struct NullBackend;
+impl GamepadBackend for NullBackend {
+    fn poll(&mut self, _events: &mut Vec<GamepadEvent>) {}
+}
+
+// In-memory controller, clones share the same event queue so a handle can be
+// kept outside of `Gamepads` to drive it
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct VirtualGamepad {
+    queue: std::rc::Rc<std::cell::RefCell<std::collections::VecDeque<GamepadEvent>>>,
+}
+#[cfg(test)]
+impl VirtualGamepad {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&self, event: GamepadEvent) {
+        self.queue.borrow_mut().push_back(event);
+    }
+
+    pub fn connect(&self, id: GamepadId) {
+        self.push(GamepadEvent::Connected(id));
+    }
+    pub fn disconnect(&self, id: GamepadId) {
+        self.push(GamepadEvent::Disconnected(id));
+    }
+    pub fn press(&self, id: GamepadId, button: GamepadButton) {
+        self.push(GamepadEvent::Button(id, button, ElementState::Pressed));
+    }
+    pub fn release(&self, id: GamepadId, button: GamepadButton) {
+        self.push(GamepadEvent::Button(id, button, ElementState::Released));
+    }
+    pub fn set_axis(&self, id: GamepadId, axis: GamepadAxis, value: f32) {
+        self.push(GamepadEvent::Axis(id, axis, value));
+    }
+    pub fn set_stick(&self, id: GamepadId, left: bool, value: Vec2<f32>) {
+        let (x, y) = if left {
+            (GamepadAxis::LeftStickX, GamepadAxis::LeftStickY)
+        } else {
+            (GamepadAxis::RightStickX, GamepadAxis::RightStickY)
+        };
+        self.set_axis(id, x, value.x);
+        self.set_axis(id, y, value.y);
+    }
+}
+#[cfg(test)]
+impl GamepadBackend for VirtualGamepad {
+    fn poll(&mut self, events: &mut Vec<GamepadEvent>) {
+        events.extend(self.queue.borrow_mut().drain(..));
+    }
+}
+
+#[cfg(feature = "gamepad")]
+pub struct GilrsBackend {
+    gilrs: gilrs::Gilrs,
+}
+#[cfg(feature = "gamepad")]
+impl GilrsBackend {
+    pub fn new() -> Option<Self> {
+        match gilrs::Gilrs::new() {
+            Ok(gilrs) => Some(Self { gilrs }),
+            Err(err) => {
+                eprintln!("Warning: Unable to initialise gamepads [{err}]");
+                None
+            }
+        }
+    }
+
+    fn button(button: gilrs::Button) -> Option<GamepadButton> {
+        use gilrs::Button;
+        Some(match button {
+            Button::South => GamepadButton::South,
+            Button::East => GamepadButton::East,
+            Button::North => GamepadButton::North,
+            Button::West => GamepadButton::West,
+            Button::LeftTrigger => GamepadButton::LeftBumper,
+            Button::RightTrigger => GamepadButton::RightBumper,
+            Button::Select => GamepadButton::Select,
+            Button::Start => GamepadButton::Start,
+            Button::Mode => GamepadButton::Mode,
+            Button::LeftThumb => GamepadButton::LeftStick,
+            Button::RightThumb => GamepadButton::RightStick,
+            Button::DPadUp => GamepadButton::DPadUp,
+            Button::DPadDown => GamepadButton::DPadDown,
+            Button::DPadLeft => GamepadButton::DPadLeft,
+            Button::DPadRight => GamepadButton::DPadRight,
+            _ => return None,
+        })
+    }
+    fn axis(axis: gilrs::Axis) -> Option<GamepadAxis> {
+        use gilrs::Axis;
+        Some(match axis {
+            Axis::LeftStickX => GamepadAxis::LeftStickX,
+            Axis::LeftStickY => GamepadAxis::LeftStickY,
+            Axis::RightStickX => GamepadAxis::RightStickX,
+            Axis::RightStickY => GamepadAxis::RightStickY,
+            Axis::LeftZ => GamepadAxis::LeftTrigger,
+            Axis::RightZ => GamepadAxis::RightTrigger,
+            _ => return None,
+        })
+    }
+}
+#[cfg(feature = "gamepad")]
+impl GamepadBackend for GilrsBackend {
+    fn poll(&mut self, events: &mut Vec<GamepadEvent>) {
+        use gilrs::EventType;
+
+        while let Some(gilrs::Event { id, event, .. }) = self.gilrs.next_event() {
+            let id = GamepadId(usize::from(id));
+            let event = match event {
+                EventType::Connected => Some(GamepadEvent::Connected(id)),
+                EventType::Disconnected => Some(GamepadEvent::Disconnected(id)),
+                EventType::ButtonPressed(button, _) => Self::button(button)
+                    .map(|button| GamepadEvent::Button(id, button, ElementState::Pressed)),
+                EventType::ButtonReleased(button, _) => Self::button(button)
+                    .map(|button| GamepadEvent::Button(id, button, ElementState::Released)),
+                // Analog triggers are reported as buttons on most controllers
+                EventType::ButtonChanged(gilrs::Button::LeftTrigger2, value, _) => {
+                    Some(GamepadEvent::Axis(id, GamepadAxis::LeftTrigger, value))
+                }
+                EventType::ButtonChanged(gilrs::Button::RightTrigger2, value, _) => {
+                    Some(GamepadEvent::Axis(id, GamepadAxis::RightTrigger, value))
+                }
+                EventType::AxisChanged(axis, value, _) => {
+                    Self::axis(axis).map(|axis| GamepadEvent::Axis(id, axis, value))
+                }
+                _ => None,
+            };
+            events.extend(event);
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct GamepadState {
+    buttons: HashMap<GamepadButton, ButtonState>,
+    axes: HashMap<GamepadAxis, f32>,
+    // Axes as of the last frame, for detecting threshold crossings
+    previous: HashMap<GamepadAxis, f32>,
+}
+impl GamepadState {
+    pub fn button(&self, button: GamepadButton) -> ButtonState {
+        self.buttons.get(&button).copied().unwrap_or_default()
+    }
+    pub fn held(&self, button: GamepadButton) -> bool {
+        self.button(button).held()
+    }
+    pub fn pressed(&self, button: GamepadButton) -> bool {
+        self.button(button).pressed()
+    }
+    pub fn released(&self, button: GamepadButton) -> bool {
+        self.button(button).released()
+    }
+
+    pub fn raw_axis(&self, axis: GamepadAxis) -> f32 {
+        self.axes.get(&axis).copied().unwrap_or(0.0)
+    }
+
+    fn refresh(&mut self) {
+        for state in self.buttons.values_mut() {
+            state.refresh();
+        }
+        self.previous.clone_from(&self.axes);
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct DeadZones {
+    pub stick: f32,
+    pub trigger: f32,
+}
+impl Default for DeadZones {
+    fn default() -> Self {
+        Self {
+            stick: 0.15,
+            trigger: 0.05,
+        }
+    }
+}
+
+pub struct Gamepads {
+    backend: Box<dyn GamepadBackend>,
+    pads: HashMap<GamepadId, GamepadState>,
+    order: Vec<GamepadId>,
+    events: Vec<GamepadEvent>,
+
+    pub dead_zones: DeadZones,
+}
+impl Gamepads {
+    pub fn new() -> Self {
+        #[cfg(feature = "gamepad")]
+        if let Some(backend) = GilrsBackend::new() {
+            return Self::with_backend(backend);
+        }
+        Self::with_backend(NullBackend)
+    }
+
+    pub fn with_backend(backend: impl GamepadBackend + 'static) -> Self {
+        Self {
+            backend: Box::new(backend),
+            pads: HashMap::new(),
+            order: Vec::new(),
+            events: Vec::new(),
+            dead_zones: DeadZones::default(),
+        }
+    }
+
+    // Call once per frame, before reading any state
+    pub fn update(&mut self) {
+        let mut events = std::mem::take(&mut self.events);
+        events.clear();
+        self.backend.poll(&mut events);
+        self.update_with(events);
+    }
+
+    // Same as `update`, but takes events from the caller instead of the backend
+    pub fn update_with(&mut self, events: Vec<GamepadEvent>) {
+        for pad in self.pads.values_mut() {
+            pad.refresh();
+        }
+
+        self.events = events;
+        for event in self.events.iter().copied() {
+            match event {
+                GamepadEvent::Connected(id) => {
+                    if !self.order.contains(&id) {
+                        self.order.push(id);
+                    }
+                    self.pads.entry(id).or_default();
+                }
+                GamepadEvent::Disconnected(id) => {
+                    self.order.retain(|other| *other != id);
+                    self.pads.remove(&id);
+                }
+                GamepadEvent::Button(id, button, state) => {
+                    if let Some(pad) = self.pads.get_mut(&id) {
+                        pad.buttons.entry(button).or_default().set(state);
+                    }
+                }
+                GamepadEvent::Axis(id, axis, value) => {
+                    if let Some(pad) = self.pads.get_mut(&id) {
+                        pad.axes.insert(axis, value.clamp(-1.0, 1.0));
+                    }
+                }
+            }
+        }
+    }
+
+    // Events received during the last `update`, including connects/disconnects
+    pub fn events(&self) -> &[GamepadEvent] {
+        &self.events
+    }
+
+    pub fn connected(&self) -> impl Iterator<Item = GamepadId> + '_ {
+        self.order.iter().copied()
+    }
+    pub fn get(&self, id: GamepadId) -> Option<&GamepadState> {
+        self.pads.get(&id)
+    }
+    // The first controller that was connected and is still present
+    pub fn primary(&self) -> Option<&GamepadState> {
+        self.order.first().and_then(|id| self.pads.get(id))
+    }
+
+    pub fn stick(&self, id: GamepadId, left: bool) -> Vec2<f32> {
+        let axis = if left {
+            GamepadAxis::LeftStickX
+        } else {
+            GamepadAxis::RightStickX
+        };
+        let Some(pad) = self.pads.get(&id) else {
+            return vec2(0.0, 0.0);
+        };
+        self.stick_from(&pad.axes, axis)
+    }
+
+    pub fn trigger(&self, id: GamepadId, left: bool) -> f32 {
+        if left {
+            self.axis(id, GamepadAxis::LeftTrigger)
+        } else {
+            self.axis(id, GamepadAxis::RightTrigger)
+        }
+    }
+
+    // Axis value with dead zones applied
+    pub fn axis(&self, id: GamepadId, axis: GamepadAxis) -> f32 {
+        self.pads
+            .get(&id)
+            .map_or(0.0, |pad| self.filter(&pad.axes, axis))
+    }
+
+    // Same as `axis`, as it was on the previous frame
+    fn previous_axis(&self, id: GamepadId, axis: GamepadAxis) -> f32 {
+        self.pads
+            .get(&id)
+            .map_or(0.0, |pad| self.filter(&pad.previous, axis))
+    }
+
+    fn filter(&self, axes: &HashMap<GamepadAxis, f32>, axis: GamepadAxis) -> f32 {
+        match axis {
+            GamepadAxis::LeftStickX | GamepadAxis::RightStickX => self.stick_from(axes, axis).x,
+            GamepadAxis::LeftStickY => self.stick_from(axes, GamepadAxis::LeftStickX).y,
+            GamepadAxis::RightStickY => self.stick_from(axes, GamepadAxis::RightStickX).y,
+            GamepadAxis::LeftTrigger | GamepadAxis::RightTrigger => {
+                let raw = axes.get(&axis).copied().unwrap_or(0.0);
+                let dead = self.dead_zones.trigger;
+                if raw <= dead {
+                    0.0
+                } else {
+                    ((raw - dead) / (1.0 - dead)).min(1.0)
+                }
+            }
+        }
+    }
+
+    // Takes the X axis of the stick
+    fn stick_from(&self, axes: &HashMap<GamepadAxis, f32>, x: GamepadAxis) -> Vec2<f32> {
+        let y = match x {
+            GamepadAxis::LeftStickX => GamepadAxis::LeftStickY,
+            _ => GamepadAxis::RightStickY,
+        };
+        let get = |axis| axes.get(&axis).copied().unwrap_or(0.0);
+        let raw = vec2(get(x), get(y));
+
+        // Radial dead zone, rescaled so output starts from zero at the edge
+        let len = raw.length();
+        let dead = self.dead_zones.stick;
+        if len <= dead {
+            return vec2(0.0, 0.0);
+        }
+        raw * (((len - dead) / (1.0 - dead)).min(1.0) / len)
+    }
+}
+impl Default for Gamepads {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Binding {
+    Button(GamepadButton),
+    // Axis pushed past the threshold, in the direction of its sign
+    Axis(GamepadAxis, f32),
+}
+
+// Maps game actions onto controller bindings, combining all connected pads
+pub struct ActionMap<A> {
+    bindings: Vec<(A, Binding)>,
+}
+impl<A: Copy + PartialEq> ActionMap<A> {
+    pub fn new() -> Self {
+        Self {
+            bindings: Vec::new(),
+        }
+    }
+
+    pub fn bind(&mut self, action: A, binding: Binding) -> &mut Self {
+        self.bindings.push((action, binding));
+        self
+    }
+    pub fn unbind(&mut self, action: A) {
+        self.bindings.retain(|(other, _)| *other != action);
+    }
+
+    fn bindings(&self, action: A) -> impl Iterator<Item = Binding> + '_ {
+        self.bindings
+            .iter()
+            .filter(move |(other, _)| *other == action)
+            .map(|(_, binding)| *binding)
+    }
+
+    // Analog strength of the action in [0, 1]
+    pub fn value(&self, pads: &Gamepads, action: A) -> f32 {
+        let mut value: f32 = 0.0;
+        for id in pads.connected() {
+            let pad = &pads.pads[&id];
+            for binding in self.bindings(action) {
+                let strength = match binding {
+                    Binding::Button(button) => {
+                        if pad.held(button) {
+                            1.0
+                        } else {
+                            0.0
+                        }
+                    }
+                    Binding::Axis(axis, threshold) => {
+                        let raw = pads.axis(id, axis) * threshold.signum();
+                        if Self::past(raw, threshold) {
+                            raw
+                        } else {
+                            0.0
+                        }
+                    }
+                };
+                value = value.max(strength);
+            }
+        }
+        value
+    }
+
+    // `value` is already signed towards the threshold
+    fn past(value: f32, threshold: f32) -> bool {
+        value > 0.0 && value >= threshold.abs()
+    }
+    // Whether the axis was past the threshold last frame, and whether it is now
+    fn crossing(pads: &Gamepads, id: GamepadId, axis: GamepadAxis, threshold: f32) -> (bool, bool) {
+        let before = pads.previous_axis(id, axis) * threshold.signum();
+        let now = pads.axis(id, axis) * threshold.signum();
+        (Self::past(before, threshold), Self::past(now, threshold))
+    }
+
+    pub fn held(&self, pads: &Gamepads, action: A) -> bool {
+        self.value(pads, action) > 0.0
+    }
+    pub fn pressed(&self, pads: &Gamepads, action: A) -> bool {
+        pads.connected().any(|id| {
+            self.bindings(action).any(|binding| match binding {
+                Binding::Button(button) => pads.pads[&id].pressed(button),
+                Binding::Axis(axis, threshold) => {
+                    Self::crossing(pads, id, axis, threshold) == (false, true)
+                }
+            })
+        })
+    }
+    pub fn released(&self, pads: &Gamepads, action: A) -> bool {
+        pads.connected().any(|id| {
+            self.bindings(action).any(|binding| match binding {
+                Binding::Button(button) => pads.pads[&id].released(button),
+                Binding::Axis(axis, threshold) => {
+                    Self::crossing(pads, id, axis, threshold) == (true, false)
+                }
+            })
+        })
+    }
+}
+impl<A: Copy + PartialEq> Default for ActionMap<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAD: GamepadId = GamepadId(0);
+
+    fn pads() -> (VirtualGamepad, Gamepads) {
+        let virt = VirtualGamepad::new();
+        let pads = Gamepads::with_backend(virt.clone());
+        (virt, pads)
+    }
+
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    enum Action {
+        Jump,
+        Right,
+        Left,
+    }
+
+    #[test]
+    fn connect_and_disconnect() {
+        let (virt, mut pads) = pads();
+        virt.connect(GamepadId(3));
+        virt.connect(PAD);
+        pads.update();
+        assert_eq!(pads.events().len(), 2);
+        assert_eq!(pads.connected().collect::<Vec<_>>(), [GamepadId(3), PAD]);
+
+        virt.disconnect(GamepadId(3));
+        pads.update();
+        assert_eq!(pads.events(), [GamepadEvent::Disconnected(GamepadId(3))]);
+        assert_eq!(pads.connected().collect::<Vec<_>>(), [PAD]);
+        assert!(pads.get(GamepadId(3)).is_none());
+        assert!(pads.primary().is_some());
+
+        pads.update();
+        assert!(pads.events().is_empty());
+    }
+
+    #[test]
+    fn ignores_unconnected_pads() {
+        let (virt, mut pads) = pads();
+        virt.press(PAD, GamepadButton::South);
+        virt.set_axis(PAD, GamepadAxis::LeftTrigger, 1.0);
+        pads.update();
+        assert!(pads.get(PAD).is_none());
+        assert_eq!(pads.trigger(PAD, true), 0.0);
+    }
+
+    #[test]
+    fn button_edges() {
+        let (virt, mut pads) = pads();
+        virt.connect(PAD);
+        virt.press(PAD, GamepadButton::South);
+        pads.update();
+        let pad = pads.get(PAD).unwrap();
+        assert!(pad.pressed(GamepadButton::South) && pad.held(GamepadButton::South));
+
+        pads.update();
+        let pad = pads.get(PAD).unwrap();
+        assert!(!pad.pressed(GamepadButton::South) && pad.held(GamepadButton::South));
+
+        virt.release(PAD, GamepadButton::South);
+        pads.update();
+        let pad = pads.get(PAD).unwrap();
+        assert!(pad.released(GamepadButton::South) && !pad.held(GamepadButton::South));
+    }
+
+    #[test]
+    fn radial_dead_zone() {
+        let (virt, mut pads) = pads();
+        virt.connect(PAD);
+        // Inside the dead zone on both axes together, even if one is past it alone
+        virt.set_stick(PAD, true, vec2(0.1, 0.1));
+        pads.update();
+        assert_eq!(pads.stick(PAD, true), vec2(0.0, 0.0));
+
+        // Rescaled from the edge of the dead zone, keeping the direction
+        virt.set_stick(PAD, true, vec2(0.6, 0.8));
+        pads.update();
+        let stick = pads.stick(PAD, true);
+        assert!((stick.length() - 1.0).abs() < 1e-5);
+        assert!((stick.x / stick.y - 0.75).abs() < 1e-5);
+
+        let half = 0.15 + 0.85 * 0.5;
+        virt.set_stick(PAD, true, vec2(0.0, -half));
+        pads.update();
+        assert!((pads.axis(PAD, GamepadAxis::LeftStickY) + 0.5).abs() < 1e-5);
+        assert_eq!(pads.stick(PAD, false), vec2(0.0, 0.0));
+
+        // Out of range values are clamped
+        virt.set_axis(PAD, GamepadAxis::RightTrigger, 2.0);
+        virt.set_axis(PAD, GamepadAxis::LeftTrigger, 0.04);
+        pads.update();
+        assert_eq!(pads.trigger(PAD, false), 1.0);
+        assert_eq!(pads.trigger(PAD, true), 0.0);
+    }
+
+    #[test]
+    fn action_bindings() {
+        let (virt, mut pads) = pads();
+        let mut actions = ActionMap::new();
+        actions
+            .bind(Action::Jump, Binding::Button(GamepadButton::South))
+            .bind(Action::Jump, Binding::Button(GamepadButton::East))
+            .bind(Action::Right, Binding::Axis(GamepadAxis::LeftStickX, 0.5))
+            .bind(Action::Left, Binding::Axis(GamepadAxis::LeftStickX, -0.5));
+
+        virt.connect(PAD);
+        virt.connect(GamepadId(1));
+        virt.press(GamepadId(1), GamepadButton::East);
+        pads.update();
+        assert!(actions.pressed(&pads, Action::Jump));
+        assert_eq!(actions.value(&pads, Action::Jump), 1.0);
+
+        virt.set_axis(PAD, GamepadAxis::LeftStickX, 0.3);
+        pads.update();
+        assert!(!actions.held(&pads, Action::Right));
+        assert!(!actions.pressed(&pads, Action::Jump));
+
+        virt.set_axis(PAD, GamepadAxis::LeftStickX, 1.0);
+        pads.update();
+        assert!(actions.pressed(&pads, Action::Right));
+        assert_eq!(actions.value(&pads, Action::Right), 1.0);
+        assert!(!actions.held(&pads, Action::Left));
+
+        pads.update();
+        assert!(!actions.pressed(&pads, Action::Right));
+        assert!(actions.held(&pads, Action::Right));
+
+        // Straight across to the other side
+        virt.set_axis(PAD, GamepadAxis::LeftStickX, -1.0);
+        pads.update();
+        assert!(actions.released(&pads, Action::Right));
+        assert!(actions.pressed(&pads, Action::Left));
+
+        actions.unbind(Action::Left);
+        assert!(!actions.held(&pads, Action::Left));
+    }
+}
@@ -0,0 +1,486 @@
+use crate::nvec::*;
+
+// Axis-aligned rectangle, always kept with min <= max
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub min: Vec2<f32>,
+    pub max: Vec2<f32>,
+}
+impl Rect {
+    // Corners may be given in any order
+    pub fn from_corners(a: Vec2<f32>, b: Vec2<f32>) -> Self {
+        Self {
+            min: a.min(b),
+            max: a.max(b),
+        }
+    }
+    // Size may be negative, e.g. for text growing downwards
+    pub fn sized(origin: Vec2<f32>, size: Vec2<f32>) -> Self {
+        Self::from_corners(origin, origin + size)
+    }
+    pub fn centered(center: Vec2<f32>, half_size: Vec2<f32>) -> Self {
+        Self::from_corners(center - half_size, center + half_size)
+    }
+
+    pub fn size(&self) -> Vec2<f32> {
+        self.max - self.min
+    }
+    pub fn center(&self) -> Vec2<f32> {
+        (self.min + self.max) * 0.5
+    }
+    pub fn corners(&self) -> [Vec2<f32>; 4] {
+        [
+            self.min,
+            vec2(self.max.x, self.min.y),
+            self.max,
+            vec2(self.min.x, self.max.y),
+        ]
+    }
+
+    pub fn contains(&self, pos: Vec2<f32>) -> bool {
+        self.min.x <= pos.x && pos.x <= self.max.x && self.min.y <= pos.y && pos.y <= self.max.y
+    }
+    pub fn contains_rect(&self, other: &Rect) -> bool {
+        self.contains(other.min) && self.contains(other.max)
+    }
+    pub fn overlaps(&self, other: &Rect) -> bool {
+        self.min.x <= other.max.x
+            && other.min.x <= self.max.x
+            && self.min.y <= other.max.y
+            && other.min.y <= self.max.y
+    }
+
+    pub fn union(&self, other: &Rect) -> Rect {
+        Rect {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+    pub fn intersection(&self, other: &Rect) -> Option<Rect> {
+        self.overlaps(other).then(|| Rect {
+            min: self.min.max(other.min),
+            max: self.max.min(other.max),
+        })
+    }
+    // Negative amounts shrink the rect, collapsing onto its center
+    pub fn inflate(&self, amount: Vec2<f32>) -> Rect {
+        let center = self.center();
+        let half = (self.size() * 0.5 + amount).max(vec2(0.0, 0.0));
+        Rect::centered(center, half)
+    }
+
+    pub fn closest_point(&self, pos: Vec2<f32>) -> Vec2<f32> {
+        pos.clamp(self.min, self.max)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Circle {
+    pub center: Vec2<f32>,
+    pub radius: f32,
+}
+impl Circle {
+    pub fn new(center: Vec2<f32>, radius: f32) -> Self {
+        Self { center, radius }
+    }
+
+    pub fn contains(&self, pos: Vec2<f32>) -> bool {
+        (pos - self.center).length_squared() <= self.radius * self.radius
+    }
+    pub fn bounds(&self) -> Rect {
+        Rect::centered(self.center, Vec2::splat(self.radius))
+    }
+
+    // Closest point on the boundary
+    pub fn closest_point(&self, pos: Vec2<f32>) -> Vec2<f32> {
+        let dir = (pos - self.center).normalize();
+        if dir.length_squared() == 0.0 {
+            return self.center + vec2(self.radius, 0.0);
+        }
+        self.center + dir * self.radius
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Segment {
+    pub a: Vec2<f32>,
+    pub b: Vec2<f32>,
+}
+impl Segment {
+    pub fn new(a: Vec2<f32>, b: Vec2<f32>) -> Self {
+        Self { a, b }
+    }
+
+    pub fn length(&self) -> f32 {
+        self.a.distance(self.b)
+    }
+    pub fn bounds(&self) -> Rect {
+        Rect::from_corners(self.a, self.b)
+    }
+
+    // Parameter along the segment in [0, 1] of the closest point
+    pub fn project(&self, pos: Vec2<f32>) -> f32 {
+        let dir = self.b - self.a;
+        let len2 = dir.length_squared();
+        if len2 == 0.0 {
+            return 0.0;
+        }
+        ((pos - self.a).dot(dir) / len2).clamp(0.0, 1.0)
+    }
+    pub fn closest_point(&self, pos: Vec2<f32>) -> Vec2<f32> {
+        self.a.lerp(self.b, self.project(pos))
+    }
+    pub fn distance(&self, pos: Vec2<f32>) -> f32 {
+        self.closest_point(pos).distance(pos)
+    }
+
+    pub fn intersection(&self, other: &Segment) -> Option<Vec2<f32>> {
+        let r = self.b - self.a;
+        let s = other.b - other.a;
+        let denom = r.perp_dot(s);
+        let offset = other.a - self.a;
+
+        if denom.abs() <= f32::EPSILON {
+            // Parallel, report an overlapping endpoint if collinear
+            if offset.perp_dot(r).abs() > f32::EPSILON {
+                return None;
+            }
+            return [other.a, other.b, self.a, self.b]
+                .into_iter()
+                .find(|p| self.distance(*p) <= f32::EPSILON && other.distance(*p) <= f32::EPSILON);
+        }
+
+        let t = offset.perp_dot(s) / denom;
+        let u = offset.perp_dot(r) / denom;
+        ((0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u)).then(|| self.a + r * t)
+    }
+}
+
+// Convex polygon with counter-clockwise winding
+#[derive(Clone, Debug, PartialEq)]
+pub struct Polygon {
+    pub points: Vec<Vec2<f32>>,
+}
+impl Polygon {
+    // Points are reordered counter-clockwise if needed
+    pub fn new(mut points: Vec<Vec2<f32>>) -> Self {
+        let area: f32 = Self::edges_of(&points)
+            .map(|edge| edge.a.perp_dot(edge.b))
+            .sum();
+        if area < 0.0 {
+            points.reverse();
+        }
+        Self { points }
+    }
+    pub fn from_rect(rect: &Rect) -> Self {
+        Self {
+            points: rect.corners().to_vec(),
+        }
+    }
+    pub fn regular(center: Vec2<f32>, radius: f32, sides: usize) -> Self {
+        let step = std::f32::consts::TAU / sides as f32;
+        let points = (0..sides)
+            .map(|i| {
+                let (sin, cos) = (step * i as f32).sin_cos();
+                center + vec2(cos, sin) * radius
+            })
+            .collect();
+        Self { points }
+    }
+
+    fn edges_of(points: &[Vec2<f32>]) -> impl Iterator<Item = Segment> + '_ {
+        let next = points.iter().cycle().skip(1);
+        points.iter().zip(next).map(|(a, b)| Segment::new(*a, *b))
+    }
+    pub fn edges(&self) -> impl Iterator<Item = Segment> + '_ {
+        Self::edges_of(&self.points)
+    }
+
+    pub fn bounds(&self) -> Rect {
+        let first = self.points.first().copied().unwrap_or_default();
+        self.points
+            .iter()
+            .fold(Rect::from_corners(first, first), |rect, p| {
+                rect.union(&Rect::from_corners(*p, *p))
+            })
+    }
+    pub fn centroid(&self) -> Vec2<f32> {
+        let sum = self
+            .points
+            .iter()
+            .fold(vec2(0.0, 0.0), |sum, point| sum + *point);
+        sum / self.points.len().max(1) as f32
+    }
+
+    pub fn contains(&self, pos: Vec2<f32>) -> bool {
+        !self.points.is_empty()
+            && self
+                .edges()
+                .all(|edge| (edge.b - edge.a).perp_dot(pos - edge.a) >= 0.0)
+    }
+
+    // Closest point on the boundary
+    pub fn closest_point(&self, pos: Vec2<f32>) -> Vec2<f32> {
+        self.edges()
+            .map(|edge| edge.closest_point(pos))
+            .min_by(|p, q| p.distance(pos).total_cmp(&q.distance(pos)))
+            .unwrap_or(pos)
+    }
+
+    // Outward normals of each edge, for separating axis tests
+    fn axes(&self) -> impl Iterator<Item = Vec2<f32>> + '_ {
+        self.edges()
+            .map(|edge| -(edge.b - edge.a).perp().normalize())
+    }
+    fn project(&self, axis: Vec2<f32>) -> (f32, f32) {
+        self.points
+            .iter()
+            .map(|p| p.dot(axis))
+            .fold((f32::MAX, f32::MIN), |(lo, hi), d| (lo.min(d), hi.max(d)))
+    }
+}
+
+// Minimum translation that pushes the first shape out of the second
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Contact {
+    pub normal: Vec2<f32>,
+    pub depth: f32,
+}
+
+pub fn circle_circle(a: &Circle, b: &Circle) -> Option<Contact> {
+    let offset = a.center - b.center;
+    let distance = offset.length();
+    let depth = a.radius + b.radius - distance;
+    if depth < 0.0 {
+        return None;
+    }
+    let normal = if distance > 0.0 {
+        offset / distance
+    } else {
+        vec2(1.0, 0.0)
+    };
+    Some(Contact { normal, depth })
+}
+
+pub fn circle_rect(circle: &Circle, rect: &Rect) -> Option<Contact> {
+    circle_polygon(circle, &Polygon::from_rect(rect))
+}
+
+pub fn rect_rect(a: &Rect, b: &Rect) -> Option<Contact> {
+    if !a.overlaps(b) {
+        return None;
+    }
+    // How far `a` has to move to clear each side of `b`
+    let exits = [
+        (vec2(-1.0, 0.0), a.max.x - b.min.x),
+        (vec2(1.0, 0.0), b.max.x - a.min.x),
+        (vec2(0.0, -1.0), a.max.y - b.min.y),
+        (vec2(0.0, 1.0), b.max.y - a.min.y),
+    ];
+    let (normal, depth) = exits.into_iter().min_by(|p, q| p.1.total_cmp(&q.1))?;
+    Some(Contact { normal, depth })
+}
+
+pub fn circle_polygon(circle: &Circle, polygon: &Polygon) -> Option<Contact> {
+    let closest = polygon.closest_point(circle.center);
+    let offset = circle.center - closest;
+    let distance = offset.length();
+
+    if polygon.contains(circle.center) {
+        let normal = if distance > 0.0 {
+            offset / -distance
+        } else {
+            (circle.center - polygon.centroid()).normalize()
+        };
+        return Some(Contact {
+            normal,
+            depth: circle.radius + distance,
+        });
+    }
+    if distance > circle.radius {
+        return None;
+    }
+    Some(Contact {
+        normal: offset / distance.max(f32::EPSILON),
+        depth: circle.radius - distance,
+    })
+}
+
+// Separating axis test
+pub fn polygon_polygon(a: &Polygon, b: &Polygon) -> Option<Contact> {
+    if a.points.is_empty() || b.points.is_empty() {
+        return None;
+    }
+    let mut best = Contact {
+        normal: vec2(0.0, 0.0),
+        depth: f32::MAX,
+    };
+    for axis in a.axes().chain(b.axes()) {
+        let (a_lo, a_hi) = a.project(axis);
+        let (b_lo, b_hi) = b.project(axis);
+        // Back along the axis or forwards, whichever clears `b` sooner
+        let (normal, depth) = match a_hi - b_lo < b_hi - a_lo {
+            true => (-axis, a_hi - b_lo),
+            false => (axis, b_hi - a_lo),
+        };
+        if depth < 0.0 {
+            return None;
+        }
+        if depth < best.depth {
+            best = Contact { normal, depth };
+        }
+    }
+    Some(best)
+}
+
+pub fn segment_circle(segment: &Segment, circle: &Circle) -> bool {
+    segment.distance(circle.center) <= circle.radius
+}
+
+pub fn segment_polygon(segment: &Segment, polygon: &Polygon) -> bool {
+    polygon.contains(segment.a)
+        || polygon
+            .edges()
+            .any(|edge| edge.intersection(segment).is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn near(a: Vec2<f32>, b: Vec2<f32>) -> bool {
+        (a - b).length() < 1e-5
+    }
+    fn assert_contact(contact: Option<Contact>, normal: Vec2<f32>, depth: f32) {
+        let contact = contact.expect("shapes should touch");
+        assert!(near(contact.normal, normal), "normal {:?}", contact.normal);
+        assert!(
+            (contact.depth - depth).abs() < 1e-5,
+            "depth {}",
+            contact.depth
+        );
+    }
+    fn square(min: Vec2<f32>, size: f32) -> Rect {
+        Rect::sized(min, vec2(size, size))
+    }
+
+    #[test]
+    fn circles() {
+        let b = Circle::new(vec2(0.0, 0.0), 1.0);
+        let touching = Circle::new(vec2(2.0, 0.0), 1.0);
+        assert_contact(circle_circle(&touching, &b), vec2(1.0, 0.0), 0.0);
+        let overlapping = Circle::new(vec2(0.0, -1.5), 1.0);
+        assert_contact(circle_circle(&overlapping, &b), vec2(0.0, -1.0), 0.5);
+        let contained = Circle::new(vec2(0.25, 0.0), 0.5);
+        assert_contact(circle_circle(&contained, &b), vec2(1.0, 0.0), 1.25);
+        let concentric = Circle::new(vec2(0.0, 0.0), 0.5);
+        assert_contact(circle_circle(&concentric, &b), vec2(1.0, 0.0), 1.5);
+        let disjoint = Circle::new(vec2(2.5, 0.0), 1.0);
+        assert_eq!(circle_circle(&disjoint, &b), None);
+    }
+
+    #[test]
+    fn circle_rects() {
+        let rect = Rect::sized(vec2(0.0, 0.0), vec2(4.0, 2.0));
+        let touching = Circle::new(vec2(5.0, 1.0), 1.0);
+        assert_contact(circle_rect(&touching, &rect), vec2(1.0, 0.0), 0.0);
+        let overlapping = Circle::new(vec2(2.0, -0.5), 1.0);
+        assert_contact(circle_rect(&overlapping, &rect), vec2(0.0, -1.0), 0.5);
+        // Pushed out through the nearest side
+        let contained = Circle::new(vec2(3.5, 1.0), 0.25);
+        assert_contact(circle_rect(&contained, &rect), vec2(1.0, 0.0), 0.75);
+        let corner = Circle::new(vec2(5.0, 3.0), 2.0);
+        let diagonal = vec2(1.0f32, 1.0).normalize();
+        assert_contact(circle_rect(&corner, &rect), diagonal, 2.0 - 2f32.sqrt());
+        let disjoint = Circle::new(vec2(6.0, 1.0), 1.0);
+        assert_eq!(circle_rect(&disjoint, &rect), None);
+    }
+
+    #[test]
+    fn circle_polygons() {
+        let triangle = Polygon::new(vec![vec2(0.0, 0.0), vec2(0.0, 2.0), vec2(2.0, 0.0)]);
+        let diagonal = vec2(1.0f32, 1.0).normalize();
+        let touching = Circle::new(vec2(1.0, 1.0) + diagonal, 1.0);
+        assert_contact(circle_polygon(&touching, &triangle), diagonal, 0.0);
+        let overlapping = Circle::new(vec2(-0.5, 1.0), 1.0);
+        assert_contact(
+            circle_polygon(&overlapping, &triangle),
+            vec2(-1.0, 0.0),
+            0.5,
+        );
+        let contained = Circle::new(vec2(0.5, 0.25), 0.1);
+        assert_contact(circle_polygon(&contained, &triangle), vec2(0.0, -1.0), 0.35);
+        let disjoint = Circle::new(vec2(3.0, 3.0), 1.0);
+        assert_eq!(circle_polygon(&disjoint, &triangle), None);
+    }
+
+    #[test]
+    fn rects() {
+        let b = square(vec2(0.0, 0.0), 4.0);
+        let touching = Rect::sized(vec2(4.0, 1.0), vec2(2.0, 2.0));
+        assert_contact(rect_rect(&touching, &b), vec2(1.0, 0.0), 0.0);
+        let overlapping = Rect::sized(vec2(1.0, 3.0), vec2(2.0, 2.0));
+        assert_contact(rect_rect(&overlapping, &b), vec2(0.0, 1.0), 1.0);
+        // Out of the nearest side, not just by its own size
+        let contained = Rect::sized(vec2(0.5, 1.0), vec2(1.0, 2.0));
+        assert_contact(rect_rect(&contained, &b), vec2(-1.0, 0.0), 1.5);
+        let disjoint = square(vec2(5.0, 0.0), 1.0);
+        assert_eq!(rect_rect(&disjoint, &b), None);
+    }
+
+    #[test]
+    fn polygons() {
+        let b = Polygon::from_rect(&square(vec2(0.0, 0.0), 2.0));
+        let touching = Polygon::from_rect(&square(vec2(2.0, 0.0), 2.0));
+        assert_contact(polygon_polygon(&touching, &b), vec2(1.0, 0.0), 0.0);
+        let overlapping = Polygon::from_rect(&square(vec2(-1.5, 0.0), 2.0));
+        assert_contact(polygon_polygon(&overlapping, &b), vec2(-1.0, 0.0), 0.5);
+        let contained = Polygon::from_rect(&Rect::sized(vec2(0.25, 0.5), vec2(0.5, 1.0)));
+        assert_contact(polygon_polygon(&contained, &b), vec2(-1.0, 0.0), 0.75);
+        let disjoint = Polygon::from_rect(&square(vec2(3.0, 3.0), 1.0));
+        assert_eq!(polygon_polygon(&disjoint, &b), None);
+
+        // Along the slanted edge, the shortest way out
+        let triangle = Polygon::new(vec![vec2(0.0, 0.0), vec2(2.0, 0.0), vec2(0.0, 2.0)]);
+        let corner = Polygon::from_rect(&square(vec2(0.9, 0.9), 2.0));
+        let diagonal = vec2(1.0f32, 1.0).normalize();
+        let depth = 0.2 / 2f32.sqrt();
+        assert_contact(polygon_polygon(&corner, &triangle), diagonal, depth);
+        assert_contact(polygon_polygon(&triangle, &corner), -diagonal, depth);
+
+        let empty = Polygon::new(Vec::new());
+        assert_eq!(polygon_polygon(&empty, &b), None);
+        assert_eq!(polygon_polygon(&b, &empty), None);
+    }
+
+    #[test]
+    fn segment_circles() {
+        let circle = Circle::new(vec2(2.0, 0.0), 1.0);
+        let touching = Segment::new(vec2(0.0, 1.0), vec2(4.0, 1.0));
+        assert!(segment_circle(&touching, &circle));
+        let crossing = Segment::new(vec2(0.0, 0.5), vec2(4.0, 0.5));
+        assert!(segment_circle(&crossing, &circle));
+        let contained = Segment::new(vec2(1.8, 0.0), vec2(2.2, 0.1));
+        assert!(segment_circle(&contained, &circle));
+        let disjoint = Segment::new(vec2(0.0, 1.5), vec2(4.0, 1.5));
+        assert!(!segment_circle(&disjoint, &circle));
+        // Past the end, the line through it would hit
+        let short = Segment::new(vec2(-2.0, 0.0), vec2(0.5, 0.0));
+        assert!(!segment_circle(&short, &circle));
+    }
+
+    #[test]
+    fn segment_polygons() {
+        let polygon = Polygon::from_rect(&square(vec2(0.0, 0.0), 2.0));
+        let touching = Segment::new(vec2(2.0, 1.0), vec2(4.0, 1.0));
+        assert!(segment_polygon(&touching, &polygon));
+        let corner = Segment::new(vec2(3.0, -1.0), vec2(2.0, 0.0));
+        assert!(segment_polygon(&corner, &polygon));
+        let crossing = Segment::new(vec2(-1.0, 1.0), vec2(3.0, 1.0));
+        assert!(segment_polygon(&crossing, &polygon));
+        let contained = Segment::new(vec2(0.5, 0.5), vec2(1.5, 1.5));
+        assert!(segment_polygon(&contained, &polygon));
+        let disjoint = Segment::new(vec2(3.0, 0.0), vec2(3.0, 2.0));
+        assert!(!segment_polygon(&disjoint, &polygon));
+    }
+}
@@ -1,6 +1,9 @@
 use wgpu::util::DeviceExt;
 
-use crate::graphics::Graphics;
+use crate::camera::CameraBuffer;
+use crate::color::Color;
+use crate::graphics::{DepthBuffer, Graphics};
+use crate::layer::{layer_range, Layer, Layered};
 use crate::nvec::*;
 
 struct FontAtlas {
@@ -36,9 +39,9 @@ impl FontAtlas {
     }
 
     fn new(gfx: &Graphics, path: &str) -> Self {
-        let image_file = std::fs::read(path).expect(format!("Could not read {path}").as_str());
+        let image_file = std::fs::read(path).unwrap_or_else(|_| panic!("Could not read {path}"));
         let image = image::load_from_memory(image_file.as_slice())
-            .expect(format!("could not parse {path}").as_str());
+            .unwrap_or_else(|_| panic!("could not parse {path}"));
 
         let dimensions = image::GenericImageView::dimensions(&image);
 
@@ -107,14 +110,23 @@ pub struct Glyph {
     pub pos: Vec3<f32>,
     pub codepoint: u32,
     pub scale: Vec2<f32>,
-    pub color: Vec4<f32>
+    pub color: Color
+}
+
+impl Layered for Glyph {
+    fn depth(&self) -> f32 {
+        self.pos.z
+    }
 }
 
 pub struct GlyphRenderer {
     pipeline: wgpu::RenderPipeline,
+    world_pipeline: wgpu::RenderPipeline,
 
     atlas: FontAtlas,
     buffer: wgpu::Buffer,
+    // Separate from `buffer` so screen and world text can share a pass
+    world_buffer: wgpu::Buffer,
 }
 impl GlyphRenderer {    
     const MAX_GLYPHS: usize = 1024;
@@ -129,6 +141,8 @@ impl GlyphRenderer {
 
 
     pub fn new(gfx: &Graphics) -> Self {
+        let module = gfx.load_shader("shaders/glyph.wgsl");
+
         let layout = gfx
             .device
             .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -136,17 +150,62 @@ impl GlyphRenderer {
                 bind_group_layouts: &[&FontAtlas::bind_group_layout(gfx)],
                 push_constant_ranges: &[],
             });
+        let pipeline = Self::create_pipeline(gfx, &module, &layout, "vert_main", None);
 
-        let module = gfx.load_shader("shaders/glyph.wgsl");
-
-        let pipeline = gfx
+        let world_layout = gfx
             .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("GlyphRenderer.WorldPipeline"),
+                bind_group_layouts: &[
+                    &FontAtlas::bind_group_layout(gfx),
+                    &CameraBuffer::layout(gfx),
+                ],
+                push_constant_ranges: &[],
+            });
+        let world_pipeline = Self::create_pipeline(
+            gfx,
+            &module,
+            &world_layout,
+            "vert_world",
+            Some(DepthBuffer::state()),
+        );
+
+        let create_buffer = |label| {
+            gfx.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: (std::mem::size_of::<Glyph>() * Self::MAX_GLYPHS) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        };
+        let buffer = create_buffer("GlyphRenderer.GlyphBuffer");
+        let world_buffer = create_buffer("GlyphRenderer.WorldGlyphBuffer");
+
+        let atlas = FontAtlas::new(gfx, "assets/BasicFont.png");
+
+        Self {
+            pipeline,
+            world_pipeline,
+            atlas,
+            buffer,
+            world_buffer,
+        }
+    }
+
+    fn create_pipeline(
+        gfx: &Graphics,
+        module: &wgpu::ShaderModule,
+        layout: &wgpu::PipelineLayout,
+        entry_point: &str,
+        depth_stencil: Option<wgpu::DepthStencilState>,
+    ) -> wgpu::RenderPipeline {
+        gfx.device
             .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: Some("GlyphRenderer.Pipeline"),
-                layout: Some(&layout),
+                label: Some(format!("GlyphRenderer.Pipeline[{entry_point}]").as_str()),
+                layout: Some(layout),
                 vertex: wgpu::VertexState {
-                    module: &module,
-                    entry_point: "vert_main",
+                    module,
+                    entry_point,
                     buffers: &[Self::GLYPH_LAYOUT],
                 },
                 primitive: wgpu::PrimitiveState {
@@ -158,14 +217,14 @@ impl GlyphRenderer {
                     polygon_mode: wgpu::PolygonMode::Fill,
                     conservative: false,
                 },
-                depth_stencil: None,
+                depth_stencil,
                 multisample: wgpu::MultisampleState {
                     count: 1,
                     mask: !0,
                     alpha_to_coverage_enabled: false,
                 },
                 fragment: Some(wgpu::FragmentState {
-                    module: &module,
+                    module,
                     entry_point: "frag_main",
                     targets: &[Some(wgpu::ColorTargetState {
                         format: gfx.get_format(),
@@ -177,30 +236,15 @@ impl GlyphRenderer {
                     })],
                 }),
                 multiview: None,
-            });
-
-        let buffer = gfx.device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("GlyphRenderer.GlyphBuffer"),
-            size: (std::mem::size_of::<Glyph>() * Self::MAX_GLYPHS) as wgpu::BufferAddress,
-            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
-
-        let atlas = FontAtlas::new(gfx, "assets/BasicFont.png");
-
-        Self {
-            pipeline,
-            atlas,
-            buffer
-        }
+            })
     }
 
-    pub fn render<'a>(&'a mut self, gfx: &Graphics, pass: &mut wgpu::RenderPass<'a>, glyphs: &[Glyph]) {
+    pub fn render<'a>(&'a self, gfx: &Graphics, pass: &mut wgpu::RenderPass<'a>, glyphs: &[Glyph]) {
         if !glyphs.is_empty() {
             gfx.queue.write_buffer(
                 &self.buffer,
                 0,
-                bytemuck::cast_slice(&glyphs),
+                bytemuck::cast_slice(glyphs),
             );    
 
             pass.set_pipeline(&self.pipeline);
@@ -210,6 +254,34 @@ impl GlyphRenderer {
         }
     }
 
+    // Glyphs positioned and sized in world units, transformed by the camera.
+    // Uploaded once per frame, sorted back to front, then drawn a layer at a
+    // time with `render_world`
+    pub fn upload_world(&self, gfx: &Graphics, glyphs: &[Glyph]) {
+        if !glyphs.is_empty() {
+            gfx.queue
+                .write_buffer(&self.world_buffer, 0, bytemuck::cast_slice(glyphs));
+        }
+    }
+
+    // Drawn with depth testing, so the pass needs a `DepthBuffer`
+    pub fn render_world<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        camera: &'a CameraBuffer,
+        glyphs: &[Glyph],
+        layer: Layer,
+    ) {
+        let range = layer_range(glyphs, layer);
+        if !range.is_empty() {
+            pass.set_pipeline(&self.world_pipeline);
+            pass.set_bind_group(0, &self.atlas.bind_group, &[]);
+            pass.set_bind_group(1, &camera.bind_group, &[]);
+            pass.set_vertex_buffer(0, self.world_buffer.slice(..));
+            pass.draw(0..6, range);
+        }
+    }
+
     // Compute size of smallest font
     pub fn get_scale(&self, screen: (u32, u32)) -> Vec2<f32> {
         let px = 1.0 / screen.0 as f32;
@@ -1,7 +1,8 @@
 use winit::window::Window;
 
 pub struct Graphics {
-    surface: wgpu::Surface,
+    // Missing when running headless, frames are then never presented
+    surface: Option<wgpu::Surface>,
     config: wgpu::SurfaceConfiguration,
 
     pub device: wgpu::Device,
@@ -10,24 +11,10 @@ pub struct Graphics {
 
 impl Graphics {
     pub async fn new(window: &Window) -> Self {
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::PRIMARY,
-            dx12_shader_compiler: Default::default(),
-        });
-
+        let instance = Self::instance(wgpu::Backends::PRIMARY);
         let surface = unsafe { instance.create_surface(window) }.expect("Unable to create Surface");
 
         let size = window.inner_size();
-        let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: wgpu::TextureFormat::Bgra8UnormSrgb,
-            width: size.width,
-            height: size.height,
-            present_mode: wgpu::PresentMode::AutoVsync,
-            alpha_mode: wgpu::CompositeAlphaMode::Inherit,
-            view_formats: vec![],
-        };
-
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
                 power_preference: wgpu::PowerPreference::LowPower,
@@ -37,13 +24,62 @@ impl Graphics {
             .await
             .expect("Unable to find GPU");
 
+        Self::connect(adapter, Some(surface), (size.width, size.height)).await
+    }
+
+    // Without a window, falling back to a software adapter when there is no
+    // GPU, e.g. for running replays on CI. GL is allowed here as it is what
+    // most software rasterisers provide
+    pub async fn headless(size: (u32, u32)) -> Self {
+        let instance = Self::instance(wgpu::Backends::all());
+
+        let mut adapter = None;
+        for force_fallback_adapter in [false, true] {
+            adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::LowPower,
+                    force_fallback_adapter,
+                    compatible_surface: None,
+                })
+                .await;
+            if adapter.is_some() {
+                break;
+            }
+        }
+        let adapter = adapter.expect("Unable to find GPU or software adapter");
+
+        Self::connect(adapter, None, size).await
+    }
+
+    fn instance(backends: wgpu::Backends) -> wgpu::Instance {
+        wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends,
+            dx12_shader_compiler: Default::default(),
+        })
+    }
+
+    async fn connect(
+        adapter: wgpu::Adapter,
+        surface: Option<wgpu::Surface>,
+        size: (u32, u32),
+    ) -> Self {
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: wgpu::TextureFormat::Bgra8UnormSrgb,
+            width: size.0,
+            height: size.1,
+            present_mode: wgpu::PresentMode::AutoVsync,
+            alpha_mode: wgpu::CompositeAlphaMode::Inherit,
+            view_formats: vec![],
+        };
+
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: Some("Graphics"),
 
-                    // Note: remove later
-                    features: wgpu::Features::POLYGON_MODE_LINE,
+                    // Note: remove later. Software adapters lack it
+                    features: adapter.features() & wgpu::Features::POLYGON_MODE_LINE,
                     limits: wgpu::Limits::downlevel_webgl2_defaults(),
                 },
                 None,
@@ -70,12 +106,14 @@ impl Graphics {
         if new_size.width != 0 && new_size.height != 0 {
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
+            if let Some(surface) = &self.surface {
+                surface.configure(&self.device, &self.config);
+            }
         }
     }
 
     pub fn new_frame(&mut self) -> Option<Frame> {
-        match self.surface.get_current_texture() {
+        match self.surface.as_ref()?.get_current_texture() {
             Ok(output) => {
                 let view = output
                     .texture
@@ -98,13 +136,25 @@ impl Graphics {
     }
 
     pub fn load_shader(&self, path: &str) -> wgpu::ShaderModule {
-        let source =
-            std::fs::read_to_string(path).expect(format!("unable to read file {path}").as_str());
+        self.create_shader(path, Self::read_shader(path))
+    }
 
+    // For passes covering the whole target, the shader gets its `Fragment`
+    // and `vert_main` from shaders/fullscreen.wgsl
+    pub fn load_fullscreen_shader(&self, path: &str) -> wgpu::ShaderModule {
+        let source = Self::read_shader("shaders/fullscreen.wgsl") + &Self::read_shader(path);
+        self.create_shader(path, source)
+    }
+
+    fn read_shader(path: &str) -> String {
+        std::fs::read_to_string(path).unwrap_or_else(|_| panic!("unable to read file {path}"))
+    }
+
+    fn create_shader(&self, label: &str, source: String) -> wgpu::ShaderModule {
         self.device
             .create_shader_module(wgpu::ShaderModuleDescriptor {
-                label: Some(path),
-                source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(&source)),
+                label: Some(label),
+                source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Owned(source)),
             })
     }
 }
@@ -118,3 +168,453 @@ impl Frame {
         self.output.present();
     }
 }
+
+// Shared by the game layer pipelines, see `layer::Layer::depth`. The stencil
+// is used by the lighting for shadows
+pub struct DepthBuffer {
+    size: (u32, u32),
+    view: wgpu::TextureView,
+}
+impl DepthBuffer {
+    pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24PlusStencil8;
+
+    pub fn new(gfx: &Graphics, size: (u32, u32)) -> Self {
+        let texture = gfx.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Graphics.DepthBuffer"),
+            size: wgpu::Extent3d {
+                width: size.0.max(1),
+                height: size.1.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self { size, view }
+    }
+
+    // Must match the size of the colour target it is used with
+    pub fn resize(&mut self, gfx: &Graphics, size: (u32, u32)) {
+        if size != self.size {
+            *self = Self::new(gfx, size);
+        }
+    }
+
+    // Items are drawn back to front, so translucent ones still blend, while
+    // the depth test keeps draw order between renderers from mattering
+    pub fn state() -> wgpu::DepthStencilState {
+        wgpu::DepthStencilState {
+            format: Self::FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }
+    }
+
+    pub fn attachment(&self) -> wgpu::RenderPassDepthStencilAttachment<'_> {
+        wgpu::RenderPassDepthStencilAttachment {
+            view: &self.view,
+            depth_ops: Some(wgpu::Operations {
+                load: wgpu::LoadOp::Clear(1.0),
+                store: false,
+            }),
+            stencil_ops: Some(wgpu::Operations {
+                load: wgpu::LoadOp::Clear(0),
+                store: false,
+            }),
+        }
+    }
+}
+
+// Render graph. Passes declare the targets they read and the one they write,
+// and run grouped by stage in the order they were added. Intermediate targets
+// are owned by the graph and reallocated when their size changes
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TargetId(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TargetSize {
+    Surface,
+    // Fraction of the surface size, e.g. 0.5 for half resolution
+    Scaled(f32),
+    // Fraction of an earlier target's size
+    Relative(TargetId, f32),
+    Fixed(u32, u32),
+}
+
+#[derive(Clone, Debug)]
+pub struct TargetDesc {
+    pub label: &'static str,
+    pub size: TargetSize,
+    // Defaults to the surface format
+    pub format: Option<wgpu::TextureFormat>,
+    // Filtering used when the target is read as an input
+    pub filter: wgpu::FilterMode,
+    pub depth: bool,
+}
+impl TargetDesc {
+    pub fn new(label: &'static str, size: TargetSize) -> Self {
+        Self {
+            label,
+            size,
+            format: None,
+            filter: wgpu::FilterMode::Nearest,
+            depth: false,
+        }
+    }
+}
+
+struct Target {
+    desc: TargetDesc,
+    size: (u32, u32),
+    view: wgpu::TextureView,
+    depth: Option<DepthBuffer>,
+    bind_group: wgpu::BindGroup,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    World,
+    Lighting,
+    PostProcess,
+    Ui,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Output {
+    Surface,
+    Target(TargetId),
+}
+
+#[derive(Clone, Debug)]
+pub struct PassDesc {
+    pub label: &'static str,
+    pub stage: Stage,
+    pub inputs: Vec<TargetId>,
+    pub output: Output,
+    // Loads the previous contents when None
+    pub clear: Option<wgpu::Color>,
+}
+
+// Layout of the bind group for reading a render graph target, with the
+// texture at binding 0 and its sampler at binding 1
+pub fn input_layout(gfx: &Graphics) -> wgpu::BindGroupLayout {
+    gfx.device
+        .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("RenderGraph.Input.BindGroupLayout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        })
+}
+
+// Pipeline for passes drawing one triangle over the whole target, with the
+// shader's `vert_main` generating it, see `Graphics::load_fullscreen_shader`
+pub fn fullscreen_pipeline(
+    gfx: &Graphics,
+    label: &str,
+    shader: &wgpu::ShaderModule,
+    entry_point: &str,
+    layouts: &[&wgpu::BindGroupLayout],
+    format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    let layout = gfx
+        .device
+        .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(format!("{label}.Pipeline.Layout").as_str()),
+            bind_group_layouts: layouts,
+            push_constant_ranges: &[],
+        });
+
+    gfx.device
+        .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(format!("{label}.Pipeline").as_str()),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vert_main",
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point,
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+        })
+}
+
+pub fn uniform_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+pub struct NodeContext<'a> {
+    pub gfx: &'a Graphics,
+    targets: &'a [Target],
+    resolved: &'a [usize],
+    // Size of the target being drawn to
+    pub size: (u32, u32),
+}
+impl<'a> NodeContext<'a> {
+    // Bind group for reading a target, laid out as `input_layout`
+    pub fn input(&self, id: TargetId) -> &'a wgpu::BindGroup {
+        &self.targets[self.resolved[id.0]].bind_group
+    }
+    pub fn input_size(&self, id: TargetId) -> (u32, u32) {
+        self.targets[self.resolved[id.0]].size
+    }
+}
+
+pub trait RenderNode<S> {
+    // Skipped nodes pass their first input through in place of their output
+    fn enabled(&self, _scene: &S) -> bool {
+        true
+    }
+    // Called for every enabled node before any pass is recorded
+    fn prepare(&mut self, _gfx: &Graphics, _scene: &mut S) {}
+    fn draw<'a>(&'a self, ctx: &NodeContext<'a>, scene: &'a S, pass: &mut wgpu::RenderPass<'a>);
+}
+
+struct Pass<S> {
+    desc: PassDesc,
+    node: Box<dyn RenderNode<S>>,
+}
+
+pub struct RenderGraph<S> {
+    targets: Vec<Target>,
+    passes: Vec<Pass<S>>,
+
+    input_layout: wgpu::BindGroupLayout,
+}
+impl<S> RenderGraph<S> {
+    pub fn new(gfx: &Graphics) -> Self {
+        Self {
+            targets: Vec::new(),
+            passes: Vec::new(),
+            input_layout: input_layout(gfx),
+        }
+    }
+
+    pub fn add_target(&mut self, gfx: &Graphics, desc: TargetDesc) -> TargetId {
+        if let TargetSize::Relative(source, _) = desc.size {
+            assert!(source.0 < self.targets.len(), "Relative target sized by a later one");
+        }
+        let target = self.create_target(gfx, desc);
+        self.targets.push(target);
+        TargetId(self.targets.len() - 1)
+    }
+    pub fn set_target_size(&mut self, id: TargetId, size: TargetSize) {
+        self.targets[id.0].desc.size = size;
+    }
+    pub fn target_size(&self, id: TargetId) -> (u32, u32) {
+        self.targets[id.0].size
+    }
+
+    pub fn add_pass(&mut self, desc: PassDesc, node: impl RenderNode<S> + 'static) {
+        // Stable, so passes within a stage keep the order they were added in
+        let index = self.passes.partition_point(|pass| pass.desc.stage <= desc.stage);
+
+        let written = |id: &TargetId| {
+            self.passes[..index]
+                .iter()
+                .any(|pass| pass.desc.output == Output::Target(*id))
+        };
+        for input in desc.inputs.iter().filter(|input| !written(input)) {
+            let label = self.targets[input.0].desc.label;
+            eprintln!(
+                "Warning: Pass [{}] reads [{label}] before any pass writes it",
+                desc.label
+            );
+        }
+
+        self.passes.insert(
+            index,
+            Pass {
+                desc,
+                node: Box::new(node),
+            },
+        );
+    }
+
+    fn resolve_size(&self, gfx: &Graphics, size: TargetSize) -> (u32, u32) {
+        let scaled = |(width, height): (u32, u32), scale: f32| {
+            ((width as f32 * scale) as u32, (height as f32 * scale) as u32)
+        };
+        let size = match size {
+            TargetSize::Surface => gfx.get_size(),
+            TargetSize::Scaled(scale) => scaled(gfx.get_size(), scale),
+            TargetSize::Relative(id, scale) => scaled(self.targets[id.0].size, scale),
+            TargetSize::Fixed(width, height) => (width, height),
+        };
+        (size.0.max(1), size.1.max(1))
+    }
+
+    fn create_target(&self, gfx: &Graphics, desc: TargetDesc) -> Target {
+        let size = self.resolve_size(gfx, desc.size);
+        let label = desc.label;
+
+        let texture = gfx.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(format!("RenderGraph.Target[{label}]").as_str()),
+            size: wgpu::Extent3d {
+                width: size.0,
+                height: size.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: desc.format.unwrap_or(gfx.get_format()),
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let sampler = gfx.device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(format!("RenderGraph.Target[{label}].Sampler").as_str()),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: desc.filter,
+            min_filter: desc.filter,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let bind_group = gfx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(format!("RenderGraph.Target[{label}].BindGroup").as_str()),
+            layout: &self.input_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        let depth = desc.depth.then(|| DepthBuffer::new(gfx, size));
+        Target {
+            desc,
+            size,
+            view,
+            depth,
+            bind_group,
+        }
+    }
+
+    // In creation order, so relative sizes see their source's new size
+    fn allocate(&mut self, gfx: &Graphics) {
+        for index in 0..self.targets.len() {
+            let target = &self.targets[index];
+            if self.resolve_size(gfx, target.desc.size) != target.size {
+                self.targets[index] = self.create_target(gfx, target.desc.clone());
+            }
+        }
+    }
+
+    pub fn run(&mut self, gfx: &Graphics, surface: &wgpu::TextureView, scene: &mut S) {
+        self.allocate(gfx);
+
+        for pass in self.passes.iter_mut() {
+            if pass.node.enabled(scene) {
+                pass.node.prepare(gfx, scene);
+            }
+        }
+        let scene: &S = scene;
+
+        let mut encoder = gfx
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("RenderGraph.Encoder"),
+            });
+
+        // Where each target is currently read from, changed by skipped passes
+        let mut resolved: Vec<usize> = (0..self.targets.len()).collect();
+
+        for pass in self.passes.iter() {
+            if !pass.node.enabled(scene) {
+                if let (Output::Target(output), Some(input)) =
+                    (pass.desc.output, pass.desc.inputs.first())
+                {
+                    resolved[output.0] = resolved[input.0];
+                }
+                continue;
+            }
+
+            let (view, depth, size) = match pass.desc.output {
+                Output::Surface => (surface, None, gfx.get_size()),
+                Output::Target(id) => {
+                    resolved[id.0] = id.0;
+                    let target = &self.targets[id.0];
+                    (&target.view, target.depth.as_ref(), target.size)
+                }
+            };
+
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some(pass.desc.label),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: match pass.desc.clear {
+                            Some(color) => wgpu::LoadOp::Clear(color),
+                            None => wgpu::LoadOp::Load,
+                        },
+                        store: true,
+                    },
+                })],
+                depth_stencil_attachment: depth.map(|depth| depth.attachment()),
+            });
+
+            let ctx = NodeContext {
+                gfx,
+                targets: &self.targets,
+                resolved: &resolved,
+                size,
+            };
+            pass.node.draw(&ctx, scene, &mut render_pass);
+        }
+
+        gfx.queue.submit([encoder.finish()]);
+    }
+}
@@ -1,25 +1,9 @@
+use crate::color::Color;
+use crate::geometry::Rect;
+use crate::layer::Layer;
+use crate::nineslice::NineSlice;
 use crate::nvec::*;
-
-#[derive(Debug)]
-struct Rect {
-    a: Vec2<f32>,
-    b: Vec2<f32>,
-}
-impl Rect {
-    fn between(value: f32, a: f32, b: f32) -> bool {
-        (a <= value && value <= b) || (b <= value && value <= a)
-    }
-
-    fn sized(origin: Vec2<f32>, size: Vec2<f32>) -> Self {
-        Rect {
-            a: origin,
-            b: origin + size,
-        }
-    }
-    fn contains(&self, pos: Vec2<f32>) -> bool {
-        Self::between(pos.x, self.a.x, self.b.x) && Self::between(pos.y, self.a.y, self.b.y)
-    }
-}
+use crate::sprite::Sprite;
 
 pub struct Text {
     raw: String,
@@ -57,7 +41,7 @@ impl Text {
 
         vec2(max_width.max(width), height)
     }
-    fn paint(&self, ui: &mut UserInterface, color: Vec4<f32>) -> Vec2<f32> {
+    fn paint(&self, ui: &mut UserInterface, color: Color) -> Vec2<f32> {
         let mut pos = ui.anchor;
 
         for byte in self.raw.as_bytes() {
@@ -66,7 +50,7 @@ impl Text {
                 pos.y -= ui.glyph_size.y;
             } else {
                 ui.glyphs.push(crate::glyph::Glyph {
-                    pos: vec3(pos.x, pos.y, 0.0),
+                    pos: vec3(pos.x, pos.y, Layer::Ui.depth(0.0)),
                     codepoint: *byte as u32,
                     scale: ui.glyph_size,
                     color,
@@ -99,6 +83,8 @@ pub struct Response {
 
 pub struct UserInterface {
     pub glyphs: Vec<crate::glyph::Glyph>,
+    // Drawn before the glyphs, from the UI frame atlas
+    pub sprites: Vec<Sprite>,
     pub mouse: crate::input::MouseState,
 
     pub anchor: Vec2<f32>,
@@ -106,21 +92,30 @@ pub struct UserInterface {
     pub glyph_unit: Vec2<f32>,
     pub glyph_size: Vec2<f32>,
 
+    // Panels and buttons are drawn with it when set
+    pub frame: Option<NineSlice>,
+    // Size of one window pixel, and window pixels per frame texel
+    pub pixel: Vec2<f32>,
+    pub frame_scale: f32,
+
     hot: String,
     active: String,
 }
 
 impl UserInterface {
     pub fn new() -> Self {
-        let mut glyphs = Vec::new();
-        glyphs.reserve(1024);
+        let glyphs = Vec::with_capacity(1024);
 
         Self {
             glyphs,
+            sprites: Vec::new(),
             mouse: crate::input::MouseState::default(),
             anchor: vec2(0.0, 0.0),
             glyph_unit: vec2(0.0, 0.0),
             glyph_size: vec2(0.0, 0.0),
+            frame: None,
+            pixel: vec2(0.0, 0.0),
+            frame_scale: 2.0,
             hot: String::new(),
             active: String::new(),
         }
@@ -131,9 +126,19 @@ impl UserInterface {
         self.glyph_size.y = size * self.glyph_unit.y;
     }
 
+    pub fn panel(&mut self, rect: Rect) {
+        self.paint_frame(rect, Color::WHITE);
+    }
+    fn paint_frame(&mut self, rect: Rect, color: Color) {
+        if let Some(frame) = self.frame {
+            let unit = self.pixel * self.frame_scale;
+            frame.push(&mut self.sprites, rect, unit, Layer::Ui.depth(0.0), color);
+        }
+    }
+
     pub fn label(&mut self, text: impl Into<Text>) {
         let block: Text = text.into();
-        self.anchor.y = block.paint(self, vec4(1.0, 1.0, 1.0, 1.0)).y - self.glyph_size.y;
+        self.anchor.y = block.paint(self, Color::WHITE).y - self.glyph_size.y;
     }
     pub fn button(&mut self, text: impl Into<Text>) -> Response {
         let block: Text = text.into();
@@ -152,13 +157,11 @@ impl UserInterface {
                 }
                 self.active.clear();
             }
-        } else if self.hot == id {
-            if self.mouse.pressed() {
-                response.active = true;
+        } else if self.hot == id && self.mouse.pressed() {
+            response.active = true;
 
-                self.active.clear();
-                self.active.push_str(&id);
-            }
+            self.active.clear();
+            self.active.push_str(&id);
         }
 
         if rect.contains(self.mouse.pos) {
@@ -167,15 +170,22 @@ impl UserInterface {
             self.hot.push_str(&id);
         }
 
-        let mut color = vec4(1.0, 1.0, 1.0, 1.0);        
+        let mut color = Color::WHITE;        
         if response.hover {
-            color = vec4(0.2, 0.4, 0.8, 1.0);
+            color = Color::MANA;
         } else if response.active {
-            color = vec4(0.9, 0.2, 0.3, 1.0);
+            color = Color::EMBER;
         }
 
+        let padding = self.glyph_size * 0.25;
+        self.paint_frame(rect.inflate(padding), color);
         self.anchor.y = block.paint(self, color).y - self.glyph_size.y;
 
         response
     }
 }
+impl Default for UserInterface {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,6 +1,12 @@
-use crate::nvec::Vec2;
+use std::collections::HashMap;
 
-use winit::event::{ElementState, MouseButton};
+use crate::gamepad::Gamepads;
+use crate::nvec::*;
+use crate::replay::*;
+use crate::screen::Scaling;
+use crate::touch::TouchState;
+
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, TouchPhase};
 
 #[derive(Clone, Copy, Debug, Default)]
 pub struct ButtonState {
@@ -25,37 +31,236 @@ impl ButtonState {
     pub fn pressed(&self) -> bool {
         self.curr && !self.prev 
     }
+    pub fn held(&self) -> bool {
+        self.curr
+    }
 }
 
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Copy, Debug)]
+pub struct Drag {
+    pub button: MouseButton,
+    pub origin: Vec2<f32>,
+    pub active: bool,
+}
+
+// Anything that can map normalised device coordinates into the game world
+pub trait ScreenToWorld {
+    fn ndc_to_world(&self, ndc: Vec2<f32>) -> Vec2<f32>;
+}
+
+#[derive(Clone, Debug)]
 pub struct MouseState {
+    // Normalised device coordinates in [-1, 1], y up
     pub pos: Vec2<f32>,
+    // Accumulated since the last refresh
     pub delta: Vec2<f32>,
 
+    // Physical pixels from the top-left of the window, y down
+    pub pixels: Vec2<f32>,
+    pub viewport: Vec2<f32>,
+    pub scale_factor: f32,
+
+    // Normalised device coordinates within the game layer, which differ from
+    // `pos` when it is letterboxed
+    pub game_pos: Vec2<f32>,
+    pub scaling: Scaling,
+
     pub left: ButtonState,
     pub right: ButtonState,
     pub middle: ButtonState,
+    pub other: HashMap<u16, ButtonState>,
+
+    // Accumulated since the last refresh
+    pub scroll_lines: Vec2<f32>,
+    pub scroll_pixels: Vec2<f32>,
+
+    pub double_click_interval: f32,
+    // In logical pixels
+    pub drag_threshold: f32,
+
+    // Consecutive presses of the last pressed button, 2 is a double click
+    pub click_count: u32,
+    pub drag: Option<Drag>,
+    pub drag_started: bool,
+    pub drag_ended: Option<Drag>,
+
+    time: f32,
+    // Position is in logical pixels
+    last_click: Option<(MouseButton, f32, Vec2<f32>)>,
+}
+
+impl Default for MouseState {
+    fn default() -> Self {
+        Self {
+            pos: vec2(0.0, 0.0),
+            delta: vec2(0.0, 0.0),
+            pixels: vec2(0.0, 0.0),
+            viewport: vec2(1.0, 1.0),
+            scale_factor: 1.0,
+            game_pos: vec2(0.0, 0.0),
+            scaling: Scaling::default(),
+            left: ButtonState::default(),
+            right: ButtonState::default(),
+            middle: ButtonState::default(),
+            other: HashMap::new(),
+            scroll_lines: vec2(0.0, 0.0),
+            scroll_pixels: vec2(0.0, 0.0),
+            double_click_interval: 0.3,
+            drag_threshold: 4.0,
+            click_count: 0,
+            drag: None,
+            drag_started: false,
+            drag_ended: None,
+            time: 0.0,
+            last_click: None,
+        }
+    }
 }
 
 impl MouseState {
+    pub fn set_viewport(&mut self, size: Vec2<f32>, scale_factor: f32) {
+        if size.x > 0.0 && size.y > 0.0 {
+            self.viewport = size;
+        }
+        self.scale_factor = scale_factor;
+        self.pos = self.pixels_to_ndc(self.pixels);
+        self.game_pos = self.scaling.window_to_ndc(self.pixels, self.viewport);
+    }
+    pub fn set_scaling(&mut self, scaling: Scaling) {
+        self.scaling = scaling;
+        self.game_pos = self.scaling.window_to_ndc(self.pixels, self.viewport);
+    }
+
+    pub fn set_pixels(&mut self, pixels: Vec2<f32>) {
+        self.pixels = pixels;
+        self.game_pos = self.scaling.window_to_ndc(pixels, self.viewport);
+        self.set_pos(self.pixels_to_ndc(pixels));
+    }
+
     pub fn set_pos(&mut self, new_pos: Vec2<f32>) {
-        self.delta = new_pos - self.pos;
+        self.delta += new_pos - self.pos;
         self.pos = new_pos;
+
+        if let Some(mut drag) = self.drag.filter(|drag| !drag.active) {
+            let origin = self.ndc_to_logical(drag.origin);
+            if origin.distance(self.logical()) > self.drag_threshold {
+                drag.active = true;
+                self.drag = Some(drag);
+                self.drag_started = true;
+            }
+        }
     }
     pub fn set_state(&mut self, new_state: ElementState, button: winit::event::MouseButton) {
         match button {
             MouseButton::Left => self.left.set(new_state),
             MouseButton::Right => self.right.set(new_state),
             MouseButton::Middle => self.middle.set(new_state),
-            _ => {}
+            MouseButton::Other(id) => self.other.entry(id).or_default().set(new_state),
+        }
+
+        match new_state {
+            ElementState::Pressed => {
+                self.click_count = match self.last_click {
+                    Some((last, time, pos))
+                        if last == button
+                            && self.time - time <= self.double_click_interval
+                            && pos.distance(self.logical()) <= self.drag_threshold =>
+                    {
+                        self.click_count + 1
+                    }
+                    _ => 1,
+                };
+                self.last_click = Some((button, self.time, self.logical()));
+
+                if self.drag.is_none() {
+                    self.drag = Some(Drag {
+                        button,
+                        origin: self.pos,
+                        active: false,
+                    });
+                }
+            }
+            ElementState::Released => {
+                if let Some(drag) = self.drag {
+                    if drag.button == button {
+                        if drag.active {
+                            self.drag_ended = Some(drag);
+                        }
+                        self.drag = None;
+                    }
+                }
+            }
+        }
+    }
+    pub fn set_scroll(&mut self, delta: MouseScrollDelta) {
+        match delta {
+            MouseScrollDelta::LineDelta(x, y) => self.scroll_lines += vec2(x, y),
+            MouseScrollDelta::PixelDelta(pos) => {
+                self.scroll_pixels += vec2(pos.x as f32, pos.y as f32)
+            }
         }
     }
 
+    pub fn pixels_to_ndc(&self, pixels: Vec2<f32>) -> Vec2<f32> {
+        vec2(
+            2.0 * pixels.x / self.viewport.x - 1.0,
+            1.0 - 2.0 * pixels.y / self.viewport.y,
+        )
+    }
+    pub fn ndc_to_pixels(&self, ndc: Vec2<f32>) -> Vec2<f32> {
+        vec2(
+            0.5 * (ndc.x + 1.0) * self.viewport.x,
+            0.5 * (1.0 - ndc.y) * self.viewport.y,
+        )
+    }
+    fn ndc_to_logical(&self, ndc: Vec2<f32>) -> Vec2<f32> {
+        self.ndc_to_pixels(ndc) / self.scale_factor
+    }
+
+    pub fn physical(&self) -> Vec2<f32> {
+        self.pixels
+    }
+    pub fn logical(&self) -> Vec2<f32> {
+        self.pixels / self.scale_factor
+    }
+    pub fn ndc(&self) -> Vec2<f32> {
+        self.pos
+    }
+    // Pixels of the game layer, which are virtual pixels when it is scaled
+    pub fn game_pixels(&self) -> Vec2<f32> {
+        self.scaling.window_to_target(self.pixels, self.viewport)
+    }
+    pub fn world(&self, camera: &impl ScreenToWorld) -> Vec2<f32> {
+        camera.ndc_to_world(self.game_pos)
+    }
+
+    pub fn advance(&mut self, dt: f32) {
+        self.time += dt;
+    }
+
     pub fn refresh(&mut self) {
         self.left.refresh();
         self.right.refresh();
         self.middle.refresh();
+        for state in self.other.values_mut() {
+            state.refresh();
+        }
+
+        self.delta = vec2(0.0, 0.0);
+        self.scroll_lines = vec2(0.0, 0.0);
+        self.scroll_pixels = vec2(0.0, 0.0);
+        self.drag_started = false;
+        self.drag_ended = None;
+    }
+
+    pub fn button(&self, button: MouseButton) -> ButtonState {
+        match button {
+            MouseButton::Left => self.left,
+            MouseButton::Right => self.right,
+            MouseButton::Middle => self.middle,
+            MouseButton::Other(id) => self.other.get(&id).copied().unwrap_or_default(),
+        }
     }
 
     pub fn pressed(&self) -> bool {
@@ -64,15 +269,350 @@ impl MouseState {
     pub fn released(&self) -> bool {
         self.left.released() || self.right.released() || self.middle.released()
     }
+
+    pub fn double_clicked(&self, button: MouseButton) -> bool {
+        self.clicked_times(button, 2)
+    }
+    pub fn triple_clicked(&self, button: MouseButton) -> bool {
+        self.clicked_times(button, 3)
+    }
+    fn clicked_times(&self, button: MouseButton, count: u32) -> bool {
+        self.button(button).pressed()
+            && self.click_count == count
+            && matches!(self.last_click, Some((last, ..)) if last == button)
+    }
+
+    // Active drag with the given button, as (origin, current position)
+    pub fn dragging(&self, button: MouseButton) -> Option<(Vec2<f32>, Vec2<f32>)> {
+        self.drag
+            .filter(|drag| drag.active && drag.button == button)
+            .map(|drag| (drag.origin, self.pos))
+    }
+}
+
+pub enum InputMode {
+    Live,
+    Record(Recorder),
+    Replay(Replay),
 }
 
 pub struct Input {
     pub mouse: MouseState,
+    pub gamepads: Gamepads,
+    pub touch: TouchState,
+    pub mode: InputMode,
+    // The last viewport a replay set. The app lays out for it rather than
+    // the window, so recorded clicks land where they did
+    pub replay_viewport: Option<Vec2<f32>>,
 }
 
 impl Input {
     pub fn new() -> Self {
         let mouse = MouseState::default();
-        Self { mouse }
+        let gamepads = Gamepads::new();
+        Self {
+            mouse,
+            gamepads,
+            touch: TouchState::new(),
+            mode: InputMode::Live,
+            replay_viewport: None,
+        }
+    }
+
+    // Live events are dropped while a replay is driving the input
+    pub fn handle(&mut self, event: InputEvent) {
+        match &mut self.mode {
+            InputMode::Replay(_) => return,
+            InputMode::Record(recorder) => recorder.push(event),
+            InputMode::Live => {}
+        }
+        self.apply(event);
+    }
+
+    fn apply(&mut self, event: InputEvent) {
+        match event {
+            InputEvent::CursorMoved(pixels) => self.mouse.set_pixels(pixels),
+            InputEvent::Viewport(size, scale) => self.mouse.set_viewport(size, scale),
+            InputEvent::MouseButton(button, state) => self.mouse.set_state(state, button),
+            InputEvent::Scroll(delta) => self.mouse.set_scroll(delta),
+            InputEvent::Touch(touch) => {
+                if self.touch.apply(touch) && self.touch.promote_to_mouse {
+                    self.mouse.set_pixels(touch.pos);
+                    match touch.phase {
+                        TouchPhase::Started => {
+                            self.mouse.set_state(ElementState::Pressed, MouseButton::Left)
+                        }
+                        TouchPhase::Moved => {}
+                        TouchPhase::Ended | TouchPhase::Cancelled => {
+                            self.mouse.set_state(ElementState::Released, MouseButton::Left)
+                        }
+                    }
+                }
+            }
+            InputEvent::Gamepad(_) => {}
+        }
+    }
+
+    // Call at the start of a frame, after window events have been handled.
+    // Returns the frame time to simulate with, which differs from `dt` on replay
+    pub fn update(&mut self, dt: f32) -> f32 {
+        match &mut self.mode {
+            InputMode::Live => {
+                self.mouse.advance(dt);
+                self.gamepads.update();
+                dt
+            }
+            InputMode::Record(_) => {
+                self.mouse.advance(dt);
+                self.gamepads.update();
+
+                if let InputMode::Record(recorder) = &mut self.mode {
+                    for event in self.gamepads.events() {
+                        recorder.push(InputEvent::Gamepad(*event));
+                    }
+                    recorder.finish_frame(dt);
+                }
+                dt
+            }
+            InputMode::Replay(replay) => {
+                let frame = replay.next_frame().unwrap_or_default();
+
+                let mut pads = Vec::new();
+                for event in frame.events {
+                    match event {
+                        InputEvent::Gamepad(event) => pads.push(event),
+                        InputEvent::Viewport(size, _) => {
+                            self.replay_viewport = Some(size);
+                            self.apply(event);
+                        }
+                        event => self.apply(event),
+                    }
+                }
+                self.mouse.advance(frame.dt);
+                self.gamepads.update_with(pads);
+                frame.dt
+            }
+        }
+    }
+    // Call once the frame has consumed the input
+    pub fn end_frame(&mut self) {
+        self.mouse.refresh();
+        self.touch.refresh();
+    }
+
+    pub fn replay_finished(&self) -> bool {
+        match &self.mode {
+            InputMode::Replay(replay) => replay.is_finished(),
+            _ => false,
+        }
+    }
+}
+impl Default for Input {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 256x128 window at a scale factor of 1, so pixels are logical pixels
+    fn input() -> Input {
+        let mut input = Input::new();
+        input.handle(InputEvent::Viewport(vec2(256.0, 128.0), 1.0));
+        input
+    }
+
+    fn click(input: &mut Input, button: MouseButton) {
+        input.handle(InputEvent::MouseButton(button, ElementState::Pressed));
+        input.end_frame();
+        input.handle(InputEvent::MouseButton(button, ElementState::Released));
+        input.end_frame();
+    }
+
+    #[test]
+    fn delta_sums_moves_within_a_frame() {
+        let mut input = input();
+        input.handle(InputEvent::CursorMoved(vec2(128.0, 64.0)));
+        input.end_frame();
+
+        input.handle(InputEvent::CursorMoved(vec2(144.0, 64.0)));
+        input.handle(InputEvent::CursorMoved(vec2(160.0, 32.0)));
+        assert_eq!(input.mouse.pos, vec2(0.25, 0.5));
+        assert_eq!(input.mouse.delta, vec2(0.25, 0.5));
+
+        input.end_frame();
+        assert_eq!(input.mouse.delta, vec2(0.0, 0.0));
+    }
+
+    #[test]
+    fn double_and_triple_clicks() {
+        let mut input = input();
+        input.handle(InputEvent::CursorMoved(vec2(50.0, 50.0)));
+
+        input.handle(InputEvent::MouseButton(
+            MouseButton::Left,
+            ElementState::Pressed,
+        ));
+        assert_eq!(input.mouse.click_count, 1);
+        assert!(!input.mouse.double_clicked(MouseButton::Left));
+        input.end_frame();
+        input.handle(InputEvent::MouseButton(
+            MouseButton::Left,
+            ElementState::Released,
+        ));
+        input.end_frame();
+
+        input.mouse.advance(0.1);
+        input.handle(InputEvent::MouseButton(
+            MouseButton::Left,
+            ElementState::Pressed,
+        ));
+        assert!(input.mouse.double_clicked(MouseButton::Left));
+        assert!(!input.mouse.double_clicked(MouseButton::Right));
+        input.end_frame();
+        // Only on the frame of the press
+        assert!(!input.mouse.double_clicked(MouseButton::Left));
+        input.handle(InputEvent::MouseButton(
+            MouseButton::Left,
+            ElementState::Released,
+        ));
+        input.end_frame();
+
+        input.mouse.advance(0.1);
+        click(&mut input, MouseButton::Left);
+        assert_eq!(input.mouse.click_count, 3);
+    }
+
+    #[test]
+    fn clicks_too_slow_far_or_different_start_over() {
+        let mut input = input();
+        click(&mut input, MouseButton::Left);
+
+        input.mouse.advance(0.5);
+        click(&mut input, MouseButton::Left);
+        assert_eq!(input.mouse.click_count, 1);
+
+        // Past the drag threshold from the last click
+        input.handle(InputEvent::CursorMoved(vec2(10.0, 0.0)));
+        click(&mut input, MouseButton::Left);
+        assert_eq!(input.mouse.click_count, 1);
+
+        click(&mut input, MouseButton::Right);
+        assert_eq!(input.mouse.click_count, 1);
+    }
+
+    #[test]
+    fn drags_start_past_the_threshold() {
+        let mut input = input();
+        input.handle(InputEvent::CursorMoved(vec2(128.0, 64.0)));
+        input.handle(InputEvent::MouseButton(
+            MouseButton::Left,
+            ElementState::Pressed,
+        ));
+        input.end_frame();
+
+        input.handle(InputEvent::CursorMoved(vec2(131.0, 64.0)));
+        assert!(!input.mouse.drag_started);
+        assert_eq!(input.mouse.dragging(MouseButton::Left), None);
+        input.end_frame();
+
+        input.handle(InputEvent::CursorMoved(vec2(160.0, 64.0)));
+        assert!(input.mouse.drag_started);
+        let (origin, pos) = input.mouse.dragging(MouseButton::Left).unwrap();
+        assert_eq!(origin, vec2(0.0, 0.0));
+        assert_eq!(pos, vec2(0.25, 0.0));
+        assert_eq!(input.mouse.dragging(MouseButton::Right), None);
+        input.end_frame();
+        assert!(!input.mouse.drag_started);
+
+        // Other buttons do not end it
+        click(&mut input, MouseButton::Right);
+        assert!(input.mouse.dragging(MouseButton::Left).is_some());
+
+        input.handle(InputEvent::MouseButton(
+            MouseButton::Left,
+            ElementState::Released,
+        ));
+        let ended = input.mouse.drag_ended.unwrap();
+        assert_eq!(ended.button, MouseButton::Left);
+        assert_eq!(input.mouse.dragging(MouseButton::Left), None);
+        input.end_frame();
+        assert!(input.mouse.drag_ended.is_none());
+    }
+
+    #[test]
+    fn short_presses_are_not_drags() {
+        let mut input = input();
+        input.handle(InputEvent::MouseButton(
+            MouseButton::Left,
+            ElementState::Pressed,
+        ));
+        input.handle(InputEvent::CursorMoved(vec2(2.0, 2.0)));
+        input.handle(InputEvent::MouseButton(
+            MouseButton::Left,
+            ElementState::Released,
+        ));
+        assert!(input.mouse.drag_ended.is_none());
+        assert!(input.mouse.drag.is_none());
+    }
+
+    #[test]
+    fn scroll_accumulates_until_refresh() {
+        let mut input = input();
+        input.handle(InputEvent::Scroll(MouseScrollDelta::LineDelta(0.0, 1.0)));
+        input.handle(InputEvent::Scroll(MouseScrollDelta::LineDelta(1.0, 2.0)));
+        let pixels = winit::dpi::PhysicalPosition::new(3.0, -4.0);
+        input.handle(InputEvent::Scroll(MouseScrollDelta::PixelDelta(pixels)));
+        input.handle(InputEvent::Scroll(MouseScrollDelta::PixelDelta(pixels)));
+        assert_eq!(input.mouse.scroll_lines, vec2(1.0, 3.0));
+        assert_eq!(input.mouse.scroll_pixels, vec2(6.0, -8.0));
+
+        input.end_frame();
+        assert_eq!(input.mouse.scroll_lines, vec2(0.0, 0.0));
+        assert_eq!(input.mouse.scroll_pixels, vec2(0.0, 0.0));
+    }
+
+    #[test]
+    fn other_buttons_are_tracked() {
+        let mut input = input();
+        input.handle(InputEvent::MouseButton(
+            MouseButton::Other(4),
+            ElementState::Pressed,
+        ));
+        assert!(input.mouse.button(MouseButton::Other(4)).pressed());
+        assert!(!input.mouse.button(MouseButton::Other(5)).held());
+        input.end_frame();
+        assert!(input.mouse.button(MouseButton::Other(4)).held());
+        assert!(!input.mouse.button(MouseButton::Other(4)).pressed());
+    }
+
+    #[test]
+    fn replays_keep_their_recorded_viewport() {
+        let frame = InputFrame {
+            dt: 0.5,
+            events: vec![
+                InputEvent::Viewport(vec2(640.0, 480.0), 2.0),
+                InputEvent::CursorMoved(vec2(480.0, 120.0)),
+            ],
+        };
+        let mut input = Input::new();
+        input.mode = InputMode::Replay(Replay::new([frame]));
+
+        // The window the replay runs in has no say
+        input.handle(InputEvent::Viewport(vec2(1280.0, 720.0), 1.0));
+        assert_eq!(input.replay_viewport, None);
+
+        assert_eq!(input.update(1.0 / 60.0), 0.5);
+        assert_eq!(input.replay_viewport, Some(vec2(640.0, 480.0)));
+        assert_eq!(input.mouse.viewport, vec2(640.0, 480.0));
+        assert_eq!(input.mouse.logical(), vec2(240.0, 60.0));
+        assert_eq!(input.mouse.pos, vec2(0.5, 0.5));
+
+        // A camera laid out for it picks the same world position as recorded
+        let camera = crate::camera::Camera2D::new(vec2(640.0, 480.0));
+        let world = input.mouse.world(&camera);
+        assert!((world - vec2(160.0, 120.0)).length() < 1e-3);
     }
 }
@@ -0,0 +1,94 @@
+use std::ops::Range;
+
+// Draw layers from back to front. Everything drawn carries a single depth in
+// its `pos.z`, built from a layer and an order within it, so sorting and
+// depth testing agree across renderers
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Layer {
+    Background,
+    World,
+    Particles,
+    WorldText,
+    Ui,
+}
+impl Layer {
+    pub const ALL: [Layer; 5] = [
+        Layer::Background,
+        Layer::World,
+        Layer::Particles,
+        Layer::WorldText,
+        Layer::Ui,
+    ];
+
+    // Clip space depth for `z` in [0, 1] within the layer, where higher z is
+    // nearer. Depth 0 is nearest, matching the default depth range
+    pub fn depth(self, z: f32) -> f32 {
+        let count = Self::ALL.len() as f32;
+        let index = self as u32 as f32;
+        // Keep a gap between layers so z = 1 never reaches the next one
+        1.0 - (index + z.clamp(0.0, 1.0) * 0.999) / count
+    }
+
+    pub fn of_depth(depth: f32) -> Layer {
+        let count = Self::ALL.len() as f32;
+        // Nudged into the gap, as `depth(0.0)` can round to just under the layer
+        let index = ((1.0 - depth) * count + 0.0005).floor() as usize;
+        Self::ALL[index.min(Self::ALL.len() - 1)]
+    }
+}
+
+pub trait Layered {
+    fn depth(&self) -> f32;
+}
+
+// Farthest first, keeping submission order for equal depths so translucent
+// items blend correctly
+pub fn sort_back_to_front<T: Layered>(items: &mut [T]) {
+    items.sort_by(|a, b| b.depth().total_cmp(&a.depth()));
+}
+
+// Where a layer's items are in a list sorted back to front
+pub fn layer_range<T: Layered>(items: &[T], layer: Layer) -> Range<u32> {
+    let start = items.partition_point(|item| Layer::of_depth(item.depth()) < layer);
+    let end = items.partition_point(|item| Layer::of_depth(item.depth()) <= layer);
+    start as u32..end as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Item(f32);
+    impl Layered for Item {
+        fn depth(&self) -> f32 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn depth_round_trip() {
+        for layer in Layer::ALL {
+            for z in [0.0, 0.5, 1.0] {
+                assert_eq!(Layer::of_depth(layer.depth(z)), layer, "{layer:?} at {z}");
+            }
+        }
+        assert!(Layer::World.depth(1.0) > Layer::Particles.depth(0.0));
+    }
+
+    #[test]
+    fn ranges() {
+        let mut items = vec![
+            Item(Layer::Particles.depth(0.2)),
+            Item(Layer::World.depth(1.0)),
+            Item(Layer::Particles.depth(0.0)),
+            Item(Layer::Background.depth(0.5)),
+            Item(Layer::World.depth(0.0)),
+        ];
+        sort_back_to_front(&mut items);
+        assert_eq!(layer_range(&items, Layer::Background), 0..1);
+        assert_eq!(layer_range(&items, Layer::World), 1..3);
+        assert_eq!(layer_range(&items, Layer::Particles), 3..5);
+        assert!(layer_range(&items, Layer::WorldText).is_empty());
+        assert!(layer_range(&items, Layer::Ui).is_empty());
+    }
+}
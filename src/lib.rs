@@ -0,0 +1,26 @@
+pub mod animation;
+pub mod aseprite;
+pub mod camera;
+pub mod color;
+pub mod gamepad;
+pub mod geometry;
+pub mod glyph;
+pub mod graphics;
+pub mod gui;
+pub mod input;
+pub mod layer;
+pub mod lighting;
+pub mod nineslice;
+pub mod nvec;
+pub mod packer;
+pub mod particle;
+pub mod postfx;
+pub mod replay;
+pub mod scene;
+pub mod screen;
+pub mod shape;
+pub mod sprite;
+pub mod tiled;
+pub mod tilemap;
+pub mod touch;
+pub mod tween;
@@ -0,0 +1,525 @@
+use crate::camera::CameraBuffer;
+use crate::color::{rgb, Color};
+use crate::geometry::Polygon;
+use crate::graphics::*;
+use crate::nvec::*;
+use crate::scene::Scene;
+use crate::sprite::SpriteRenderer;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightKind {
+    Point,
+    // Angles are half the cone's width in radians, light fades out between
+    // `inner` and `outer`
+    Spot {
+        direction: Vec2<f32>,
+        inner: f32,
+        outer: f32,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Light {
+    pub pos: Vec2<f32>,
+    pub color: Color,
+    pub intensity: f32,
+    // World units, nothing past it is lit
+    pub radius: f32,
+    // Exponent of the fade towards the radius, 1 is linear
+    pub falloff: f32,
+    // Distance above the scene, lower makes normal maps more pronounced
+    pub height: f32,
+    pub kind: LightKind,
+    pub shadows: bool,
+}
+impl Light {
+    pub fn point(pos: Vec2<f32>, color: Color, radius: f32) -> Self {
+        Self {
+            pos,
+            color,
+            intensity: 1.0,
+            radius,
+            falloff: 2.0,
+            height: radius * 0.25,
+            kind: LightKind::Point,
+            shadows: true,
+        }
+    }
+    pub fn spot(
+        pos: Vec2<f32>,
+        color: Color,
+        radius: f32,
+        direction: Vec2<f32>,
+        angle: f32,
+    ) -> Self {
+        Self {
+            kind: LightKind::Spot {
+                direction: direction.normalize(),
+                inner: angle * 0.8,
+                outer: angle,
+            },
+            ..Self::point(pos, color, radius)
+        }
+    }
+
+    fn instance(&self) -> LightInstance {
+        let (direction, cone) = match self.kind {
+            // Wider than any angle, so the cone never cuts in
+            LightKind::Point => (vec2(1.0, 0.0), vec2(-3.0, -2.0)),
+            LightKind::Spot {
+                direction,
+                inner,
+                outer,
+            } => (direction, vec2(outer.cos(), inner.cos())),
+        };
+        let color = self
+            .color
+            .multiply(rgb(self.intensity, self.intensity, self.intensity));
+
+        LightInstance {
+            pos: self.pos,
+            radius: self.radius,
+            falloff: self.falloff,
+            color,
+            direction,
+            cone: vec3(cone.x, cone.y, self.height),
+        }
+    }
+
+    // Quads behind each edge facing away from the light, reaching past its
+    // radius. The occluder itself stays lit
+    fn shadow_volume(&self, occluder: &Polygon, out: &mut Vec<Vec2<f32>>) {
+        let reach = self.radius * 2.0;
+        for edge in occluder.edges() {
+            let outward = -(edge.b - edge.a).perp();
+            if outward.dot(self.pos - edge.a) >= 0.0 {
+                continue;
+            }
+            let far_a = edge.a + (edge.a - self.pos).normalize() * reach;
+            let far_b = edge.b + (edge.b - self.pos).normalize() * reach;
+            out.extend_from_slice(&[edge.a, edge.b, far_b, edge.a, far_b, far_a]);
+        }
+    }
+}
+
+pub struct Lighting {
+    pub enabled: bool,
+    pub ambient: Color,
+    // Both are cleared by the game each frame before being refilled
+    pub lights: Vec<Light>,
+    pub occluders: Vec<Polygon>,
+}
+impl Lighting {
+    pub fn new() -> Self {
+        Self {
+            enabled: true,
+            ambient: rgb(0.25, 0.22, 0.3),
+            lights: Vec::new(),
+            occluders: Vec::new(),
+        }
+    }
+
+    // Whether any light needs the normals, the ambient still applies without
+    pub fn active(&self) -> bool {
+        self.enabled && !self.lights.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.lights.clear();
+        self.occluders.clear();
+    }
+}
+impl Default for Lighting {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+struct LightInstance {
+    pos: Vec2<f32>,
+    radius: f32,
+    falloff: f32,
+    color: Color,
+    direction: Vec2<f32>,
+    cone: Vec3<f32>,
+}
+
+// Sprite normals for the lights to read
+struct NormalNode;
+impl RenderNode<Scene> for NormalNode {
+    fn enabled(&self, scene: &Scene) -> bool {
+        scene.lighting.active()
+    }
+
+    fn draw<'a>(
+        &'a self,
+        _ctx: &NodeContext<'a>,
+        scene: &'a Scene,
+        pass: &mut wgpu::RenderPass<'a>,
+    ) {
+        scene
+            .spriter
+            .render_normals(pass, &scene.camera_buffer, scene.world_groups());
+    }
+}
+
+// Adds every light into the light map. Each light first marks its shadows in
+// the stencil with its own reference value, then skips the marked pixels, so
+// the stencil never needs clearing between lights
+struct LightNode {
+    light_pipeline: wgpu::RenderPipeline,
+    shadow_pipeline: wgpu::RenderPipeline,
+
+    instances: wgpu::Buffer,
+    shadows: wgpu::Buffer,
+    // Vertex range of each light's shadow volumes
+    ranges: Vec<std::ops::Range<u32>>,
+
+    normals: TargetId,
+}
+impl LightNode {
+    // Limited by the 8 bit stencil reference
+    const MAX_LIGHTS: usize = 255;
+    const MAX_SHADOW_VERTICES: usize = 64 * 1024;
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    const LIGHT_ATTRIBUTES: &[wgpu::VertexAttribute] = &wgpu::vertex_attr_array![
+        0 => Float32x2, 1 => Float32, 2 => Float32, 3 => Float32x4, 4 => Float32x2, 5 => Float32x3
+    ];
+    const LIGHT_LAYOUT: wgpu::VertexBufferLayout<'static> = wgpu::VertexBufferLayout {
+        array_stride: std::mem::size_of::<LightInstance>() as wgpu::BufferAddress,
+        step_mode: wgpu::VertexStepMode::Instance,
+        attributes: Self::LIGHT_ATTRIBUTES,
+    };
+    const SHADOW_ATTRIBUTES: &[wgpu::VertexAttribute] = &wgpu::vertex_attr_array![0 => Float32x2];
+    const SHADOW_LAYOUT: wgpu::VertexBufferLayout<'static> = wgpu::VertexBufferLayout {
+        array_stride: std::mem::size_of::<Vec2<f32>>() as wgpu::BufferAddress,
+        step_mode: wgpu::VertexStepMode::Vertex,
+        attributes: Self::SHADOW_ATTRIBUTES,
+    };
+
+    fn new(gfx: &Graphics, normals: TargetId) -> Self {
+        let shader = gfx.load_shader("shaders/light.wgsl");
+        let layout = gfx
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Lighting.Light.Pipeline.Layout"),
+                bind_group_layouts: &[&input_layout(gfx), &CameraBuffer::layout(gfx)],
+                push_constant_ranges: &[],
+            });
+
+        let additive = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let stencil = |compare, pass_op, write_mask| {
+            let face = wgpu::StencilFaceState {
+                compare,
+                fail_op: wgpu::StencilOperation::Keep,
+                depth_fail_op: wgpu::StencilOperation::Keep,
+                pass_op,
+            };
+            wgpu::DepthStencilState {
+                format: DepthBuffer::FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState {
+                    front: face,
+                    back: face,
+                    read_mask: 0xff,
+                    write_mask,
+                },
+                bias: wgpu::DepthBiasState::default(),
+            }
+        };
+
+        let pipeline = |label, vertex, fragment, buffer, depth_stencil, blend, write_mask| {
+            gfx.device
+                .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some(label),
+                    layout: Some(&layout),
+                    vertex: wgpu::VertexState {
+                        module: &shader,
+                        entry_point: vertex,
+                        buffers: &[buffer],
+                    },
+                    primitive: wgpu::PrimitiveState::default(),
+                    depth_stencil: Some(depth_stencil),
+                    multisample: wgpu::MultisampleState::default(),
+                    fragment: Some(wgpu::FragmentState {
+                        module: &shader,
+                        entry_point: fragment,
+                        targets: &[Some(wgpu::ColorTargetState {
+                            format: Self::FORMAT,
+                            blend,
+                            write_mask,
+                        })],
+                    }),
+                    multiview: None,
+                })
+        };
+
+        let light_pipeline = pipeline(
+            "Lighting.Light.Pipeline",
+            "vert_light",
+            "frag_light",
+            Self::LIGHT_LAYOUT,
+            stencil(
+                wgpu::CompareFunction::NotEqual,
+                wgpu::StencilOperation::Keep,
+                0,
+            ),
+            Some(wgpu::BlendState {
+                color: additive,
+                alpha: additive,
+            }),
+            wgpu::ColorWrites::ALL,
+        );
+        let shadow_pipeline = pipeline(
+            "Lighting.Shadow.Pipeline",
+            "vert_shadow",
+            "frag_shadow",
+            Self::SHADOW_LAYOUT,
+            stencil(
+                wgpu::CompareFunction::Always,
+                wgpu::StencilOperation::Replace,
+                0xff,
+            ),
+            None,
+            wgpu::ColorWrites::empty(),
+        );
+
+        let instances = gfx.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Lighting.Light.Buffer"),
+            size: (std::mem::size_of::<LightInstance>() * Self::MAX_LIGHTS) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let shadows = gfx.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Lighting.Shadow.Buffer"),
+            size: (std::mem::size_of::<Vec2<f32>>() * Self::MAX_SHADOW_VERTICES)
+                as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            light_pipeline,
+            shadow_pipeline,
+            instances,
+            shadows,
+            ranges: Vec::new(),
+            normals,
+        }
+    }
+}
+impl RenderNode<Scene> for LightNode {
+    fn enabled(&self, scene: &Scene) -> bool {
+        // Still clears the light map to black when there are no lights
+        scene.lighting.enabled
+    }
+
+    fn prepare(&mut self, gfx: &Graphics, scene: &mut Scene) {
+        let lighting = &scene.lighting;
+        if lighting.lights.len() > Self::MAX_LIGHTS {
+            eprintln!(
+                "Warning: {} lights exceed the limit of {}, dropping the rest",
+                lighting.lights.len(),
+                Self::MAX_LIGHTS
+            );
+        }
+        let lights = &lighting.lights[..lighting.lights.len().min(Self::MAX_LIGHTS)];
+
+        let mut vertices = Vec::new();
+        self.ranges.clear();
+        for light in lights {
+            let start = vertices.len();
+            if light.shadows {
+                for occluder in lighting.occluders.iter() {
+                    light.shadow_volume(occluder, &mut vertices);
+                }
+            }
+            // Lights past the buffer are drawn without shadows
+            let end = vertices.len().min(Self::MAX_SHADOW_VERTICES);
+            self.ranges.push(start.min(end) as u32..end as u32);
+        }
+        vertices.truncate(Self::MAX_SHADOW_VERTICES);
+
+        let instances: Vec<LightInstance> = lights.iter().map(Light::instance).collect();
+        if !instances.is_empty() {
+            gfx.queue
+                .write_buffer(&self.instances, 0, bytemuck::cast_slice(&instances));
+        }
+        if !vertices.is_empty() {
+            gfx.queue
+                .write_buffer(&self.shadows, 0, bytemuck::cast_slice(&vertices));
+        }
+    }
+
+    fn draw<'a>(
+        &'a self,
+        ctx: &NodeContext<'a>,
+        scene: &'a Scene,
+        pass: &mut wgpu::RenderPass<'a>,
+    ) {
+        pass.set_bind_group(0, ctx.input(self.normals), &[]);
+        pass.set_bind_group(1, &scene.camera_buffer.bind_group, &[]);
+
+        for (index, range) in self.ranges.iter().enumerate() {
+            pass.set_stencil_reference(index as u32 + 1);
+
+            if !range.is_empty() {
+                pass.set_pipeline(&self.shadow_pipeline);
+                pass.set_vertex_buffer(0, self.shadows.slice(..));
+                pass.draw(range.clone(), 0..1);
+            }
+
+            let instance = index as u32;
+            pass.set_pipeline(&self.light_pipeline);
+            pass.set_vertex_buffer(0, self.instances.slice(..));
+            pass.draw(0..6, instance..instance + 1);
+        }
+    }
+}
+
+// Multiplies the scene by the ambient and light map
+struct ApplyNode {
+    pipeline: wgpu::RenderPipeline,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+
+    source: TargetId,
+    light_map: TargetId,
+}
+impl ApplyNode {
+    fn new(gfx: &Graphics, source: TargetId, light_map: TargetId) -> Self {
+        let layout = gfx
+            .device
+            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("Lighting.Apply.BindGroupLayout"),
+                entries: &[uniform_entry(0)],
+            });
+
+        let shader = gfx.load_fullscreen_shader("shaders/light_apply.wgsl");
+        let input = input_layout(gfx);
+        let pipeline = fullscreen_pipeline(
+            gfx,
+            "Lighting.Apply",
+            &shader,
+            "frag_main",
+            &[&input, &input, &layout],
+            gfx.get_format(),
+        );
+
+        let buffer = gfx.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Lighting.Apply.Buffer"),
+            size: std::mem::size_of::<Color>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = gfx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Lighting.Apply.BindGroup"),
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+
+        Self {
+            pipeline,
+            buffer,
+            bind_group,
+            source,
+            light_map,
+        }
+    }
+}
+impl RenderNode<Scene> for ApplyNode {
+    fn enabled(&self, scene: &Scene) -> bool {
+        scene.lighting.enabled
+    }
+
+    fn prepare(&mut self, gfx: &Graphics, scene: &mut Scene) {
+        gfx.queue
+            .write_buffer(&self.buffer, 0, bytemuck::bytes_of(&scene.lighting.ambient));
+    }
+
+    fn draw<'a>(
+        &'a self,
+        ctx: &NodeContext<'a>,
+        _scene: &'a Scene,
+        pass: &mut wgpu::RenderPass<'a>,
+    ) {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, ctx.input(self.source), &[]);
+        pass.set_bind_group(1, ctx.input(self.light_map), &[]);
+        pass.set_bind_group(2, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+// Adds the lighting passes over `source`, returning the target holding the
+// lit scene
+pub fn build(gfx: &Graphics, graph: &mut RenderGraph<Scene>, source: TargetId) -> TargetId {
+    let normals = graph.add_target(
+        gfx,
+        TargetDesc {
+            format: Some(SpriteRenderer::NORMAL_FORMAT),
+            depth: true,
+            ..TargetDesc::new("Lighting.Normals", TargetSize::Relative(source, 1.0))
+        },
+    );
+    let light_map = graph.add_target(
+        gfx,
+        TargetDesc {
+            format: Some(LightNode::FORMAT),
+            depth: true,
+            ..TargetDesc::new("Lighting.LightMap", TargetSize::Relative(source, 1.0))
+        },
+    );
+    let output = graph.add_target(
+        gfx,
+        TargetDesc::new("Lighting.Output", TargetSize::Relative(source, 1.0)),
+    );
+
+    graph.add_pass(
+        PassDesc {
+            label: "Lighting.Normals",
+            stage: Stage::Lighting,
+            inputs: vec![],
+            output: Output::Target(normals),
+            clear: Some(wgpu::Color {
+                r: 0.5,
+                g: 0.5,
+                b: 1.0,
+                a: 1.0,
+            }),
+        },
+        NormalNode,
+    );
+    graph.add_pass(
+        PassDesc {
+            label: "Lighting.Lights",
+            stage: Stage::Lighting,
+            inputs: vec![normals],
+            output: Output::Target(light_map),
+            clear: Some(wgpu::Color::BLACK),
+        },
+        LightNode::new(gfx, normals),
+    );
+    graph.add_pass(
+        PassDesc {
+            label: "Lighting.Apply",
+            stage: Stage::Lighting,
+            inputs: vec![source, light_map],
+            output: Output::Target(output),
+            clear: None,
+        },
+        ApplyNode::new(gfx, source, light_map),
+    );
+    output
+}
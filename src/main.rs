@@ -1,27 +1,334 @@
-mod glyph;
-mod graphics;
-mod gui;
-mod input;
-mod nvec;
-mod sprite;
-
-use crate::graphics::*;
-use crate::nvec::*;
-use crate::sprite::*;
-
-use winit::event::KeyboardInput;
+use great_jam23::geometry::Rect;
+use great_jam23::graphics::*;
+use great_jam23::layer::Layer;
+use great_jam23::nvec::*;
+use great_jam23::replay::InputEvent;
+use great_jam23::sprite::*;
+use great_jam23::{
+    animation, camera, color, gamepad, glyph, gui, input, lighting, nineslice, particle, postfx,
+    replay, scene, screen, shape, tiled, tilemap, touch, tween,
+};
+
+use std::cell::Cell;
+use std::rc::Rc;
+
 use winit::window::Window;
 
-struct Region {}
+// A tilemap to explore, shown once entered
+struct Region {
+    map: tilemap::Tilemap,
+    spawns: Vec<tiled::Spawn>,
+}
+impl Region {
+    // Tiled maps are laid out in pixels, one per world unit
+    fn load(gfx: &Graphics, path: &str) -> Self {
+        let tiled = tiled::TiledMap::load(path);
+        let origin = vec2(0.0, 0.0);
+        let map = tiled
+            .tilemap(gfx, origin, 1.0)
+            .unwrap_or_else(|| panic!("No tiles to place in {path}"));
+        Self {
+            map,
+            spawns: tiled.spawns(origin, 1.0),
+        }
+    }
+
+    // Marked by an object of class "Start", otherwise the middle of the map
+    fn start(&self) -> Vec2<f32> {
+        self.spawns
+            .iter()
+            .find(|spawn| spawn.class == "Start")
+            .map(|spawn| spawn.pos)
+            .unwrap_or_else(|| self.map.bounds().center())
+    }
+
+    // Objects of class "Occluder" cast shadows
+    fn enter(self, scene: &mut scene::Scene) {
+        let occluders = self
+            .spawns
+            .iter()
+            .filter(|spawn| spawn.class == "Occluder")
+            .filter_map(|spawn| spawn.polygon());
+        scene.lighting.occluders = occluders.collect();
+        scene.camera.bounds = Some(self.map.bounds());
+        scene.tilemap = Some(self.map);
+    }
+}
 
 struct Player {
-    mesh: SpriteGroup,
+    animator: animation::Animator,
+    pos: Vec2<f32>,
+    facing_left: bool,
 }
 impl Player {
-    fn new(gfx: &Graphics) -> Self {
-        let mesh = SpriteGroup::new(gfx, "Player.png", 1);
+    const ATLAS: &str = "assets/Player.png";
+    // World units per second
+    const SPEED: f32 = 48.0;
+    // Half the size of what collides with solid tiles
+    const BODY: f32 = 3.0;
+
+    fn new() -> Self {
+        use animation::{Animator, Clip, PlayMode};
+
+        // One row of four frames per clip
+        let frame = vec2(0.25, 1.0 / 3.0);
+        let animator = Animator::new(vec![
+            Clip::grid("idle", vec2(0.0, 0.0), frame, 4, 4, 0.25),
+            Clip::grid("walk", vec2(0.0, frame.y), frame, 4, 4, 0.1),
+            Clip::grid("cast", vec2(0.0, frame.y * 2.0), frame, 4, 4, 0.08)
+                .mode(PlayMode::Once)
+                .durations(&[0.08, 0.08, 0.12, 0.2])
+                .event(2, "release"),
+        ]);
+
+        Self {
+            animator,
+            pos: vec2(0.0, 0.0),
+            facing_left: false,
+        }
+    }
+
+    // Each axis moves on its own, so the player slides along walls. False
+    // when blocked both ways
+    fn walk(&mut self, step: Vec2<f32>, map: Option<&tilemap::Tilemap>) -> bool {
+        let blocked = |pos| {
+            let body = Rect::centered(pos, vec2(Self::BODY, Self::BODY));
+            map.is_some_and(|map| !map.query(body, tilemap::TileFlags::SOLID).is_empty())
+        };
+        let mut moved = false;
+        for step in [vec2(step.x, 0.0), vec2(0.0, step.y)] {
+            if step != vec2(0.0, 0.0) && !blocked(self.pos + step) {
+                self.pos += step;
+                moved = true;
+            }
+        }
+        moved
+    }
 
-        Self { mesh }
+    fn cast(&mut self) {
+        self.animator.play_from_start("cast");
+    }
+
+    // Where the staff ends, in front of the player
+    fn staff_tip(&self) -> Vec2<f32> {
+        let side = if self.facing_left { -1.0 } else { 1.0 };
+        self.pos + vec2(6.0 * side, 6.0)
+    }
+
+    fn animate(&mut self, dt: f32, velocity: Vec2<f32>) {
+        let casting = self.animator.clip().name == "cast" && !self.animator.is_finished();
+        if !casting {
+            let moving = velocity.length_squared() > 0.0;
+            self.animator.play(if moving { "walk" } else { "idle" });
+        }
+        if velocity.x != 0.0 {
+            self.facing_left = velocity.x < 0.0;
+        }
+        self.animator.update(dt);
+    }
+
+    fn draw(&self, group: &mut SpriteGroup) {
+        let sprite = Sprite::new(
+            vec3(self.pos.x, self.pos.y, Layer::World.depth(0.5)),
+            vec2(8.0, 8.0),
+            color::Color::WHITE,
+            self.animator.rect(),
+        );
+        group.push(sprite.flipped(self.facing_left, false));
+    }
+
+    fn update_camera(&self, camera: &mut camera::Camera2D, dt: f32) {
+        camera.follow(self.pos, dt);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Action {
+    Left,
+    Right,
+    Up,
+    Down,
+    Cast,
+}
+
+// Everything live while the game page is open
+struct Game {
+    player: Player,
+    sparks: Rc<particle::EmitterDef>,
+    emitters: Vec<particle::Emitter>,
+    actions: gamepad::ActionMap<Action>,
+    // Clicked in the world, walked towards until reached
+    target: Option<Vec2<f32>>,
+    casts: u32,
+    // Fixed so replays scatter the same particles
+    seed: u32,
+}
+impl Game {
+    // Indices into `Scene::sprite_groups`
+    const PLAYER: usize = 0;
+    const SPARKS: usize = 1;
+    // World units shown from the top of the view to the bottom
+    const VIEW_HEIGHT: f32 = 180.0;
+    const REGION: &str = "assets/Region.tmj";
+
+    fn new() -> Self {
+        use gamepad::{Binding, GamepadAxis, GamepadButton};
+
+        let mut actions = gamepad::ActionMap::new();
+        actions
+            .bind(Action::Left, Binding::Axis(GamepadAxis::LeftStickX, -0.2))
+            .bind(Action::Left, Binding::Button(GamepadButton::DPadLeft))
+            .bind(Action::Right, Binding::Axis(GamepadAxis::LeftStickX, 0.2))
+            .bind(Action::Right, Binding::Button(GamepadButton::DPadRight))
+            .bind(Action::Up, Binding::Axis(GamepadAxis::LeftStickY, 0.2))
+            .bind(Action::Up, Binding::Button(GamepadButton::DPadUp))
+            .bind(Action::Down, Binding::Axis(GamepadAxis::LeftStickY, -0.2))
+            .bind(Action::Down, Binding::Button(GamepadButton::DPadDown))
+            .bind(Action::Cast, Binding::Button(GamepadButton::South))
+            .bind(Action::Cast, Binding::Axis(GamepadAxis::RightTrigger, 0.5));
+
+        Self {
+            player: Player::new(),
+            sparks: Rc::new(particle::EmitterDef::load("assets/Sparks.json")),
+            emitters: Vec::new(),
+            actions,
+            target: None,
+            casts: 0,
+            seed: 1,
+        }
+    }
+
+    fn enter(&mut self, gfx: &Graphics, scene: &mut scene::Scene) {
+        let region = Region::load(gfx, Self::REGION);
+        self.player.pos = region.start();
+        region.enter(scene);
+
+        let player = SpriteGroup::with_capacity(gfx, Player::ATLAS, 1);
+        let sparks = SpriteGroup::with_capacity(gfx, "assets/Sparks.png", 1024)
+            .with_blend(BlendMode::Additive);
+        scene.sprite_groups = vec![player, sparks];
+        scene.camera.position = self.player.pos;
+    }
+
+    fn leave(self, scene: &mut scene::Scene) {
+        scene.sprite_groups.clear();
+        scene.lighting.clear();
+        scene.tilemap = None;
+        scene.camera.bounds = None;
+        scene.camera.zoom = 1.0;
+    }
+
+    fn update(&mut self, dt: f32, input: &input::Input, scene: &mut scene::Scene) {
+        let pads = &input.gamepads;
+        let axis = |negative, positive| {
+            self.actions.value(pads, positive) - self.actions.value(pads, negative)
+        };
+        let mut velocity = vec2(axis(Action::Left, Action::Right), axis(Action::Down, Action::Up));
+
+        // The pad takes over from a click until the player stops steering
+        if velocity.length_squared() > 0.0 {
+            self.target = None;
+        } else if let Some(target) = self.target {
+            let to_target = target - self.player.pos;
+            if to_target.length() < Player::SPEED * dt {
+                self.player.pos = target;
+                self.target = None;
+            } else {
+                velocity = to_target.normalize();
+            }
+        }
+        if velocity.length() > 1.0 {
+            velocity = velocity.normalize();
+        }
+        let step = velocity * Player::SPEED * dt;
+        if !self.player.walk(step, scene.tilemap.as_ref()) {
+            // Clicked somewhere out of reach
+            self.target = None;
+        }
+
+        if self.actions.pressed(pads, Action::Cast) || input.mouse.right.pressed() {
+            self.player.cast();
+        }
+        self.player.animate(dt, velocity);
+
+        let events: Vec<String> = self.player.animator.drain_events().collect();
+        for event in events {
+            if event == "release" {
+                let emitter =
+                    particle::Emitter::new(self.sparks.clone(), self.player.staff_tip(), self.seed);
+                self.emitters.push(emitter);
+                self.seed += 1;
+                self.casts += 1;
+                scene.camera.shake(0.2);
+            }
+        }
+        for emitter in self.emitters.iter_mut() {
+            emitter.update(dt);
+        }
+        self.emitters.retain(|emitter| !emitter.is_finished());
+
+        scene.camera.zoom = scene.camera.viewport.y / Self::VIEW_HEIGHT;
+        self.player.update_camera(&mut scene.camera, dt);
+        self.draw(scene);
+    }
+
+    fn draw(&self, scene: &mut scene::Scene) {
+        let player = &mut scene.sprite_groups[Self::PLAYER];
+        player.clear();
+        self.player.draw(player);
+
+        let sparks = &mut scene.sprite_groups[Self::SPARKS];
+        sparks.clear();
+        for emitter in self.emitters.iter() {
+            emitter.draw(sparks);
+        }
+
+        use lighting::Light;
+        let lights = &mut scene.lighting.lights;
+        lights.clear();
+        lights.push(Light::point(self.player.pos, color::Color::ARCANE, 96.0));
+        for emitter in self.emitters.iter() {
+            let flash = Light {
+                shadows: false,
+                ..Light::point(emitter.pos, color::Color::EMBER, 32.0)
+            };
+            lights.push(flash);
+        }
+    }
+
+    // Debug overlay of what the game sees rather than what it shows. Drawn
+    // after the shapes are cleared for the frame
+    fn gizmos(&self, scene: &mut scene::Scene) {
+        use shape::{Cap, Join, Stroke};
+        use color::Color;
+
+        let shapes = &mut scene.shapes;
+        let pixel = shapes.feather;
+        let thin = Stroke::new(pixel, Color::WHITE);
+
+        for occluder in scene.lighting.occluders.iter() {
+            shapes.polygon(&occluder.points, Color::BLACK.with_alpha(0.3));
+            shapes.polyline(&occluder.points, true, thin.join(Join::Bevel));
+        }
+        if let Some(map) = &scene.tilemap {
+            let near = Rect::centered(self.player.pos, vec2(32.0, 32.0));
+            for (_, cell) in map.query(near, tilemap::TileFlags::SOLID) {
+                shapes.rect_outline(cell, Stroke { color: Color::RED, ..thin });
+            }
+        }
+
+        let body = Rect::centered(self.player.pos, vec2(Player::BODY, Player::BODY));
+        shapes.rect(body, Color::GREEN.with_alpha(0.25));
+        shapes.rect_outline(body, Stroke { color: Color::GREEN, ..thin });
+        if let Some(target) = self.target {
+            let path = Stroke::new(pixel * 2.0, Color::YELLOW.with_alpha(0.5)).cap(Cap::Round);
+            shapes.line(self.player.pos, target, path);
+            shapes.circle(target, pixel * 3.0, Color::YELLOW);
+        }
+
+        for emitter in self.emitters.iter() {
+            shapes.circle_outline(emitter.pos, 4.0, Stroke { color: Color::CYAN, ..thin });
+        }
     }
 }
 
@@ -36,78 +343,249 @@ enum PageState {
 
 struct Settings {
     fullscreen: bool,
+    scaling: screen::Scaling,
+    post: postfx::PostSettings,
+    lighting: bool,
+    // Debug shapes over the game, see `Game::gizmos`
+    gizmos: bool,
+}
+
+#[derive(Default)]
+struct Options {
+    record: Option<String>,
+    replay: Option<String>,
+    // Run without a window or rendering, exiting when the replay ends
+    headless: bool,
+}
+impl Options {
+    fn from_args() -> Self {
+        let mut options = Self::default();
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--record" => options.record = args.next(),
+                "--replay" => options.replay = args.next(),
+                "--headless" => options.headless = true,
+                _ => eprintln!("Warning: Unknown argument [{arg}]"),
+            }
+        }
+
+        // Nothing would ever stop it otherwise
+        if options.headless && options.replay.is_none() {
+            eprintln!("Error: --headless needs a --replay to run");
+            std::process::exit(2);
+        }
+        options
+    }
 }
 
 struct App {
-    window: Window,
+    // None when headless
+    window: Option<Window>,
     gfx: Graphics,
-    glyph: glyph::GlyphRenderer,
     ui: gui::UserInterface,
+    scene: scene::Scene,
+    graph: RenderGraph<scene::Scene>,
 
     input: input::Input,
+    tweens: tween::Tweener,
+    // Written by the menu tweens
+    title_offset: Rc<Cell<f32>>,
+    buttons_offset: Rc<Cell<f32>>,
+    cursor_color: Rc<Cell<color::Color>>,
     page: PageState,
+    // Only while the game page is open
+    game: Option<Game>,
     settings: Settings,
+
+    last_frame: std::time::Instant,
 }
 impl App {
-    async fn new(event_loop: &winit::event_loop::EventLoop<()>) -> Self {
-        let settings = Settings { fullscreen: false };
+    // Size of the game layer when there is no window to take it from
+    const HEADLESS_SIZE: (u32, u32) = (1280, 720);
 
-        let window = winit::window::WindowBuilder::new()
+    fn create_window(event_loop: &winit::event_loop::EventLoop<()>) -> Window {
+        winit::window::WindowBuilder::new()
             .with_title("Hello Winit")
             .with_min_inner_size(winit::dpi::PhysicalSize::new(600, 600))
             .with_resizable(true)
-            .build(&event_loop)
-            .expect("Unable to create window");
+            .build(event_loop)
+            .expect("Unable to create window")
+    }
 
-        let gfx = Graphics::new(&window).await;
+    async fn new(window: Option<Window>, options: Options) -> Self {
+        let settings = Settings {
+            fullscreen: false,
+            scaling: screen::Scaling::default(),
+            post: postfx::PostSettings::default(),
+            lighting: true,
+            gizmos: false,
+        };
 
-        let glyph = glyph::GlyphRenderer::new(&gfx);
-        //let spriter = SpriteRenderer::new(&gfx);
+        let mut input = input::Input::new();
+        if let Some(path) = &options.replay {
+            let replay = replay::Replay::load(path).unwrap_or_else(|err| {
+                eprintln!("Error: {err}");
+                std::process::exit(1);
+            });
+            input.mode = input::InputMode::Replay(replay);
+        } else if let Some(path) = &options.record {
+            input.mode = input::InputMode::Record(replay::Recorder::new(path));
+        }
 
-        let ui = gui::UserInterface::new();
-        let input = input::Input::new();
+        let gfx = match &window {
+            Some(window) => Graphics::new(window).await,
+            None => Graphics::headless(Self::HEADLESS_SIZE).await,
+        };
+
+        let (scene, graph) = scene::Scene::new(&gfx, settings.scaling);
+
+        let mut ui = gui::UserInterface::new();
+        let whole = UVRect {
+            a: vec2(0.0, 0.0),
+            b: vec2(1.0, 1.0),
+        };
+        ui.frame = Some(nineslice::NineSlice::new(
+            whole,
+            vec2(12.0, 12.0),
+            nineslice::Insets::uniform(4.0),
+        ));
 
         let page = PageState::MainMenu;
 
-        Self {
+        let mut app = Self {
             window,
             gfx,
-            glyph,
             ui,
+            scene,
+            graph,
             input,
+            tweens: tween::Tweener::new(),
+            title_offset: Rc::new(Cell::new(0.0)),
+            buttons_offset: Rc::new(Cell::new(0.0)),
+            cursor_color: Rc::new(Cell::new(color::Color::ARCANE)),
             page,
+            game: None,
             settings,
+            last_frame: std::time::Instant::now(),
+        };
+        app.update_viewport();
+        app.start_tweens();
+        app.enter_page(page);
+        app
+    }
+
+    fn start_tweens(&mut self) {
+        use tween::{Ease, Looped, Repeat, Tween};
+
+        // One pulse out and back, forever
+        let pulse = Tween::new(color::Color::ARCANE, color::Color::MANA, 0.6)
+            .ease(Ease::QuadInOut)
+            .yoyo(true)
+            .repeat(Repeat::Times(2))
+            .target(&self.cursor_color);
+        self.tweens.add(Looped::new(pulse, Repeat::Forever));
+    }
+
+    fn enter_page(&mut self, page: PageState) {
+        use tween::{Delay, Ease, Parallel, Sequence, Tween};
+
+        if let Some(game) = self.game.take() {
+            game.leave(&mut self.scene);
+        }
+        if page == PageState::Game {
+            let mut game = Game::new();
+            game.enter(&self.gfx, &mut self.scene);
+            self.game = Some(game);
+        }
 
+        if page == PageState::MainMenu {
+            // Title drops in, then the buttons slide across after it
+            let title = Tween::new(0.5, 0.0, 0.6)
+                .ease(Ease::BounceOut)
+                .target(&self.title_offset);
+            let buttons = Tween::new(-1.0, 0.0, 0.5)
+                .ease(Ease::BackOut)
+                .target(&self.buttons_offset);
+            self.tweens.add(
+                Parallel::new()
+                    .with(title)
+                    .with(Sequence::new().then(Delay::new(0.25)).then(buttons)),
+            );
         }
     }
 
-    fn render(&mut self) -> Option<()> {
-        let frame = self.gfx.new_frame()?;
+    fn update_viewport(&mut self) {
+        if let Some(size) = self.input.replay_viewport {
+            // Whatever size the window ended up, the replay keeps its own
+            self.gfx.resize(Self::physical(size));
+            self.layout(size);
+            return;
+        }
+        let (size, scale) = match &self.window {
+            Some(window) => {
+                let size = window.inner_size();
+                ((size.width, size.height), window.scale_factor() as f32)
+            }
+            None => (self.gfx.get_size(), 1.0),
+        };
+        let size = vec2(size.0 as f32, size.1 as f32);
+        self.layout(size);
+        self.input.handle(InputEvent::Viewport(size, scale));
+    }
 
-        let mut encoder = self
-            .gfx
-            .device
-            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                label: Some("State.InitEncoder"),
-            });
-        {
-            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("State.RenderPass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &frame.view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                        store: true,
-                    },
-                })],
-                depth_stencil_attachment: None,
-            });
+    // Replays lay out for the viewport they were recorded at, resizing the
+    // window to match where there is one
+    fn follow_replay_viewport(&mut self) {
+        let Some(size) = self.input.replay_viewport else {
+            return;
+        };
+        let physical = Self::physical(size);
+        if (physical.width, physical.height) == self.gfx.get_size() {
+            return;
+        }
+        if let Some(window) = &self.window {
+            window.set_inner_size(physical);
+        }
+        self.gfx.resize(physical);
+        self.layout(size);
+    }
+    fn physical(size: Vec2<f32>) -> winit::dpi::PhysicalSize<u32> {
+        winit::dpi::PhysicalSize::new(size.x as u32, size.y as u32)
+    }
 
-            self.glyph.render(&self.gfx, &mut pass, &self.ui.glyphs);
+    // Sizes the game layer, camera and cursor mapping for a viewport of
+    // `size` physical pixels
+    fn layout(&mut self, size: Vec2<f32>) {
+        let scaling = self.settings.scaling;
+        self.scene.camera.viewport = scaling.target_size(size);
+        self.scene.scaling = scaling;
+        self.graph
+            .set_target_size(self.scene.color, scene::Scene::target_size(&scaling));
+        self.input.mouse.set_scaling(scaling);
+    }
+
+    fn set_cursor_visible(&self, visible: bool) {
+        if let Some(window) = &self.window {
+            window.set_cursor_visible(visible);
+        }
+    }
+    fn request_redraw(&self) {
+        if let Some(window) = &self.window {
+            window.request_redraw();
         }
+    }
 
-        self.gfx.queue.submit([encoder.finish()]);
+    fn render(&mut self) -> Option<()> {
+        let frame = self.gfx.new_frame()?;
+
+        // Hand this frame's interface over, the old buffer is cleared on update
+        std::mem::swap(&mut self.scene.ui_glyphs, &mut self.ui.glyphs);
+        std::mem::swap(self.scene.ui_sprites.sprites_mut(), &mut self.ui.sprites);
+        self.scene.post = self.settings.post;
+        self.scene.lighting.enabled = self.settings.lighting;
+        self.graph.run(&self.gfx, &frame.view, &mut self.scene);
         frame.present();
 
         Some(())
@@ -122,12 +600,12 @@ impl App {
                 let left = f32::min(1.0, 52.0 * fx);
                 let top = f32::min(1.0, 16.0 * fy);
 
-                self.ui.anchor = vec2(-left, top);
+                self.ui.anchor = vec2(-left, top + self.title_offset.get());
                 self.ui.set_fontsize(12.0);
                 self.ui.label("Geomagika");
 
-                self.ui.anchor.y += -2.0 * fx;
-                self.ui.anchor.x += 10.0 * fx;
+                self.ui.anchor.y += -2.0 * fx - self.title_offset.get();
+                self.ui.anchor.x += 10.0 * fx + self.buttons_offset.get();
                 self.ui.set_fontsize(5.0);
                 
                 if self.ui.button("New Game").clicked {
@@ -156,23 +634,57 @@ impl App {
                 }
             }
             PageState::Game => {
+                let casts = self.game.as_ref().map_or(0, |game| game.casts);
+                let corner = vec2(-1.0 + 2.0 * fx, 1.0 - 2.0 * fy);
+                self.ui.panel(Rect::sized(corner, vec2(44.0 * fx, -8.0 * fy)));
+                self.ui.anchor = corner + vec2(4.0 * fx, -2.0 * fy);
+                self.ui.set_fontsize(4.0);
+                self.ui.label(format!("Casts: {casts}").as_str());
+
                 self.ui.anchor = vec2(1.0, -1.0);
-                if self.ui.button("Quit").clicked {
+                let quit = self.ui.button("Quit");
+                if quit.clicked {
                     return PageState::MainMenu;
                 }
+                // Clicks on the interface are not walked to
+                if let Some(game) = &mut self.game {
+                    if !quit.hover && self.input.mouse.left.pressed() {
+                        game.target = Some(self.input.mouse.world(&self.scene.camera));
+                    }
+                }
             }
             PageState::Settings => {
                 self.ui.anchor = vec2(0.0, 0.0);
                 self.ui.set_fontsize(5.0);
 
                 if self.ui.button("Fullscreen").clicked {
-                    if !self.settings.fullscreen {
-                        self.window
-                            .set_fullscreen(Some(winit::window::Fullscreen::Borderless(None)));
-                    } else {
-                        self.window.set_fullscreen(None);
-                    }
                     self.settings.fullscreen = !self.settings.fullscreen;
+                    if let Some(window) = &self.window {
+                        let fullscreen = winit::window::Fullscreen::Borderless(None);
+                        window.set_fullscreen(self.settings.fullscreen.then_some(fullscreen));
+                    }
+                }
+                let scaling = format!("Scaling: {}", self.settings.scaling.mode.name());
+                if self.ui.button(gui::Text::with_id(&scaling, "Scaling")).clicked {
+                    self.settings.scaling.mode = self.settings.scaling.mode.next();
+                    self.update_viewport();
+                }
+
+                let post = &mut self.settings.post;
+                let effects = [
+                    ("Lighting", &mut self.settings.lighting),
+                    ("Bloom", &mut post.bloom.enabled),
+                    ("CRT", &mut post.crt.enabled),
+                    ("Vignette", &mut post.vignette.enabled),
+                    ("Aberration", &mut post.aberration.enabled),
+                    ("Grading", &mut post.grading.enabled),
+                    ("Gizmos", &mut self.settings.gizmos),
+                ];
+                for (name, enabled) in effects {
+                    let label = format!("{name}: {}", if *enabled { "On" } else { "Off" });
+                    if self.ui.button(gui::Text::with_id(&label, name)).clicked {
+                        *enabled = !*enabled;
+                    }
                 }
                 if self.ui.button("Back").clicked {
                     return PageState::MainMenu;
@@ -180,69 +692,108 @@ impl App {
             }
             PageState::Exit => {}
         }
-        return self.page;
+        self.page
     }
 
     fn update(&mut self) -> PageState {
-        //self.input.mouse.refresh();
+        let now = std::time::Instant::now();
+        let dt = (now - self.last_frame).as_secs_f32();
+        self.last_frame = now;
+
+        let dt = self.input.update(dt);
+        self.follow_replay_viewport();
+        self.tweens.update(dt);
+        self.scene.camera.update(dt);
+        if let Some(game) = &mut self.game {
+            game.update(dt, &self.input, &mut self.scene);
+        }
+        if let Some(tilemap) = &mut self.scene.tilemap {
+            tilemap.update(dt);
+        }
+        self.scene.shapes.clear();
+        // About a pixel of the game layer
+        self.scene.shapes.feather = 1.0 / self.scene.camera.zoom;
+        if let Some(game) = self.game.as_ref().filter(|_| self.settings.gizmos) {
+            game.gizmos(&mut self.scene);
+        }
         self.ui.mouse = self.input.mouse.clone();
 
         self.ui.glyphs.clear();
-        self.ui.glyph_unit = self.glyph.get_scale(self.gfx.get_size());
-
-        let cursor = crate::glyph::Glyph {
-            pos: vec3(self.input.mouse.pos.x, self.input.mouse.pos.y, 0.0),
+        self.ui.sprites.clear();
+        let size = self.gfx.get_size();
+        self.ui.glyph_unit = self.scene.glyph.get_scale(size);
+        self.ui.pixel = vec2(2.0 / size.0 as f32, 2.0 / size.1 as f32);
+
+        let cursor = glyph::Glyph {
+            pos: vec3(
+                self.input.mouse.pos.x,
+                self.input.mouse.pos.y,
+                Layer::Ui.depth(1.0),
+            ),
             codepoint: b'^' as u32,
             scale: self.ui.glyph_size,
-            color: vec4(0.6, 0.3, 0.8, 1.0),
+            color: self.cursor_color.get(),
         };
 
         self.ui.glyphs.push(cursor);
 
-        self.page = self.pager();
+        let page = self.pager();
+        if page != self.page {
+            self.enter_page(page);
+        }
+        self.page = page;
+        self.input.end_frame();
         self.page
     }
 }
 
 fn main() {
+    let options = Options::from_args();
+    if options.headless {
+        let mut app = pollster::block_on(App::new(None, options));
+        while !app.input.replay_finished() && app.update() != PageState::Exit {}
+        return;
+    }
+
     let event_loop = winit::event_loop::EventLoop::new();
-    let mut app = pollster::block_on(App::new(&event_loop));
+    let window = App::create_window(&event_loop);
+    let window_id = window.id();
+    let mut app = pollster::block_on(App::new(Some(window), options));
 
     use winit::event::{Event, WindowEvent};
     use winit::event_loop::ControlFlow;
 
     event_loop.run(move |event, _target, flow| match event {
-        Event::WindowEvent { window_id, event } if app.window.id() == window_id => match event {
+        Event::WindowEvent { window_id: id, event } if id == window_id => match event {
             WindowEvent::CloseRequested => *flow = ControlFlow::Exit,
-            WindowEvent::Resized(new_size) => app.gfx.resize(new_size),
+            WindowEvent::Resized(new_size) => {
+                app.gfx.resize(new_size);
+                app.update_viewport();
+            }
             WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                app.gfx.resize(*new_inner_size)
-            }
-            WindowEvent::KeyboardInput {
-                input: KeyboardInput {
-                    virtual_keycode, state, ..
-                },
-                ..
-            } => {}
+                app.gfx.resize(*new_inner_size);
+                app.update_viewport();
+            }
             WindowEvent::CursorMoved { position, .. } => {
-                let size = app.window.inner_size();
-
-                let x = 2.0 * position.x / size.width as f64 - 1.0;
-                let y = 1.0 - 2.0 * position.y / size.height as f64;
-
-                app.input.mouse.set_pos(vec2(x as f32, y as f32));
+                let pixels = vec2(position.x as f32, position.y as f32);
+                app.input.handle(InputEvent::CursorMoved(pixels));
             }
             WindowEvent::MouseInput { state, button, .. } => {
-                app.input.mouse.set_state(state, button);
+                app.input.handle(InputEvent::MouseButton(button, state));
+            }
+            WindowEvent::MouseWheel { delta, .. } => app.input.handle(InputEvent::Scroll(delta)),
+            WindowEvent::Touch(touch) => {
+                let touch = touch::TouchEvent::from_winit(&touch);
+                app.input.handle(InputEvent::Touch(touch));
             }
-            WindowEvent::CursorEntered { .. } => app.window.set_cursor_visible(false),
-            WindowEvent::CursorLeft { .. } => app.window.set_cursor_visible(true),
+            WindowEvent::CursorEntered { .. } => app.set_cursor_visible(false),
+            WindowEvent::CursorLeft { .. } => app.set_cursor_visible(true),
             _ => {}
         },
         Event::MainEventsCleared => {
             if app.update() != PageState::Exit {
                 app.render();
-                app.window.request_redraw();
+                app.request_redraw();
             } else {
                 *flow = ControlFlow::Exit
             }
@@ -0,0 +1,261 @@
+use crate::color::Color;
+use crate::geometry::Rect;
+use crate::nvec::*;
+use crate::sprite::{Sprite, UVRect};
+
+// Border widths in texels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Insets {
+    pub left: f32,
+    pub right: f32,
+    pub top: f32,
+    pub bottom: f32,
+}
+impl Insets {
+    pub fn uniform(width: f32) -> Self {
+        Self {
+            left: width,
+            right: width,
+            top: width,
+            bottom: width,
+        }
+    }
+}
+
+// Atlas region drawn at any size. Corners keep their size, edges stretch
+// along one axis and the centre along both
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NineSlice {
+    pub rect: UVRect,
+    // Texels covered by `rect`
+    pub size: Vec2<f32>,
+    pub insets: Insets,
+}
+impl NineSlice {
+    pub fn new(rect: UVRect, size: Vec2<f32>, insets: Insets) -> Self {
+        Self { rect, size, insets }
+    }
+
+    // Adds the sprites covering `bounds`, where `unit` is the size of one
+    // texel. Borders shrink evenly when `bounds` is smaller than them
+    pub fn push(
+        &self,
+        out: &mut Vec<Sprite>,
+        bounds: Rect,
+        unit: Vec2<f32>,
+        depth: f32,
+        color: Color,
+    ) {
+        let insets = self.insets;
+        let low = vec2(insets.left, insets.bottom) * unit;
+        let high = vec2(insets.right, insets.top) * unit;
+        let borders = low + high;
+        let fit = vec2(
+            (bounds.size().x / borders.x).min(1.0),
+            (bounds.size().y / borders.y).min(1.0),
+        )
+        .map(|fit| if fit.is_finite() { fit } else { 1.0 });
+        let (low, high) = (low * fit, high * fit);
+
+        // Cell edges from left to right and bottom to top
+        let xs = [
+            bounds.min.x,
+            bounds.min.x + low.x,
+            bounds.max.x - high.x,
+            bounds.max.x,
+        ];
+        let ys = [
+            bounds.min.y,
+            bounds.min.y + low.y,
+            bounds.max.y - high.y,
+            bounds.max.y,
+        ];
+
+        // The same edges in the atlas, where v grows downwards
+        let (a, b) = (self.rect.a, self.rect.b);
+        let texel = (b - a) / self.size;
+        let us = [
+            a.x,
+            a.x + insets.left * texel.x,
+            b.x - insets.right * texel.x,
+            b.x,
+        ];
+        let vs = [
+            b.y,
+            b.y - insets.bottom * texel.y,
+            a.y + insets.top * texel.y,
+            a.y,
+        ];
+
+        for row in 0..3 {
+            for column in 0..3 {
+                let cell = Rect::from_corners(
+                    vec2(xs[column], ys[row]),
+                    vec2(xs[column + 1], ys[row + 1]),
+                );
+                let size = cell.size();
+                if size.x <= 0.0 || size.y <= 0.0 {
+                    continue;
+                }
+
+                let center = cell.center();
+                let rect = UVRect {
+                    a: vec2(us[column], vs[row + 1]),
+                    b: vec2(us[column + 1], vs[row]),
+                };
+                out.push(Sprite::new(
+                    vec3(center.x, center.y, depth),
+                    size * 0.5,
+                    color,
+                    rect,
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 16x16 texel region in the right half of the atlas
+    fn frame() -> NineSlice {
+        NineSlice::new(
+            UVRect {
+                a: vec2(0.5, 0.25),
+                b: vec2(1.0, 0.75),
+            },
+            vec2(16.0, 16.0),
+            Insets {
+                left: 2.0,
+                right: 4.0,
+                top: 3.0,
+                bottom: 1.0,
+            },
+        )
+    }
+    fn push(slice: &NineSlice, bounds: Rect, unit: Vec2<f32>) -> Vec<Sprite> {
+        let mut out = Vec::new();
+        slice.push(&mut out, bounds, unit, 0.25, Color::ARCANE);
+        out
+    }
+    fn cell(sprite: &Sprite) -> Rect {
+        let center = vec2(sprite.pos.x, sprite.pos.y);
+        Rect::centered(center, sprite.scale)
+    }
+    fn area(sprites: &[Sprite]) -> f32 {
+        sprites
+            .iter()
+            .map(|sprite| cell(sprite).size().x * cell(sprite).size().y)
+            .sum()
+    }
+
+    #[test]
+    fn cells_cover_the_bounds() {
+        let bounds = Rect::from_corners(vec2(0.0, 0.0), vec2(40.0, 20.0));
+        let sprites = push(&frame(), bounds, vec2(1.0, 1.0));
+        assert_eq!(sprites.len(), 9);
+        assert_eq!(area(&sprites), 800.0);
+        assert!(sprites
+            .iter()
+            .all(|sprite| sprite.pos.z == 0.25 && sprite.color == Color::ARCANE));
+
+        // Bottom row first, left to right, with corners at their own size
+        let corners = [&sprites[0], &sprites[2], &sprites[6], &sprites[8]].map(cell);
+        assert_eq!(
+            corners[0],
+            Rect::from_corners(vec2(0.0, 0.0), vec2(2.0, 1.0))
+        );
+        assert_eq!(
+            corners[1],
+            Rect::from_corners(vec2(36.0, 0.0), vec2(40.0, 1.0))
+        );
+        assert_eq!(
+            corners[2],
+            Rect::from_corners(vec2(0.0, 17.0), vec2(2.0, 20.0))
+        );
+        assert_eq!(
+            corners[3],
+            Rect::from_corners(vec2(36.0, 17.0), vec2(40.0, 20.0))
+        );
+        assert_eq!(
+            cell(&sprites[4]),
+            Rect::from_corners(vec2(2.0, 1.0), vec2(36.0, 17.0))
+        );
+    }
+
+    #[test]
+    fn uv_edges_follow_the_insets() {
+        let bounds = Rect::from_corners(vec2(0.0, 0.0), vec2(40.0, 20.0));
+        let sprites = push(&frame(), bounds, vec2(1.0, 1.0));
+        // Texels are 1/32 of the atlas, and v grows downwards so the bottom
+        // row comes from the bottom of the region
+        let uv = |ax: f32, ay: f32, bx: f32, by: f32| UVRect {
+            a: vec2(ax, ay),
+            b: vec2(bx, by),
+        };
+        assert_eq!(sprites[0].rect, uv(0.5, 0.71875, 0.5625, 0.75));
+        assert_eq!(sprites[4].rect, uv(0.5625, 0.34375, 0.875, 0.71875));
+        assert_eq!(sprites[8].rect, uv(0.875, 0.25, 1.0, 0.34375));
+        assert_eq!(sprites[1].rect, uv(0.5625, 0.71875, 0.875, 0.75));
+        assert_eq!(sprites[3].rect, uv(0.5, 0.34375, 0.5625, 0.71875));
+    }
+
+    #[test]
+    fn unit_scales_the_borders() {
+        let bounds = Rect::from_corners(vec2(10.0, 10.0), vec2(50.0, 30.0));
+        let sprites = push(&frame(), bounds, vec2(2.0, 0.5));
+        assert_eq!(
+            cell(&sprites[0]),
+            Rect::from_corners(vec2(10.0, 10.0), vec2(14.0, 10.5))
+        );
+        assert_eq!(
+            cell(&sprites[8]),
+            Rect::from_corners(vec2(42.0, 28.5), vec2(50.0, 30.0))
+        );
+    }
+
+    #[test]
+    fn borders_shrink_in_small_bounds() {
+        // Half the width of the left and right borders, so the centre
+        // column vanishes and the borders keep their proportions
+        let bounds = Rect::from_corners(vec2(0.0, 0.0), vec2(3.0, 20.0));
+        let sprites = push(&frame(), bounds, vec2(1.0, 1.0));
+        assert_eq!(sprites.len(), 6);
+        assert_eq!(area(&sprites), 60.0);
+        assert_eq!(
+            cell(&sprites[0]),
+            Rect::from_corners(vec2(0.0, 0.0), vec2(1.0, 1.0))
+        );
+        assert_eq!(
+            cell(&sprites[1]),
+            Rect::from_corners(vec2(1.0, 0.0), vec2(3.0, 1.0))
+        );
+        // The UVs still hold the whole corner
+        assert_eq!(sprites[0].rect.b.x, 0.5625);
+
+        let sprites = push(
+            &frame(),
+            Rect::from_corners(vec2(0.0, 0.0), vec2(3.0, 2.0)),
+            vec2(1.0, 1.0),
+        );
+        assert_eq!(sprites.len(), 4);
+        assert_eq!(area(&sprites), 6.0);
+
+        let empty = Rect::from_corners(vec2(5.0, 5.0), vec2(5.0, 5.0));
+        assert!(push(&frame(), empty, vec2(1.0, 1.0)).is_empty());
+    }
+
+    #[test]
+    fn no_insets_is_one_stretched_sprite() {
+        let slice = NineSlice {
+            insets: Insets::uniform(0.0),
+            ..frame()
+        };
+        let bounds = Rect::from_corners(vec2(0.0, 0.0), vec2(40.0, 20.0));
+        let sprites = push(&slice, bounds, vec2(1.0, 1.0));
+        assert_eq!(sprites.len(), 1);
+        assert_eq!(cell(&sprites[0]), bounds);
+        assert_eq!(sprites[0].rect, slice.rect);
+    }
+}
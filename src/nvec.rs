@@ -1,4 +1,15 @@
-use std::ops::{Add, AddAssign, Sub, SubAssign};
+use std::hash::{Hash, Hasher};
+use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+
+macro_rules! sum {
+    ($x:expr) => ($x);
+    ($x:expr, $($rest:expr),+) => ($x + sum!($($rest),+));
+}
+
+macro_rules! count {
+    () => (0usize);
+    ($x:tt $($rest:tt)*) => (1usize + count!($($rest)*));
+}
 
 macro_rules! fixed_vector {
     ($vec:ident, $($i:ident),+) => {
@@ -8,6 +19,18 @@ macro_rules! fixed_vector {
             }
         }
 
+        impl<T: PartialEq> PartialEq for $vec<T> {
+            fn eq(&self, rhs: &Self) -> bool {
+                $(self.$i == rhs.$i)&&+
+            }
+        }
+        impl<T: Eq> Eq for $vec<T> {}
+        impl<T: Hash> Hash for $vec<T> {
+            fn hash<H: Hasher>(&self, state: &mut H) {
+                $(self.$i.hash(state);)+
+            }
+        }
+
         impl<T: Add<Output=T>> Add for $vec<T> {
             type Output = $vec<T>;
             fn add(self, rhs: Self) -> Self::Output {
@@ -20,6 +43,37 @@ macro_rules! fixed_vector {
                 Self { $($i: self.$i - rhs.$i),* }
             }
         }
+        impl<T: Mul<Output=T>> Mul for $vec<T> {
+            type Output = $vec<T>;
+            fn mul(self, rhs: Self) -> Self::Output {
+                Self { $($i: self.$i * rhs.$i),* }
+            }
+        }
+        impl<T: Div<Output=T>> Div for $vec<T> {
+            type Output = $vec<T>;
+            fn div(self, rhs: Self) -> Self::Output {
+                Self { $($i: self.$i / rhs.$i),* }
+            }
+        }
+        impl<T: Mul<Output=T> + Copy> Mul<T> for $vec<T> {
+            type Output = $vec<T>;
+            fn mul(self, rhs: T) -> Self::Output {
+                Self { $($i: self.$i * rhs),* }
+            }
+        }
+        impl<T: Div<Output=T> + Copy> Div<T> for $vec<T> {
+            type Output = $vec<T>;
+            fn div(self, rhs: T) -> Self::Output {
+                Self { $($i: self.$i / rhs),* }
+            }
+        }
+        impl<T: Neg<Output=T>> Neg for $vec<T> {
+            type Output = $vec<T>;
+            fn neg(self) -> Self::Output {
+                Self { $($i: -self.$i),* }
+            }
+        }
+
         impl<T: AddAssign> AddAssign for $vec<T> {
             fn add_assign(&mut self, rhs: Self) {
                 $(self.$i += rhs.$i);*
@@ -30,6 +84,121 @@ macro_rules! fixed_vector {
                 $(self.$i -= rhs.$i);*
             }
         }
+        impl<T: MulAssign> MulAssign for $vec<T> {
+            fn mul_assign(&mut self, rhs: Self) {
+                $(self.$i *= rhs.$i);*
+            }
+        }
+        impl<T: DivAssign> DivAssign for $vec<T> {
+            fn div_assign(&mut self, rhs: Self) {
+                $(self.$i /= rhs.$i);*
+            }
+        }
+        impl<T: MulAssign + Copy> MulAssign<T> for $vec<T> {
+            fn mul_assign(&mut self, rhs: T) {
+                $(self.$i *= rhs);*
+            }
+        }
+        impl<T: DivAssign + Copy> DivAssign<T> for $vec<T> {
+            fn div_assign(&mut self, rhs: T) {
+                $(self.$i /= rhs);*
+            }
+        }
+
+        impl<T: Copy> $vec<T> {
+            pub const fn splat(value: T) -> Self {
+                Self { $($i: value),* }
+            }
+
+            pub fn map<U>(self, f: impl Fn(T) -> U) -> $vec<U> {
+                $vec { $($i: f(self.$i)),* }
+            }
+            pub fn zip<U>(self, rhs: Self, f: impl Fn(T, T) -> U) -> $vec<U> {
+                $vec { $($i: f(self.$i, rhs.$i)),* }
+            }
+        }
+        impl<T: Copy + Add<Output=T> + Mul<Output=T>> $vec<T> {
+            pub fn dot(self, rhs: Self) -> T {
+                sum!($(self.$i * rhs.$i),+)
+            }
+            pub fn length_squared(self) -> T {
+                self.dot(self)
+            }
+        }
+        impl<T: Copy + PartialOrd> $vec<T> {
+            pub fn min(self, rhs: Self) -> Self {
+                Self { $($i: if rhs.$i < self.$i { rhs.$i } else { self.$i }),* }
+            }
+            pub fn max(self, rhs: Self) -> Self {
+                Self { $($i: if rhs.$i > self.$i { rhs.$i } else { self.$i }),* }
+            }
+            pub fn clamp(self, lo: Self, hi: Self) -> Self {
+                self.max(lo).min(hi)
+            }
+        }
+
+        impl<T> From<[T; count!($($i)+)]> for $vec<T> {
+            fn from([$($i),+]: [T; count!($($i)+)]) -> Self {
+                Self { $($i),+ }
+            }
+        }
+        impl<T> From<$vec<T>> for [T; count!($($i)+)] {
+            fn from(value: $vec<T>) -> Self {
+                [$(value.$i),+]
+            }
+        }
+    }
+}
+
+// Operations that need a square root or a sign
+macro_rules! float_vector {
+    ($vec:ident, $($T:ty),+) => {
+        $(
+            impl $vec<$T> {
+                pub fn length(self) -> $T {
+                    self.length_squared().sqrt()
+                }
+                pub fn distance(self, rhs: Self) -> $T {
+                    (rhs - self).length()
+                }
+                // Zero vectors stay zero instead of becoming NaN
+                pub fn normalize(self) -> Self {
+                    let length = self.length();
+                    if length > 0.0 {
+                        self / length
+                    } else {
+                        self
+                    }
+                }
+                pub fn lerp(self, rhs: Self, t: $T) -> Self {
+                    self + (rhs - self) * t
+                }
+                pub fn abs(self) -> Self {
+                    self.map(<$T>::abs)
+                }
+            }
+        )+
+    }
+}
+
+macro_rules! cast_vector {
+    ($vec:ident, $($T:ty),+) => {
+        $(
+            impl $vec<$T> {
+                pub fn as_f32(self) -> $vec<f32> {
+                    self.map(|v| v as f32)
+                }
+                pub fn as_f64(self) -> $vec<f64> {
+                    self.map(|v| v as f64)
+                }
+                pub fn as_i32(self) -> $vec<i32> {
+                    self.map(|v| v as i32)
+                }
+                pub fn as_u32(self) -> $vec<u32> {
+                    self.map(|v| v as u32)
+                }
+            }
+        )+
     }
 }
 
@@ -64,6 +233,74 @@ fixed_vector!(Vec2, x, y);
 fixed_vector!(Vec3, x, y, z);
 fixed_vector!(Vec4, x, y, z, w);
 
+float_vector!(Vec2, f32, f64);
+float_vector!(Vec3, f32, f64);
+float_vector!(Vec4, f32, f64);
+
+cast_vector!(Vec2, u8, u16, u32, u64, i32, f32, f64);
+cast_vector!(Vec3, u8, u16, u32, u64, i32, f32, f64);
+cast_vector!(Vec4, u8, u16, u32, u64, i32, f32, f64);
+
+impl<T: Copy + Mul<Output = T> + Sub<Output = T>> Vec2<T> {
+    // Z component of the 3D cross product, positive if rhs is counter-clockwise
+    pub fn perp_dot(self, rhs: Self) -> T {
+        self.x * rhs.y - self.y * rhs.x
+    }
+}
+impl<T: Neg<Output = T>> Vec2<T> {
+    // Rotated 90 degrees counter-clockwise
+    pub fn perp(self) -> Self {
+        Vec2 {
+            x: -self.y,
+            y: self.x,
+        }
+    }
+}
+impl<T: Copy + Mul<Output = T> + Sub<Output = T>> Vec3<T> {
+    pub fn cross(self, rhs: Self) -> Self {
+        Vec3 {
+            x: self.y * rhs.z - self.z * rhs.y,
+            y: self.z * rhs.x - self.x * rhs.z,
+            z: self.x * rhs.y - self.y * rhs.x,
+        }
+    }
+}
+
+impl<T> Vec2<T> {
+    pub fn extend(self, z: T) -> Vec3<T> {
+        Vec3 {
+            x: self.x,
+            y: self.y,
+            z,
+        }
+    }
+}
+impl<T> Vec3<T> {
+    pub fn extend(self, w: T) -> Vec4<T> {
+        Vec4 {
+            x: self.x,
+            y: self.y,
+            z: self.z,
+            w,
+        }
+    }
+    pub fn truncate(self) -> Vec2<T> {
+        Vec2 {
+            x: self.x,
+            y: self.y,
+        }
+    }
+}
+impl<T> Vec4<T> {
+    pub fn truncate(self) -> Vec3<T> {
+        Vec3 {
+            x: self.x,
+            y: self.y,
+            z: self.z,
+        }
+    }
+}
+
 pub const fn vec2<T>(x: T, y: T) -> Vec2<T> {
     Vec2 { x, y }
 }
@@ -87,4 +324,586 @@ macro_rules! derive_vectors {
         )+
     }
 }
-derive_vectors!(u8, u16, u32, u64, f32, f64);
\ No newline at end of file
+derive_vectors!(u8, u16, u32, u64, i32, f32, f64);
+
+// Column-major square matrices, matching the WGSL matNxN memory layout.
+// Mat3 has no padding, so it has to go through `Mat4::from` before being
+// written into a uniform buffer
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Mat2<T> {
+    pub x: Vec2<T>,
+    pub y: Vec2<T>,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Mat3<T> {
+    pub x: Vec3<T>,
+    pub y: Vec3<T>,
+    pub z: Vec3<T>,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Mat4<T> {
+    pub x: Vec4<T>,
+    pub y: Vec4<T>,
+    pub z: Vec4<T>,
+    pub w: Vec4<T>,
+}
+
+macro_rules! square_matrix {
+    ($mat:ident, $vec:ident, $($c:ident),+) => {
+        impl<T> $mat<T> {
+            pub const fn from_cols($($c: $vec<T>),+) -> Self {
+                Self { $($c),+ }
+            }
+        }
+
+        impl<T: Copy> $mat<T> {
+            pub fn to_cols_array(self) -> [[T; count!($($c)+)]; count!($($c)+)] {
+                [$(self.$c.into()),+]
+            }
+            pub fn from_cols_array(cols: [[T; count!($($c)+)]; count!($($c)+)]) -> Self {
+                let [$($c),+] = cols;
+                Self { $($c: $c.into()),+ }
+            }
+
+            pub fn transpose(self) -> Self {
+                let cols = self.to_cols_array();
+                Self::from_cols_array(std::array::from_fn(|i| {
+                    std::array::from_fn(|j| cols[j][i])
+                }))
+            }
+        }
+
+        impl<T: Copy + Add<Output = T> + Mul<Output = T>> Mul<$vec<T>> for $mat<T> {
+            type Output = $vec<T>;
+            fn mul(self, rhs: $vec<T>) -> Self::Output {
+                sum!($(self.$c * rhs.$c),+)
+            }
+        }
+        impl<T: Copy + Add<Output = T> + Mul<Output = T>> Mul for $mat<T> {
+            type Output = $mat<T>;
+            fn mul(self, rhs: Self) -> Self::Output {
+                Self { $($c: self * rhs.$c),+ }
+            }
+        }
+        impl<T: Copy + Add<Output = T> + Mul<Output = T>> MulAssign for $mat<T> {
+            fn mul_assign(&mut self, rhs: Self) {
+                *self = *self * rhs;
+            }
+        }
+    };
+}
+
+square_matrix!(Mat2, Vec2, x, y);
+square_matrix!(Mat3, Vec3, x, y, z);
+square_matrix!(Mat4, Vec4, x, y, z, w);
+
+// Scalar operations needed by the matrix helpers
+pub trait Float:
+    Copy
+    + PartialOrd
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+    + AddAssign
+    + SubAssign
+    + MulAssign
+    + DivAssign
+{
+    const ZERO: Self;
+    const ONE: Self;
+    const TWO: Self;
+    const EPSILON: Self;
+
+    fn abs(self) -> Self;
+    fn sqrt(self) -> Self;
+    fn tan(self) -> Self;
+    fn sin_cos(self) -> (Self, Self);
+}
+
+macro_rules! float {
+    ($($T:ty),+) => {
+        $(
+            impl Float for $T {
+                const ZERO: Self = 0.0;
+                const ONE: Self = 1.0;
+                const TWO: Self = 2.0;
+                const EPSILON: Self = <$T>::EPSILON;
+
+                fn abs(self) -> Self {
+                    <$T>::abs(self)
+                }
+                fn sqrt(self) -> Self {
+                    <$T>::sqrt(self)
+                }
+                fn tan(self) -> Self {
+                    <$T>::tan(self)
+                }
+                fn sin_cos(self) -> (Self, Self) {
+                    <$T>::sin_cos(self)
+                }
+            }
+        )+
+    }
+}
+float!(f32, f64);
+
+macro_rules! float_matrix {
+    ($mat:ident) => {
+        impl<T: Float> $mat<T> {
+            pub fn identity() -> Self {
+                Self::from_cols_array(std::array::from_fn(|i| {
+                    std::array::from_fn(|j| if i == j { T::ONE } else { T::ZERO })
+                }))
+            }
+
+            // Gauss-Jordan elimination with partial pivoting, also yields the determinant
+            fn eliminate(self) -> (T, Option<Self>) {
+                let mut a = self.to_cols_array();
+                let mut inv = Self::identity().to_cols_array();
+                let n = a.len();
+                let mut det = T::ONE;
+
+                // Pivots are compared against the largest element of their
+                // column, so uniformly tiny matrices aren't treated as singular
+                let mut tolerance = a[0];
+                for (col, tolerance) in tolerance.iter_mut().enumerate() {
+                    let mut largest = T::ZERO;
+                    for row in &a {
+                        if row[col].abs() > largest {
+                            largest = row[col].abs();
+                        }
+                    }
+                    *tolerance = largest * T::EPSILON;
+                }
+
+                // Columns are treated as rows, which inverts the transpose and
+                // therefore gives the columns of the inverse
+                for col in 0..n {
+                    let mut pivot = col;
+                    for row in col + 1..n {
+                        if a[row][col].abs() > a[pivot][col].abs() {
+                            pivot = row;
+                        }
+                    }
+                    if a[pivot][col].abs() <= tolerance[col] {
+                        return (T::ZERO, None);
+                    }
+                    if pivot != col {
+                        a.swap(pivot, col);
+                        inv.swap(pivot, col);
+                        det = -det;
+                    }
+
+                    let scale = a[col][col];
+                    det *= scale;
+                    for k in 0..n {
+                        a[col][k] /= scale;
+                        inv[col][k] /= scale;
+                    }
+
+                    for row in 0..n {
+                        if row != col {
+                            let factor = a[row][col];
+                            for k in 0..n {
+                                let (a_k, inv_k) = (a[col][k], inv[col][k]);
+                                a[row][k] -= factor * a_k;
+                                inv[row][k] -= factor * inv_k;
+                            }
+                        }
+                    }
+                }
+                (det, Some(Self::from_cols_array(inv)))
+            }
+
+            pub fn determinant(self) -> T {
+                self.eliminate().0
+            }
+            // None if the matrix is singular
+            pub fn inverse(self) -> Option<Self> {
+                self.eliminate().1
+            }
+        }
+    };
+}
+
+float_matrix!(Mat2);
+float_matrix!(Mat3);
+float_matrix!(Mat4);
+
+impl<T: Float> Mat2<T> {
+    pub fn rotation(angle: T) -> Self {
+        let (sin, cos) = angle.sin_cos();
+        Self::from_cols(vec2(cos, sin), vec2(-sin, cos))
+    }
+    pub fn scale(scale: Vec2<T>) -> Self {
+        Self::from_cols(vec2(scale.x, T::ZERO), vec2(T::ZERO, scale.y))
+    }
+}
+
+// 2D affine transforms in homogeneous coordinates
+impl<T: Float> Mat3<T> {
+    pub fn translation(offset: Vec2<T>) -> Self {
+        let (o, l) = (T::ZERO, T::ONE);
+        Self::from_cols(vec3(l, o, o), vec3(o, l, o), offset.extend(l))
+    }
+    pub fn rotation(angle: T) -> Self {
+        let (o, l) = (T::ZERO, T::ONE);
+        let (sin, cos) = angle.sin_cos();
+        Self::from_cols(vec3(cos, sin, o), vec3(-sin, cos, o), vec3(o, o, l))
+    }
+    pub fn scale(scale: Vec2<T>) -> Self {
+        let (o, l) = (T::ZERO, T::ONE);
+        Self::from_cols(vec3(scale.x, o, o), vec3(o, scale.y, o), vec3(o, o, l))
+    }
+
+    pub fn transform_point(self, point: Vec2<T>) -> Vec2<T> {
+        (self * point.extend(T::ONE)).truncate()
+    }
+    pub fn transform_vector(self, vector: Vec2<T>) -> Vec2<T> {
+        (self * vector.extend(T::ZERO)).truncate()
+    }
+}
+
+impl<T: Float> From<Mat3<T>> for Mat4<T> {
+    fn from(m: Mat3<T>) -> Self {
+        let (o, l) = (T::ZERO, T::ONE);
+        Self::from_cols(
+            vec4(m.x.x, m.x.y, o, m.x.z),
+            vec4(m.y.x, m.y.y, o, m.y.z),
+            vec4(o, o, l, o),
+            vec4(m.z.x, m.z.y, o, m.z.z),
+        )
+    }
+}
+
+// Right-handed, with clip space depth in [0, 1] as used by wgpu
+impl<T: Float> Mat4<T> {
+    pub fn translation(offset: Vec3<T>) -> Self {
+        let (o, l) = (T::ZERO, T::ONE);
+        Self::from_cols(
+            vec4(l, o, o, o),
+            vec4(o, l, o, o),
+            vec4(o, o, l, o),
+            offset.extend(l),
+        )
+    }
+    pub fn scale(scale: Vec3<T>) -> Self {
+        let (o, l) = (T::ZERO, T::ONE);
+        Self::from_cols(
+            vec4(scale.x, o, o, o),
+            vec4(o, scale.y, o, o),
+            vec4(o, o, scale.z, o),
+            vec4(o, o, o, l),
+        )
+    }
+    pub fn rotation_x(angle: T) -> Self {
+        let (o, l) = (T::ZERO, T::ONE);
+        let (sin, cos) = angle.sin_cos();
+        Self::from_cols(
+            vec4(l, o, o, o),
+            vec4(o, cos, sin, o),
+            vec4(o, -sin, cos, o),
+            vec4(o, o, o, l),
+        )
+    }
+    pub fn rotation_y(angle: T) -> Self {
+        let (o, l) = (T::ZERO, T::ONE);
+        let (sin, cos) = angle.sin_cos();
+        Self::from_cols(
+            vec4(cos, o, -sin, o),
+            vec4(o, l, o, o),
+            vec4(sin, o, cos, o),
+            vec4(o, o, o, l),
+        )
+    }
+    pub fn rotation_z(angle: T) -> Self {
+        let (o, l) = (T::ZERO, T::ONE);
+        let (sin, cos) = angle.sin_cos();
+        Self::from_cols(
+            vec4(cos, sin, o, o),
+            vec4(-sin, cos, o, o),
+            vec4(o, o, l, o),
+            vec4(o, o, o, l),
+        )
+    }
+    // Rotation around a unit axis
+    pub fn rotation(axis: Vec3<T>, angle: T) -> Self {
+        let (o, l) = (T::ZERO, T::ONE);
+        let (sin, cos) = angle.sin_cos();
+        let Vec3 { x, y, z } = axis;
+        let t = l - cos;
+        Self::from_cols(
+            vec4(t * x * x + cos, t * x * y + sin * z, t * x * z - sin * y, o),
+            vec4(t * x * y - sin * z, t * y * y + cos, t * y * z + sin * x, o),
+            vec4(t * x * z + sin * y, t * y * z - sin * x, t * z * z + cos, o),
+            vec4(o, o, o, l),
+        )
+    }
+
+    pub fn orthographic(left: T, right: T, bottom: T, top: T, near: T, far: T) -> Self {
+        let (o, l) = (T::ZERO, T::ONE);
+        let w = right - left;
+        let h = top - bottom;
+        let d = far - near;
+        Self::from_cols(
+            vec4(T::TWO / w, o, o, o),
+            vec4(o, T::TWO / h, o, o),
+            vec4(o, o, -l / d, o),
+            vec4(-(right + left) / w, -(top + bottom) / h, -near / d, l),
+        )
+    }
+    // Vertical field of view in radians
+    pub fn perspective(fov_y: T, aspect: T, near: T, far: T) -> Self {
+        let (o, l) = (T::ZERO, T::ONE);
+        let f = l / (fov_y / T::TWO).tan();
+        let d = near - far;
+        Self::from_cols(
+            vec4(f / aspect, o, o, o),
+            vec4(o, f, o, o),
+            vec4(o, o, far / d, -l),
+            vec4(o, o, near * far / d, o),
+        )
+    }
+
+    pub fn transform_point(self, point: Vec3<T>) -> Vec3<T> {
+        let p = self * point.extend(T::ONE);
+        p.truncate() / p.w
+    }
+    pub fn transform_vector(self, vector: Vec3<T>) -> Vec3<T> {
+        (self * vector.extend(T::ZERO)).truncate()
+    }
+}
+
+macro_rules! derive_matrices {
+    ($($T:ty),+) => {
+        $(
+            unsafe impl bytemuck::Zeroable for Mat2<$T> {}
+            unsafe impl bytemuck::Zeroable for Mat3<$T> {}
+            unsafe impl bytemuck::Zeroable for Mat4<$T> {}
+
+            unsafe impl bytemuck::Pod for Mat2<$T> {}
+            unsafe impl bytemuck::Pod for Mat3<$T> {}
+            unsafe impl bytemuck::Pod for Mat4<$T> {}
+        )+
+    }
+}
+derive_matrices!(f32, f64);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vector_ops() {
+        let a = vec3(1.0, 2.0, 3.0);
+        let b = vec3(4.0, -5.0, 6.0);
+        assert_eq!(a + b, vec3(5.0, -3.0, 9.0));
+        assert_eq!(a - b, vec3(-3.0, 7.0, -3.0));
+        assert_eq!(a * b, vec3(4.0, -10.0, 18.0));
+        assert_eq!(b / a, vec3(4.0, -2.5, 2.0));
+        assert_eq!(a * 2.0, vec3(2.0, 4.0, 6.0));
+        assert_eq!(a / 2.0, vec3(0.5, 1.0, 1.5));
+        assert_eq!(-a, vec3(-1.0, -2.0, -3.0));
+
+        let mut c = a;
+        c += b;
+        c -= a;
+        assert_eq!(c, b);
+        c *= 2.0;
+        c /= vec3(2.0, 2.0, 2.0);
+        assert_eq!(c, b);
+
+        assert_eq!(a.dot(b), 12.0);
+        assert_eq!(vec4(1, 2, 3, 4).length_squared(), 30);
+        assert_eq!(Vec2::splat(7u8), vec2(7, 7));
+        assert_eq!(<[f32; 3]>::from(a), [1.0, 2.0, 3.0]);
+        assert_eq!(Vec4::from([1, 2, 3, 4]), vec4(1, 2, 3, 4));
+        assert_eq!(a.extend(4.0).truncate(), a);
+        assert_eq!(vec2(1, 2).extend(3), vec3(1, 2, 3));
+    }
+
+    #[test]
+    fn float_vectors() {
+        let v = vec2(3.0f32, -4.0);
+        assert_eq!(v.length(), 5.0);
+        assert_eq!(v.distance(vec2(0.0, 0.0)), 5.0);
+        assert_eq!(v.normalize(), vec2(0.6, -0.8));
+        assert_eq!(v.abs(), vec2(3.0, 4.0));
+        // Zero stays zero rather than turning into NaN
+        assert_eq!(Vec3::<f64>::default().normalize(), vec3(0.0, 0.0, 0.0));
+
+        let a = vec2(0.0f64, 10.0);
+        let b = vec2(10.0, 20.0);
+        assert_eq!(a.lerp(b, 0.0), a);
+        assert_eq!(a.lerp(b, 1.0), b);
+        assert_eq!(a.lerp(b, 0.25), vec2(2.5, 12.5));
+        assert_eq!(a.lerp(b, 2.0), vec2(20.0, 30.0));
+    }
+
+    #[test]
+    fn min_max_clamp() {
+        let a = vec3(1.0, 5.0, -2.0);
+        let b = vec3(3.0, 2.0, -2.0);
+        assert_eq!(a.min(b), vec3(1.0, 2.0, -2.0));
+        assert_eq!(a.max(b), vec3(3.0, 5.0, -2.0));
+
+        let lo = Vec3::splat(0.0);
+        let hi = Vec3::splat(4.0);
+        assert_eq!(a.clamp(lo, hi), vec3(1.0, 4.0, 0.0));
+        assert_eq!(vec2(-7, 9).clamp(vec2(-1, -1), vec2(1, 1)), vec2(-1, 1));
+    }
+
+    #[test]
+    fn casts() {
+        let v = vec2(1.75f32, -2.5);
+        assert_eq!(v.as_i32(), vec2(1, -2));
+        assert_eq!(v.as_f64(), vec2(1.75, -2.5));
+        // Saturates like `as`
+        assert_eq!(v.as_u32(), vec2(1, 0));
+        assert_eq!(vec3(200u8, 0, 255).as_f32(), vec3(200.0, 0.0, 255.0));
+        assert_eq!(vec4(u64::MAX, 1, 2, 3).as_u32().x, u32::MAX);
+        assert_eq!(vec2(-3i32, 4).as_f32(), vec2(-3.0, 4.0));
+        assert_eq!(vec2(0.1f64, 0.2).as_f32(), vec2(0.1f32, 0.2f32));
+        assert_eq!(Vec2::<i32>::from([1, 2]).map(|v| v * 10), vec2(10, 20));
+        assert_eq!(vec2(1, 2).zip(vec2(3, 4), |a, b| a * b), vec2(3, 8));
+    }
+
+    #[test]
+    fn perp_and_cross() {
+        let x = vec2(1.0, 0.0);
+        let y = vec2(0.0, 1.0);
+        assert_eq!(x.perp(), y);
+        assert_eq!(x.perp().perp(), -x);
+        assert_eq!(x.perp_dot(y), 1.0);
+        assert_eq!(y.perp_dot(x), -1.0);
+        assert_eq!(x.perp_dot(x * 3.0), 0.0);
+        let v = vec2(2.0, 3.0);
+        assert_eq!(v.dot(v.perp()), 0.0);
+
+        let x = vec3(1.0, 0.0, 0.0);
+        let y = vec3(0.0, 1.0, 0.0);
+        let z = vec3(0.0, 0.0, 1.0);
+        assert_eq!(x.cross(y), z);
+        assert_eq!(y.cross(z), x);
+        assert_eq!(z.cross(x), y);
+        assert_eq!(y.cross(x), -z);
+        let a = vec3(1.0, 2.0, 3.0);
+        let b = vec3(-2.0, 0.5, 4.0);
+        assert_eq!(a.cross(b).dot(a), 0.0);
+        assert_eq!(a.cross(b).dot(b), 0.0);
+    }
+
+    #[test]
+    fn integer_vectors() {
+        let a = vec2(3i32, -4);
+        let b = vec2(-1i32, 2);
+        assert_eq!(a + b, vec2(2, -2));
+        assert_eq!(a * b, vec2(-3, -8));
+        assert_eq!(a / 2, vec2(1, -2));
+        assert_eq!(-a, vec2(-3, 4));
+        assert_eq!(a.dot(b), -11);
+        assert_eq!(a.perp(), vec2(4, 3));
+        assert_eq!(a.perp_dot(b), 2);
+        assert_eq!(vec3(1, 0, 0).cross(vec3(0, 1, 0)), vec3(0, 0, 1));
+        assert_eq!(a.min(b), vec2(-1, -4));
+
+        // Usable as map keys, e.g. for grid cells
+        let mut cells = std::collections::HashSet::new();
+        cells.insert(vec2(-1i32, 5));
+        assert!(cells.contains(&vec2(-1, 5)));
+        assert!(!cells.contains(&vec2(5, -1)));
+
+        let cell = vec2(1i32, -1);
+        assert_eq!(bytemuck::cast::<_, [i32; 2]>(cell), [1, -1]);
+    }
+
+    fn assert_near(a: Mat4<f64>, b: Mat4<f64>) {
+        let (a, b) = (a.to_cols_array(), b.to_cols_array());
+        for (a, b) in a.iter().flatten().zip(b.iter().flatten()) {
+            assert!((a - b).abs() < 1e-9, "{a} != {b}");
+        }
+    }
+
+    #[test]
+    fn inverse() {
+        let m = Mat4::translation(vec3(1.0, -2.0, 3.0))
+            * Mat4::rotation(vec3(1.0, 2.0, 2.0) / 3.0, 0.7)
+            * Mat4::scale(vec3(2.0, 0.5, 4.0));
+        let inv = m.inverse().unwrap();
+        assert_near(inv * m, Mat4::identity());
+        assert_near(m * inv, Mat4::identity());
+        assert!((m.determinant() - 4.0).abs() < 1e-9);
+
+        let m = Mat3::translation(vec2(5.0f32, 1.0))
+            * Mat3::rotation(1.0)
+            * Mat3::scale(vec2(3.0, 2.0));
+        let p = vec2(0.25, -4.0);
+        let back = m.inverse().unwrap().transform_point(m.transform_point(p));
+        assert!(back.distance(p) < 1e-5);
+
+        // Needs a row swap to find a pivot
+        let m = Mat2::from_cols(vec2(0.0, 1.0), vec2(1.0, 0.0));
+        assert_eq!(m.inverse(), Some(m));
+        assert_eq!(m.determinant(), -1.0);
+    }
+
+    #[test]
+    fn inverse_tolerance() {
+        // Tiny but well conditioned
+        let tiny = 1e-3f32 * 1e-3 * 1e-3;
+        let m = Mat4::scale(vec3(tiny, tiny, tiny));
+        let inv = m.inverse().unwrap();
+        assert!(((inv * m).x.x - 1.0).abs() < 1e-5);
+        assert!((inv.y.y * tiny - 1.0).abs() < 1e-5);
+
+        let m = Mat3::<f32>::scale(vec2(1.0, 0.0));
+        assert!(m.inverse().is_none());
+        assert_eq!(m.determinant(), 0.0);
+
+        // Singular only up to rounding
+        let m = Mat3::from_cols(
+            vec3(0.1f32, 0.2, 0.3),
+            vec3(0.4, 0.5, 0.6),
+            vec3(0.7, 0.8, 0.9),
+        );
+        assert!(m.inverse().is_none());
+        assert!(Mat4::<f64>::from_cols_array([[0.0; 4]; 4]).inverse().is_none());
+    }
+
+    #[test]
+    fn transpose() {
+        let m = Mat3::from_cols(vec3(1, 2, 3), vec3(4, 5, 6), vec3(7, 8, 9));
+        let t = m.transpose();
+        assert_eq!(t, Mat3::from_cols(vec3(1, 4, 7), vec3(2, 5, 8), vec3(3, 6, 9)));
+        assert_eq!(t.transpose(), m);
+
+        let r = Mat4::rotation_y(0.3f64);
+        assert_near(r.transpose(), r.inverse().unwrap());
+    }
+
+    #[test]
+    fn projection_depth() {
+        let ortho = Mat4::orthographic(-4.0, 4.0, -2.0, 2.0, 0.5, 10.0);
+        assert_near(ortho.inverse().unwrap() * ortho, Mat4::identity());
+        // Right-handed, looking down -z
+        let near = ortho.transform_point(vec3(-4.0, 2.0, -0.5));
+        let far = ortho.transform_point(vec3(4.0, -2.0, -10.0));
+        assert!(near.distance(vec3(-1.0, 1.0, 0.0)) < 1e-9);
+        assert!(far.distance(vec3(1.0, -1.0, 1.0)) < 1e-9);
+
+        let perspective = Mat4::perspective(std::f64::consts::FRAC_PI_2, 2.0, 0.1, 100.0);
+        let near = perspective.transform_point(vec3(0.2, 0.1, -0.1));
+        let far = perspective.transform_point(vec3(0.0, 0.0, -100.0));
+        assert!(near.distance(vec3(1.0, 1.0, 0.0)) < 1e-9);
+        assert!((far.z - 1.0).abs() < 1e-9);
+        let middle = perspective.transform_point(vec3(0.0, 0.0, -1.0)).z;
+        assert!(middle > 0.0 && middle < 1.0);
+    }
+}
@@ -0,0 +1,411 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+
+use crate::aseprite::Aseprite;
+use crate::nvec::*;
+use crate::sprite::UVRect;
+
+// Where a packed image ended up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AtlasEntry {
+    pub page: usize,
+    pub rect: UVRect,
+    // Pixels, without padding or extrusion
+    pub size: Vec2<u32>,
+}
+
+// Added to the packer, files are only read once it packs
+enum Source {
+    Image(String, image::RgbaImage),
+    File(String),
+}
+
+// Combines many images into as few pages as fit, so sprites of different
+// types can share a `SpriteGroup`
+pub struct AtlasPacker {
+    page_size: u32,
+    // Transparent pixels between images, outside the extrusion
+    padding: u32,
+    // Edge pixels repeated outwards, so filtering never reads a neighbour
+    extrude: u32,
+    sources: Vec<Source>,
+}
+impl AtlasPacker {
+    pub fn new(page_size: u32) -> Self {
+        Self {
+            page_size,
+            padding: 1,
+            extrude: 1,
+            sources: Vec::new(),
+        }
+    }
+    pub fn padding(mut self, padding: u32) -> Self {
+        self.padding = padding;
+        self
+    }
+    pub fn extrude(mut self, extrude: u32) -> Self {
+        self.extrude = extrude;
+        self
+    }
+
+    pub fn add(&mut self, name: &str, image: image::RgbaImage) {
+        self.sources.push(Source::Image(name.to_string(), image));
+    }
+
+    // Named after the file stem. Aseprite frames are added as "stem:0",
+    // "stem:1" and so on, or just "stem" with a single frame
+    pub fn add_file(&mut self, path: &str) {
+        self.sources.push(Source::File(path.to_string()));
+    }
+
+    fn read_file(path: &str, images: &mut Vec<(String, image::RgbaImage)>) {
+        let stem = std::path::Path::new(path)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or(path);
+
+        if path.ends_with(".ase") || path.ends_with(".aseprite") {
+            let ase = Aseprite::load(path);
+            for frame in 0..ase.frames.len() {
+                let name = match ase.frames.len() {
+                    1 => stem.to_string(),
+                    _ => format!("{stem}:{frame}"),
+                };
+                images.push((name, ase.frame_image(frame)));
+            }
+            return;
+        }
+
+        let image_file = std::fs::read(path).unwrap_or_else(|_| panic!("Cannot read {path}"));
+        let image = image::load_from_memory(&image_file)
+            .unwrap_or_else(|_| panic!("Could not parse file {path}"));
+        images.push((stem.to_string(), image.to_rgba8()));
+    }
+
+    // Changes whenever packing again could give a different result: the
+    // settings, the added images, or the size and modification time of the
+    // added files
+    pub fn key(&self) -> u64 {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        (self.page_size, self.padding, self.extrude).hash(&mut hasher);
+        for source in self.sources.iter() {
+            match source {
+                Source::Image(name, image) => {
+                    name.hash(&mut hasher);
+                    image.dimensions().hash(&mut hasher);
+                    image.as_raw().hash(&mut hasher);
+                }
+                Source::File(path) => {
+                    path.hash(&mut hasher);
+                    if let Ok(metadata) = std::fs::metadata(path) {
+                        metadata.len().hash(&mut hasher);
+                        metadata.modified().ok().hash(&mut hasher);
+                    }
+                }
+            }
+        }
+        hasher.finish()
+    }
+
+    // Shelf packing, tallest first. Each shelf fills left to right and a new
+    // page starts when no shelf has room
+    pub fn pack(self) -> PackedAtlas {
+        let key = self.key();
+        let border = self.padding + self.extrude;
+
+        let mut images = Vec::new();
+        for source in self.sources {
+            match source {
+                Source::Image(name, image) => images.push((name, image)),
+                Source::File(path) => Self::read_file(&path, &mut images),
+            }
+        }
+        // Names are how sprites look the images up, so only one can keep it
+        let mut names = HashSet::new();
+        images.retain(|(name, _)| {
+            let unique = names.insert(name.clone());
+            if !unique {
+                eprintln!("Warning: Atlas image {name} added more than once, keeping the first");
+            }
+            unique
+        });
+        images.sort_by(|a, b| b.1.height().cmp(&a.1.height()).then(a.0.cmp(&b.0)));
+
+        let mut pages: Vec<image::RgbaImage> = Vec::new();
+        let mut entries = HashMap::new();
+        // Cursor on the current page and the height of its open shelf
+        let mut cursor = vec2(0u32, 0u32);
+        let mut shelf = 0;
+
+        for (name, image) in images.iter() {
+            let size = vec2(image.width(), image.height());
+            if size.x == 0 || size.y == 0 {
+                continue;
+            }
+            let cell = size + vec2(border * 2, border * 2);
+            if cell.x > self.page_size || cell.y > self.page_size {
+                eprintln!(
+                    "Warning: {name} is {}x{}, too large for a {} atlas page",
+                    size.x, size.y, self.page_size
+                );
+                continue;
+            }
+
+            if cursor.x + cell.x > self.page_size {
+                cursor = vec2(0, cursor.y + shelf);
+                shelf = 0;
+            }
+            if pages.is_empty() || cursor.y + cell.y > self.page_size {
+                pages.push(image::RgbaImage::new(self.page_size, self.page_size));
+                cursor = vec2(0, 0);
+                shelf = 0;
+            }
+
+            let page = pages.last_mut().unwrap();
+            let origin = cursor + vec2(border, border);
+            Self::blit_extruded(page, image, origin, self.extrude);
+
+            let scale = 1.0 / self.page_size as f32;
+            let a = origin.as_f32() * scale;
+            entries.insert(
+                name.clone(),
+                AtlasEntry {
+                    page: pages.len() - 1,
+                    rect: UVRect {
+                        a,
+                        b: a + size.as_f32() * scale,
+                    },
+                    size,
+                },
+            );
+
+            cursor.x += cell.x;
+            shelf = shelf.max(cell.y);
+        }
+
+        PackedAtlas {
+            pages,
+            entries,
+            key,
+        }
+    }
+
+    fn blit_extruded(
+        page: &mut image::RgbaImage,
+        image: &image::RgbaImage,
+        origin: Vec2<u32>,
+        extrude: u32,
+    ) {
+        let (width, height) = (image.width() as i64, image.height() as i64);
+        let extrude = extrude as i64;
+        for y in -extrude..height + extrude {
+            for x in -extrude..width + extrude {
+                let source =
+                    image.get_pixel(x.clamp(0, width - 1) as u32, y.clamp(0, height - 1) as u32);
+                let target = (origin.x as i64 + x, origin.y as i64 + y);
+                page.put_pixel(target.0 as u32, target.1 as u32, *source);
+            }
+        }
+    }
+}
+
+pub struct PackedAtlas {
+    pub pages: Vec<image::RgbaImage>,
+    entries: HashMap<String, AtlasEntry>,
+    // `AtlasPacker::key` of the packer that made it
+    key: u64,
+}
+impl PackedAtlas {
+    pub fn get(&self, name: &str) -> Option<AtlasEntry> {
+        self.entries.get(name).copied()
+    }
+    pub fn rect(&self, name: &str) -> Option<UVRect> {
+        self.get(name).map(|entry| entry.rect)
+    }
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(|name| name.as_str())
+    }
+
+    // Writes `{base}.json` with the entries and `{base}.{page}.png` per page
+    pub fn save(&self, base: &str) {
+        for (index, page) in self.pages.iter().enumerate() {
+            if let Err(err) = page.save(format!("{base}.{index}.png")) {
+                eprintln!("Warning: Unable to save atlas page {base}.{index}.png [{err}]");
+                return;
+            }
+        }
+
+        let mut entries = json::JsonValue::new_object();
+        for (name, entry) in self.entries.iter() {
+            entries[name.as_str()] = json::object! {
+                page: entry.page,
+                rect: [entry.rect.a.x, entry.rect.a.y, entry.rect.b.x, entry.rect.b.y],
+                size: [entry.size.x, entry.size.y],
+            };
+        }
+        let index = json::object! {
+            pages: self.pages.len(),
+            key: format!("{:016x}", self.key),
+            entries: entries,
+        };
+        if let Err(err) = std::fs::write(format!("{base}.json"), index.pretty(2)) {
+            eprintln!("Warning: Unable to save atlas index {base}.json [{err}]");
+        }
+    }
+
+    pub fn load(base: &str) -> Option<Self> {
+        let text = std::fs::read_to_string(format!("{base}.json")).ok()?;
+        let index = json::parse(&text).ok()?;
+        let key = u64::from_str_radix(index["key"].as_str()?, 16).ok()?;
+
+        let pages = (0..index["pages"].as_usize()?)
+            .map(|page| {
+                let path = format!("{base}.{page}.png");
+                Some(image::open(path).ok()?.to_rgba8())
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        let entries = index["entries"]
+            .entries()
+            .map(|(name, entry)| {
+                let rect = &entry["rect"];
+                let entry = AtlasEntry {
+                    page: entry["page"].as_usize()?,
+                    rect: UVRect {
+                        a: vec2(rect[0].as_f32()?, rect[1].as_f32()?),
+                        b: vec2(rect[2].as_f32()?, rect[3].as_f32()?),
+                    },
+                    size: vec2(entry["size"][0].as_u32()?, entry["size"][1].as_u32()?),
+                };
+                Some((name.to_string(), entry))
+            })
+            .collect::<Option<HashMap<_, _>>>()?;
+
+        Some(Self {
+            pages,
+            entries,
+            key,
+        })
+    }
+
+    // Loads the packed result from `base` when it was packed from the same
+    // sources, otherwise packs and saves it for the next start
+    pub fn cached(base: &str, build: impl FnOnce() -> AtlasPacker) -> Self {
+        let packer = build();
+        let key = packer.key();
+        if let Some(atlas) = Self::load(base).filter(|atlas| atlas.key == key) {
+            return atlas;
+        }
+        let atlas = packer.pack();
+        atlas.save(base);
+        atlas
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(width: u32, height: u32, value: u8) -> image::RgbaImage {
+        image::RgbaImage::from_pixel(width, height, image::Rgba([value, value, value, 255]))
+    }
+
+    #[test]
+    fn shelves_tallest_first() {
+        let mut packer = AtlasPacker::new(32);
+        packer.add("small", solid(2, 2, 1));
+        packer.add("tall", solid(4, 6, 2));
+        packer.add("wide", solid(8, 4, 3));
+        packer.add("last", solid(10, 2, 4));
+        let atlas = packer.pack();
+        assert_eq!(atlas.pages.len(), 1);
+
+        // One pixel of padding and one of extrusion around each, equal
+        // heights by name
+        let origin = |name| (atlas.get(name).unwrap().rect.a * 32.0).map(f32::round);
+        assert_eq!(origin("tall"), vec2(2.0, 2.0));
+        assert_eq!(origin("wide"), vec2(10.0, 2.0));
+        // Starts the next shelf under the tallest of the first
+        assert_eq!(origin("last"), vec2(2.0, 12.0));
+        assert_eq!(origin("small"), vec2(16.0, 12.0));
+        assert_eq!(atlas.get("wide").unwrap().size, vec2(8, 4));
+    }
+
+    #[test]
+    fn new_page_when_full() {
+        let mut packer = AtlasPacker::new(8);
+        packer.add("a", solid(4, 4, 1));
+        packer.add("b", solid(4, 4, 2));
+        let atlas = packer.pack();
+        assert_eq!(atlas.pages.len(), 2);
+        assert_eq!(atlas.get("a").unwrap().page, 0);
+        assert_eq!(atlas.get("b").unwrap().page, 1);
+    }
+
+    #[test]
+    fn extrudes_edges() {
+        let mut image = solid(2, 2, 10);
+        image.put_pixel(1, 1, image::Rgba([200, 0, 0, 255]));
+        let mut packer = AtlasPacker::new(8).padding(0).extrude(2);
+        packer.add("image", image);
+        let atlas = packer.pack();
+
+        let page = &atlas.pages[0];
+        assert_eq!(page.get_pixel(0, 0).0, [10, 10, 10, 255]);
+        assert_eq!(page.get_pixel(5, 5).0, [200, 0, 0, 255]);
+        assert_eq!(page.get_pixel(3, 5).0, [200, 0, 0, 255]);
+        assert_eq!(page.get_pixel(6, 6).0, [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn duplicates_keep_first() {
+        let mut packer = AtlasPacker::new(32);
+        packer.add("same", solid(2, 2, 1));
+        packer.add("same", solid(4, 4, 2));
+        let atlas = packer.pack();
+        assert_eq!(atlas.names().count(), 1);
+        assert_eq!(atlas.get("same").unwrap().size, vec2(2, 2));
+    }
+
+    #[test]
+    fn key_follows_sources() {
+        let packer = |value| {
+            let mut packer = AtlasPacker::new(32);
+            packer.add("image", solid(2, 2, value));
+            packer
+        };
+        assert_eq!(packer(1).key(), packer(1).key());
+        assert_ne!(packer(1).key(), packer(2).key());
+        assert_ne!(packer(1).key(), packer(1).padding(3).key());
+    }
+
+    #[test]
+    fn cached_repacks_on_change() {
+        let dir = std::env::temp_dir().join(format!("packer_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("Source.png").to_str().unwrap().to_string();
+        let base = dir.join("Atlas").to_str().unwrap().to_string();
+        let build = || {
+            let mut packer = AtlasPacker::new(32);
+            packer.add_file(&source);
+            packer
+        };
+
+        solid(4, 4, 1).save(&source).unwrap();
+        let atlas = PackedAtlas::cached(&base, build);
+        assert_eq!(atlas.get("Source").unwrap().size, vec2(4, 4));
+
+        // Unchanged sources are read back rather than packed again
+        let marker = solid(32, 32, 99);
+        marker.save(format!("{base}.0.png")).unwrap();
+        let atlas = PackedAtlas::cached(&base, build);
+        assert_eq!(atlas.pages[0], marker);
+
+        solid(6, 6, 1).save(&source).unwrap();
+        let atlas = PackedAtlas::cached(&base, build);
+        assert_eq!(atlas.get("Source").unwrap().size, vec2(6, 6));
+        assert_ne!(atlas.pages[0], marker);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,771 @@
+use std::f32::consts::TAU;
+use std::rc::Rc;
+
+use crate::color::{rgba, Color};
+use crate::layer::Layer;
+use crate::nvec::*;
+use crate::sprite::{Sprite, SpriteGroup, UVRect};
+use crate::tween::Lerp;
+
+// Xorshift, good enough for scattering particles and cheap to seed per emitter
+#[derive(Clone, Copy, Debug)]
+pub struct Random(u32);
+impl Random {
+    pub fn new(seed: u32) -> Self {
+        // Zero would get stuck
+        Self(seed.max(1))
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        x
+    }
+    // Uniform in [0, 1)
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1 << 24) as f32
+    }
+    pub fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+}
+
+// Value picked uniformly between `min` and `max` for each particle
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Range {
+    pub min: f32,
+    pub max: f32,
+}
+impl Range {
+    pub fn new(min: f32, max: f32) -> Self {
+        Self { min, max }
+    }
+    pub fn constant(value: f32) -> Self {
+        Self::new(value, value)
+    }
+
+    pub fn sample(&self, random: &mut Random) -> f32 {
+        random.range(self.min, self.max)
+    }
+
+    // Accepts a number or [min, max]
+    pub fn from_json(value: &json::JsonValue) -> Option<Range> {
+        if let Some(value) = value.as_f32() {
+            return Some(Self::constant(value));
+        }
+        match value
+            .members()
+            .map(|v| v.as_f32())
+            .collect::<Option<Vec<_>>>()?[..]
+        {
+            [min, max] => Some(Self::new(min, max)),
+            _ => None,
+        }
+    }
+}
+
+// Keyframes over a particle's life, t in [0, 1], linearly interpolated
+#[derive(Clone, Debug, PartialEq)]
+pub struct Curve<T: Lerp> {
+    keys: Vec<(f32, T)>,
+}
+impl<T: Lerp> Curve<T> {
+    pub fn constant(value: T) -> Self {
+        Self {
+            keys: vec![(0.0, value)],
+        }
+    }
+    pub fn linear(from: T, to: T) -> Self {
+        Self {
+            keys: vec![(0.0, from), (1.0, to)],
+        }
+    }
+    pub fn new(mut keys: Vec<(f32, T)>) -> Self {
+        assert!(!keys.is_empty(), "Curve needs at least one key");
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self { keys }
+    }
+
+    pub fn sample(&self, t: f32) -> T {
+        let next = self.keys.iter().position(|(time, _)| *time > t);
+        match next {
+            Some(0) => self.keys[0].1,
+            Some(index) => {
+                let (start, from) = self.keys[index - 1];
+                let (end, to) = self.keys[index];
+                from.lerp(to, (t - start) / (end - start))
+            }
+            None => self.keys[self.keys.len() - 1].1,
+        }
+    }
+
+    // Accepts a single value, or [[t, value], ...] with keys spread evenly
+    // when given as a plain list of values
+    pub fn from_json(
+        value: &json::JsonValue,
+        parse: impl Fn(&json::JsonValue) -> Option<T>,
+    ) -> Option<Curve<T>> {
+        if let Some(value) = parse(value) {
+            return Some(Self::constant(value));
+        }
+        if !value.is_array() || value.is_empty() {
+            return None;
+        }
+
+        let count = value.len();
+        let keys = value
+            .members()
+            .enumerate()
+            .map(|(index, key)| match parse(key) {
+                Some(value) => Some((index as f32 / (count - 1).max(1) as f32, value)),
+                None => Some((key[0].as_f32()?, parse(&key[1])?)),
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self::new(keys))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EmitterShape {
+    Point,
+    // Anywhere inside
+    Circle(f32),
+    // On the edge only
+    Ring(f32),
+    Rect(Vec2<f32>),
+}
+impl EmitterShape {
+    fn sample(&self, random: &mut Random) -> Vec2<f32> {
+        let angle = random.range(0.0, TAU);
+        let (sin, cos) = angle.sin_cos();
+        match *self {
+            EmitterShape::Point => vec2(0.0, 0.0),
+            // Square root keeps the points evenly spread over the area
+            EmitterShape::Circle(radius) => vec2(cos, sin) * radius * random.next_f32().sqrt(),
+            EmitterShape::Ring(radius) => vec2(cos, sin) * radius,
+            EmitterShape::Rect(half_size) => {
+                vec2(random.range(-1.0, 1.0), random.range(-1.0, 1.0)) * half_size
+            }
+        }
+    }
+
+    // {"type": "circle", "radius": 4} and the like
+    fn from_json(value: &json::JsonValue) -> Option<EmitterShape> {
+        if value.is_null() {
+            return Some(EmitterShape::Point);
+        }
+        Some(match value["type"].as_str()? {
+            "point" => EmitterShape::Point,
+            "circle" => EmitterShape::Circle(value["radius"].as_f32()?),
+            "ring" => EmitterShape::Ring(value["radius"].as_f32()?),
+            "rect" => EmitterShape::Rect(vec2(
+                value["width"].as_f32()? * 0.5,
+                value["height"].as_f32()? * 0.5,
+            )),
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Burst {
+    // Seconds after the emitter starts, repeated each cycle when looping
+    pub time: f32,
+    pub count: u32,
+}
+
+// Frames played through over each particle's life, or at a fixed rate
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameAnimation {
+    pub frames: Vec<UVRect>,
+    // Zero spreads the frames over the lifetime
+    pub fps: f32,
+}
+impl FrameAnimation {
+    fn frame(&self, age: f32, life: f32) -> UVRect {
+        let count = self.frames.len();
+        let index = if self.fps > 0.0 {
+            (age * self.fps) as usize % count
+        } else {
+            ((life * count as f32) as usize).min(count - 1)
+        };
+        self.frames[index]
+    }
+
+    // Either explicit [[ax, ay, bx, by], ...] UV rects, or a grid of `count`
+    // cells of `size` read left to right from `origin`, `columns` per row
+    fn from_json(value: &json::JsonValue) -> Option<FrameAnimation> {
+        let fps = value["fps"].as_f32().unwrap_or(0.0);
+        let uv = |value: &json::JsonValue| Some(vec2(value[0].as_f32()?, value[1].as_f32()?));
+
+        let frames = if value["frames"].is_array() {
+            value["frames"]
+                .members()
+                .map(|rect| {
+                    Some(UVRect {
+                        a: vec2(rect[0].as_f32()?, rect[1].as_f32()?),
+                        b: vec2(rect[2].as_f32()?, rect[3].as_f32()?),
+                    })
+                })
+                .collect::<Option<Vec<_>>>()?
+        } else {
+            let origin = uv(&value["origin"]).unwrap_or(vec2(0.0, 0.0));
+            let size = uv(&value["size"])?;
+            let count = value["count"].as_usize()?;
+            let columns = value["columns"].as_usize().unwrap_or(count);
+            UVRect::grid(origin, size, count, columns)
+        };
+
+        if frames.is_empty() {
+            return None;
+        }
+        Some(Self { frames, fps })
+    }
+}
+
+// Everything about how an effect looks, shared by all emitters playing it
+#[derive(Clone, Debug, PartialEq)]
+pub struct EmitterDef {
+    pub shape: EmitterShape,
+    // Particles per second
+    pub rate: f32,
+    pub bursts: Vec<Burst>,
+    // Seconds of emission per cycle
+    pub duration: f32,
+    pub looping: bool,
+    pub max_particles: usize,
+
+    pub lifetime: Range,
+    pub speed: Range,
+    // Radians, 0 is +x. `spread` is the total width of the cone particles
+    // leave in, centred on `direction`
+    pub direction: f32,
+    pub spread: f32,
+    pub gravity: Vec2<f32>,
+    // Fraction of velocity lost per second
+    pub drag: f32,
+
+    // Half extents, like `Sprite::scale`
+    pub size: Vec2<f32>,
+    pub size_over_life: Curve<f32>,
+    pub color_over_life: Curve<Color>,
+    pub animation: FrameAnimation,
+    // Order within `Layer::Particles`
+    pub z: f32,
+}
+impl Default for EmitterDef {
+    fn default() -> Self {
+        Self {
+            shape: EmitterShape::Point,
+            rate: 10.0,
+            bursts: Vec::new(),
+            duration: 1.0,
+            looping: true,
+            max_particles: 256,
+            lifetime: Range::constant(1.0),
+            speed: Range::constant(0.0),
+            direction: 0.0,
+            spread: TAU,
+            gravity: vec2(0.0, 0.0),
+            drag: 0.0,
+            size: vec2(1.0, 1.0),
+            size_over_life: Curve::constant(1.0),
+            color_over_life: Curve::constant(rgba(1.0, 1.0, 1.0, 1.0)),
+            animation: FrameAnimation {
+                frames: vec![UVRect {
+                    a: vec2(0.0, 0.0),
+                    b: vec2(1.0, 1.0),
+                }],
+                fps: 0.0,
+            },
+            z: 0.5,
+        }
+    }
+}
+impl EmitterDef {
+    // Missing fields take their default, angles are given in degrees
+    pub fn from_json(value: &json::JsonValue) -> Option<EmitterDef> {
+        let defaults = Self::default();
+        let f32_field = |name: &str, default: f32| match &value[name] {
+            json::JsonValue::Null => Some(default),
+            field => field.as_f32(),
+        };
+        let range_field = |name: &str, default: Range| match &value[name] {
+            json::JsonValue::Null => Some(default),
+            field => Range::from_json(field),
+        };
+        let vec2_field = |name: &str, default: Vec2<f32>| match &value[name] {
+            json::JsonValue::Null => Some(default),
+            field => Some(vec2(field[0].as_f32()?, field[1].as_f32()?)),
+        };
+
+        let bursts = value["bursts"]
+            .members()
+            .map(|burst| {
+                Some(Burst {
+                    time: burst["time"].as_f32().unwrap_or(0.0),
+                    count: burst["count"].as_u32()?,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        let size_over_life = match &value["size_over_life"] {
+            json::JsonValue::Null => defaults.size_over_life,
+            field => Curve::from_json(field, |v| v.as_f32())?,
+        };
+        let color_over_life = match &value["color_over_life"] {
+            json::JsonValue::Null => defaults.color_over_life,
+            field => Curve::from_json(field, Color::from_json)?,
+        };
+        let animation = match &value["animation"] {
+            json::JsonValue::Null => defaults.animation,
+            field => FrameAnimation::from_json(field)?,
+        };
+
+        Some(Self {
+            shape: EmitterShape::from_json(&value["shape"])?,
+            rate: f32_field("rate", defaults.rate)?,
+            bursts,
+            duration: f32_field("duration", defaults.duration)?,
+            looping: value["looping"].as_bool().unwrap_or(defaults.looping),
+            max_particles: value["max_particles"]
+                .as_usize()
+                .unwrap_or(defaults.max_particles),
+            lifetime: range_field("lifetime", defaults.lifetime)?,
+            speed: range_field("speed", defaults.speed)?,
+            direction: f32_field("direction", 0.0)?.to_radians(),
+            spread: f32_field("spread", 360.0)?.to_radians(),
+            gravity: vec2_field("gravity", defaults.gravity)?,
+            drag: f32_field("drag", defaults.drag)?,
+            size: vec2_field("size", defaults.size)?,
+            size_over_life,
+            color_over_life,
+            animation,
+            z: f32_field("z", defaults.z)?,
+        })
+    }
+
+    pub fn load(path: &str) -> Self {
+        let text = std::fs::read_to_string(path).unwrap_or_else(|_| panic!("Cannot read {path}"));
+        json::parse(&text)
+            .ok()
+            .and_then(|value| Self::from_json(&value))
+            .unwrap_or_else(|| panic!("Invalid emitter definition in {path}"))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Particle {
+    pos: Vec2<f32>,
+    vel: Vec2<f32>,
+    age: f32,
+    lifetime: f32,
+}
+
+pub struct Emitter {
+    pub def: Rc<EmitterDef>,
+    pub pos: Vec2<f32>,
+    // Stops new particles, the live ones play out
+    pub emitting: bool,
+
+    time: f32,
+    // Fractional particles owed by the rate
+    pending: f32,
+    particles: Vec<Particle>,
+    random: Random,
+}
+impl Emitter {
+    pub fn new(def: Rc<EmitterDef>, pos: Vec2<f32>, seed: u32) -> Self {
+        Self {
+            particles: Vec::with_capacity(def.max_particles),
+            def,
+            pos,
+            emitting: true,
+            time: 0.0,
+            pending: 0.0,
+            random: Random::new(seed),
+        }
+    }
+
+    // Nothing left to emit or draw, so the emitter can be dropped
+    pub fn is_finished(&self) -> bool {
+        (!self.emitting || (!self.def.looping && self.time >= self.def.duration))
+            && self.particles.is_empty()
+    }
+
+    pub fn burst(&mut self, count: u32) {
+        for _ in 0..count {
+            self.spawn();
+        }
+    }
+
+    fn spawn(&mut self) {
+        let def = &self.def;
+        if self.particles.len() >= def.max_particles {
+            return;
+        }
+        let random = &mut self.random;
+
+        let angle = def.direction + random.range(-def.spread, def.spread) * 0.5;
+        let (sin, cos) = angle.sin_cos();
+        let particle = Particle {
+            pos: self.pos + def.shape.sample(random),
+            vel: vec2(cos, sin) * def.speed.sample(random),
+            age: 0.0,
+            lifetime: def.lifetime.sample(random).max(f32::EPSILON),
+        };
+        self.particles.push(particle);
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        let def = self.def.clone();
+
+        for particle in self.particles.iter_mut() {
+            particle.vel += def.gravity * dt;
+            particle.vel *= (1.0 - def.drag * dt).max(0.0);
+            particle.pos += particle.vel * dt;
+            particle.age += dt;
+        }
+        self.particles
+            .retain(|particle| particle.age < particle.lifetime);
+
+        if !self.emitting || (!def.looping && self.time >= def.duration) {
+            return;
+        }
+
+        // Bursts due this frame, wrapping around at the end of a cycle
+        let start = self.time;
+        let cycle = if def.looping && def.duration > 0.0 {
+            start % def.duration
+        } else {
+            start
+        };
+        for burst in def.bursts.iter() {
+            let due = |time: f32| cycle <= time && time < cycle + dt;
+            if due(burst.time) || (def.looping && due(burst.time + def.duration)) {
+                self.burst(burst.count);
+            }
+        }
+
+        let active = if def.looping {
+            dt
+        } else {
+            (def.duration - start).clamp(0.0, dt)
+        };
+        self.pending += def.rate * active;
+        while self.pending >= 1.0 {
+            self.pending -= 1.0;
+            self.spawn();
+        }
+        self.time += dt;
+    }
+
+    pub fn draw(&self, group: &mut SpriteGroup) {
+        let def = &self.def;
+        let depth = Layer::Particles.depth(def.z);
+        for particle in self.particles.iter() {
+            let life = particle.age / particle.lifetime;
+            group.push(Sprite::new(
+                vec3(particle.pos.x, particle.pos.y, depth),
+                def.size * def.size_over_life.sample(life),
+                def.color_over_life.sample(life),
+                def.animation.frame(particle.age, life),
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> json::JsonValue {
+        json::parse(text).unwrap()
+    }
+    fn emitter(def: EmitterDef) -> Emitter {
+        Emitter::new(Rc::new(def), vec2(10.0, 20.0), 7)
+    }
+    // Only the emission under test, with particles that outlive the test
+    fn quiet() -> EmitterDef {
+        EmitterDef {
+            rate: 0.0,
+            lifetime: Range::constant(100.0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn random_is_repeatable_and_in_range() {
+        let mut a = Random::new(42);
+        let mut b = Random::new(42);
+        let mut other = Random::new(43);
+        let sequence: Vec<u32> = (0..8).map(|_| a.next_u32()).collect();
+        assert_eq!(sequence, (0..8).map(|_| b.next_u32()).collect::<Vec<_>>());
+        assert_ne!(
+            sequence,
+            (0..8).map(|_| other.next_u32()).collect::<Vec<_>>()
+        );
+
+        // Seeding with zero must not produce zeros forever
+        let mut zero = Random::new(0);
+        assert!((0..4).all(|_| zero.next_u32() != 0));
+
+        let mut random = Random::new(1234);
+        let mut sum = 0.0;
+        for _ in 0..10000 {
+            let value = random.next_f32();
+            assert!((0.0..1.0).contains(&value));
+            sum += value;
+            let ranged = random.range(-3.0, 5.0);
+            assert!((-3.0..5.0).contains(&ranged));
+        }
+        assert!((sum / 10000.0 - 0.5).abs() < 0.02);
+    }
+
+    #[test]
+    fn range_json_and_sampling() {
+        assert_eq!(Range::from_json(&parse("3")), Some(Range::constant(3.0)));
+        assert_eq!(
+            Range::from_json(&parse("[1, 2.5]")),
+            Some(Range::new(1.0, 2.5))
+        );
+        assert_eq!(Range::from_json(&parse("[1]")), None);
+        assert_eq!(Range::from_json(&parse("[1, 2, 3]")), None);
+        assert_eq!(Range::from_json(&parse(r#"[1, "2"]"#)), None);
+        assert_eq!(Range::from_json(&parse(r#""3""#)), None);
+
+        let mut random = Random::new(5);
+        assert_eq!(Range::constant(4.0).sample(&mut random), 4.0);
+        for _ in 0..100 {
+            assert!((1.0..2.5).contains(&Range::new(1.0, 2.5).sample(&mut random)));
+        }
+    }
+
+    #[test]
+    fn curve_sampling() {
+        assert_eq!(Curve::constant(2.0f32).sample(0.7), 2.0);
+
+        let linear = Curve::linear(0.0f32, 10.0);
+        assert_eq!(linear.sample(0.25), 2.5);
+        assert_eq!(linear.sample(-1.0), 0.0);
+        assert_eq!(linear.sample(2.0), 10.0);
+
+        // Keys are sorted, values hold before the first and after the last
+        let keys = Curve::new(vec![(0.75, 4.0f32), (0.25, 1.0), (0.5, 2.0)]);
+        assert_eq!(keys.sample(0.0), 1.0);
+        assert_eq!(keys.sample(0.375), 1.5);
+        assert_eq!(keys.sample(0.5), 2.0);
+        assert_eq!(keys.sample(0.625), 3.0);
+        assert_eq!(keys.sample(1.0), 4.0);
+    }
+
+    #[test]
+    fn curve_json() {
+        let number = |v: &json::JsonValue| v.as_f32();
+        assert_eq!(
+            Curve::from_json(&parse("3"), number),
+            Some(Curve::constant(3.0))
+        );
+        assert_eq!(
+            Curve::from_json(&parse("[1, 2, 4]"), number),
+            Some(Curve::new(vec![(0.0, 1.0), (0.5, 2.0), (1.0, 4.0)]))
+        );
+        assert_eq!(
+            Curve::from_json(&parse("[[0, 1], [0.7, 0.8], [1, 0]]"), number),
+            Some(Curve::new(vec![(0.0, 1.0), (0.7, 0.8), (1.0, 0.0)]))
+        );
+        assert_eq!(
+            Curve::from_json(&parse("[5]"), number),
+            Some(Curve::new(vec![(0.0, 5.0)]))
+        );
+        assert_eq!(Curve::from_json(&parse("[]"), number), None);
+        assert_eq!(Curve::from_json(&parse(r#"[1, "two"]"#), number), None);
+        assert_eq!(Curve::from_json(&parse("[[0.5]]"), number), None);
+
+        // Colours that are arrays themselves still spread evenly
+        let colors =
+            Curve::from_json(&parse(r#"["red", [0, 0, 1, 0]]"#), Color::from_json).unwrap();
+        assert_eq!(colors.sample(0.5), rgba(0.5, 0.0, 0.5, 0.5));
+    }
+
+    #[test]
+    fn definition_json() {
+        let def = EmitterDef::from_json(&parse("{}")).unwrap();
+        assert_eq!(def, EmitterDef::default());
+
+        let def = EmitterDef::load("assets/Sparks.json");
+        assert_eq!(def.shape, EmitterShape::Circle(2.0));
+        assert_eq!(
+            def.bursts,
+            vec![Burst {
+                time: 0.0,
+                count: 20
+            }]
+        );
+        assert!(!def.looping);
+        assert_eq!(def.lifetime, Range::new(0.4, 0.9));
+        assert!((def.direction - TAU / 4.0).abs() < 1e-6);
+        assert!((def.spread - TAU / 6.0).abs() < 1e-6);
+        assert_eq!(def.gravity, vec2(0.0, -120.0));
+        assert_eq!(def.size_over_life.sample(0.35), 0.9);
+        assert_eq!(def.animation.frames.len(), 4);
+
+        let shape = |text: &str| EmitterDef::from_json(&parse(text)).map(|def| def.shape);
+        assert_eq!(
+            shape(r#"{"shape": {"type": "ring", "radius": 3}}"#),
+            Some(EmitterShape::Ring(3.0))
+        );
+        assert_eq!(
+            shape(r#"{"shape": {"type": "rect", "width": 4, "height": 2}}"#),
+            Some(EmitterShape::Rect(vec2(2.0, 1.0)))
+        );
+        assert_eq!(shape(r#"{"shape": {"type": "star"}}"#), None);
+        assert_eq!(shape(r#"{"shape": {"type": "circle"}}"#), None);
+
+        for bad in [
+            r#"{"rate": "fast"}"#,
+            r#"{"lifetime": [1, 2, 3]}"#,
+            r#"{"bursts": [{"time": 1}]}"#,
+            r#"{"gravity": [0]}"#,
+            r#"{"color_over_life": ["nope"]}"#,
+            r#"{"animation": {"frames": []}}"#,
+        ] {
+            assert_eq!(EmitterDef::from_json(&parse(bad)), None, "{bad}");
+        }
+    }
+
+    #[test]
+    fn sparks_burst_then_die_out() {
+        let def = EmitterDef::load("assets/Sparks.json");
+        let mut sparks = Emitter::new(Rc::new(def), vec2(10.0, 20.0), 3);
+        let dt = 1.0 / 32.0;
+
+        // The burst and the first of the rate come out in the first frame,
+        // heading up within the cone
+        sparks.update(dt);
+        assert_eq!(sparks.particles.len(), 21);
+        for particle in sparks.particles.iter() {
+            let speed = particle.vel.length();
+            let angle = particle.vel.y.atan2(particle.vel.x).to_degrees();
+            assert!((30.0..80.0).contains(&speed));
+            assert!((60.0..120.0).contains(&angle), "{angle}");
+            assert!((particle.pos - vec2(10.0, 20.0)).length() <= 2.0);
+            assert!((0.4..0.9).contains(&particle.lifetime));
+        }
+
+        // 40 per second for 0.2 seconds adds 8 more, then emission stops
+        let mut time = dt;
+        while time < 0.375 {
+            sparks.update(dt);
+            time += dt;
+        }
+        assert_eq!(sparks.particles.len(), 28);
+        assert!(!sparks.is_finished());
+
+        // Nothing outlives the longest lifetime after the last spawn
+        while time < 1.125 {
+            sparks.update(dt);
+            time += dt;
+        }
+        assert!(sparks.particles.is_empty());
+        assert!(sparks.is_finished());
+    }
+
+    #[test]
+    fn rate_accumulates_fractions() {
+        let mut emitter = emitter(EmitterDef {
+            rate: 16.0,
+            ..quiet()
+        });
+        // A quarter of a particle per frame
+        let mut counts = Vec::new();
+        for _ in 0..8 {
+            emitter.update(1.0 / 64.0);
+            counts.push(emitter.particles.len());
+        }
+        assert_eq!(counts, [0, 0, 0, 1, 1, 1, 1, 2]);
+
+        // One long frame owes several at once
+        emitter.update(0.5);
+        assert_eq!(emitter.particles.len(), 10);
+    }
+
+    #[test]
+    fn emission_stops_after_the_duration_unless_looping() {
+        let def = EmitterDef {
+            rate: 16.0,
+            duration: 0.5,
+            looping: false,
+            ..quiet()
+        };
+        let mut once = emitter(def.clone());
+        let mut looping = emitter(EmitterDef {
+            looping: true,
+            ..def
+        });
+        for _ in 0..64 {
+            once.update(1.0 / 64.0);
+            looping.update(1.0 / 64.0);
+        }
+        assert_eq!(once.particles.len(), 8);
+        assert_eq!(looping.particles.len(), 16);
+
+        looping.emitting = false;
+        looping.update(1.0);
+        assert_eq!(looping.particles.len(), 16);
+    }
+
+    #[test]
+    fn bursts_fire_once_per_cycle() {
+        let def = EmitterDef {
+            bursts: vec![
+                Burst {
+                    time: 0.0,
+                    count: 2,
+                },
+                Burst {
+                    time: 0.5,
+                    count: 3,
+                },
+            ],
+            duration: 1.0,
+            ..quiet()
+        };
+        let mut looping = emitter(def.clone());
+        let mut counts = Vec::new();
+        for _ in 0..8 {
+            looping.update(0.25);
+            counts.push(looping.particles.len());
+        }
+        assert_eq!(counts, [2, 2, 5, 5, 7, 7, 10, 10]);
+
+        // A frame spanning the end of a cycle still catches the next start
+        let mut wrapping = emitter(def.clone());
+        wrapping.update(0.25);
+        wrapping.update(0.5);
+        wrapping.update(0.5);
+        assert_eq!(wrapping.particles.len(), 7);
+
+        let mut once = emitter(EmitterDef {
+            looping: false,
+            ..def
+        });
+        for _ in 0..8 {
+            once.update(0.25);
+        }
+        assert_eq!(once.particles.len(), 5);
+    }
+
+    #[test]
+    fn max_particles_caps_spawning() {
+        let mut emitter = emitter(EmitterDef {
+            max_particles: 4,
+            ..quiet()
+        });
+        emitter.burst(10);
+        assert_eq!(emitter.particles.len(), 4);
+    }
+}
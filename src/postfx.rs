@@ -0,0 +1,479 @@
+use wgpu::util::DeviceExt;
+
+use crate::graphics::*;
+use crate::nvec::*;
+use crate::scene::Scene;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bloom {
+    pub enabled: bool,
+    // Brightness above which pixels start to glow
+    pub threshold: f32,
+    pub intensity: f32,
+    // Blur spread in half resolution texels
+    pub radius: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Crt {
+    pub enabled: bool,
+    // Darkening of every other row
+    pub scanlines: f32,
+    pub curvature: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vignette {
+    pub enabled: bool,
+    pub strength: f32,
+    // Distance from the centre where darkening starts, 1 is the corners
+    pub radius: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aberration {
+    pub enabled: bool,
+    // Channel offset at the edges, in pixels
+    pub offset: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Grading {
+    pub enabled: bool,
+    // Blend between the original and graded colour
+    pub strength: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PostSettings {
+    pub bloom: Bloom,
+    pub crt: Crt,
+    pub vignette: Vignette,
+    pub aberration: Aberration,
+    pub grading: Grading,
+}
+impl Default for PostSettings {
+    fn default() -> Self {
+        Self {
+            bloom: Bloom {
+                enabled: true,
+                threshold: 0.7,
+                intensity: 0.8,
+                radius: 4.0,
+            },
+            crt: Crt {
+                enabled: false,
+                scanlines: 0.25,
+                curvature: 0.06,
+            },
+            vignette: Vignette {
+                enabled: true,
+                strength: 0.35,
+                radius: 0.6,
+            },
+            aberration: Aberration {
+                enabled: false,
+                offset: 1.5,
+            },
+            grading: Grading {
+                enabled: true,
+                strength: 1.0,
+            },
+        }
+    }
+}
+impl PostSettings {
+    // Whether the composite pass has anything to do
+    fn any(&self) -> bool {
+        self.bloom.enabled
+            || self.crt.enabled
+            || self.vignette.enabled
+            || self.aberration.enabled
+            || self.grading.enabled
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+struct BloomUniform {
+    direction: Vec2<f32>,
+    threshold: f32,
+    radius: f32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+struct PostUniform {
+    effects: Vec4<f32>,
+    screen: Vec4<f32>,
+}
+impl PostUniform {
+    fn new(post: &PostSettings) -> Self {
+        let strength = |enabled: bool, value: f32| if enabled { value } else { 0.0 };
+        Self {
+            effects: vec4(
+                strength(post.bloom.enabled, post.bloom.intensity),
+                strength(post.aberration.enabled, post.aberration.offset),
+                strength(post.grading.enabled, post.grading.strength),
+                0.0,
+            ),
+            screen: vec4(
+                strength(post.vignette.enabled, post.vignette.strength),
+                post.vignette.radius,
+                strength(post.crt.enabled, post.crt.scanlines),
+                strength(post.crt.enabled, post.crt.curvature),
+            ),
+        }
+    }
+}
+
+// Bright pass when `direction` is None, otherwise one axis of the blur
+struct BloomNode {
+    pipeline: wgpu::RenderPipeline,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+
+    source: TargetId,
+    direction: Option<Vec2<f32>>,
+}
+impl BloomNode {
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    fn new(
+        gfx: &Graphics,
+        shader: &wgpu::ShaderModule,
+        source: TargetId,
+        direction: Option<Vec2<f32>>,
+    ) -> Self {
+        let layout = gfx
+            .device
+            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("PostFx.Bloom.BindGroupLayout"),
+                entries: &[uniform_entry(0)],
+            });
+
+        let entry_point = match direction {
+            Some(_) => "frag_blur",
+            None => "frag_bright",
+        };
+        let pipeline = fullscreen_pipeline(
+            gfx,
+            "PostFx.Bloom",
+            shader,
+            entry_point,
+            &[&input_layout(gfx), &layout],
+            Self::FORMAT,
+        );
+
+        let buffer = gfx.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("PostFx.Bloom.Buffer"),
+            size: std::mem::size_of::<BloomUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = gfx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("PostFx.Bloom.BindGroup"),
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+
+        Self {
+            pipeline,
+            buffer,
+            bind_group,
+            source,
+            direction,
+        }
+    }
+}
+impl RenderNode<Scene> for BloomNode {
+    fn enabled(&self, scene: &Scene) -> bool {
+        scene.post.bloom.enabled
+    }
+
+    fn prepare(&mut self, gfx: &Graphics, scene: &mut Scene) {
+        let uniform = BloomUniform {
+            direction: self.direction.unwrap_or(vec2(0.0, 0.0)),
+            threshold: scene.post.bloom.threshold,
+            radius: scene.post.bloom.radius,
+        };
+        gfx.queue
+            .write_buffer(&self.buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    fn draw<'a>(
+        &'a self,
+        ctx: &NodeContext<'a>,
+        _scene: &'a Scene,
+        pass: &mut wgpu::RenderPass<'a>,
+    ) {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, ctx.input(self.source), &[]);
+        pass.set_bind_group(1, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+// Applies bloom, aberration, grading, scanlines and vignette in one pass
+struct CompositeNode {
+    pipeline: wgpu::RenderPipeline,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+
+    source: TargetId,
+    bloom: TargetId,
+}
+impl CompositeNode {
+    fn new(gfx: &Graphics, source: TargetId, bloom: TargetId) -> Self {
+        let layout = gfx
+            .device
+            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("PostFx.Composite.BindGroupLayout"),
+                entries: &[
+                    uniform_entry(0),
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D3,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+            });
+
+        let shader = gfx.load_fullscreen_shader("shaders/post.wgsl");
+        let input = input_layout(gfx);
+        let pipeline = fullscreen_pipeline(
+            gfx,
+            "PostFx.Composite",
+            &shader,
+            "frag_main",
+            &[&input, &input, &layout],
+            gfx.get_format(),
+        );
+
+        let buffer = gfx.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("PostFx.Composite.Buffer"),
+            size: std::mem::size_of::<PostUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let lut = Lut::load(gfx);
+        let sampler = gfx.device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("PostFx.Lut.Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let bind_group = gfx.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("PostFx.Composite.BindGroup"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&lut.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+        });
+
+        Self {
+            pipeline,
+            buffer,
+            bind_group,
+            source,
+            bloom,
+        }
+    }
+}
+impl RenderNode<Scene> for CompositeNode {
+    fn enabled(&self, scene: &Scene) -> bool {
+        scene.post.any()
+    }
+
+    fn prepare(&mut self, gfx: &Graphics, scene: &mut Scene) {
+        let uniform = PostUniform::new(&scene.post);
+        gfx.queue
+            .write_buffer(&self.buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    fn draw<'a>(
+        &'a self,
+        ctx: &NodeContext<'a>,
+        _scene: &'a Scene,
+        pass: &mut wgpu::RenderPass<'a>,
+    ) {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, ctx.input(self.source), &[]);
+        pass.set_bind_group(1, ctx.input(self.bloom), &[]);
+        pass.set_bind_group(2, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+// Colour grading lookup table, mapping linear colour to linear colour
+struct Lut {
+    view: wgpu::TextureView,
+}
+impl Lut {
+    const SIZE: u32 = 16;
+    // Strip of SIZE slices of SIZE x SIZE side by side, red along x, green
+    // along y and blue across slices. Falls back to the built in grade
+    const PATH: &str = "assets/GradeLut.png";
+
+    fn load(gfx: &Graphics) -> Self {
+        let data = match std::fs::read(Self::PATH) {
+            Ok(file) => Self::parse(&file).unwrap_or_else(|| {
+                eprintln!(
+                    "Warning: Invalid LUT [{}], using the default grade",
+                    Self::PATH
+                );
+                Self::generate()
+            }),
+            Err(_) => Self::generate(),
+        };
+
+        let texture = gfx.device.create_texture_with_data(
+            &gfx.queue,
+            &wgpu::TextureDescriptor {
+                label: Some("PostFx.Lut.Texture"),
+                size: wgpu::Extent3d {
+                    width: Self::SIZE,
+                    height: Self::SIZE,
+                    depth_or_array_layers: Self::SIZE,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D3,
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            &data,
+        );
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("PostFx.Lut.TextureView"),
+            ..Default::default()
+        });
+
+        Self { view }
+    }
+
+    fn parse(file: &[u8]) -> Option<Vec<u8>> {
+        let image = image::load_from_memory(file).ok()?.to_rgba8();
+        let size = Self::SIZE;
+        if image.width() != size * size || image.height() != size {
+            return None;
+        }
+
+        let mut data = Vec::with_capacity((size * size * size * 4) as usize);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    data.extend_from_slice(&image.get_pixel(b * size + r, g).0);
+                }
+            }
+        }
+        Some(data)
+    }
+
+    // Pushes shadows towards arcane purple and highlights towards warm gold,
+    // with a little extra saturation
+    fn generate() -> Vec<u8> {
+        let size = Self::SIZE;
+        let unit = |i: u32| i as f32 / (size - 1) as f32;
+
+        let mut data = Vec::with_capacity((size * size * size * 4) as usize);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    let color = vec3(unit(r), unit(g), unit(b));
+                    let luma = color.dot(vec3(0.2126, 0.7152, 0.0722));
+
+                    let saturated = Vec3::splat(luma) + (color - Vec3::splat(luma)) * 1.15;
+                    let shadows = (1.0 - luma) * (1.0 - luma);
+                    let highlights = luma * luma;
+                    let graded = saturated
+                        + vec3(0.04, -0.02, 0.06) * shadows
+                        + vec3(0.04, 0.02, -0.04) * highlights;
+
+                    let byte = |v: f32| (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+                    data.extend_from_slice(&[byte(graded.x), byte(graded.y), byte(graded.z), 255]);
+                }
+            }
+        }
+        data
+    }
+}
+
+// Adds the post processing passes reading `source`, returning the target
+// holding the result
+pub fn build(gfx: &Graphics, graph: &mut RenderGraph<Scene>, source: TargetId) -> TargetId {
+    let bloom_target = |label| TargetDesc {
+        format: Some(BloomNode::FORMAT),
+        filter: wgpu::FilterMode::Linear,
+        ..TargetDesc::new(label, TargetSize::Relative(source, 0.5))
+    };
+    let bloom_a = graph.add_target(gfx, bloom_target("PostFx.BloomA"));
+    let bloom_b = graph.add_target(gfx, bloom_target("PostFx.BloomB"));
+    let output = graph.add_target(
+        gfx,
+        TargetDesc::new("PostFx.Output", TargetSize::Relative(source, 1.0)),
+    );
+
+    let shader = gfx.load_fullscreen_shader("shaders/bloom.wgsl");
+    let passes = [
+        ("PostFx.BloomBright", source, bloom_a, None),
+        ("PostFx.BloomBlurX", bloom_a, bloom_b, Some(vec2(1.0, 0.0))),
+        ("PostFx.BloomBlurY", bloom_b, bloom_a, Some(vec2(0.0, 1.0))),
+    ];
+    for (label, input, output, direction) in passes {
+        graph.add_pass(
+            PassDesc {
+                label,
+                stage: Stage::PostProcess,
+                inputs: vec![input],
+                output: Output::Target(output),
+                clear: None,
+            },
+            BloomNode::new(gfx, &shader, input, direction),
+        );
+    }
+
+    graph.add_pass(
+        PassDesc {
+            label: "PostFx.Composite",
+            stage: Stage::PostProcess,
+            inputs: vec![source, bloom_a],
+            output: Output::Target(output),
+            clear: None,
+        },
+        CompositeNode::new(gfx, source, bloom_a),
+    );
+    output
+}
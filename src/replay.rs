@@ -0,0 +1,319 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+
+use crate::gamepad::*;
+use crate::nvec::*;
+use crate::touch::TouchEvent;
+
+use winit::dpi::PhysicalPosition;
+use winit::event::{ElementState, MouseButton, MouseScrollDelta, TouchPhase};
+
+// Input as seen by the game, independent of where it came from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputEvent {
+    // Physical pixels
+    CursorMoved(Vec2<f32>),
+    // Window size in physical pixels, and its scale factor
+    Viewport(Vec2<f32>, f32),
+    MouseButton(MouseButton, ElementState),
+    Scroll(MouseScrollDelta),
+    Touch(TouchEvent),
+    Gamepad(GamepadEvent),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct InputFrame {
+    pub dt: f32,
+    pub events: Vec<InputEvent>,
+}
+
+fn pressed(state: ElementState) -> bool {
+    state == ElementState::Pressed
+}
+fn element_state(pressed: bool) -> ElementState {
+    if pressed {
+        ElementState::Pressed
+    } else {
+        ElementState::Released
+    }
+}
+
+fn button_name(button: MouseButton) -> json::JsonValue {
+    match button {
+        MouseButton::Left => "left".into(),
+        MouseButton::Right => "right".into(),
+        MouseButton::Middle => "middle".into(),
+        MouseButton::Other(id) => id.into(),
+    }
+}
+fn parse_button(value: &json::JsonValue) -> Option<MouseButton> {
+    match value.as_str() {
+        Some("left") => Some(MouseButton::Left),
+        Some("right") => Some(MouseButton::Right),
+        Some("middle") => Some(MouseButton::Middle),
+        Some(_) => None,
+        None => value.as_u16().map(MouseButton::Other),
+    }
+}
+
+fn phase_name(phase: TouchPhase) -> &'static str {
+    match phase {
+        TouchPhase::Started => "started",
+        TouchPhase::Moved => "moved",
+        TouchPhase::Ended => "ended",
+        TouchPhase::Cancelled => "cancelled",
+    }
+}
+fn parse_phase(value: &json::JsonValue) -> Option<TouchPhase> {
+    match value.as_str()? {
+        "started" => Some(TouchPhase::Started),
+        "moved" => Some(TouchPhase::Moved),
+        "ended" => Some(TouchPhase::Ended),
+        "cancelled" => Some(TouchPhase::Cancelled),
+        _ => None,
+    }
+}
+
+fn parse_named<T: Copy + std::fmt::Debug>(all: &[T], value: &json::JsonValue) -> Option<T> {
+    let name = value.as_str()?;
+    all.iter().copied().find(|item| format!("{item:?}") == name)
+}
+
+impl InputEvent {
+    fn to_json(self) -> json::JsonValue {
+        match self {
+            InputEvent::CursorMoved(pos) => json::object! {
+                type: "cursor", x: pos.x, y: pos.y
+            },
+            InputEvent::Viewport(size, scale) => json::object! {
+                type: "viewport", width: size.x, height: size.y, scale: scale
+            },
+            InputEvent::MouseButton(button, state) => json::object! {
+                type: "mouse", button: button_name(button), pressed: pressed(state)
+            },
+            InputEvent::Scroll(MouseScrollDelta::LineDelta(x, y)) => json::object! {
+                type: "scroll_lines", x: x, y: y
+            },
+            InputEvent::Scroll(MouseScrollDelta::PixelDelta(pos)) => json::object! {
+                type: "scroll_pixels", x: pos.x, y: pos.y
+            },
+            InputEvent::Touch(touch) => json::object! {
+                type: "touch",
+                id: touch.id,
+                phase: phase_name(touch.phase),
+                x: touch.pos.x,
+                y: touch.pos.y,
+                force: touch.force,
+                altitude: touch.altitude
+            },
+            InputEvent::Gamepad(GamepadEvent::Connected(id)) => json::object! {
+                type: "pad_connected", id: id.0
+            },
+            InputEvent::Gamepad(GamepadEvent::Disconnected(id)) => json::object! {
+                type: "pad_disconnected", id: id.0
+            },
+            InputEvent::Gamepad(GamepadEvent::Button(id, button, state)) => json::object! {
+                type: "pad_button", id: id.0, button: format!("{button:?}"), pressed: pressed(state)
+            },
+            InputEvent::Gamepad(GamepadEvent::Axis(id, axis, value)) => json::object! {
+                type: "pad_axis", id: id.0, axis: format!("{axis:?}"), value: value
+            },
+        }
+    }
+
+    fn from_json(value: &json::JsonValue) -> Option<Self> {
+        let f32_field = |name: &str| value[name].as_f32();
+        let pad = || value["id"].as_usize().map(GamepadId);
+
+        Some(match value["type"].as_str()? {
+            "cursor" => InputEvent::CursorMoved(vec2(f32_field("x")?, f32_field("y")?)),
+            "viewport" => InputEvent::Viewport(
+                vec2(f32_field("width")?, f32_field("height")?),
+                f32_field("scale")?,
+            ),
+            "mouse" => InputEvent::MouseButton(
+                parse_button(&value["button"])?,
+                element_state(value["pressed"].as_bool()?),
+            ),
+            "scroll_lines" => InputEvent::Scroll(MouseScrollDelta::LineDelta(
+                f32_field("x")?,
+                f32_field("y")?,
+            )),
+            "scroll_pixels" => InputEvent::Scroll(MouseScrollDelta::PixelDelta(
+                PhysicalPosition::new(value["x"].as_f64()?, value["y"].as_f64()?),
+            )),
+            "touch" => InputEvent::Touch(TouchEvent {
+                id: value["id"].as_u64()?,
+                phase: parse_phase(&value["phase"])?,
+                pos: vec2(f32_field("x")?, f32_field("y")?),
+                force: f32_field("force"),
+                altitude: f32_field("altitude"),
+            }),
+            "pad_connected" => InputEvent::Gamepad(GamepadEvent::Connected(pad()?)),
+            "pad_disconnected" => InputEvent::Gamepad(GamepadEvent::Disconnected(pad()?)),
+            "pad_button" => InputEvent::Gamepad(GamepadEvent::Button(
+                pad()?,
+                parse_named(&GamepadButton::ALL, &value["button"])?,
+                element_state(value["pressed"].as_bool()?),
+            )),
+            "pad_axis" => InputEvent::Gamepad(GamepadEvent::Axis(
+                pad()?,
+                parse_named(&GamepadAxis::ALL, &value["axis"])?,
+                f32_field("value")?,
+            )),
+            _ => return None,
+        })
+    }
+}
+
+impl InputFrame {
+    pub fn to_json(&self) -> json::JsonValue {
+        let events: Vec<json::JsonValue> = self.events.iter().map(|e| e.to_json()).collect();
+        json::object! { dt: self.dt, events: events }
+    }
+
+    pub fn from_json(value: &json::JsonValue) -> Option<Self> {
+        let dt = value["dt"].as_f32()?;
+        let events = value["events"]
+            .members()
+            .map(InputEvent::from_json)
+            .collect::<Option<Vec<_>>>()?;
+        Some(Self { dt, events })
+    }
+}
+
+// Writes one frame per line, so a trace is still usable if the game crashes
+pub struct Recorder {
+    file: std::io::BufWriter<std::fs::File>,
+    frame: InputFrame,
+}
+impl Recorder {
+    pub fn new(path: &str) -> Self {
+        let file = std::fs::File::create(path).unwrap_or_else(|_| panic!("Cannot create {path}"));
+        Self {
+            file: std::io::BufWriter::new(file),
+            frame: InputFrame::default(),
+        }
+    }
+
+    pub fn push(&mut self, event: InputEvent) {
+        self.frame.events.push(event);
+    }
+
+    pub fn finish_frame(&mut self, dt: f32) {
+        self.frame.dt = dt;
+
+        let line = self.frame.to_json().dump();
+        if let Err(err) = writeln!(self.file, "{line}").and_then(|_| self.file.flush()) {
+            eprintln!("Warning: Unable to write input recording [{err}]");
+        }
+        self.frame.events.clear();
+    }
+}
+
+pub struct Replay {
+    frames: VecDeque<InputFrame>,
+}
+impl Replay {
+    pub fn new(frames: impl IntoIterator<Item = InputFrame>) -> Self {
+        Self {
+            frames: frames.into_iter().collect(),
+        }
+    }
+
+    // Fails on the first line that is not a valid frame
+    pub fn load(path: &str) -> Result<Self, String> {
+        let file =
+            std::fs::File::open(path).map_err(|err| format!("Cannot read {path} [{err}]"))?;
+
+        let mut frames = VecDeque::new();
+        for (number, line) in std::io::BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|err| format!("Cannot read {path} [{err}]"))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let frame = json::parse(&line)
+                .ok()
+                .and_then(|value| InputFrame::from_json(&value))
+                .ok_or_else(|| format!("Invalid input frame at {path}:{}", number + 1))?;
+            frames.push_back(frame);
+        }
+
+        Ok(Self { frames })
+    }
+
+    pub fn next_frame(&mut self) -> Option<InputFrame> {
+        self.frames.pop_front()
+    }
+    pub fn is_finished(&self) -> bool {
+        self.frames.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_round_trip() {
+        let frame = InputFrame {
+            dt: 1.0 / 60.0,
+            events: vec![
+                InputEvent::CursorMoved(vec2(12.5, 300.0)),
+                InputEvent::Viewport(vec2(800.0, 600.0), 1.5),
+                InputEvent::MouseButton(MouseButton::Left, ElementState::Pressed),
+                InputEvent::MouseButton(MouseButton::Other(7), ElementState::Released),
+                InputEvent::Scroll(MouseScrollDelta::LineDelta(0.0, -2.0)),
+                InputEvent::Scroll(MouseScrollDelta::PixelDelta(PhysicalPosition::new(
+                    3.0, 4.0,
+                ))),
+                InputEvent::Touch(TouchEvent {
+                    id: 42,
+                    phase: TouchPhase::Moved,
+                    pos: vec2(1.0, 2.0),
+                    force: Some(0.5),
+                    altitude: None,
+                }),
+                InputEvent::Gamepad(GamepadEvent::Connected(GamepadId(1))),
+                InputEvent::Gamepad(GamepadEvent::Button(
+                    GamepadId(1),
+                    GamepadButton::DPadLeft,
+                    ElementState::Pressed,
+                )),
+                InputEvent::Gamepad(GamepadEvent::Axis(
+                    GamepadId(1),
+                    GamepadAxis::RightTrigger,
+                    -0.25,
+                )),
+                InputEvent::Gamepad(GamepadEvent::Disconnected(GamepadId(1))),
+            ],
+        };
+
+        // Through text, the same way frames are written and read back
+        let line = frame.to_json().dump();
+        let parsed = InputFrame::from_json(&json::parse(&line).unwrap()).unwrap();
+        assert_eq!(parsed.dt, frame.dt);
+        assert_eq!(parsed.events, frame.events);
+    }
+
+    #[test]
+    fn rejects_unknown_events() {
+        let value = json::parse(r#"{"dt": 0.1, "events": [{"type": "teleport"}]}"#).unwrap();
+        assert!(InputFrame::from_json(&value).is_none());
+        let value = json::parse(r#"{"events": []}"#).unwrap();
+        assert!(InputFrame::from_json(&value).is_none());
+    }
+
+    #[test]
+    fn load_reports_line() {
+        let path = std::env::temp_dir().join(format!("replay-{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "{\"dt\": 0.1, \"events\": []}\n\n{\"dt\": }\n").unwrap();
+        let err = Replay::load(path).err().unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert!(err.ends_with(&format!("{path}:3")), "{err}");
+
+        assert!(Replay::load("missing.jsonl").is_err());
+    }
+}
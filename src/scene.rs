@@ -0,0 +1,200 @@
+use crate::camera::{Camera2D, CameraBuffer};
+use crate::glyph::{Glyph, GlyphRenderer};
+use crate::graphics::*;
+use crate::layer::{sort_back_to_front, Layer};
+use crate::lighting::{self, Lighting};
+use crate::nvec::*;
+use crate::postfx::{self, PostSettings};
+use crate::screen::{ScaleMode, Scaling, Upscaler};
+use crate::shape::{ShapeBatch, ShapeRenderer};
+use crate::sprite::{SpriteGroup, SpriteRenderer};
+use crate::tilemap::Tilemap;
+
+// Everything the render graph draws, handed to each node
+pub struct Scene {
+    pub camera: Camera2D,
+    pub camera_buffer: CameraBuffer,
+    pub glyph: GlyphRenderer,
+    pub spriter: SpriteRenderer,
+    pub shaper: ShapeRenderer,
+
+    // Game layer, drawn with depth testing
+    pub tilemap: Option<Tilemap>,
+    pub sprite_groups: Vec<SpriteGroup>,
+    // Cleared by the owner, like the text
+    pub shapes: ShapeBatch,
+    pub world_text: Vec<Glyph>,
+    pub ui_glyphs: Vec<Glyph>,
+    // Screen space, drawn under the UI glyphs
+    pub ui_sprites: SpriteGroup,
+    ui_camera: CameraBuffer,
+
+    pub scaling: Scaling,
+    pub post: PostSettings,
+    pub lighting: Lighting,
+    // Game layer target, see `Scaling::target_size`
+    pub color: TargetId,
+}
+impl Scene {
+    pub fn target_size(scaling: &Scaling) -> TargetSize {
+        match scaling.mode {
+            ScaleMode::Native => TargetSize::Surface,
+            _ => TargetSize::Fixed(scaling.resolution.x as u32, scaling.resolution.y as u32),
+        }
+    }
+
+    pub fn new(gfx: &Graphics, scaling: Scaling) -> (Self, RenderGraph<Scene>) {
+        let mut graph = RenderGraph::new(gfx);
+
+        let color = graph.add_target(
+            gfx,
+            TargetDesc {
+                depth: true,
+                ..TargetDesc::new("Scene.Color", Self::target_size(&scaling))
+            },
+        );
+
+        graph.add_pass(
+            PassDesc {
+                label: "Scene.World",
+                stage: Stage::World,
+                inputs: vec![],
+                output: Output::Target(color),
+                clear: Some(wgpu::Color::BLACK),
+            },
+            WorldNode,
+        );
+        let lit = lighting::build(gfx, &mut graph, color);
+        let output = postfx::build(gfx, &mut graph, lit);
+
+        graph.add_pass(
+            PassDesc {
+                label: "Scene.Composite",
+                stage: Stage::Ui,
+                inputs: vec![output],
+                output: Output::Surface,
+                clear: Some(wgpu::Color::BLACK),
+            },
+            CompositeNode {
+                upscaler: Upscaler::new(gfx),
+                source: output,
+            },
+        );
+        graph.add_pass(
+            PassDesc {
+                label: "Scene.Ui",
+                stage: Stage::Ui,
+                inputs: vec![],
+                output: Output::Surface,
+                clear: None,
+            },
+            UiNode,
+        );
+
+        let size = gfx.get_size();
+        let scene = Self {
+            camera: Camera2D::new(vec2(size.0 as f32, size.1 as f32)),
+            camera_buffer: CameraBuffer::new(gfx),
+            glyph: GlyphRenderer::new(gfx),
+            spriter: SpriteRenderer::new(gfx),
+            shaper: ShapeRenderer::new(gfx),
+            tilemap: None,
+            sprite_groups: Vec::new(),
+            shapes: ShapeBatch::new(),
+            world_text: Vec::new(),
+            ui_glyphs: Vec::new(),
+            ui_sprites: SpriteGroup::new(gfx, "assets/UiFrame.png", 256),
+            ui_camera: CameraBuffer::new(gfx),
+            scaling,
+            post: PostSettings::default(),
+            lighting: Lighting::new(),
+            color,
+        };
+        (scene, graph)
+    }
+
+    // Tilemap chunks in view followed by the sprite groups
+    pub fn world_groups(&self) -> impl Iterator<Item = &SpriteGroup> {
+        let view = self.camera.visible();
+        self.tilemap
+            .iter()
+            .flat_map(move |tilemap| tilemap.groups(view))
+            .chain(self.sprite_groups.iter())
+    }
+}
+
+struct WorldNode;
+impl RenderNode<Scene> for WorldNode {
+    fn prepare(&mut self, gfx: &Graphics, scene: &mut Scene) {
+        scene.camera_buffer.upload(gfx, &scene.camera);
+        if let Some(tilemap) = &mut scene.tilemap {
+            tilemap.prepare(gfx);
+        }
+        for group in scene.sprite_groups.iter_mut() {
+            group.upload(gfx);
+        }
+        sort_back_to_front(&mut scene.world_text);
+        scene.glyph.upload_world(gfx, &scene.world_text);
+        scene.shapes.sort_back_to_front();
+        scene.shaper.upload(gfx, &scene.shapes);
+    }
+
+    fn draw<'a>(
+        &'a self,
+        _ctx: &NodeContext<'a>,
+        scene: &'a Scene,
+        pass: &mut wgpu::RenderPass<'a>,
+    ) {
+        // Back to front a layer at a time, whichever renderer draws it, so
+        // translucent edges blend over everything behind them. Within a layer
+        // sprites go first, then shapes, then text
+        let camera = &scene.camera_buffer;
+        for layer in Layer::ALL {
+            scene.spriter.render(pass, camera, scene.world_groups(), layer);
+            scene.shaper.render(pass, camera, &scene.shapes, layer);
+            scene.glyph.render_world(pass, camera, &scene.world_text, layer);
+        }
+    }
+}
+
+// Upscales the game layer into the window, letterboxed
+struct CompositeNode {
+    upscaler: Upscaler,
+    source: TargetId,
+}
+impl RenderNode<Scene> for CompositeNode {
+    fn draw<'a>(
+        &'a self,
+        ctx: &NodeContext<'a>,
+        scene: &'a Scene,
+        pass: &mut wgpu::RenderPass<'a>,
+    ) {
+        let window = vec2(ctx.size.0 as f32, ctx.size.1 as f32);
+        let area = scene.scaling.area(window);
+        self.upscaler
+            .draw(pass, ctx.input(self.source), area, ctx.size);
+    }
+}
+
+struct UiNode;
+impl RenderNode<Scene> for UiNode {
+    fn prepare(&mut self, gfx: &Graphics, scene: &mut Scene) {
+        scene.ui_camera.upload_matrix(gfx, Mat4::identity());
+        scene.ui_sprites.upload(gfx);
+        sort_back_to_front(&mut scene.ui_glyphs);
+    }
+
+    fn draw<'a>(
+        &'a self,
+        ctx: &NodeContext<'a>,
+        scene: &'a Scene,
+        pass: &mut wgpu::RenderPass<'a>,
+    ) {
+        scene.spriter.render_overlay(
+            pass,
+            &scene.ui_camera,
+            std::iter::once(&scene.ui_sprites),
+        );
+        scene.glyph.render(ctx.gfx, pass, &scene.ui_glyphs);
+    }
+}
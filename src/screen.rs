@@ -0,0 +1,308 @@
+use crate::geometry::Rect;
+use crate::graphics::{input_layout, Graphics};
+use crate::nvec::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScaleMode {
+    // Render the game layer straight to the window at its own resolution
+    Native,
+    // Largest whole multiple of the virtual resolution that fits, letterboxed
+    Integer,
+    // Largest size that keeps the aspect ratio, letterboxed
+    Fit,
+    // Fill the window, ignoring the aspect ratio
+    Stretch,
+}
+impl ScaleMode {
+    pub const ALL: [ScaleMode; 4] = [
+        ScaleMode::Native,
+        ScaleMode::Integer,
+        ScaleMode::Fit,
+        ScaleMode::Stretch,
+    ];
+
+    pub fn next(self) -> ScaleMode {
+        let index = Self::ALL.iter().position(|mode| *mode == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            ScaleMode::Native => "Native",
+            ScaleMode::Integer => "Integer",
+            ScaleMode::Fit => "Fit",
+            ScaleMode::Stretch => "Stretch",
+        }
+    }
+}
+
+// Placement of the game layer in the window. All window positions are in
+// physical pixels from the top-left, y down
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Scaling {
+    pub resolution: Vec2<f32>,
+    pub mode: ScaleMode,
+}
+impl Default for Scaling {
+    fn default() -> Self {
+        Self {
+            resolution: vec2(320.0, 180.0),
+            mode: ScaleMode::Native,
+        }
+    }
+}
+impl Scaling {
+    // Size the game layer is rendered at
+    pub fn target_size(&self, window: Vec2<f32>) -> Vec2<f32> {
+        match self.mode {
+            ScaleMode::Native => window,
+            _ => self.resolution,
+        }
+    }
+
+    // Area of the window the game layer is drawn to
+    pub fn area(&self, window: Vec2<f32>) -> Rect {
+        let fit = (window.x / self.resolution.x).min(window.y / self.resolution.y);
+        let scale = match self.mode {
+            ScaleMode::Native | ScaleMode::Stretch => {
+                return Rect::from_corners(vec2(0.0, 0.0), window)
+            }
+            // Falls back to shrinking when the window is smaller than the
+            // virtual resolution
+            ScaleMode::Integer if fit >= 1.0 => fit.floor(),
+            ScaleMode::Integer | ScaleMode::Fit => fit,
+        };
+
+        let size = (self.resolution * scale).map(f32::round);
+        let origin = ((window - size) * 0.5).map(f32::floor);
+        Rect::sized(origin, size)
+    }
+
+    // Normalised device coordinates of the game layer, y up. Points in the
+    // letterbox map outside [-1, 1]
+    pub fn window_to_ndc(&self, pixels: Vec2<f32>, window: Vec2<f32>) -> Vec2<f32> {
+        let area = self.area(window);
+        let size = area.size().max(vec2(1.0, 1.0));
+        let unit = (pixels - area.min) / size;
+        vec2(2.0 * unit.x - 1.0, 1.0 - 2.0 * unit.y)
+    }
+    // Pixels of the game layer from its top-left, y down
+    pub fn window_to_target(&self, pixels: Vec2<f32>, window: Vec2<f32>) -> Vec2<f32> {
+        let area = self.area(window);
+        let size = area.size().max(vec2(1.0, 1.0));
+        (pixels - area.min) / size * self.target_size(window)
+    }
+    pub fn target_to_window(&self, pixels: Vec2<f32>, window: Vec2<f32>) -> Vec2<f32> {
+        let area = self.area(window);
+        area.min + pixels / self.target_size(window) * area.size()
+    }
+}
+
+// Draws a render graph target into part of the window, used to upscale the
+// game layer
+pub struct Upscaler {
+    pipeline: wgpu::RenderPipeline,
+}
+impl Upscaler {
+    pub fn new(gfx: &Graphics) -> Self {
+        let pipeline_layout = gfx
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Upscaler.Pipeline.Layout"),
+                bind_group_layouts: &[&input_layout(gfx)],
+                push_constant_ranges: &[],
+            });
+
+        let shader = gfx.load_fullscreen_shader("shaders/blit.wgsl");
+
+        let pipeline = gfx
+            .device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Upscaler.Pipeline"),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vert_main",
+                    buffers: &[],
+                },
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "frag_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: gfx.get_format(),
+                        blend: Some(wgpu::BlendState::REPLACE),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                multiview: None,
+            });
+
+        Self { pipeline }
+    }
+
+    // Draws `source` into `area` of a pass over the whole window, which
+    // should already be cleared to the letterbox colour. Resets the viewport
+    pub fn draw<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        source: &'a wgpu::BindGroup,
+        area: Rect,
+        window: (u32, u32),
+    ) {
+        let (width, height) = (window.0 as f32, window.1 as f32);
+        let bounds = Rect::from_corners(vec2(0.0, 0.0), vec2(width, height));
+        let Some(area) = area.intersection(&bounds) else {
+            return;
+        };
+        if area.size().x < 1.0 || area.size().y < 1.0 {
+            return;
+        }
+
+        let size = area.size();
+        pass.set_viewport(area.min.x, area.min.y, size.x, size.y, 0.0, 1.0);
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, source, &[]);
+        pass.draw(0..3, 0..1);
+        pass.set_viewport(0.0, 0.0, width, height, 0.0, 1.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scaling(mode: ScaleMode) -> Scaling {
+        Scaling {
+            resolution: vec2(320.0, 180.0),
+            mode,
+        }
+    }
+    fn rect(x: f32, y: f32, width: f32, height: f32) -> Rect {
+        Rect::sized(vec2(x, y), vec2(width, height))
+    }
+
+    #[test]
+    fn native_and_stretch_fill_the_window() {
+        for mode in [ScaleMode::Native, ScaleMode::Stretch] {
+            let window = vec2(1000.0, 333.0);
+            assert_eq!(scaling(mode).area(window), rect(0.0, 0.0, 1000.0, 333.0));
+        }
+        assert_eq!(
+            scaling(ScaleMode::Native).target_size(vec2(1000.0, 333.0)),
+            vec2(1000.0, 333.0)
+        );
+        assert_eq!(
+            scaling(ScaleMode::Stretch).target_size(vec2(1000.0, 333.0)),
+            vec2(320.0, 180.0)
+        );
+    }
+
+    #[test]
+    fn integer_uses_whole_multiples() {
+        let integer = scaling(ScaleMode::Integer);
+        assert_eq!(
+            integer.area(vec2(1280.0, 720.0)),
+            rect(0.0, 0.0, 1280.0, 720.0)
+        );
+        assert_eq!(
+            integer.area(vec2(1000.0, 600.0)),
+            rect(20.0, 30.0, 960.0, 540.0)
+        );
+        // Much taller than wide, so the width decides the multiple
+        assert_eq!(
+            integer.area(vec2(700.0, 1900.0)),
+            rect(30.0, 770.0, 640.0, 360.0)
+        );
+        // Too small for even one multiple, so it shrinks like Fit
+        assert_eq!(
+            integer.area(vec2(200.0, 150.0)),
+            rect(0.0, 18.0, 200.0, 113.0)
+        );
+    }
+
+    #[test]
+    fn fit_keeps_the_aspect_ratio() {
+        let fit = scaling(ScaleMode::Fit);
+        assert_eq!(
+            fit.area(vec2(1000.0, 600.0)),
+            rect(0.0, 18.0, 1000.0, 563.0)
+        );
+        assert_eq!(
+            fit.area(vec2(1000.0, 400.0)),
+            rect(144.0, 0.0, 711.0, 400.0)
+        );
+        assert_eq!(
+            fit.area(vec2(400.0, 1000.0)),
+            rect(0.0, 387.0, 400.0, 225.0)
+        );
+        assert_eq!(fit.area(vec2(160.0, 90.0)), rect(0.0, 0.0, 160.0, 90.0));
+    }
+
+    #[test]
+    fn window_to_ndc_covers_the_area() {
+        let integer = scaling(ScaleMode::Integer);
+        let window = vec2(1000.0, 600.0);
+        assert_eq!(
+            integer.window_to_ndc(vec2(20.0, 30.0), window),
+            vec2(-1.0, 1.0)
+        );
+        assert_eq!(
+            integer.window_to_ndc(vec2(980.0, 570.0), window),
+            vec2(1.0, -1.0)
+        );
+        assert_eq!(
+            integer.window_to_ndc(vec2(500.0, 300.0), window),
+            vec2(0.0, 0.0)
+        );
+
+        // The letterbox is outside [-1, 1]
+        let ndc = integer.window_to_ndc(vec2(10.0, 5.0), window);
+        assert!(ndc.x < -1.0 && ndc.y > 1.0);
+
+        let stretch = scaling(ScaleMode::Stretch);
+        assert_eq!(
+            stretch.window_to_ndc(vec2(250.0, 75.0), vec2(1000.0, 100.0)),
+            vec2(-0.5, -0.5)
+        );
+    }
+
+    #[test]
+    fn window_to_ndc_survives_an_empty_window() {
+        for mode in ScaleMode::ALL {
+            let ndc = scaling(mode).window_to_ndc(vec2(0.0, 0.0), vec2(0.0, 0.0));
+            assert!(ndc.x.is_finite() && ndc.y.is_finite());
+        }
+    }
+
+    #[test]
+    fn target_round_trip() {
+        let window = vec2(1000.0, 400.0);
+        for mode in ScaleMode::ALL {
+            let scaling = scaling(mode);
+            for pixels in [vec2(0.0, 0.0), vec2(37.0, 111.0), vec2(320.0, 180.0)] {
+                let there = scaling.target_to_window(pixels, window);
+                assert!((scaling.window_to_target(there, window) - pixels).length() < 1e-3);
+            }
+        }
+        let fit = scaling(ScaleMode::Fit);
+        assert_eq!(
+            fit.target_to_window(vec2(0.0, 0.0), window),
+            vec2(144.0, 0.0)
+        );
+        assert_eq!(
+            fit.window_to_target(vec2(144.0, 400.0), window),
+            vec2(0.0, 180.0)
+        );
+    }
+
+    #[test]
+    fn modes_cycle() {
+        let mut mode = ScaleMode::Native;
+        for expected in ["Integer", "Fit", "Stretch", "Native"] {
+            mode = mode.next();
+            assert_eq!(mode.name(), expected);
+        }
+    }
+}
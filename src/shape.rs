@@ -0,0 +1,791 @@
+use std::f32::consts::{FRAC_PI_2, TAU};
+use std::ops::Range;
+
+use crate::camera::CameraBuffer;
+use crate::color::Color;
+use crate::geometry::Rect;
+use crate::graphics::{DepthBuffer, Graphics};
+use crate::layer::Layer;
+use crate::nvec::*;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+pub struct ShapeVertex {
+    pub pos: Vec3<f32>,
+    pub color: Color,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Join {
+    // Falls back to `Bevel` past the batch's miter limit
+    Miter,
+    Bevel,
+    Round,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cap {
+    Butt,
+    // Extends past the end by half the width
+    Square,
+    Round,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stroke {
+    pub width: f32,
+    pub color: Color,
+    pub join: Join,
+    pub cap: Cap,
+}
+impl Stroke {
+    pub fn new(width: f32, color: Color) -> Self {
+        Self {
+            width,
+            color,
+            join: Join::Miter,
+            cap: Cap::Butt,
+        }
+    }
+    pub fn join(mut self, join: Join) -> Self {
+        self.join = join;
+        self
+    }
+    pub fn cap(mut self, cap: Cap) -> Self {
+        self.cap = cap;
+        self
+    }
+}
+
+// Cross section of a stroke, where `pos + left * half_width` is its left
+// edge. The offsets are longer than one at miters
+#[derive(Clone, Copy)]
+struct Section {
+    pos: Vec2<f32>,
+    left: Vec2<f32>,
+    right: Vec2<f32>,
+    alpha: f32,
+}
+impl Section {
+    fn new(pos: Vec2<f32>, left: Vec2<f32>, right: Vec2<f32>) -> Self {
+        Self {
+            pos,
+            left,
+            right,
+            alpha: 1.0,
+        }
+    }
+}
+
+// Triangles tessellated on the CPU, rebuilt every frame like the sprites.
+// Edges fade out over `feather`, which gives antialiasing without MSAA
+pub struct ShapeBatch {
+    vertices: Vec<ShapeVertex>,
+    indices: Vec<u32>,
+    // About one pixel in the units drawn in
+    pub feather: f32,
+    // Applied to shapes added afterwards, see `Layer::depth`
+    pub depth: f32,
+    // Longest miter allowed, in half widths
+    pub miter_limit: f32,
+}
+impl ShapeBatch {
+    pub const MAX_VERTICES: usize = 64 * 1024;
+    pub const MAX_INDICES: usize = Self::MAX_VERTICES * 3;
+
+    pub fn new() -> Self {
+        Self {
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            feather: 1.0,
+            depth: Layer::Particles.depth(0.5),
+            miter_limit: 4.0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+        self.indices.clear();
+    }
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    // Stable, so shapes at the same depth keep the order they were added in
+    pub fn sort_back_to_front(&mut self) {
+        let vertices = &self.vertices;
+        let depth = |triangle: &[u32; 3]| vertices[triangle[0] as usize].pos.z;
+        let triangles: &mut [[u32; 3]] = bytemuck::cast_slice_mut(&mut self.indices);
+        triangles.sort_by(|a, b| depth(b).total_cmp(&depth(a)));
+    }
+
+    // Indices of the triangles within `layer`, once sorted
+    pub fn layer_range(&self, layer: Layer) -> Range<u32> {
+        let layer_of =
+            |triangle: &[u32; 3]| Layer::of_depth(self.vertices[triangle[0] as usize].pos.z);
+        let triangles: &[[u32; 3]] = bytemuck::cast_slice(&self.indices);
+        let start = triangles.partition_point(|triangle| layer_of(triangle) < layer);
+        let end = triangles.partition_point(|triangle| layer_of(triangle) <= layer);
+        start as u32 * 3..end as u32 * 3
+    }
+
+    // Filled outline of any simple polygon, in either winding
+    pub fn polygon(&mut self, points: &[Vec2<f32>], color: Color) {
+        let mut points = dedup(points, true);
+        if points.len() < 3 {
+            return;
+        }
+        let area: f32 = (0..points.len())
+            .map(|i| points[i].perp_dot(points[(i + 1) % points.len()]))
+            .sum();
+        if area < 0.0 {
+            points.reverse();
+        }
+
+        let (vertices, indices) = (self.vertices.len(), self.indices.len());
+        let base = vertices as u32;
+        let count = points.len();
+        let clear = color.with_alpha(0.0);
+        let half = self.feather * 0.5;
+
+        // An inner ring at full colour and an outer one faded out, each half
+        // a feather from the outline
+        for i in 0..count {
+            let prev = points[(i + count - 1) % count];
+            let next = points[(i + 1) % count];
+            // Outwards is to the right of a counter-clockwise outline
+            let n0 = -(points[i] - prev).normalize().perp();
+            let n1 = -(next - points[i]).normalize().perp();
+            let miter = n0 + n1;
+            let miter = match miter.length_squared() > 1e-6 {
+                true => miter.normalize(),
+                false => n0,
+            };
+            // Limited, so sharp corners do not spike
+            let offset = miter * (half / miter.dot(n1).max(0.25));
+            self.vertex(points[i] - offset, color);
+            self.vertex(points[i] + offset, clear);
+        }
+        for i in 0..count as u32 {
+            let j = (i + 1) % count as u32;
+            self.quad(
+                base + i * 2,
+                base + i * 2 + 1,
+                base + j * 2,
+                base + j * 2 + 1,
+            );
+        }
+        for triangle in triangulate(&points) {
+            self.indices
+                .extend(triangle.iter().map(|index| base + index * 2));
+        }
+
+        self.commit(vertices, indices);
+    }
+
+    pub fn polyline(&mut self, points: &[Vec2<f32>], closed: bool, stroke: Stroke) {
+        let points = dedup(points, closed);
+        if points.len() < 2 || stroke.width <= 0.0 {
+            return;
+        }
+        let closed = closed && points.len() > 2;
+
+        // Strokes thinner than the feather fade instead of thinning further
+        let (half, alpha) = match stroke.width < self.feather {
+            true => (self.feather * 0.5, stroke.width / self.feather),
+            false => (stroke.width * 0.5, 1.0),
+        };
+        let color = stroke.color.with_alpha(stroke.color.a * alpha);
+
+        let count = points.len();
+        let dir = |i: usize| (points[(i + 1) % count] - points[i]).normalize();
+        let length = |i: usize| (points[(i + 1) % count] - points[i]).length();
+
+        let mut sections = Vec::new();
+        if !closed {
+            sections.extend(self.cap(points[0], dir(0), half, stroke.cap));
+        }
+        let joins = match closed {
+            true => 0..count,
+            false => 1..count - 1,
+        };
+        for i in joins {
+            let before = (i + count - 1) % count;
+            let shortest = length(before).min(length(i));
+            sections.extend(self.join(points[i], dir(before), dir(i), half, shortest, stroke.join));
+        }
+        if !closed {
+            // Built as a start cap facing back along the line, then turned
+            // around to continue the sections
+            let end = self.cap(points[count - 1], -dir(count - 2), half, stroke.cap);
+            sections.extend(end.into_iter().rev().map(|section| Section {
+                left: section.right,
+                right: section.left,
+                ..section
+            }));
+        }
+
+        self.stroke_sections(&sections, closed, half, color);
+    }
+
+    pub fn line(&mut self, a: Vec2<f32>, b: Vec2<f32>, stroke: Stroke) {
+        self.polyline(&[a, b], false, stroke);
+    }
+
+    pub fn rect(&mut self, rect: Rect, color: Color) {
+        self.polygon(&rect.corners(), color);
+    }
+    pub fn rect_outline(&mut self, rect: Rect, stroke: Stroke) {
+        self.polyline(&rect.corners(), true, stroke);
+    }
+
+    pub fn circle(&mut self, center: Vec2<f32>, radius: f32, color: Color) {
+        let mut points = self.arc_points(center, radius, 0.0, TAU);
+        points.pop();
+        self.polygon(&points, color);
+    }
+    pub fn circle_outline(&mut self, center: Vec2<f32>, radius: f32, stroke: Stroke) {
+        let mut points = self.arc_points(center, radius, 0.0, TAU);
+        points.pop();
+        self.polyline(&points, true, stroke);
+    }
+
+    // Counter-clockwise from `start` to `end`, in radians
+    pub fn arc(&mut self, center: Vec2<f32>, radius: f32, start: f32, end: f32, stroke: Stroke) {
+        let points = self.arc_points(center, radius, start, end);
+        self.polyline(&points, false, stroke);
+    }
+
+    pub fn quadratic(&mut self, a: Vec2<f32>, control: Vec2<f32>, b: Vec2<f32>, stroke: Stroke) {
+        let length = (control - a).length() + (b - control).length();
+        let points = self.flatten(length, |t| {
+            let s = 1.0 - t;
+            a * (s * s) + control * (2.0 * s * t) + b * (t * t)
+        });
+        self.polyline(&points, false, stroke);
+    }
+
+    pub fn cubic(
+        &mut self,
+        a: Vec2<f32>,
+        c0: Vec2<f32>,
+        c1: Vec2<f32>,
+        b: Vec2<f32>,
+        stroke: Stroke,
+    ) {
+        let length = (c0 - a).length() + (c1 - c0).length() + (b - c1).length();
+        let points = self.flatten(length, |t| {
+            let s = 1.0 - t;
+            a * (s * s * s) + c0 * (3.0 * s * s * t) + c1 * (3.0 * s * t * t) + b * (t * t * t)
+        });
+        self.polyline(&points, false, stroke);
+    }
+
+    // Enough points that no chord strays more than a quarter feather from
+    // the circle
+    fn arc_points(&self, center: Vec2<f32>, radius: f32, start: f32, end: f32) -> Vec<Vec2<f32>> {
+        if radius <= 0.0 {
+            return Vec::new();
+        }
+        let tolerance = (self.feather * 0.25).max(1e-3).min(radius);
+        let step = 2.0 * (1.0 - tolerance / radius).acos();
+        let sweep = end - start;
+        let segments = ((sweep.abs() / step).ceil() as usize).clamp(4, 256);
+        (0..=segments)
+            .map(|i| {
+                let (sin, cos) = (start + sweep * i as f32 / segments as f32).sin_cos();
+                center + vec2(cos, sin) * radius
+            })
+            .collect()
+    }
+
+    // `length` is the length of the control polygon, an upper bound on the
+    // curve's
+    fn flatten(&self, length: f32, curve: impl Fn(f32) -> Vec2<f32>) -> Vec<Vec2<f32>> {
+        let segments = ((length / (self.feather * 4.0)).ceil() as usize).clamp(4, 128);
+        (0..=segments)
+            .map(|i| curve(i as f32 / segments as f32))
+            .collect()
+    }
+
+    fn vertex(&mut self, pos: Vec2<f32>, color: Color) -> u32 {
+        self.vertices.push(ShapeVertex {
+            pos: vec3(pos.x, pos.y, self.depth),
+            color,
+        });
+        (self.vertices.len() - 1) as u32
+    }
+
+    // `a` and `b` are one edge, `c` and `d` the opposite one in the same order
+    fn quad(&mut self, a: u32, b: u32, c: u32, d: u32) {
+        self.indices.extend_from_slice(&[a, b, c, b, d, c]);
+    }
+
+    // Drops the shape started at the given counts if it overflowed the buffers
+    fn commit(&mut self, vertices: usize, indices: usize) {
+        if self.vertices.len() > Self::MAX_VERTICES || self.indices.len() > Self::MAX_INDICES {
+            self.vertices.truncate(vertices);
+            self.indices.truncate(indices);
+        }
+    }
+
+    // Sections from the tip of the cap to the full width, `dir` points into
+    // the line
+    fn cap(&self, pos: Vec2<f32>, dir: Vec2<f32>, half: f32, cap: Cap) -> Vec<Section> {
+        let normal = dir.perp();
+        match cap {
+            Cap::Butt | Cap::Square => {
+                let end = match cap {
+                    Cap::Square => pos - dir * half,
+                    _ => pos,
+                };
+                // Fades in across the end, like the feathered sides
+                let fade = dir * (self.feather * 0.5);
+                vec![
+                    Section {
+                        alpha: 0.0,
+                        ..Section::new(end - fade, normal, -normal)
+                    },
+                    Section::new(end + fade, normal, -normal),
+                ]
+            }
+            Cap::Round => {
+                let steps = self.arc_points(pos, half, 0.0, FRAC_PI_2).len() - 1;
+                (0..=steps)
+                    .map(|step| {
+                        let (sin, cos) = (FRAC_PI_2 * step as f32 / steps as f32).sin_cos();
+                        let back = -dir * cos;
+                        Section::new(pos, back + normal * sin, back - normal * sin)
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    // `shortest` is the length of the shorter neighbouring segment, which
+    // limits how far the inner corner can reach
+    fn join(
+        &self,
+        pos: Vec2<f32>,
+        d0: Vec2<f32>,
+        d1: Vec2<f32>,
+        half: f32,
+        shortest: f32,
+        join: Join,
+    ) -> Vec<Section> {
+        let (n0, n1) = (d0.perp(), d1.perp());
+        let miter = n0 + n1;
+        if miter.length_squared() < 1e-6 {
+            // Doubles straight back, only a bevel fits
+            return vec![Section::new(pos, n0, -n0), Section::new(pos, n1, -n1)];
+        }
+        let miter = miter.normalize();
+        let scale = 1.0 / miter.dot(n1).max(1e-3);
+        let turn = d0.perp_dot(d1);
+        if turn.abs() < 1e-4 || (join == Join::Miter && scale <= self.miter_limit) {
+            return vec![Section::new(pos, miter * scale, -miter * scale)];
+        }
+
+        let inner = miter * scale.min((shortest / half).max(1.0));
+        // Outer edge directions, from the incoming segment to the outgoing one
+        let (from, to) = match turn > 0.0 {
+            true => (-n0, -n1),
+            false => (n0, n1),
+        };
+        let outer: Vec<Vec2<f32>> = match join {
+            Join::Round => {
+                let sweep = from.perp_dot(to).atan2(from.dot(to));
+                let start = from.y.atan2(from.x);
+                let arc = self.arc_points(vec2(0.0, 0.0), half, start, start + sweep);
+                arc.into_iter().map(|point| point / half).collect()
+            }
+            _ => vec![from, to],
+        };
+        outer
+            .into_iter()
+            .map(|dir| match turn > 0.0 {
+                true => Section::new(pos, inner, dir),
+                false => Section::new(pos, dir, -inner),
+            })
+            .collect()
+    }
+
+    // Four vertices per section, faded out at the sides, joined by quads
+    fn stroke_sections(&mut self, sections: &[Section], closed: bool, half: f32, color: Color) {
+        if sections.len() < 2 {
+            return;
+        }
+        let (vertices, indices) = (self.vertices.len(), self.indices.len());
+        let base = vertices as u32;
+        let inner = (half - self.feather * 0.5).max(0.0);
+        let outer = half + self.feather * 0.5;
+        let clear = color.with_alpha(0.0);
+
+        for section in sections {
+            let solid = color.with_alpha(color.a * section.alpha);
+            self.vertex(section.pos + section.left * outer, clear);
+            self.vertex(section.pos + section.left * inner, solid);
+            self.vertex(section.pos + section.right * inner, solid);
+            self.vertex(section.pos + section.right * outer, clear);
+        }
+
+        let count = sections.len() as u32;
+        let spans = match closed {
+            true => count,
+            false => count - 1,
+        };
+        for i in 0..spans {
+            let (a, b) = (base + i * 4, base + (i + 1) % count * 4);
+            for side in 0..3 {
+                self.quad(a + side, a + side + 1, b + side, b + side + 1);
+            }
+        }
+
+        self.commit(vertices, indices);
+    }
+}
+impl Default for ShapeBatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Without repeated points, which have no direction. Closed outlines also
+// drop a last point equal to the first
+fn dedup(points: &[Vec2<f32>], closed: bool) -> Vec<Vec2<f32>> {
+    let same = |a: Vec2<f32>, b: Vec2<f32>| (a - b).length_squared() < 1e-10;
+    let mut out: Vec<Vec2<f32>> = Vec::with_capacity(points.len());
+    for point in points {
+        if !out.last().is_some_and(|last| same(*last, *point)) {
+            out.push(*point);
+        }
+    }
+    if closed && out.len() > 1 && same(out[0], out[out.len() - 1]) {
+        out.pop();
+    }
+    out
+}
+
+// Ear clipping for a counter-clockwise simple polygon. Outlines that cross
+// themselves run out of ears and the rest is filled as a fan
+fn triangulate(points: &[Vec2<f32>]) -> Vec<[u32; 3]> {
+    // Edges count, or an ear could cut past a reflex corner on its diagonal
+    let inside = |p: Vec2<f32>, a: Vec2<f32>, b: Vec2<f32>, c: Vec2<f32>| {
+        (b - a).perp_dot(p - a) >= 0.0
+            && (c - b).perp_dot(p - b) >= 0.0
+            && (a - c).perp_dot(p - c) >= 0.0
+    };
+
+    let mut remaining: Vec<u32> = (0..points.len() as u32).collect();
+    let mut triangles = Vec::with_capacity(points.len());
+    let (mut i, mut misses) = (0, 0);
+    while remaining.len() > 3 {
+        let count = remaining.len();
+        i %= count;
+        let (a, b, c) = (
+            remaining[(i + count - 1) % count],
+            remaining[i],
+            remaining[(i + 1) % count],
+        );
+        let (pa, pb, pc) = (points[a as usize], points[b as usize], points[c as usize]);
+        let ear = (pb - pa).perp_dot(pc - pb) >= 0.0
+            && remaining
+                .iter()
+                .filter(|&&k| k != a && k != b && k != c)
+                .all(|&k| !inside(points[k as usize], pa, pb, pc));
+
+        if ear {
+            triangles.push([a, b, c]);
+            remaining.remove(i);
+            misses = 0;
+        } else {
+            i += 1;
+            misses += 1;
+            if misses > count {
+                break;
+            }
+        }
+    }
+    for k in 1..remaining.len().saturating_sub(1) {
+        triangles.push([remaining[0], remaining[k], remaining[k + 1]]);
+    }
+    triangles
+}
+
+pub struct ShapeRenderer {
+    pipeline: wgpu::RenderPipeline,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+}
+impl ShapeRenderer {
+    const VERTEX_ATTRIBUTES: &[wgpu::VertexAttribute] =
+        &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4];
+    const VERTEX_LAYOUT: wgpu::VertexBufferLayout<'static> = wgpu::VertexBufferLayout {
+        array_stride: std::mem::size_of::<ShapeVertex>() as wgpu::BufferAddress,
+        step_mode: wgpu::VertexStepMode::Vertex,
+        attributes: Self::VERTEX_ATTRIBUTES,
+    };
+
+    pub fn new(gfx: &Graphics) -> Self {
+        let shader = gfx.load_shader("shaders/shape.wgsl");
+        let layout = gfx
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("ShapeRenderer.Pipeline.Layout"),
+                bind_group_layouts: &[&CameraBuffer::layout(gfx)],
+                push_constant_ranges: &[],
+            });
+
+        let pipeline = gfx
+            .device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("ShapeRenderer.Pipeline"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vert_main",
+                    buffers: &[Self::VERTEX_LAYOUT],
+                },
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: None,
+                    unclipped_depth: false,
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    conservative: false,
+                },
+                // Tested but not written, so faded edges never hide what
+                // is drawn behind them afterwards
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    depth_write_enabled: false,
+                    ..DepthBuffer::state()
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: 1,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "frag_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: gfx.get_format(),
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                }),
+                multiview: None,
+            });
+
+        let create_buffer = |label, size, usage| {
+            gfx.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: size as wgpu::BufferAddress,
+                usage: usage | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        };
+        let vertex_buffer = create_buffer(
+            "ShapeRenderer.VertexBuffer",
+            std::mem::size_of::<ShapeVertex>() * ShapeBatch::MAX_VERTICES,
+            wgpu::BufferUsages::VERTEX,
+        );
+        let index_buffer = create_buffer(
+            "ShapeRenderer.IndexBuffer",
+            std::mem::size_of::<u32>() * ShapeBatch::MAX_INDICES,
+            wgpu::BufferUsages::INDEX,
+        );
+
+        Self {
+            pipeline,
+            vertex_buffer,
+            index_buffer,
+        }
+    }
+
+    // Once per frame, after `ShapeBatch::sort_back_to_front`
+    pub fn upload(&self, gfx: &Graphics, batch: &ShapeBatch) {
+        if batch.is_empty() {
+            return;
+        }
+        gfx.queue.write_buffer(
+            &self.vertex_buffer,
+            0,
+            bytemuck::cast_slice(&batch.vertices),
+        );
+        gfx.queue
+            .write_buffer(&self.index_buffer, 0, bytemuck::cast_slice(&batch.indices));
+    }
+
+    // World space, the pass needs a `DepthBuffer`
+    pub fn render<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        camera: &'a CameraBuffer,
+        batch: &ShapeBatch,
+        layer: Layer,
+    ) {
+        let indices = batch.layer_range(layer);
+        if indices.is_empty() {
+            return;
+        }
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &camera.bind_group, &[]);
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        pass.draw_indexed(indices, 0, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn area(points: &[Vec2<f32>], triangle: [u32; 3]) -> f32 {
+        let [a, b, c] = triangle.map(|index| points[index as usize]);
+        (b - a).perp_dot(c - a) * 0.5
+    }
+
+    #[test]
+    fn triangulate_concave() {
+        // An L, counter-clockwise, with a reflex corner at (1, 1)
+        let points = [
+            vec2(0.0, 0.0),
+            vec2(2.0, 0.0),
+            vec2(2.0, 1.0),
+            vec2(1.0, 1.0),
+            vec2(1.0, 2.0),
+            vec2(0.0, 2.0),
+        ];
+        let triangles = triangulate(&points);
+        assert_eq!(triangles.len(), points.len() - 2);
+        for triangle in triangles.iter() {
+            assert!(area(&points, *triangle) > 0.0);
+        }
+        let total: f32 = triangles
+            .iter()
+            .map(|triangle| area(&points, *triangle))
+            .sum();
+        assert!((total - 3.0).abs() < 1e-5);
+    }
+
+    // Whether `p` is in any of the triangles, whichever way they wind
+    fn covered(points: &[Vec2<f32>], triangles: &[[u32; 3]], p: Vec2<f32>) -> bool {
+        triangles.iter().any(|triangle| {
+            let [a, b, c] = triangle.map(|index| points[index as usize]);
+            let sides = [
+                (b - a).perp_dot(p - a),
+                (c - b).perp_dot(p - b),
+                (a - c).perp_dot(p - c),
+            ];
+            sides.iter().all(|side| *side >= 0.0) || sides.iter().all(|side| *side <= 0.0)
+        })
+    }
+
+    #[test]
+    fn triangulate_self_crossing() {
+        // A bow tie, crossing itself at (1, 1). Its two lobes must be filled
+        // without gaps, though the fan may draw past them
+        let points = [
+            vec2(0.0, 0.0),
+            vec2(2.0, 2.0),
+            vec2(2.0, 0.0),
+            vec2(0.0, 2.0),
+        ];
+        let triangles = triangulate(&points);
+        assert_eq!(triangles.len(), points.len() - 2);
+
+        let in_lobes = |p: Vec2<f32>| (p.x < p.y) == (p.x + p.y < 2.0);
+        // Each sample stands for the area of a 0.1 by 0.1 cell
+        let cell = 0.01;
+        let (mut lobes, mut filled) = (0.0, 0.0);
+        for y in 0..20 {
+            for x in 0..20 {
+                // Off centre, so no sample lands on a diagonal
+                let p = vec2(x as f32 + 0.25, y as f32 + 0.4) * 0.1;
+                if in_lobes(p) {
+                    assert!(covered(&points, &triangles, p), "{p:?}");
+                    lobes += cell;
+                }
+                if covered(&points, &triangles, p) {
+                    filled += cell;
+                }
+            }
+        }
+        assert!((lobes - 2.0f32).abs() < 0.1);
+        // The fan overdraws one of the two wedges between the lobes
+        assert!((filled - 3.0f32).abs() < 0.1);
+    }
+
+    #[test]
+    fn dedup_repeats() {
+        let a = vec2(0.0, 0.0);
+        let b = vec2(1.0, 0.0);
+        let c = vec2(1.0, 1.0);
+        assert_eq!(dedup(&[a, a, b, b, b, c, a], false), vec![a, b, c, a]);
+        assert_eq!(dedup(&[a, a, b, b, b, c, a], true), vec![a, b, c]);
+        assert_eq!(dedup(&[a, a], true), vec![a]);
+        assert!(dedup(&[], true).is_empty());
+    }
+
+    // Four vertices per section and three quads between neighbouring ones
+    fn assert_sections(batch: &ShapeBatch, sections: usize, closed: bool) {
+        let spans = if closed { sections } else { sections - 1 };
+        assert_eq!(batch.vertices.len(), sections * 4);
+        assert_eq!(batch.indices.len(), spans * 18);
+    }
+
+    #[test]
+    fn polyline_counts() {
+        // Two units wide with a feather of one, so round parts take the
+        // fewest segments, four per quarter turn
+        let corner = [vec2(0.0, 0.0), vec2(10.0, 0.0), vec2(10.0, 10.0)];
+        let cases = [
+            (Join::Miter, Cap::Butt, 2 + 1 + 2),
+            (Join::Bevel, Cap::Butt, 2 + 2 + 2),
+            (Join::Round, Cap::Butt, 2 + 5 + 2),
+            (Join::Miter, Cap::Square, 2 + 1 + 2),
+            (Join::Miter, Cap::Round, 5 + 1 + 5),
+        ];
+        for (join, cap, sections) in cases {
+            let mut batch = ShapeBatch::new();
+            batch.polyline(
+                &corner,
+                false,
+                Stroke::new(2.0, Color::WHITE).join(join).cap(cap),
+            );
+            assert_sections(&batch, sections, false);
+        }
+
+        // Closed outlines have no caps
+        let square = [
+            vec2(0.0, 0.0),
+            vec2(4.0, 0.0),
+            vec2(4.0, 4.0),
+            vec2(0.0, 4.0),
+        ];
+        let mut batch = ShapeBatch::new();
+        batch.polyline(
+            &square,
+            true,
+            Stroke::new(2.0, Color::WHITE).cap(Cap::Round),
+        );
+        assert_sections(&batch, 4, true);
+
+        // Past the miter limit a miter falls back to a bevel
+        let spike = [vec2(0.0, 0.0), vec2(10.0, 0.0), vec2(0.0, 0.5)];
+        let mut batch = ShapeBatch::new();
+        batch.polyline(&spike, false, Stroke::new(2.0, Color::WHITE));
+        assert_sections(&batch, 2 + 2 + 2, false);
+
+        // Too few points after dedup draw nothing
+        let mut batch = ShapeBatch::new();
+        batch.polyline(
+            &[corner[0], corner[0]],
+            false,
+            Stroke::new(2.0, Color::WHITE),
+        );
+        assert!(batch.is_empty());
+    }
+}
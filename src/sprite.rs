@@ -1,10 +1,17 @@
-use std::collections::HashMap;
+use std::rc::Rc;
 
-use crate::graphics::Graphics;
+use crate::animation::Clip;
+use crate::aseprite::Aseprite;
+use crate::camera::CameraBuffer;
+use crate::color::Color;
+use crate::graphics::{DepthBuffer, Graphics};
+use crate::layer::*;
 use crate::nvec::*;
+use crate::packer::PackedAtlas;
 
 struct Atlas {
-    texture: wgpu::Texture,
+    // Only read through the bind group, held so it lives as long as the atlas
+    _texture: wgpu::Texture,
     bind_group: wgpu::BindGroup,
 }
 impl Atlas {
@@ -34,12 +41,55 @@ impl Atlas {
     }
 
     fn new(gfx: &Graphics, path: &str) -> Self {
-        use wgpu::util::DeviceExt;
+        if path.ends_with(".ase") || path.ends_with(".aseprite") {
+            let (sheet, _) = Aseprite::load(path).sheet();
+            return Self::from_rgba(gfx, path, sheet);
+        }
 
-        let image_file = std::fs::read(path).expect(format!("Cannot read {path}").as_str());
+        let image_file = std::fs::read(path).unwrap_or_else(|_| panic!("Cannot read {path}"));
 
         let image = image::load_from_memory(&image_file)
-            .expect(format!("Could not parse file {path}").as_str());
+            .unwrap_or_else(|_| panic!("Could not parse file {path}"));
+
+        Self::from_image(gfx, path, &image, wgpu::TextureFormat::Rgba8UnormSrgb)
+    }
+
+    fn from_rgba(gfx: &Graphics, path: &str, image: image::RgbaImage) -> Self {
+        Self::from_image(
+            gfx,
+            path,
+            &image::DynamicImage::ImageRgba8(image),
+            wgpu::TextureFormat::Rgba8UnormSrgb,
+        )
+    }
+
+    // Normal maps hold directions rather than colours, so are not sRGB
+    fn normal_map(gfx: &Graphics, path: &str) -> Self {
+        let image_file = std::fs::read(path).unwrap_or_else(|_| panic!("Cannot read {path}"));
+
+        let image = image::load_from_memory(&image_file)
+            .unwrap_or_else(|_| panic!("Could not parse file {path}"));
+
+        Self::from_image(gfx, path, &image, wgpu::TextureFormat::Rgba8Unorm)
+    }
+    // Facing the viewer everywhere, for sprites without a normal map
+    fn flat_normal(gfx: &Graphics) -> Self {
+        let image = image::RgbaImage::from_pixel(1, 1, image::Rgba([128, 128, 255, 255]));
+        Self::from_image(
+            gfx,
+            "FlatNormal",
+            &image::DynamicImage::ImageRgba8(image),
+            wgpu::TextureFormat::Rgba8Unorm,
+        )
+    }
+
+    fn from_image(
+        gfx: &Graphics,
+        path: &str,
+        image: &image::DynamicImage,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        use wgpu::util::DeviceExt;
 
         let extent = wgpu::Extent3d {
             width: image.width(),
@@ -55,7 +105,7 @@ impl Atlas {
                 mip_level_count: 1,
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                format,
                 usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
                 view_formats: &[],
             },
@@ -92,82 +142,349 @@ impl Atlas {
         });
 
         Self {
-            texture,
+            _texture: texture,
             bind_group,
         }
     }
 }
 
 #[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Zeroable, bytemuck::Pod)]
 pub struct UVRect {
     pub a: Vec2<f32>,
     pub b: Vec2<f32>,
 }
+impl UVRect {
+    // `count` cells of `size` read left to right from `origin`, `columns` per
+    // row, as laid out in most sprite sheets
+    pub fn grid(origin: Vec2<f32>, size: Vec2<f32>, count: usize, columns: usize) -> Vec<UVRect> {
+        let columns = columns.max(1);
+        (0..count)
+            .map(|index| {
+                let cell = vec2((index % columns) as f32, (index / columns) as f32);
+                let a = origin + cell * size;
+                UVRect { a, b: a + size }
+            })
+            .collect()
+    }
+}
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Zeroable, bytemuck::Pod)]
 pub struct Sprite {
     pub pos: Vec3<f32>,
     pub scale: Vec2<f32>,
-    pub color: Vec4<f32>,
+    pub color: Color,
     pub rect: UVRect,
+    // Radians counter-clockwise around `pivot`
+    pub rotation: f32,
+    // Point of the quad placed at `pos`, from -1 to 1 on each axis with 0
+    // being the centre
+    pub pivot: Vec2<f32>,
+    // `FLIP_X` and `FLIP_Y` bits
+    pub flip: u32,
+}
+impl Sprite {
+    pub const FLIP_X: u32 = 1;
+    pub const FLIP_Y: u32 = 2;
+
+    pub fn new(pos: Vec3<f32>, scale: Vec2<f32>, color: Color, rect: UVRect) -> Self {
+        Self {
+            pos,
+            scale,
+            color,
+            rect,
+            rotation: 0.0,
+            pivot: vec2(0.0, 0.0),
+            flip: 0,
+        }
+    }
+
+    pub fn rotated(mut self, rotation: f32) -> Self {
+        self.rotation = rotation;
+        self
+    }
+    pub fn pivot(mut self, pivot: Vec2<f32>) -> Self {
+        self.pivot = pivot;
+        self
+    }
+    pub fn flipped(mut self, x: bool, y: bool) -> Self {
+        self.flip = if x { Self::FLIP_X } else { 0 } | if y { Self::FLIP_Y } else { 0 };
+        self
+    }
+}
+
+impl Layered for Sprite {
+    fn depth(&self) -> f32 {
+        self.pos.z
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendMode {
+    Alpha,
+    // Brightens what is behind, for glows and spell effects
+    Additive,
+    // Darkens what is behind, for shadows and tints
+    Multiply,
+}
+impl BlendMode {
+    const ALL: [BlendMode; 3] = [BlendMode::Alpha, BlendMode::Additive, BlendMode::Multiply];
+
+    fn state(self) -> wgpu::BlendState {
+        // Alpha of the target is kept by the modes that do not cover it
+        let keep_alpha = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::Zero,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        match self {
+            BlendMode::Alpha => wgpu::BlendState::ALPHA_BLENDING,
+            BlendMode::Additive => wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::One,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: keep_alpha,
+            },
+            // Premultiplied, so transparent texels leave the target as is
+            BlendMode::Multiply => wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::Dst,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: keep_alpha,
+            },
+        }
+    }
+    fn entry_point(self) -> &'static str {
+        match self {
+            BlendMode::Alpha => "frag_main",
+            BlendMode::Additive | BlendMode::Multiply => "frag_premultiplied",
+        }
+    }
 }
 
 pub struct SpriteGroup {
-    atlas: Atlas,
+    // Shared between groups made with `sharing`
+    atlas: Rc<Atlas>,
+    // Laid out like the atlas, used by the lighting
+    normals: Option<Rc<Atlas>>,
+    blend: BlendMode,
     data: Vec<Sprite>,
+    capacity: usize,
     buffer: wgpu::Buffer,
 }
 impl SpriteGroup {
     const MAX_SIZE: wgpu::BufferAddress = 128 * 1024;
 
     pub fn new(gfx: &Graphics, atlas_path: &str, instances: usize) -> Self {
-        let atlas = Atlas::new(gfx, atlas_path);
+        Self::with_atlas(gfx, Atlas::new(gfx, atlas_path), instances)
+    }
+
+    // Like `new`, with room for `capacity` sprites rather than the maximum
+    pub fn with_capacity(gfx: &Graphics, atlas_path: &str, capacity: usize) -> Self {
+        let atlas = Rc::new(Atlas::new(gfx, atlas_path));
+        Self::with_buffer(gfx, atlas, capacity, capacity)
+    }
+
+    // Every frame of the file goes into the atlas, with a clip per tag
+    pub fn from_aseprite(gfx: &Graphics, path: &str, instances: usize) -> (Self, Vec<Clip>) {
+        let ase = Aseprite::load(path);
+        let (sheet, rects) = ase.sheet();
+        let atlas = Atlas::from_rgba(gfx, path, sheet);
+        (Self::with_atlas(gfx, atlas, instances), ase.clips(&rects))
+    }
+
+    // One page of a packed atlas, look sprites up with `PackedAtlas::rect`
+    pub fn from_packed(gfx: &Graphics, atlas: &PackedAtlas, page: usize, instances: usize) -> Self {
+        let label = format!("Packed[{page}]");
+        let atlas = Atlas::from_rgba(gfx, &label, atlas.pages[page].clone());
+        Self::with_atlas(gfx, atlas, instances)
+    }
+
+    // An atlas made at runtime, holding at most `capacity` sprites
+    pub fn from_image(gfx: &Graphics, label: &str, image: image::RgbaImage, capacity: usize) -> Self {
+        let atlas = Rc::new(Atlas::from_rgba(gfx, label, image));
+        Self::with_buffer(gfx, atlas, capacity, capacity)
+    }
 
-        let data = Vec::new();
+    fn with_atlas(gfx: &Graphics, atlas: Atlas, instances: usize) -> Self {
+        Self::with_buffer(gfx, Rc::new(atlas), instances, Self::MAX_SIZE as usize)
+    }
+
+    fn with_buffer(gfx: &Graphics, atlas: Rc<Atlas>, instances: usize, capacity: usize) -> Self {
+        let data = Vec::with_capacity(instances);
         let buffer = gfx.device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("SpriteGroup"),
-            size: Self::MAX_SIZE * std::mem::size_of::<Sprite>() as wgpu::BufferAddress,
+            size: capacity.max(1) as wgpu::BufferAddress
+                * std::mem::size_of::<Sprite>() as wgpu::BufferAddress,
             usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
 
         Self {
             atlas,
+            normals: None,
+            blend: BlendMode::Alpha,
             data,
+            capacity,
             buffer,
         }
     }
-}
 
-#[derive(Clone, Copy, Debug)]
-pub struct AtlasID(usize);
+    // Another group drawing from the same atlas and normal map, holding at
+    // most `capacity` sprites. Cheap enough to split a tilemap into chunks
+    pub fn sharing(&self, gfx: &Graphics, capacity: usize) -> Self {
+        let mut group = Self::with_buffer(gfx, self.atlas.clone(), capacity, capacity);
+        group.normals = self.normals.clone();
+        group.blend = self.blend;
+        group
+    }
+
+    pub fn with_normal_map(mut self, gfx: &Graphics, path: &str) -> Self {
+        self.normals = Some(Rc::new(Atlas::normal_map(gfx, path)));
+        self
+    }
+
+    pub fn with_blend(mut self, blend: BlendMode) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn clear(&mut self) {
+        self.data.clear();
+    }
+    pub fn push(&mut self, sprite: Sprite) {
+        if self.data.len() < self.capacity {
+            self.data.push(sprite);
+        }
+    }
+    pub fn sprites_mut(&mut self) -> &mut Vec<Sprite> {
+        &mut self.data
+    }
+
+    // Sorts back to front before uploading, so translucent sprites blend
+    pub fn upload(&mut self, gfx: &Graphics) {
+        self.data.truncate(self.capacity);
+        sort_back_to_front(&mut self.data);
+        if !self.data.is_empty() {
+            gfx.queue
+                .write_buffer(&self.buffer, 0, bytemuck::cast_slice(&self.data));
+        }
+    }
+}
 
 pub struct SpriteRenderer {
-    pipeline: wgpu::RenderPipeline,
+    // One per `BlendMode`, in `BlendMode::ALL` order
+    pipelines: Vec<wgpu::RenderPipeline>,
+    normal_pipeline: wgpu::RenderPipeline,
+    // Without depth testing, for screen space passes such as the UI
+    overlay_pipeline: wgpu::RenderPipeline,
+    flat_normal: Atlas,
 }
 impl SpriteRenderer {
+    const SPRITE_ATTRIBUTES: &[wgpu::VertexAttribute] = &wgpu::vertex_attr_array![
+        0 => Float32x3, 1 => Float32x2, 2 => Float32x4, 3 => Float32x2, 4 => Float32x2,
+        5 => Float32, 6 => Float32x2, 7 => Uint32
+    ];
+    const SPRITE_LAYOUT: wgpu::VertexBufferLayout<'static> = wgpu::VertexBufferLayout {
+        array_stride: std::mem::size_of::<Sprite>() as wgpu::BufferAddress,
+        step_mode: wgpu::VertexStepMode::Instance,
+        attributes: Self::SPRITE_ATTRIBUTES,
+    };
+    pub const NORMAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
     pub fn new(gfx: &Graphics) -> Self {
+        let shader = gfx.load_shader("shaders/sprite.wgsl");
+
         let layout = gfx
             .device
             .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("SpriteRenderer.Pipeline.Layout"),
-                bind_group_layouts: &[&Atlas::layout(gfx)],
+                bind_group_layouts: &[&Atlas::layout(gfx), &CameraBuffer::layout(gfx)],
                 push_constant_ranges: &[],
             });
+        let pipelines = BlendMode::ALL
+            .iter()
+            .map(|mode| {
+                Self::create_pipeline(
+                    gfx,
+                    &format!("{mode:?}"),
+                    &shader,
+                    &layout,
+                    mode.entry_point(),
+                    Self::target(gfx.get_format(), mode.state()),
+                    Some(DepthBuffer::state()),
+                )
+            })
+            .collect();
 
-        let shader = gfx.load_shader("shaders/sprite.wgsl");
-
-        let pipeline = gfx
+        let normal_layout = gfx
             .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("SpriteRenderer.NormalPipeline.Layout"),
+                bind_group_layouts: &[
+                    &Atlas::layout(gfx),
+                    &CameraBuffer::layout(gfx),
+                    &Atlas::layout(gfx),
+                ],
+                push_constant_ranges: &[],
+            });
+        let normal_pipeline = Self::create_pipeline(
+            gfx,
+            "Normal",
+            &shader,
+            &normal_layout,
+            "frag_normal",
+            Self::target(Self::NORMAL_FORMAT, wgpu::BlendState::REPLACE),
+            Some(DepthBuffer::state()),
+        );
+        let overlay_pipeline = Self::create_pipeline(
+            gfx,
+            "Overlay",
+            &shader,
+            &layout,
+            "frag_main",
+            Self::target(gfx.get_format(), wgpu::BlendState::ALPHA_BLENDING),
+            None,
+        );
+
+        Self {
+            pipelines,
+            normal_pipeline,
+            overlay_pipeline,
+            flat_normal: Atlas::flat_normal(gfx),
+        }
+    }
+
+    fn target(format: wgpu::TextureFormat, blend: wgpu::BlendState) -> wgpu::ColorTargetState {
+        wgpu::ColorTargetState {
+            format,
+            blend: Some(blend),
+            write_mask: wgpu::ColorWrites::ALL,
+        }
+    }
+
+    fn create_pipeline(
+        gfx: &Graphics,
+        name: &str,
+        shader: &wgpu::ShaderModule,
+        layout: &wgpu::PipelineLayout,
+        entry_point: &str,
+        target: wgpu::ColorTargetState,
+        depth_stencil: Option<wgpu::DepthStencilState>,
+    ) -> wgpu::RenderPipeline {
+        gfx.device
             .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: Some("SpriteRenderer.Pipeline"),
-                layout: Some(&layout),
+                label: Some(format!("SpriteRenderer.Pipeline[{name}]").as_str()),
+                layout: Some(layout),
                 vertex: wgpu::VertexState {
-                    module: &shader,
+                    module: shader,
                     entry_point: "vert_main",
-                    buffers: &[],
+                    buffers: &[Self::SPRITE_LAYOUT],
                 },
                 primitive: wgpu::PrimitiveState {
                     topology: wgpu::PrimitiveTopology::TriangleList,
@@ -178,34 +495,81 @@ impl SpriteRenderer {
                     polygon_mode: wgpu::PolygonMode::Fill,
                     conservative: false,
                 },
-                depth_stencil: None,
+                depth_stencil,
                 multisample: wgpu::MultisampleState {
                     count: 1,
                     mask: !0,
                     alpha_to_coverage_enabled: false,
                 },
                 fragment: Some(wgpu::FragmentState {
-                    module: &shader,
-                    entry_point: "frag_main",
-                    targets: &[Some(wgpu::ColorTargetState {
-                        format: gfx.get_format(),
-                        blend: Some(wgpu::BlendState::REPLACE),
-                        write_mask: wgpu::ColorWrites::ALL,
-                    })],
+                    module: shader,
+                    entry_point,
+                    targets: &[Some(target)],
                 }),
                 multiview: None,
-            });
+            })
+    }
 
-        Self {
-            pipeline,
+    // Only the sprites within `layer`. Groups must have been uploaded before
+    // the pass is recorded, and the pass needs a `DepthBuffer`
+    pub fn render<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        camera: &'a CameraBuffer,
+        groups: impl Iterator<Item = &'a SpriteGroup>,
+        layer: Layer,
+    ) {
+        pass.set_bind_group(1, &camera.bind_group, &[]);
+        let mut current = None;
+        for group in groups {
+            let range = layer_range(&group.data, layer);
+            if range.is_empty() {
+                continue;
+            }
+            if current != Some(group.blend) {
+                let index = BlendMode::ALL.iter().position(|mode| *mode == group.blend);
+                pass.set_pipeline(&self.pipelines[index.unwrap()]);
+                current = Some(group.blend);
+            }
+            pass.set_bind_group(0, &group.atlas.bind_group, &[]);
+            pass.set_vertex_buffer(0, group.buffer.slice(..));
+            pass.draw(0..6, range);
         }
     }
 
-    fn render<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>, groups: impl Iterator<Item = &'a SpriteGroup>) {
-        pass.set_pipeline(&self.pipeline);
-        for group in groups {
+    // Alpha blended in draw order, for passes without a `DepthBuffer`
+    pub fn render_overlay<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        camera: &'a CameraBuffer,
+        groups: impl Iterator<Item = &'a SpriteGroup>,
+    ) {
+        pass.set_pipeline(&self.overlay_pipeline);
+        pass.set_bind_group(1, &camera.bind_group, &[]);
+        for group in groups.filter(|group| !group.data.is_empty()) {
+            pass.set_bind_group(0, &group.atlas.bind_group, &[]);
+            pass.set_vertex_buffer(0, group.buffer.slice(..));
+            pass.draw(0..6, 0..group.data.len() as u32);
+        }
+    }
+
+    // Same as `render`, but writes normals into a `NORMAL_FORMAT` target.
+    // Only alpha blended groups are solid enough to be lit
+    pub fn render_normals<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        camera: &'a CameraBuffer,
+        groups: impl Iterator<Item = &'a SpriteGroup>,
+    ) {
+        pass.set_pipeline(&self.normal_pipeline);
+        pass.set_bind_group(1, &camera.bind_group, &[]);
+        let lit = |group: &&SpriteGroup| !group.data.is_empty() && group.blend == BlendMode::Alpha;
+        for group in groups.filter(lit) {
+            let normals = group.normals.as_deref().unwrap_or(&self.flat_normal);
             pass.set_bind_group(0, &group.atlas.bind_group, &[]);
+            pass.set_bind_group(2, &normals.bind_group, &[]);
             pass.set_vertex_buffer(0, group.buffer.slice(..));
+            pass.draw(0..6, 0..group.data.len() as u32);
         }
     }
 }
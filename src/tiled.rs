@@ -0,0 +1,1251 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+
+use crate::geometry::Polygon;
+use crate::graphics::Graphics;
+use crate::layer::Layer;
+use crate::nvec::*;
+use crate::packer::AtlasPacker;
+use crate::sprite::UVRect;
+use crate::tilemap::{self, TileFlags, TileId, Tilemap};
+
+// Reader for maps made with Tiled, see
+// https://doc.mapeditor.org/en/stable/reference/tmx-map-format/
+// Reads .tmj (JSON) and .tmx (XML) maps with embedded or external .tsj/.tsx
+// tilesets. Only finite orthogonal maps are supported and image layers are
+// skipped
+
+// Set on the gids of flipped or rotated tiles
+const GID_FLAGS: u32 = 0xF000_0000;
+// The bits Tiled sets, the hexagonal rotation bit is ignored
+const GID_FLIPS: [(u32, TileFlags); 3] = [
+    (0x8000_0000, TileFlags::FLIP_X),
+    (0x4000_0000, TileFlags::FLIP_Y),
+    (0x2000_0000, TileFlags::FLIP_DIAGONAL),
+];
+// Several tilesets are packed onto a single page of this size
+const PAGE_SIZE: u32 = 2048;
+
+// A gid without its flag bits, and how the tile is flipped
+pub fn split_gid(gid: u32) -> (u32, TileFlags) {
+    let flips = GID_FLIPS
+        .iter()
+        .filter(|(bit, _)| gid & bit != 0)
+        .fold(TileFlags::NONE, |flips, (_, flip)| flips | *flip);
+    (gid & !GID_FLAGS, flips)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Property {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    // Also colors, files and anything else
+    String(String),
+}
+impl Property {
+    fn parse(kind: &str, value: &str) -> Self {
+        let parsed = match kind {
+            "bool" => value.parse().ok().map(Property::Bool),
+            "int" | "object" => value.parse().ok().map(Property::Int),
+            "float" => value.parse().ok().map(Property::Float),
+            _ => None,
+        };
+        parsed.unwrap_or_else(|| Property::String(value.to_string()))
+    }
+
+    fn from_json(kind: &str, value: &json::JsonValue) -> Self {
+        if let Some(value) = value.as_bool() {
+            return Property::Bool(value);
+        }
+        if let Some(value) = value.as_str() {
+            return Property::String(value.to_string());
+        }
+        match (kind, value.as_i64(), value.as_f64()) {
+            ("int" | "object", Some(value), _) => Property::Int(value),
+            (_, _, Some(value)) => Property::Float(value),
+            _ => Property::String(value.dump()),
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Property::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+    pub fn as_f32(&self) -> Option<f32> {
+        match self {
+            Property::Int(value) => Some(*value as f32),
+            Property::Float(value) => Some(*value as f32),
+            _ => None,
+        }
+    }
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Property::String(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+pub type Properties = HashMap<String, Property>;
+
+// Bool properties named "solid", "hazard" and "trigger"
+pub fn flags_of(properties: &Properties) -> TileFlags {
+    let names = [
+        ("solid", TileFlags::SOLID),
+        ("hazard", TileFlags::HAZARD),
+        ("trigger", TileFlags::TRIGGER),
+    ];
+    names
+        .iter()
+        .filter(|(name, _)| properties.get(*name).and_then(Property::as_bool) == Some(true))
+        .fold(TileFlags::NONE, |flags, (_, flag)| flags | *flag)
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TileData {
+    pub class: String,
+    pub properties: Properties,
+    // Tiles of the same tileset and their duration in seconds
+    pub animation: Vec<(u32, f32)>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tileset {
+    pub first_gid: u32,
+    pub name: String,
+    // Relative to the working directory, None for collections of images
+    pub image: Option<String>,
+    pub image_size: Vec2<u32>,
+    pub tile_size: Vec2<u32>,
+    pub margin: u32,
+    pub spacing: u32,
+    pub columns: u32,
+    pub count: u32,
+    // Only tiles with properties or animations are listed
+    pub tiles: HashMap<u32, TileData>,
+}
+impl Tileset {
+    pub fn contains(&self, gid: u32) -> bool {
+        gid >= self.first_gid && gid < self.first_gid + self.count
+    }
+
+    // Atlas region of each tile, in tile order
+    pub fn rects(&self) -> Vec<UVRect> {
+        let image = self.image_size.as_f32();
+        let step = self.tile_size + vec2(self.spacing, self.spacing);
+        (0..self.count)
+            .map(|tile| {
+                let cell = vec2(tile % self.columns.max(1), tile / self.columns.max(1));
+                let a = (cell * step + vec2(self.margin, self.margin)).as_f32() / image;
+                UVRect {
+                    a,
+                    b: a + self.tile_size.as_f32() / image,
+                }
+            })
+            .collect()
+    }
+
+    fn load(path: &Path, first_gid: u32) -> Option<Self> {
+        let Ok(text) = std::fs::read_to_string(path) else {
+            eprintln!("Warning: Cannot read tileset {}", path.display());
+            return None;
+        };
+        let dir = path.parent().unwrap_or(Path::new(""));
+        if path.extension().is_some_and(|ext| ext == "tsx") {
+            let doc = roxmltree::Document::parse(&text).ok()?;
+            Self::from_xml(doc.root_element(), first_gid, dir)
+        } else {
+            Self::from_json(&json::parse(&text).ok()?, first_gid, dir)
+        }
+    }
+
+    fn from_json(value: &json::JsonValue, first_gid: u32, dir: &Path) -> Option<Self> {
+        if let Some(source) = value["source"].as_str() {
+            return Self::load(&dir.join(source), first_gid);
+        }
+
+        let tiles = value["tiles"]
+            .members()
+            .filter_map(|tile| {
+                let animation = tile["animation"]
+                    .members()
+                    .filter_map(|frame| {
+                        Some((
+                            frame["tileid"].as_u32()?,
+                            frame["duration"].as_f32()? / 1000.0,
+                        ))
+                    })
+                    .collect();
+                let data = TileData {
+                    class: class_from_json(tile),
+                    properties: properties_from_json(&tile["properties"]),
+                    animation,
+                };
+                Some((tile["id"].as_u32()?, data))
+            })
+            .collect();
+
+        Some(Self {
+            first_gid,
+            name: value["name"].as_str().unwrap_or_default().to_string(),
+            image: value["image"].as_str().map(|image| resolve(dir, image)),
+            image_size: vec2(
+                value["imagewidth"].as_u32().unwrap_or(0),
+                value["imageheight"].as_u32().unwrap_or(0),
+            ),
+            tile_size: vec2(value["tilewidth"].as_u32()?, value["tileheight"].as_u32()?),
+            margin: value["margin"].as_u32().unwrap_or(0),
+            spacing: value["spacing"].as_u32().unwrap_or(0),
+            columns: value["columns"].as_u32().unwrap_or(0),
+            count: value["tilecount"].as_u32()?,
+            tiles,
+        })
+    }
+
+    fn from_xml(node: roxmltree::Node, first_gid: u32, dir: &Path) -> Option<Self> {
+        if let Some(source) = node.attribute("source") {
+            return Self::load(&dir.join(source), first_gid);
+        }
+
+        let image = child(node, "image");
+        let tiles = children(node, "tile")
+            .filter_map(|tile| {
+                let animation = child(tile, "animation")
+                    .map(|animation| {
+                        children(animation, "frame")
+                            .filter_map(|frame| {
+                                let duration: f32 = attr(frame, "duration")?;
+                                Some((attr(frame, "tileid")?, duration / 1000.0))
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let data = TileData {
+                    class: class_from_xml(tile),
+                    properties: properties_from_xml(tile),
+                    animation,
+                };
+                Some((attr(tile, "id")?, data))
+            })
+            .collect();
+
+        Some(Self {
+            first_gid,
+            name: node.attribute("name").unwrap_or_default().to_string(),
+            image: image
+                .and_then(|image| image.attribute("source"))
+                .map(|source| resolve(dir, source)),
+            image_size: vec2(
+                image.and_then(|image| attr(image, "width")).unwrap_or(0),
+                image.and_then(|image| attr(image, "height")).unwrap_or(0),
+            ),
+            tile_size: vec2(attr(node, "tilewidth")?, attr(node, "tileheight")?),
+            margin: attr(node, "margin").unwrap_or(0),
+            spacing: attr(node, "spacing").unwrap_or(0),
+            columns: attr(node, "columns").unwrap_or(0),
+            count: attr(node, "tilecount")?,
+            tiles,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TileLayer {
+    pub name: String,
+    pub visible: bool,
+    pub size: Vec2<u32>,
+    // Row by row from the top left, 0 for empty cells
+    pub gids: Vec<u32>,
+    pub properties: Properties,
+}
+
+impl TileLayer {
+    fn new(
+        name: String,
+        visible: bool,
+        size: Vec2<u32>,
+        gids: Vec<u32>,
+        properties: Properties,
+    ) -> Option<Self> {
+        if gids.len() != (size.x * size.y) as usize {
+            eprintln!(
+                "Warning: Tile layer {name} has {} cells, expected {}",
+                gids.len(),
+                size.x * size.y
+            );
+            return None;
+        }
+        Some(Self {
+            name,
+            visible,
+            size,
+            gids,
+            properties,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Shape {
+    Rect,
+    Ellipse,
+    Point,
+    // Pixels relative to the object's position
+    Polygon(Vec<Vec2<f32>>),
+    Polyline(Vec<Vec2<f32>>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Object {
+    pub id: u32,
+    pub name: String,
+    pub class: String,
+    // Pixels from the top left of the map, y down
+    pub pos: Vec2<f32>,
+    pub size: Vec2<f32>,
+    // Degrees clockwise around `pos`
+    pub rotation: f32,
+    // Tile objects, whose `pos` is their bottom left corner
+    pub gid: Option<u32>,
+    pub shape: Shape,
+    pub properties: Properties,
+}
+impl Object {
+    // Outline in map pixels, empty for points
+    fn outline(&self) -> Vec<Vec2<f32>> {
+        let size = self.size;
+        let points = match &self.shape {
+            Shape::Point => Vec::new(),
+            Shape::Rect => {
+                // Tile objects grow upwards from their position
+                let top = if self.gid.is_some() { -size.y } else { 0.0 };
+                vec![
+                    vec2(0.0, top),
+                    vec2(size.x, top),
+                    vec2(size.x, top + size.y),
+                    vec2(0.0, top + size.y),
+                ]
+            }
+            Shape::Ellipse => {
+                let sides = 16;
+                let step = std::f32::consts::TAU / sides as f32;
+                (0..sides)
+                    .map(|i| {
+                        let (sin, cos) = (step * i as f32).sin_cos();
+                        (vec2(cos, sin) + vec2(1.0, 1.0)) * size * 0.5
+                    })
+                    .collect()
+            }
+            Shape::Polygon(points) | Shape::Polyline(points) => points.clone(),
+        };
+
+        // Clockwise on screen, since y points down
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        points
+            .into_iter()
+            .map(|p| self.pos + vec2(p.x * cos - p.y * sin, p.x * sin + p.y * cos))
+            .collect()
+    }
+
+    fn from_json(value: &json::JsonValue) -> Option<Self> {
+        let points = |key: &str| {
+            value[key]
+                .members()
+                .filter_map(|point| Some(vec2(point["x"].as_f32()?, point["y"].as_f32()?)))
+                .collect()
+        };
+        let shape = if value["point"].as_bool() == Some(true) {
+            Shape::Point
+        } else if value["ellipse"].as_bool() == Some(true) {
+            Shape::Ellipse
+        } else if value["polygon"].is_array() {
+            Shape::Polygon(points("polygon"))
+        } else if value["polyline"].is_array() {
+            Shape::Polyline(points("polyline"))
+        } else {
+            Shape::Rect
+        };
+
+        Some(Self {
+            id: value["id"].as_u32().unwrap_or(0),
+            name: value["name"].as_str().unwrap_or_default().to_string(),
+            class: class_from_json(value),
+            pos: vec2(value["x"].as_f32()?, value["y"].as_f32()?),
+            size: vec2(
+                value["width"].as_f32().unwrap_or(0.0),
+                value["height"].as_f32().unwrap_or(0.0),
+            ),
+            rotation: value["rotation"].as_f32().unwrap_or(0.0),
+            gid: value["gid"].as_u32(),
+            shape,
+            properties: properties_from_json(&value["properties"]),
+        })
+    }
+
+    fn from_xml(node: roxmltree::Node) -> Option<Self> {
+        let points = |node: roxmltree::Node| {
+            node.attribute("points")
+                .unwrap_or_default()
+                .split_whitespace()
+                .filter_map(|point| {
+                    let (x, y) = point.split_once(',')?;
+                    Some(vec2(x.parse().ok()?, y.parse().ok()?))
+                })
+                .collect()
+        };
+        let shape = if child(node, "point").is_some() {
+            Shape::Point
+        } else if child(node, "ellipse").is_some() {
+            Shape::Ellipse
+        } else if let Some(polygon) = child(node, "polygon") {
+            Shape::Polygon(points(polygon))
+        } else if let Some(polyline) = child(node, "polyline") {
+            Shape::Polyline(points(polyline))
+        } else {
+            Shape::Rect
+        };
+
+        Some(Self {
+            id: attr(node, "id").unwrap_or(0),
+            name: node.attribute("name").unwrap_or_default().to_string(),
+            class: class_from_xml(node),
+            pos: vec2(attr(node, "x")?, attr(node, "y")?),
+            size: vec2(
+                attr(node, "width").unwrap_or(0.0),
+                attr(node, "height").unwrap_or(0.0),
+            ),
+            rotation: attr(node, "rotation").unwrap_or(0.0),
+            gid: attr(node, "gid"),
+            shape,
+            properties: properties_from_xml(node),
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjectLayer {
+    pub name: String,
+    pub visible: bool,
+    pub objects: Vec<Object>,
+    pub properties: Properties,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MapLayer {
+    Tiles(TileLayer),
+    Objects(ObjectLayer),
+}
+
+// An object placed in the world, for the game to spawn entities from by class
+#[derive(Clone, Debug, PartialEq)]
+pub struct Spawn {
+    pub name: String,
+    pub class: String,
+    pub pos: Vec2<f32>,
+    // World space, empty for points
+    pub outline: Vec<Vec2<f32>>,
+    // False for polylines
+    pub closed: bool,
+    pub properties: Properties,
+}
+impl Spawn {
+    // Area covered, for colliders and triggers
+    pub fn polygon(&self) -> Option<Polygon> {
+        (self.closed && self.outline.len() >= 3).then(|| Polygon::new(self.outline.clone()))
+    }
+    pub fn flags(&self) -> TileFlags {
+        flags_of(&self.properties)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TiledMap {
+    pub size: Vec2<u32>,
+    pub tile_size: Vec2<u32>,
+    pub tilesets: Vec<Tileset>,
+    // Groups are flattened, bottom layer first
+    pub layers: Vec<MapLayer>,
+    pub properties: Properties,
+}
+impl TiledMap {
+    pub fn load(path: &str) -> Self {
+        let text = std::fs::read_to_string(path).unwrap_or_else(|_| panic!("Cannot read {path}"));
+        let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+        let map = if path.ends_with(".tmx") {
+            Self::from_xml(&text, dir)
+        } else {
+            Self::from_json(&text, dir)
+        };
+        map.unwrap_or_else(|| panic!("Invalid Tiled map {path}"))
+    }
+
+    // `dir` is where external tilesets and images are looked up
+    pub fn from_json(text: &str, dir: &Path) -> Option<Self> {
+        let value = json::parse(text).ok()?;
+        if value["infinite"].as_bool() == Some(true) {
+            eprintln!("Warning: Infinite Tiled maps are not supported");
+            return None;
+        }
+
+        let tilesets = value["tilesets"]
+            .members()
+            .filter_map(|tileset| Tileset::from_json(tileset, tileset["firstgid"].as_u32()?, dir))
+            .collect();
+        let mut layers = Vec::new();
+        Self::layers_from_json(&value["layers"], true, &mut layers)?;
+
+        Some(Self {
+            size: vec2(value["width"].as_u32()?, value["height"].as_u32()?),
+            tile_size: vec2(value["tilewidth"].as_u32()?, value["tileheight"].as_u32()?),
+            tilesets,
+            layers,
+            properties: properties_from_json(&value["properties"]),
+        })
+    }
+
+    fn layers_from_json(
+        value: &json::JsonValue,
+        visible: bool,
+        out: &mut Vec<MapLayer>,
+    ) -> Option<()> {
+        for layer in value.members() {
+            let name = layer["name"].as_str().unwrap_or_default().to_string();
+            let visible = visible && layer["visible"].as_bool().unwrap_or(true);
+            let properties = properties_from_json(&layer["properties"]);
+            match layer["type"].as_str()? {
+                "tilelayer" => {
+                    let data = &layer["data"];
+                    let gids = match data.as_str() {
+                        Some(text) => decode_gids(
+                            text,
+                            layer["encoding"].as_str(),
+                            layer["compression"].as_str(),
+                        )?,
+                        None => data
+                            .members()
+                            .map(|gid| gid.as_u32())
+                            .collect::<Option<_>>()?,
+                    };
+                    let size = vec2(layer["width"].as_u32()?, layer["height"].as_u32()?);
+                    out.push(MapLayer::Tiles(TileLayer::new(
+                        name, visible, size, gids, properties,
+                    )?));
+                }
+                "objectgroup" => {
+                    let objects = layer["objects"].members().filter_map(Object::from_json);
+                    out.push(MapLayer::Objects(ObjectLayer {
+                        name,
+                        visible,
+                        objects: objects.collect(),
+                        properties,
+                    }));
+                }
+                "group" => Self::layers_from_json(&layer["layers"], visible, out)?,
+                _ => {}
+            }
+        }
+        Some(())
+    }
+
+    pub fn from_xml(text: &str, dir: &Path) -> Option<Self> {
+        let doc = roxmltree::Document::parse(text).ok()?;
+        let root = doc.root_element();
+        if !root.has_tag_name("map") {
+            return None;
+        }
+        if attr(root, "infinite") == Some(1) {
+            eprintln!("Warning: Infinite Tiled maps are not supported");
+            return None;
+        }
+
+        let tilesets = children(root, "tileset")
+            .filter_map(|tileset| Tileset::from_xml(tileset, attr(tileset, "firstgid")?, dir))
+            .collect();
+        let mut layers = Vec::new();
+        Self::layers_from_xml(root, true, &mut layers)?;
+
+        Some(Self {
+            size: vec2(attr(root, "width")?, attr(root, "height")?),
+            tile_size: vec2(attr(root, "tilewidth")?, attr(root, "tileheight")?),
+            tilesets,
+            layers,
+            properties: properties_from_xml(root),
+        })
+    }
+
+    fn layers_from_xml(
+        node: roxmltree::Node,
+        visible: bool,
+        out: &mut Vec<MapLayer>,
+    ) -> Option<()> {
+        for layer in node.children().filter(|child| child.is_element()) {
+            let name = layer.attribute("name").unwrap_or_default().to_string();
+            let visible = visible && attr(layer, "visible") != Some(0);
+            let properties = properties_from_xml(layer);
+            match layer.tag_name().name() {
+                "layer" => {
+                    let data = child(layer, "data")?;
+                    let gids = match data.attribute("encoding") {
+                        None => children(data, "tile")
+                            .map(|tile| attr(tile, "gid").unwrap_or(0))
+                            .collect(),
+                        encoding => decode_gids(
+                            data.text().unwrap_or_default(),
+                            encoding,
+                            data.attribute("compression"),
+                        )?,
+                    };
+                    let size = vec2(attr(layer, "width")?, attr(layer, "height")?);
+                    out.push(MapLayer::Tiles(TileLayer::new(
+                        name, visible, size, gids, properties,
+                    )?));
+                }
+                "objectgroup" => {
+                    let objects = children(layer, "object").filter_map(Object::from_xml);
+                    out.push(MapLayer::Objects(ObjectLayer {
+                        name,
+                        visible,
+                        objects: objects.collect(),
+                        properties,
+                    }));
+                }
+                "group" => Self::layers_from_xml(layer, visible, out)?,
+                _ => {}
+            }
+        }
+        Some(())
+    }
+
+    // Map pixels, y down, to world units, y up
+    fn to_world(origin: Vec2<f32>, scale: f32, pos: Vec2<f32>) -> Vec2<f32> {
+        origin + vec2(pos.x, -pos.y) * scale
+    }
+
+    // Every tileset with an image in one atlas, along with the id its first
+    // tile gets there. A single tileset is used as is, several are packed
+    // onto one page. Collections of images are skipped
+    fn atlas(&self, gfx: &Graphics) -> Option<(tilemap::Tileset, Vec<(&Tileset, TileId)>)> {
+        let sheets: Vec<&Tileset> = self
+            .tilesets
+            .iter()
+            .filter(|tileset| tileset.image.is_some())
+            .collect();
+        if sheets.is_empty() {
+            eprintln!("Warning: Tiled map has no tileset with an image");
+            return None;
+        }
+        for tileset in self
+            .tilesets
+            .iter()
+            .filter(|tileset| tileset.image.is_none())
+        {
+            eprintln!(
+                "Warning: Tiled tileset {} has no single image, its tiles are skipped",
+                tileset.name
+            );
+        }
+
+        let mut offsets = Vec::new();
+        let mut next = 0;
+        for sheet in sheets.iter() {
+            offsets.push((*sheet, next));
+            next += sheet.count as TileId;
+        }
+
+        if let [sheet] = sheets[..] {
+            let tiles = tilemap::Tileset::from_rects(gfx, sheet.image.as_ref()?, sheet.rects());
+            return Some((tiles, offsets));
+        }
+
+        let mut packer = AtlasPacker::new(PAGE_SIZE);
+        for (index, sheet) in sheets.iter().enumerate() {
+            let path = sheet.image.as_ref()?;
+            let image = image::open(path).unwrap_or_else(|_| panic!("Cannot read {path}"));
+            packer.add(&index.to_string(), image.to_rgba8());
+        }
+        let mut packed = packer.pack();
+        if packed.pages.len() > 1 {
+            eprintln!("Warning: Tiled map tilesets do not fit on one {PAGE_SIZE}x{PAGE_SIZE} page");
+            return None;
+        }
+
+        // Tile rects are relative to their sheet, moved to where it was packed
+        let mut rects = Vec::new();
+        for (index, sheet) in sheets.iter().enumerate() {
+            let area = packed.rect(&index.to_string())?;
+            rects.extend(sheet.rects().into_iter().map(|rect| UVRect {
+                a: area.a + rect.a * (area.b - area.a),
+                b: area.a + rect.b * (area.b - area.a),
+            }));
+        }
+        let page = packed.pages.swap_remove(0);
+        Some((
+            tilemap::Tileset::from_image(gfx, "Tiled tilesets", page, rects),
+            offsets,
+        ))
+    }
+
+    // Tile layers placed with their top left corner at `origin`, `scale`
+    // world units per pixel. Layers go into the background, later ones in
+    // front. Tile and layer properties set the `TileFlags`, see `flags_of`,
+    // flipped tiles keep their flips
+    pub fn tilemap(&self, gfx: &Graphics, origin: Vec2<f32>, scale: f32) -> Option<Tilemap> {
+        let (mut tiles, offsets) = self.atlas(gfx)?;
+        for (tileset, offset) in offsets.iter() {
+            for (id, tile) in tileset.tiles.iter() {
+                let id = offset + *id as TileId;
+                tiles.set_flags(id, flags_of(&tile.properties));
+                // Tiles animate at a fixed rate, taken from the first frame
+                if let Some((_, duration)) = tile.animation.first() {
+                    let frames = tile
+                        .animation
+                        .iter()
+                        .map(|(frame, _)| offset + *frame as TileId);
+                    tiles.animate(id, frames.collect(), *duration);
+                }
+            }
+        }
+
+        let tile_size = self.tile_size.as_f32() * scale;
+        let mut map = Tilemap::new(tiles, origin, self.size, tile_size);
+
+        let layers: Vec<&TileLayer> = self
+            .layers
+            .iter()
+            .filter_map(|layer| match layer {
+                MapLayer::Tiles(layer) => Some(layer),
+                MapLayer::Objects(_) => None,
+            })
+            .collect();
+        for (index, layer) in layers.iter().enumerate() {
+            let depth = Layer::Background.depth(index as f32 / layers.len() as f32);
+            let target = map.add_layer(&layer.name, depth);
+            map.layer_mut(target).visible = layer.visible;
+
+            let flags = flags_of(&layer.properties);
+            for (cell, gid) in layer.gids.iter().enumerate() {
+                let (gid, flips) = split_gid(*gid);
+                let Some((tileset, offset)) =
+                    offsets.iter().find(|(tileset, _)| tileset.contains(gid))
+                else {
+                    continue;
+                };
+                let cell = vec2(cell as u32 % layer.size.x, cell as u32 / layer.size.x);
+                map.set(
+                    target,
+                    cell,
+                    Some(offset + (gid - tileset.first_gid) as TileId),
+                );
+                if flags | flips != TileFlags::NONE {
+                    map.set_flags(target, cell, flags | flips);
+                }
+            }
+        }
+        Some(map)
+    }
+
+    // Objects of every object layer, placed like `tilemap`. Tile objects
+    // without a class take the class of their tile
+    pub fn spawns(&self, origin: Vec2<f32>, scale: f32) -> Vec<Spawn> {
+        let objects = self.layers.iter().flat_map(|layer| match layer {
+            MapLayer::Objects(layer) => layer.objects.iter(),
+            MapLayer::Tiles(_) => [].iter(),
+        });
+
+        objects
+            .map(|object| {
+                let tile_class = object.gid.and_then(|gid| {
+                    let (gid, _) = split_gid(gid);
+                    let tileset = self.tilesets.iter().find(|tileset| tileset.contains(gid))?;
+                    Some(tileset.tiles.get(&(gid - tileset.first_gid))?.class.clone())
+                });
+                let class = match object.class.is_empty() {
+                    true => tile_class.unwrap_or_default(),
+                    false => object.class.clone(),
+                };
+
+                Spawn {
+                    name: object.name.clone(),
+                    class,
+                    pos: Self::to_world(origin, scale, object.pos),
+                    outline: object
+                        .outline()
+                        .into_iter()
+                        .map(|point| Self::to_world(origin, scale, point))
+                        .collect(),
+                    closed: !matches!(object.shape, Shape::Polyline(_)),
+                    properties: object.properties.clone(),
+                }
+            })
+            .collect()
+    }
+}
+
+// Tiled 1.9 renamed the object type to class
+fn class_from_json(value: &json::JsonValue) -> String {
+    let class = value["class"].as_str().or(value["type"].as_str());
+    class.unwrap_or_default().to_string()
+}
+fn class_from_xml(node: roxmltree::Node) -> String {
+    let class = node.attribute("class").or(node.attribute("type"));
+    class.unwrap_or_default().to_string()
+}
+
+fn properties_from_json(value: &json::JsonValue) -> Properties {
+    value
+        .members()
+        .filter_map(|property| {
+            let kind = property["type"].as_str().unwrap_or("string");
+            let value = Property::from_json(kind, &property["value"]);
+            Some((property["name"].as_str()?.to_string(), value))
+        })
+        .collect()
+}
+fn properties_from_xml(node: roxmltree::Node) -> Properties {
+    let Some(properties) = child(node, "properties") else {
+        return Properties::new();
+    };
+    children(properties, "property")
+        .filter_map(|property| {
+            let kind = property.attribute("type").unwrap_or("string");
+            // Multiline strings are stored as text rather than an attribute
+            let value = property.attribute("value").or(property.text())?;
+            Some((
+                property.attribute("name")?.to_string(),
+                Property::parse(kind, value),
+            ))
+        })
+        .collect()
+}
+
+fn resolve(dir: &Path, path: &str) -> String {
+    dir.join(path).to_string_lossy().into_owned()
+}
+
+fn attr<T: std::str::FromStr>(node: roxmltree::Node, name: &str) -> Option<T> {
+    node.attribute(name)?.trim().parse().ok()
+}
+fn child<'a, 'input>(
+    node: roxmltree::Node<'a, 'input>,
+    tag: &str,
+) -> Option<roxmltree::Node<'a, 'input>> {
+    node.children().find(|child| child.has_tag_name(tag))
+}
+fn children<'a, 'input: 'a>(
+    node: roxmltree::Node<'a, 'input>,
+    tag: &'a str,
+) -> impl Iterator<Item = roxmltree::Node<'a, 'input>> {
+    node.children().filter(move |child| child.has_tag_name(tag))
+}
+
+// Layer data stored as text, comma separated or base64 little endian u32s
+fn decode_gids(text: &str, encoding: Option<&str>, compression: Option<&str>) -> Option<Vec<u32>> {
+    match encoding {
+        Some("csv") => text.split(',').map(|gid| gid.trim().parse().ok()).collect(),
+        Some("base64") => {
+            let bytes = base64(text)?;
+            let mut data = Vec::new();
+            match compression.unwrap_or_default() {
+                "" => data = bytes,
+                "zlib" => {
+                    flate2::read::ZlibDecoder::new(bytes.as_slice())
+                        .read_to_end(&mut data)
+                        .ok()?;
+                }
+                "gzip" => {
+                    flate2::read::GzDecoder::new(bytes.as_slice())
+                        .read_to_end(&mut data)
+                        .ok()?;
+                }
+                other => {
+                    eprintln!("Warning: Unsupported Tiled layer compression {other}");
+                    return None;
+                }
+            }
+            let gids = data.chunks_exact(4);
+            Some(
+                gids.map(|gid| u32::from_le_bytes([gid[0], gid[1], gid[2], gid[3]]))
+                    .collect(),
+            )
+        }
+        _ => None,
+    }
+}
+
+fn base64(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let (mut bits, mut count) = (0u32, 0);
+    for c in text.bytes().filter(|c| !c.is_ascii_whitespace()) {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            _ => return None,
+        };
+        bits = (bits << 6 | value as u32) & 0xFFFF;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ZLIB: &str = "eJxjYGBgYARiJiBmBmIWIGYFYjYGCGAHYg6IGgcQHwAEzABm";
+    const GZIP: &str = "H4sIAAAAAAACA2NjYGBgQ8LogBGImRgYFJiBNAsDwwIADGD8CDAAAAA=";
+    const RAW: &str = "AQAAAAIAAAADAAAABAAAAAUAAAAGAAAABwAAAAgAAAAJAAAACgAAAAsAAAAMAAAA";
+
+    // Every way a tile layer can be stored, nested in a hidden group along
+    // with objects, and an embedded tileset next to an external one
+    fn tmx(external: &str) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.10" orientation="orthogonal" width="4" height="3" tilewidth="8" tileheight="8" infinite="0">
+ <properties>
+  <property name="music" value="cave.ogg"/>
+ </properties>
+ <tileset firstgid="1" name="Ground" tilewidth="8" tileheight="8" spacing="1" margin="1" tilecount="6" columns="3">
+  <image source="ground.png" width="28" height="19"/>
+  <tile id="1" class="Wall">
+   <properties>
+    <property name="solid" type="bool" value="true"/>
+    <property name="height" type="float" value="1.5"/>
+    <property name="note">Two
+lines</property>
+   </properties>
+  </tile>
+  <tile id="4">
+   <animation>
+    <frame tileid="4" duration="100"/>
+    <frame tileid="5" duration="250"/>
+   </animation>
+  </tile>
+ </tileset>
+ <tileset firstgid="7" source="{external}"/>
+ <layer id="1" name="Csv" width="4" height="3">
+  <data encoding="csv">
+1,2,3,0,
+0,2147483650,0,0,
+7,7,7,7
+</data>
+ </layer>
+ <group id="2" name="Deco" visible="0">
+  <layer id="3" name="Zlib" width="4" height="3">
+   <data encoding="base64" compression="zlib">
+    {ZLIB}
+   </data>
+  </layer>
+  <objectgroup id="4" name="Spawns">
+   <object id="1" name="Start" type="Spawn" x="12" y="20">
+    <point/>
+   </object>
+   <object id="2" class="Lava" x="0" y="8" width="16" height="8" rotation="90">
+    <properties>
+     <property name="hazard" type="bool" value="true"/>
+    </properties>
+   </object>
+   <object id="3" x="4" y="4">
+    <polygon points="0,0 8,0 4,6"/>
+   </object>
+   <object id="4" gid="8" x="16" y="24" width="8" height="8"/>
+  </objectgroup>
+ </group>
+ <imagelayer id="5" name="Sky"/>
+ <layer id="6" name="Plain" width="4" height="3">
+  <data>
+   <tile gid="1"/><tile/><tile/><tile/>
+   <tile/><tile gid="3"/><tile/><tile/>
+   <tile/><tile/><tile/><tile gid="6"/>
+  </data>
+ </layer>
+ <layer id="7" name="Gzip" width="4" height="3">
+  <data encoding="base64" compression="gzip">{GZIP}</data>
+ </layer>
+ <layer id="8" name="Raw" width="4" height="3" visible="0">
+  <data encoding="base64">{RAW}</data>
+ </layer>
+</map>"#
+        )
+    }
+
+    fn layers(map: &TiledMap) -> Vec<(&str, bool)> {
+        map.layers
+            .iter()
+            .map(|layer| match layer {
+                MapLayer::Tiles(layer) => (layer.name.as_str(), layer.visible),
+                MapLayer::Objects(layer) => (layer.name.as_str(), layer.visible),
+            })
+            .collect()
+    }
+    fn gids<'a>(map: &'a TiledMap, name: &str) -> &'a [u32] {
+        map.layers
+            .iter()
+            .find_map(|layer| match layer {
+                MapLayer::Tiles(layer) if layer.name == name => Some(layer.gids.as_slice()),
+                _ => None,
+            })
+            .unwrap()
+    }
+
+    // Standard alphabet with padding, the inverse of `base64`
+    fn encode(bytes: &[u8]) -> String {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut text = String::new();
+        for group in bytes.chunks(3) {
+            let bits = group.iter().enumerate().fold(0u32, |bits, (i, byte)| {
+                bits | (*byte as u32) << (16 - 8 * i)
+            });
+            for i in 0..4 {
+                if i <= group.len() {
+                    text.push(ALPHABET[(bits >> (18 - 6 * i) & 63) as usize] as char);
+                } else {
+                    text.push('=');
+                }
+            }
+        }
+        text
+    }
+
+    #[test]
+    fn split_gid_flips() {
+        assert_eq!(split_gid(10), (10, TileFlags::NONE));
+        assert_eq!(split_gid(0x8000_000A), (10, TileFlags::FLIP_X));
+        assert_eq!(
+            split_gid(0x6000_000A),
+            (10, TileFlags::FLIP_Y | TileFlags::FLIP_DIAGONAL)
+        );
+        // Hexagonal rotation
+        assert_eq!(split_gid(0x1000_000A), (10, TileFlags::NONE));
+    }
+
+    #[test]
+    fn base64_round_trip() {
+        assert_eq!(base64("TWFu"), Some(b"Man".to_vec()));
+        assert_eq!(base64("TWE="), Some(b"Ma".to_vec()));
+        assert_eq!(base64("TQ=="), Some(b"M".to_vec()));
+        assert_eq!(base64(" TW\n Fu\t"), Some(b"Man".to_vec()));
+        assert_eq!(base64(""), Some(Vec::new()));
+        assert_eq!(base64("TW-u"), None);
+
+        let bytes: Vec<u8> = (0..=255).rev().collect();
+        for length in 0..=bytes.len() {
+            let text = encode(&bytes[..length]);
+            assert_eq!(base64(&text).as_deref(), Some(&bytes[..length]), "{text}");
+        }
+    }
+
+    #[test]
+    fn decode_every_encoding() {
+        let zlib = [0, 1, 2, 3, 4, 5, 6, 0, 7, 8, 0x4000_0001, 0];
+        let gzip = [6, 6, 6, 6, 0, 0, 0, 0, 1, 0x2000_0002, 3, 0xA000_0004];
+        let raw: Vec<u32> = (1..=12).collect();
+        assert_eq!(
+            decode_gids(ZLIB, Some("base64"), Some("zlib")).unwrap(),
+            zlib
+        );
+        assert_eq!(
+            decode_gids(GZIP, Some("base64"), Some("gzip")).unwrap(),
+            gzip
+        );
+        assert_eq!(decode_gids(RAW, Some("base64"), None).unwrap(), raw);
+        assert_eq!(decode_gids(RAW, Some("base64"), Some("")).unwrap(), raw);
+        assert_eq!(
+            decode_gids(" 1, 2,\n3 ,2147483650\n", Some("csv"), None).unwrap(),
+            [1, 2, 3, 0x8000_0002]
+        );
+
+        assert_eq!(decode_gids("1,,2", Some("csv"), None), None);
+        assert_eq!(decode_gids("1,x", Some("csv"), None), None);
+        assert_eq!(decode_gids(RAW, Some("base64"), Some("zstd")), None);
+        assert_eq!(decode_gids(RAW, Some("base64"), Some("zlib")), None);
+        assert_eq!(decode_gids(ZLIB, Some("base64"), Some("gzip")), None);
+        assert_eq!(decode_gids("AQ*A", Some("base64"), None), None);
+        assert_eq!(decode_gids("1,2", Some("xml"), None), None);
+    }
+
+    #[test]
+    fn tmx_map() {
+        let map = TiledMap::from_xml(&tmx("missing.tsx"), Path::new("")).unwrap();
+        assert_eq!(map.size, vec2(4, 3));
+        assert_eq!(map.tile_size, vec2(8, 8));
+        assert_eq!(
+            map.properties["music"],
+            Property::String("cave.ogg".to_string())
+        );
+
+        // Groups are flattened, hiding what they hold, and image layers skipped
+        assert_eq!(
+            layers(&map),
+            [
+                ("Csv", true),
+                ("Zlib", false),
+                ("Spawns", false),
+                ("Plain", true),
+                ("Gzip", true),
+                ("Raw", false),
+            ]
+        );
+        assert_eq!(
+            gids(&map, "Csv"),
+            [1, 2, 3, 0, 0, 0x8000_0002, 0, 0, 7, 7, 7, 7]
+        );
+        assert_eq!(
+            gids(&map, "Zlib"),
+            [0, 1, 2, 3, 4, 5, 6, 0, 7, 8, 0x4000_0001, 0]
+        );
+        assert_eq!(gids(&map, "Plain"), [1, 0, 0, 0, 0, 3, 0, 0, 0, 0, 0, 6]);
+        assert_eq!(gids(&map, "Raw"), (1..=12).collect::<Vec<_>>());
+        assert_eq!(gids(&map, "Gzip")[11], 0xA000_0004);
+    }
+
+    #[test]
+    fn tmx_tilesets() {
+        let map = TiledMap::from_xml(&tmx("missing.tsx"), Path::new("maps")).unwrap();
+        // External tilesets that cannot be read are left out
+        assert_eq!(map.tilesets.len(), 1);
+
+        let ground = &map.tilesets[0];
+        assert_eq!(ground.first_gid, 1);
+        assert_eq!(ground.name, "Ground");
+        assert_eq!(ground.image.as_deref(), Some("maps/ground.png"));
+        assert_eq!(ground.image_size, vec2(28, 19));
+        assert_eq!((ground.margin, ground.spacing), (1, 1));
+        assert!(ground.contains(6) && !ground.contains(7) && !ground.contains(0));
+
+        let wall = &ground.tiles[&1];
+        assert_eq!(wall.class, "Wall");
+        assert_eq!(flags_of(&wall.properties), TileFlags::SOLID);
+        assert_eq!(wall.properties["height"].as_f32(), Some(1.5));
+        assert_eq!(wall.properties["note"].as_str(), Some("Two\nlines"));
+        assert_eq!(ground.tiles[&4].animation, [(4, 0.1), (5, 0.25)]);
+
+        // Past the margin, and a tile and its spacing further along
+        let rects = ground.rects();
+        let pixels = |uv: Vec2<f32>| (uv * vec2(28.0, 19.0)).map(f32::round);
+        assert_eq!(rects.len(), 6);
+        assert_eq!(pixels(rects[0].a), vec2(1.0, 1.0));
+        assert_eq!(pixels(rects[0].b), vec2(9.0, 9.0));
+        assert_eq!(pixels(rects[4].a), vec2(10.0, 10.0));
+        assert_eq!(pixels(rects[5].b), vec2(27.0, 18.0));
+    }
+
+    #[test]
+    fn tmx_external_tileset() {
+        let dir = std::env::temp_dir().join(format!("tiled-external-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("maps")).unwrap();
+        let external = r#"<tileset name="Props" tilewidth="8" tileheight="16" tilecount="4" columns="4">
+ <image source="../art/props.png" width="32" height="16"/>
+</tileset>"#;
+        std::fs::write(dir.join("maps/props.tsx"), external).unwrap();
+        let map = TiledMap::from_xml(&tmx("props.tsx"), &dir.join("maps"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let props = &map.unwrap().tilesets[1];
+        assert_eq!(props.first_gid, 7);
+        assert_eq!(props.name, "Props");
+        assert_eq!(props.tile_size, vec2(8, 16));
+        // Relative to the tileset rather than the map
+        let image = props.image.as_deref().unwrap();
+        assert!(image.ends_with("maps/../art/props.png"), "{image}");
+    }
+
+    #[test]
+    fn tmx_objects() {
+        let map = TiledMap::from_xml(&tmx("missing.tsx"), Path::new("")).unwrap();
+        let Some(MapLayer::Objects(spawns)) = map.layers.get(2) else {
+            panic!("No object layer");
+        };
+        let objects = &spawns.objects;
+        assert_eq!(objects.len(), 4);
+        assert_eq!(objects[0].shape, Shape::Point);
+        assert_eq!(
+            (objects[0].name.as_str(), objects[0].class.as_str()),
+            ("Start", "Spawn")
+        );
+        assert_eq!(objects[1].class, "Lava");
+        assert_eq!(objects[1].rotation, 90.0);
+        assert_eq!(
+            objects[2].shape,
+            Shape::Polygon(vec![vec2(0.0, 0.0), vec2(8.0, 0.0), vec2(4.0, 6.0)])
+        );
+        assert_eq!(objects[3].gid, Some(8));
+
+        let spawns = map.spawns(vec2(100.0, 0.0), 2.0);
+        assert_eq!(spawns[0].pos, vec2(124.0, -40.0));
+        assert!(spawns[0].outline.is_empty());
+        // Turned a quarter clockwise on screen around its top left corner
+        let lava = spawns[1].polygon().unwrap();
+        assert_eq!(spawns[1].flags(), TileFlags::HAZARD);
+        let bounds = lava.bounds();
+        assert!(
+            (bounds.min - vec2(84.0, -48.0)).length() < 1e-4,
+            "{bounds:?}"
+        );
+        assert!(
+            (bounds.max - vec2(100.0, -16.0)).length() < 1e-4,
+            "{bounds:?}"
+        );
+    }
+
+    #[test]
+    fn tmx_rejects() {
+        let map = tmx("missing.tsx");
+        let from_xml = |text: &str| TiledMap::from_xml(text, Path::new(""));
+        assert!(from_xml(&map).is_some());
+        assert!(from_xml(&map.replace(r#"infinite="0""#, r#"infinite="1""#)).is_none());
+        assert!(from_xml(&map.replace("7,7,7,7", "7,7,7")).is_none());
+        assert!(from_xml(&map.replace(r#"compression="gzip""#, r#"compression="zstd""#)).is_none());
+        assert!(from_xml(
+            &map.replace(r#"<map version"#, r#"<world version"#)
+                .replace("</map>", "</world>")
+        )
+        .is_none());
+        assert!(from_xml("<map").is_none());
+    }
+
+    #[test]
+    fn region_map() {
+        let map = TiledMap::load("assets/Region.tmj");
+        assert_eq!(map.size, vec2(30, 20));
+        assert_eq!(map.tilesets.len(), 2);
+        assert_eq!(map.tilesets[1].first_gid, 9);
+        assert_eq!(map.tilesets[1].image.as_deref(), Some("assets/Props.png"));
+        assert_eq!(map.tilesets[0].tiles[&4].animation.len(), 4);
+
+        let Some(MapLayer::Tiles(props)) = map.layers.iter().find(|layer| match layer {
+            MapLayer::Tiles(layer) => layer.name == "Props",
+            MapLayer::Objects(_) => false,
+        }) else {
+            panic!("No Props layer");
+        };
+        let flipped = props
+            .gids
+            .iter()
+            .map(|gid| split_gid(*gid))
+            .filter(|(gid, flips)| map.tilesets[1].contains(*gid) && *flips != TileFlags::NONE);
+        assert_eq!(flipped.count(), 5);
+
+        let spawns = map.spawns(vec2(0.0, 0.0), 1.0);
+        let start = spawns.iter().find(|spawn| spawn.class == "Start").unwrap();
+        assert_eq!(start.pos, vec2(96.0, -160.0));
+        assert_eq!(
+            spawns
+                .iter()
+                .filter(|spawn| spawn.polygon().is_some())
+                .count(),
+            3
+        );
+    }
+}
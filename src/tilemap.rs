@@ -0,0 +1,781 @@
+use std::collections::HashMap;
+use std::ops::{BitOr, BitOrAssign};
+
+use crate::color::Color;
+use crate::geometry::Rect;
+use crate::graphics::Graphics;
+use crate::nvec::*;
+use crate::sprite::{Sprite, SpriteGroup, UVRect};
+
+// Index into a `Tileset`
+pub type TileId = u16;
+
+// Gameplay properties of a tile, combined with `|`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TileFlags(pub u32);
+impl TileFlags {
+    pub const NONE: TileFlags = TileFlags(0);
+    // Blocks movement
+    pub const SOLID: TileFlags = TileFlags(1);
+    // Hurts whatever stands on it
+    pub const HAZARD: TileFlags = TileFlags(2);
+    // Runs a script or event when entered
+    pub const TRIGGER: TileFlags = TileFlags(4);
+    // How the tile is drawn in the cell, the same bits as Tiled uses. The
+    // diagonal flip swaps x and y and goes first
+    pub const FLIP_X: TileFlags = TileFlags(1 << 31);
+    pub const FLIP_Y: TileFlags = TileFlags(1 << 30);
+    pub const FLIP_DIAGONAL: TileFlags = TileFlags(1 << 29);
+
+    pub fn contains(self, other: TileFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+    pub fn intersects(self, other: TileFlags) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+impl BitOr for TileFlags {
+    type Output = TileFlags;
+    fn bitor(self, other: TileFlags) -> TileFlags {
+        TileFlags(self.0 | other.0)
+    }
+}
+impl BitOrAssign for TileFlags {
+    fn bitor_assign(&mut self, other: TileFlags) {
+        self.0 |= other.0;
+    }
+}
+
+// Tiles shown in turn wherever the animated tile is placed
+#[derive(Clone, Debug, PartialEq)]
+pub struct TileAnimation {
+    pub frames: Vec<TileId>,
+    // Seconds per frame
+    pub frame_time: f32,
+}
+impl TileAnimation {
+    fn frame(&self, time: f32) -> usize {
+        if self.frame_time <= 0.0 {
+            return 0;
+        }
+        (time / self.frame_time) as usize % self.frames.len()
+    }
+}
+
+// A grid of equally sized tiles in one atlas
+pub struct Tileset {
+    // Never drawn, chunks share its atlas. Missing from tilesets made
+    // without a GPU, whose maps can be filled and queried but not drawn
+    group: Option<SpriteGroup>,
+    rects: Vec<UVRect>,
+    flags: Vec<TileFlags>,
+    animations: HashMap<TileId, TileAnimation>,
+    // Bumped whenever the animations change, so maps already filled with
+    // the tiles know to rebuild
+    revision: u32,
+}
+impl Tileset {
+    // `columns` by `rows` tiles covering the whole image, numbered left to
+    // right and top to bottom
+    pub fn new(gfx: &Graphics, path: &str, columns: usize, rows: usize) -> Self {
+        let size = vec2(1.0 / columns as f32, 1.0 / rows as f32);
+        let rects = UVRect::grid(vec2(0.0, 0.0), size, columns * rows, columns);
+        Self::from_rects(gfx, path, rects)
+    }
+
+    // Tiles at arbitrary places in the image, such as sheets with spacing
+    pub fn from_rects(gfx: &Graphics, path: &str, rects: Vec<UVRect>) -> Self {
+        Self {
+            group: Some(SpriteGroup::with_capacity(gfx, path, 0)),
+            ..Self::without_atlas(rects)
+        }
+    }
+
+    // Tiles from an image made at runtime, such as several packed together
+    pub fn from_image(
+        gfx: &Graphics,
+        label: &str,
+        image: image::RgbaImage,
+        rects: Vec<UVRect>,
+    ) -> Self {
+        Self {
+            group: Some(SpriteGroup::from_image(gfx, label, image, 0)),
+            ..Self::without_atlas(rects)
+        }
+    }
+
+    // Tiles with nothing to draw them from, for maps only used for their
+    // layout, such as in tests
+    pub fn without_atlas(rects: Vec<UVRect>) -> Self {
+        Self {
+            group: None,
+            flags: vec![TileFlags::NONE; rects.len()],
+            rects,
+            animations: HashMap::new(),
+            revision: 0,
+        }
+    }
+
+    pub fn with_normal_map(mut self, gfx: &Graphics, path: &str) -> Self {
+        self.group = self.group.map(|group| group.with_normal_map(gfx, path));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.rects.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+
+    pub fn flags(&self, tile: TileId) -> TileFlags {
+        self.flags.get(tile as usize).copied().unwrap_or_default()
+    }
+    pub fn set_flags(&mut self, tile: TileId, flags: TileFlags) {
+        match self.flags.get_mut(tile as usize) {
+            Some(slot) => *slot = flags,
+            None => eprintln!("Warning: Tile {tile} is outside the tileset"),
+        }
+    }
+
+    pub fn animate(&mut self, tile: TileId, frames: Vec<TileId>, frame_time: f32) {
+        self.revision += 1;
+        if frames.is_empty() {
+            self.animations.remove(&tile);
+            return;
+        }
+        self.animations
+            .insert(tile, TileAnimation { frames, frame_time });
+    }
+    pub fn is_animated(&self, tile: TileId) -> bool {
+        self.animations.contains_key(&tile)
+    }
+
+    // The atlas region shown for `tile` at `time`
+    pub fn rect(&self, tile: TileId, time: f32) -> UVRect {
+        let tile = match self.animations.get(&tile) {
+            Some(animation) => animation.frames[animation.frame(time)],
+            None => tile,
+        };
+        self.rects.get(tile as usize).copied().unwrap_or(UVRect {
+            a: vec2(0.0, 0.0),
+            b: vec2(0.0, 0.0),
+        })
+    }
+}
+
+// Square block of cells with its own buffer, uploaded only when changed
+struct Chunk {
+    // Made on the first upload
+    group: Option<SpriteGroup>,
+    // The cells as built by `Tilemap::rebuild`, copied into `group` by
+    // `Tilemap::prepare`
+    sprites: Vec<Sprite>,
+    bounds: Rect,
+    // Cells changed since the last rebuild
+    dirty: bool,
+    // Rebuilt since the last upload
+    changed: bool,
+    // Holds an animated tile, so it is rebuilt when the animations advance
+    animated: bool,
+}
+
+pub struct TileLayer {
+    pub name: String,
+    pub visible: bool,
+    depth: f32,
+    tiles: Vec<Option<TileId>>,
+    // Per cell, on top of the tileset's flags
+    flags: Vec<TileFlags>,
+    chunks: Vec<Chunk>,
+}
+impl TileLayer {
+    pub fn depth(&self) -> f32 {
+        self.depth
+    }
+}
+
+// Layers of tiles on a grid. Cell (0, 0) is the top left, with rows going
+// down from `origin`
+pub struct Tilemap {
+    pub tileset: Tileset,
+    origin: Vec2<f32>,
+    tile_size: Vec2<f32>,
+    size: Vec2<u32>,
+    layers: Vec<TileLayer>,
+    time: f32,
+    // `Tileset::revision` the chunks were built with
+    revision: u32,
+}
+impl Tilemap {
+    pub const CHUNK_SIZE: u32 = 16;
+
+    pub fn new(tileset: Tileset, origin: Vec2<f32>, size: Vec2<u32>, tile_size: Vec2<f32>) -> Self {
+        Self {
+            revision: tileset.revision,
+            tileset,
+            origin,
+            tile_size,
+            size,
+            layers: Vec::new(),
+            time: 0.0,
+        }
+    }
+
+    pub fn size(&self) -> Vec2<u32> {
+        self.size
+    }
+    pub fn tile_size(&self) -> Vec2<f32> {
+        self.tile_size
+    }
+    pub fn bounds(&self) -> Rect {
+        let size = self.size.as_f32() * self.tile_size;
+        Rect::from_corners(self.origin, self.origin + vec2(size.x, -size.y))
+    }
+
+    fn chunks(&self) -> Vec2<u32> {
+        vec2(
+            self.size.x.div_ceil(Self::CHUNK_SIZE),
+            self.size.y.div_ceil(Self::CHUNK_SIZE),
+        )
+    }
+
+    // Empty layer drawn at `depth`, see `Layer::depth`
+    pub fn add_layer(&mut self, name: &str, depth: f32) -> usize {
+        let cells = (self.size.x * self.size.y) as usize;
+        let chunks = self.chunks();
+        let span = self.tile_size * Self::CHUNK_SIZE as f32;
+
+        let mut layer = TileLayer {
+            name: name.to_string(),
+            visible: true,
+            depth,
+            tiles: vec![None; cells],
+            flags: vec![TileFlags::NONE; cells],
+            chunks: Vec::new(),
+        };
+        for y in 0..chunks.y {
+            for x in 0..chunks.x {
+                let min = self.origin + vec2(x as f32 * span.x, -(y as f32 + 1.0) * span.y);
+                layer.chunks.push(Chunk {
+                    group: None,
+                    sprites: Vec::new(),
+                    bounds: Rect::sized(min, span),
+                    dirty: false,
+                    changed: false,
+                    animated: false,
+                });
+            }
+        }
+
+        self.layers.push(layer);
+        self.layers.len() - 1
+    }
+
+    pub fn layers(&self) -> &[TileLayer] {
+        &self.layers
+    }
+    pub fn layer_mut(&mut self, layer: usize) -> &mut TileLayer {
+        &mut self.layers[layer]
+    }
+    pub fn find_layer(&self, name: &str) -> Option<usize> {
+        self.layers.iter().position(|layer| layer.name == name)
+    }
+
+    fn index(&self, cell: Vec2<u32>) -> Option<usize> {
+        (cell.x < self.size.x && cell.y < self.size.y)
+            .then(|| (cell.y * self.size.x + cell.x) as usize)
+    }
+    fn chunk_index(&self, cell: Vec2<u32>) -> usize {
+        let chunk = cell.map(|value| value / Self::CHUNK_SIZE);
+        (chunk.y * self.chunks().x + chunk.x) as usize
+    }
+
+    pub fn get(&self, layer: usize, cell: Vec2<u32>) -> Option<TileId> {
+        self.layers[layer].tiles[self.index(cell)?]
+    }
+
+    pub fn set(&mut self, layer: usize, cell: Vec2<u32>, tile: Option<TileId>) {
+        let Some(index) = self.index(cell) else {
+            return;
+        };
+        let chunk = self.chunk_index(cell);
+        let layer = &mut self.layers[layer];
+        if layer.tiles[index] != tile {
+            layer.tiles[index] = tile;
+            layer.chunks[chunk].dirty = true;
+        }
+    }
+
+    // Flips show on the next rebuild of the chunk
+    pub fn set_flags(&mut self, layer: usize, cell: Vec2<u32>, flags: TileFlags) {
+        let Some(index) = self.index(cell) else {
+            return;
+        };
+        let chunk = self.chunk_index(cell);
+        let layer = &mut self.layers[layer];
+        if layer.flags[index] != flags {
+            layer.flags[index] = flags;
+            layer.chunks[chunk].dirty = true;
+        }
+    }
+
+    // Everything set on the cell across all layers
+    pub fn flags(&self, cell: Vec2<u32>) -> TileFlags {
+        let Some(index) = self.index(cell) else {
+            return TileFlags::NONE;
+        };
+        self.layers
+            .iter()
+            .fold(TileFlags::NONE, |mut flags, layer| {
+                flags |= layer.flags[index];
+                if let Some(tile) = layer.tiles[index] {
+                    flags |= self.tileset.flags(tile);
+                }
+                flags
+            })
+    }
+
+    pub fn cell_at(&self, pos: Vec2<f32>) -> Option<Vec2<u32>> {
+        let offset = pos - self.origin;
+        let cell = vec2(offset.x, -offset.y) / self.tile_size;
+        if cell.x < 0.0 || cell.y < 0.0 {
+            return None;
+        }
+        let cell = vec2(cell.x as u32, cell.y as u32);
+        self.index(cell).map(|_| cell)
+    }
+
+    pub fn cell_rect(&self, cell: Vec2<u32>) -> Rect {
+        let min = self.origin
+            + vec2(
+                cell.x as f32 * self.tile_size.x,
+                -(cell.y as f32 + 1.0) * self.tile_size.y,
+            );
+        Rect::sized(min, self.tile_size)
+    }
+
+    // Cells overlapping `area` with any of `flags`, for collision and triggers
+    pub fn query(&self, area: Rect, flags: TileFlags) -> Vec<(Vec2<u32>, Rect)> {
+        let Some(area) = area.intersection(&self.bounds()) else {
+            return Vec::new();
+        };
+        if self.size.x == 0 || self.size.y == 0 {
+            return Vec::new();
+        }
+        let last = self.size.map(|value| value - 1);
+        let corner = |pos: Vec2<f32>| {
+            let offset = pos - self.origin;
+            let cell = (vec2(offset.x, -offset.y) / self.tile_size).map(|value| value.max(0.0));
+            vec2((cell.x as u32).min(last.x), (cell.y as u32).min(last.y))
+        };
+        let start = corner(vec2(area.min.x, area.max.y));
+        let end = corner(vec2(area.max.x, area.min.y));
+
+        let mut cells = Vec::new();
+        for y in start.y..=end.y {
+            for x in start.x..=end.x {
+                let cell = vec2(x, y);
+                let rect = self.cell_rect(cell);
+                if self.flags(cell).intersects(flags) && rect.overlaps(&area) {
+                    cells.push((cell, rect));
+                }
+            }
+        }
+        cells
+    }
+
+    // Advances tile animations, only touching chunks whose frames changed
+    pub fn update(&mut self, dt: f32) {
+        let before = self.time;
+        self.time += dt;
+        let changed = self
+            .tileset
+            .animations
+            .values()
+            .any(|animation| animation.frame(before) != animation.frame(self.time));
+        if !changed {
+            return;
+        }
+        for layer in self.layers.iter_mut() {
+            for chunk in layer.chunks.iter_mut().filter(|chunk| chunk.animated) {
+                chunk.dirty = true;
+            }
+        }
+    }
+
+    // Rebuilds and uploads the chunks changed since the last call
+    pub fn prepare(&mut self, gfx: &Graphics) {
+        self.rebuild();
+        let Some(atlas) = &self.tileset.group else {
+            return;
+        };
+        let capacity = (Self::CHUNK_SIZE * Self::CHUNK_SIZE) as usize;
+        for layer in self.layers.iter_mut() {
+            for chunk in layer.chunks.iter_mut().filter(|chunk| chunk.changed) {
+                let group = chunk
+                    .group
+                    .get_or_insert_with(|| atlas.sharing(gfx, capacity));
+                group.sprites_mut().clone_from(&chunk.sprites);
+                group.upload(gfx);
+                chunk.changed = false;
+            }
+        }
+    }
+
+    // Turns the cells of dirty chunks into sprites, leaving the upload to
+    // `prepare`
+    fn rebuild(&mut self) {
+        // Any chunk may hold a tile that gained or lost an animation since
+        if self.revision != self.tileset.revision {
+            self.revision = self.tileset.revision;
+            for layer in self.layers.iter_mut() {
+                for chunk in layer.chunks.iter_mut() {
+                    chunk.dirty = true;
+                }
+            }
+        }
+
+        let chunks = self.chunks();
+        for layer in self.layers.iter_mut() {
+            for (index, chunk) in layer.chunks.iter_mut().enumerate() {
+                if !chunk.dirty {
+                    continue;
+                }
+                let first =
+                    vec2(index as u32 % chunks.x, index as u32 / chunks.x) * Self::CHUNK_SIZE;
+                let last = (first + vec2(Self::CHUNK_SIZE, Self::CHUNK_SIZE)).min(self.size);
+
+                chunk.sprites.clear();
+                chunk.animated = false;
+                for y in first.y..last.y {
+                    for x in first.x..last.x {
+                        let Some(tile) = layer.tiles[(y * self.size.x + x) as usize] else {
+                            continue;
+                        };
+                        chunk.animated |= self.tileset.is_animated(tile);
+                        let center =
+                            self.origin + vec2(x as f32 + 0.5, -(y as f32 + 0.5)) * self.tile_size;
+                        let sprite = Sprite::new(
+                            vec3(center.x, center.y, layer.depth),
+                            self.tile_size * 0.5,
+                            Color::WHITE,
+                            self.tileset.rect(tile, self.time),
+                        );
+                        let flags = layer.flags[(y * self.size.x + x) as usize];
+                        chunk.sprites.push(Self::orient(sprite, flags));
+                    }
+                }
+                chunk.dirty = false;
+                chunk.changed = true;
+            }
+        }
+    }
+
+    // Applies the flip flags. A diagonal flip is a quarter turn followed by a
+    // flip, with the other flips swapped around
+    fn orient(sprite: Sprite, flags: TileFlags) -> Sprite {
+        let x = flags.contains(TileFlags::FLIP_X);
+        let y = flags.contains(TileFlags::FLIP_Y);
+        if !flags.contains(TileFlags::FLIP_DIAGONAL) {
+            return sprite.flipped(x, y);
+        }
+        let scale = vec2(sprite.scale.y, sprite.scale.x);
+        Sprite { scale, ..sprite }
+            .rotated(std::f32::consts::FRAC_PI_2)
+            .flipped(!y, x)
+    }
+
+    // Chunks of visible layers overlapping `view`, back to front
+    pub fn groups(&self, view: Rect) -> impl Iterator<Item = &SpriteGroup> {
+        let mut layers: Vec<&TileLayer> =
+            self.layers.iter().filter(|layer| layer.visible).collect();
+        layers.sort_by(|a, b| b.depth.total_cmp(&a.depth));
+        layers.into_iter().flat_map(move |layer| {
+            layer
+                .chunks
+                .iter()
+                .filter(move |chunk| chunk.bounds.overlaps(&view))
+                .filter_map(|chunk| chunk.group.as_ref())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Four by four tiles, 1 solid and 2 hazardous
+    fn tileset() -> Tileset {
+        let rects = UVRect::grid(vec2(0.0, 0.0), vec2(0.25, 0.25), 16, 4);
+        let mut tileset = Tileset::without_atlas(rects);
+        tileset.set_flags(1, TileFlags::SOLID);
+        tileset.set_flags(2, TileFlags::HAZARD);
+        tileset
+    }
+    // 20 by 18 cells of 8 units, so two by two chunks, with two empty layers
+    fn map() -> Tilemap {
+        let mut map = Tilemap::new(tileset(), vec2(-16.0, 32.0), vec2(20, 18), vec2(8.0, 8.0));
+        map.add_layer("ground", 0.5);
+        map.add_layer("walls", 0.4);
+        map
+    }
+    fn dirty(map: &Tilemap, layer: usize) -> Vec<bool> {
+        map.layers[layer]
+            .chunks
+            .iter()
+            .map(|chunk| chunk.dirty)
+            .collect()
+    }
+    fn sprites(map: &Tilemap, layer: usize, chunk: usize) -> &[Sprite] {
+        &map.layers[layer].chunks[chunk].sprites
+    }
+
+    #[test]
+    fn cells_and_positions() {
+        let map = map();
+        assert_eq!(
+            map.bounds(),
+            Rect::from_corners(vec2(-16.0, -112.0), vec2(144.0, 32.0))
+        );
+        assert_eq!(map.cell_at(vec2(-16.0, 32.0)), Some(vec2(0, 0)));
+        assert_eq!(map.cell_at(vec2(-8.5, 24.5)), Some(vec2(0, 0)));
+        assert_eq!(map.cell_at(vec2(-8.0, 24.0)), Some(vec2(1, 1)));
+        assert_eq!(map.cell_at(vec2(143.9, -111.9)), Some(vec2(19, 17)));
+
+        assert_eq!(map.cell_at(vec2(144.0, 0.0)), None);
+        assert_eq!(map.cell_at(vec2(0.0, -112.0)), None);
+        assert_eq!(map.cell_at(vec2(-16.1, 0.0)), None);
+        assert_eq!(map.cell_at(vec2(0.0, 32.1)), None);
+
+        for cell in [vec2(0, 0), vec2(7, 3), vec2(19, 17)] {
+            assert_eq!(map.cell_at(map.cell_rect(cell).center()), Some(cell));
+        }
+        assert_eq!(
+            map.cell_rect(vec2(1, 2)),
+            Rect::sized(vec2(-8.0, 8.0), vec2(8.0, 8.0))
+        );
+    }
+
+    #[test]
+    fn flags_combine_across_layers() {
+        let mut map = map();
+        map.set(0, vec2(2, 3), Some(2));
+        map.set(1, vec2(2, 3), Some(1));
+        map.set_flags(0, vec2(5, 5), TileFlags::SOLID);
+        map.set(1, vec2(6, 6), Some(0));
+        map.set_flags(1, vec2(6, 6), TileFlags::TRIGGER | TileFlags::FLIP_X);
+
+        assert_eq!(map.flags(vec2(2, 3)), TileFlags::SOLID | TileFlags::HAZARD);
+        assert_eq!(map.flags(vec2(5, 5)), TileFlags::SOLID);
+        assert_eq!(
+            map.flags(vec2(6, 6)),
+            TileFlags::TRIGGER | TileFlags::FLIP_X
+        );
+        assert_eq!(map.flags(vec2(0, 0)), TileFlags::NONE);
+        assert_eq!(map.flags(vec2(20, 0)), TileFlags::NONE);
+
+        // Clearing the tile keeps the flags set on the cell
+        map.set(1, vec2(6, 6), None);
+        assert!(map.flags(vec2(6, 6)).contains(TileFlags::TRIGGER));
+        map.set(1, vec2(2, 3), None);
+        assert_eq!(map.flags(vec2(2, 3)), TileFlags::HAZARD);
+    }
+
+    #[test]
+    fn query_finds_flagged_cells_on_any_layer() {
+        let mut map = map();
+        map.set(1, vec2(2, 3), Some(1));
+        map.set(1, vec2(3, 3), Some(1));
+        map.set(0, vec2(2, 3), Some(2));
+        // Solid from the cell rather than the tile, on the other layer
+        map.set_flags(0, vec2(5, 5), TileFlags::SOLID);
+
+        let cells = |area: Rect, flags: TileFlags| -> Vec<Vec2<u32>> {
+            map.query(area, flags)
+                .into_iter()
+                .map(|(cell, _)| cell)
+                .collect()
+        };
+        let around = Rect::from_corners(vec2(-6.0, -2.0), vec2(14.0, 14.0));
+        assert_eq!(cells(around, TileFlags::SOLID), [vec2(2, 3), vec2(3, 3)]);
+        assert_eq!(cells(around, TileFlags::HAZARD), [vec2(2, 3)]);
+        assert_eq!(
+            cells(around, TileFlags::SOLID | TileFlags::TRIGGER),
+            [vec2(2, 3), vec2(3, 3)]
+        );
+        assert!(cells(around, TileFlags::TRIGGER).is_empty());
+
+        assert_eq!(
+            cells(map.bounds(), TileFlags::SOLID),
+            [vec2(2, 3), vec2(3, 3), vec2(5, 5)]
+        );
+        let (_, rect) = map.query(map.bounds(), TileFlags::HAZARD)[0];
+        assert_eq!(rect, map.cell_rect(vec2(2, 3)));
+
+        // Areas hanging off the map are cut to it
+        let past = Rect::from_corners(vec2(-100.0, -100.0), vec2(0.0, 100.0));
+        assert_eq!(cells(past, TileFlags::SOLID), [vec2(2, 3)]);
+        let outside = Rect::from_corners(vec2(200.0, 0.0), vec2(300.0, 10.0));
+        assert!(cells(outside, TileFlags::SOLID).is_empty());
+    }
+
+    #[test]
+    fn rebuild_places_tiles_in_their_chunk() {
+        let mut map = map();
+        map.rebuild();
+        assert!(map
+            .layers
+            .iter()
+            .all(|layer| layer.chunks.iter().all(|chunk| !chunk.changed)));
+
+        map.set(0, vec2(0, 0), Some(5));
+        map.set(1, vec2(17, 1), Some(6));
+        assert_eq!(dirty(&map, 0), [true, false, false, false]);
+        assert_eq!(dirty(&map, 1), [false, true, false, false]);
+
+        map.rebuild();
+        assert_eq!(dirty(&map, 0), [false; 4]);
+        assert!(map.layers[0].chunks[0].changed);
+        assert!(!map.layers[0].chunks[1].changed);
+
+        let sprite = sprites(&map, 0, 0)[0];
+        assert_eq!(sprite.pos, vec3(-12.0, 28.0, 0.5));
+        assert_eq!(sprite.scale, vec2(4.0, 4.0));
+        assert_eq!(sprite.rect, map.tileset.rect(5, 0.0));
+        let sprite = sprites(&map, 1, 1)[0];
+        assert_eq!(sprite.pos, vec3(124.0, 20.0, 0.4));
+    }
+
+    #[test]
+    fn only_changes_dirty_chunks() {
+        let mut map = map();
+        map.set(0, vec2(3, 17), Some(5));
+        map.rebuild();
+
+        // Setting what is already there does nothing
+        map.set(0, vec2(3, 17), Some(5));
+        map.set_flags(0, vec2(3, 17), TileFlags::NONE);
+        map.set(0, vec2(30, 0), Some(5));
+        assert_eq!(dirty(&map, 0), [false; 4]);
+
+        map.set_flags(0, vec2(3, 17), TileFlags::FLIP_Y);
+        assert_eq!(dirty(&map, 0), [false, false, true, false]);
+        map.rebuild();
+        assert_eq!(sprites(&map, 0, 2)[0].flip, Sprite::FLIP_Y);
+
+        map.set(0, vec2(3, 17), None);
+        map.rebuild();
+        assert!(sprites(&map, 0, 2).is_empty());
+
+        // Nothing animates, so time passing changes nothing
+        map.update(10.0);
+        assert_eq!(dirty(&map, 0), [false; 4]);
+    }
+
+    #[test]
+    fn animations_dirty_only_their_chunks() {
+        let mut map = map();
+        map.tileset.animate(3, vec![3, 4, 5], 0.5);
+        map.set(0, vec2(0, 0), Some(3));
+        map.set(0, vec2(19, 0), Some(6));
+        map.rebuild();
+        assert_eq!(sprites(&map, 0, 0)[0].rect, map.tileset.rects[3]);
+
+        map.update(0.25);
+        assert_eq!(dirty(&map, 0), [false; 4]);
+        map.update(0.25);
+        assert_eq!(dirty(&map, 0), [true, false, false, false]);
+        map.rebuild();
+        assert_eq!(sprites(&map, 0, 0)[0].rect, map.tileset.rects[4]);
+
+        map.update(1.0);
+        map.rebuild();
+        assert_eq!(sprites(&map, 0, 0)[0].rect, map.tileset.rects[3]);
+    }
+
+    #[test]
+    fn animations_set_after_filling_the_map() {
+        let mut map = map();
+        map.set(0, vec2(0, 0), Some(3));
+        map.set(1, vec2(19, 17), Some(3));
+        map.rebuild();
+        assert!(!map.layers[0].chunks[0].animated);
+
+        // The chunks holding the tile only learn of it by rebuilding
+        map.tileset.animate(3, vec![3, 4], 0.5);
+        map.rebuild();
+        assert!(map.layers[0].chunks[0].animated);
+        assert!(map.layers[1].chunks[3].animated);
+
+        map.update(0.5);
+        assert_eq!(dirty(&map, 0), [true, false, false, false]);
+        assert_eq!(dirty(&map, 1), [false, false, false, true]);
+        map.rebuild();
+        assert_eq!(sprites(&map, 0, 0)[0].rect, map.tileset.rects[4]);
+        assert_eq!(sprites(&map, 1, 3)[0].rect, map.tileset.rects[4]);
+
+        // And stop again once it is removed
+        map.tileset.animate(3, Vec::new(), 0.0);
+        map.rebuild();
+        assert!(!map.layers[0].chunks[0].animated);
+        assert_eq!(sprites(&map, 0, 0)[0].rect, map.tileset.rects[3]);
+    }
+
+    // Where the corner of the tile image at `texel`, (0, 0) being its top
+    // left, is drawn relative to the sprite's centre, as the sprite shader
+    // places it
+    fn drawn_at(sprite: &Sprite, texel: Vec2<f32>) -> Vec2<f32> {
+        let flip = |bit: u32| if sprite.flip & bit != 0 { -1.0 } else { 1.0 };
+        let mesh = vec2(
+            (2.0 * texel.x - 1.0) * flip(Sprite::FLIP_X),
+            (1.0 - 2.0 * texel.y) * flip(Sprite::FLIP_Y),
+        );
+        let local = mesh * sprite.scale;
+        let (sin, cos) = sprite.rotation.sin_cos();
+        vec2(cos * local.x - sin * local.y, sin * local.x + cos * local.y)
+    }
+
+    #[test]
+    fn orient_matches_tiled_flips() {
+        let sprite = Sprite::new(
+            vec3(0.0, 0.0, 0.0),
+            vec2(1.0, 1.0),
+            Color::WHITE,
+            UVRect {
+                a: vec2(0.0, 0.0),
+                b: vec2(1.0, 1.0),
+            },
+        );
+        let corners = [vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(0.0, 1.0)];
+        for bits in 0..8u32 {
+            let flags = TileFlags(bits << 29);
+            let oriented = Tilemap::orient(sprite, flags);
+            for texel in corners {
+                // Tiled swaps x and y first, then flips each axis
+                let mut expected = texel;
+                if flags.contains(TileFlags::FLIP_DIAGONAL) {
+                    expected = vec2(expected.y, expected.x);
+                }
+                if flags.contains(TileFlags::FLIP_X) {
+                    expected.x = 1.0 - expected.x;
+                }
+                if flags.contains(TileFlags::FLIP_Y) {
+                    expected.y = 1.0 - expected.y;
+                }
+                let expected = vec2(2.0 * expected.x - 1.0, 1.0 - 2.0 * expected.y);
+                let drawn = drawn_at(&oriented, texel);
+                assert!((drawn - expected).length() < 1e-5, "{flags:?} {texel:?}");
+            }
+        }
+
+        // Turning a quarter keeps a wide tile filling a wide cell
+        let wide = Sprite {
+            scale: vec2(2.0, 1.0),
+            ..sprite
+        };
+        let turned = Tilemap::orient(wide, TileFlags::FLIP_DIAGONAL);
+        let corner = drawn_at(&turned, vec2(1.0, 1.0));
+        assert!((corner.abs() - vec2(2.0, 1.0)).length() < 1e-5);
+    }
+}
@@ -0,0 +1,312 @@
+use crate::nvec::*;
+
+use winit::event::TouchPhase;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TouchEvent {
+    pub id: u64,
+    pub phase: TouchPhase,
+    // Physical pixels, same space as `MouseState::pixels`
+    pub pos: Vec2<f32>,
+    // Normalised pressure in [0, 1], if the device reports it
+    pub force: Option<f32>,
+    // Pen altitude in radians, 0 is parallel to the surface
+    pub altitude: Option<f32>,
+}
+impl TouchEvent {
+    pub fn from_winit(touch: &winit::event::Touch) -> Self {
+        let altitude = match touch.force {
+            Some(winit::event::Force::Calibrated { altitude_angle, .. }) => {
+                altitude_angle.map(|angle| angle as f32)
+            }
+            _ => None,
+        };
+
+        Self {
+            id: touch.id,
+            phase: touch.phase,
+            pos: vec2(touch.location.x as f32, touch.location.y as f32),
+            force: touch.force.map(|force| force.normalized() as f32),
+            altitude,
+        }
+    }
+
+    pub fn is_pen(&self) -> bool {
+        self.altitude.is_some()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TouchPoint {
+    pub id: u64,
+    pub phase: TouchPhase,
+    pub pos: Vec2<f32>,
+    pub origin: Vec2<f32>,
+    pub delta: Vec2<f32>,
+    pub force: Option<f32>,
+    pub altitude: Option<f32>,
+}
+impl TouchPoint {
+    pub fn active(&self) -> bool {
+        matches!(self.phase, TouchPhase::Started | TouchPhase::Moved)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TouchState {
+    points: Vec<TouchPoint>,
+    primary: Option<u64>,
+
+    // Drive the mouse's left button and cursor from the primary touch
+    pub promote_to_mouse: bool,
+}
+impl TouchState {
+    pub fn new() -> Self {
+        Self {
+            points: Vec::new(),
+            primary: None,
+            promote_to_mouse: true,
+        }
+    }
+
+    // Returns true if the event belongs to the primary touch
+    pub fn apply(&mut self, event: TouchEvent) -> bool {
+        match self.points.iter_mut().find(|point| point.id == event.id) {
+            Some(point) => {
+                point.delta += event.pos - point.pos;
+                point.pos = event.pos;
+                point.phase = event.phase;
+                point.force = event.force;
+                point.altitude = event.altitude;
+            }
+            None => self.points.push(TouchPoint {
+                id: event.id,
+                phase: event.phase,
+                pos: event.pos,
+                origin: event.pos,
+                delta: vec2(0.0, 0.0),
+                force: event.force,
+                altitude: event.altitude,
+            }),
+        }
+
+        if event.phase == TouchPhase::Started && self.primary.is_none() {
+            self.primary = Some(event.id);
+        }
+        let is_primary = self.primary == Some(event.id);
+        if is_primary && matches!(event.phase, TouchPhase::Ended | TouchPhase::Cancelled) {
+            self.primary = None;
+        }
+        is_primary
+    }
+
+    // Drops finished touches, call once per frame after input has been consumed
+    pub fn refresh(&mut self) {
+        self.points.retain(|point| point.active());
+        for point in self.points.iter_mut() {
+            point.delta = vec2(0.0, 0.0);
+            if point.phase == TouchPhase::Started {
+                point.phase = TouchPhase::Moved;
+            }
+        }
+    }
+
+    // All touches seen this frame, including ones that ended during it
+    pub fn points(&self) -> &[TouchPoint] {
+        &self.points
+    }
+    pub fn active(&self) -> impl Iterator<Item = &TouchPoint> {
+        self.points.iter().filter(|point| point.active())
+    }
+    pub fn get(&self, id: u64) -> Option<&TouchPoint> {
+        self.points.iter().find(|point| point.id == id)
+    }
+    pub fn primary(&self) -> Option<&TouchPoint> {
+        self.primary.and_then(|id| self.get(id))
+    }
+}
+impl Default for TouchState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Builds synthetic touch events, for feeding into `Input::handle` without a
+// touchscreen
+#[cfg(test)]
+#[derive(Default)]
+pub struct TouchInjector {
+    next_id: u64,
+}
+#[cfg(test)]
+impl TouchInjector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn event(id: u64, phase: TouchPhase, pos: Vec2<f32>) -> TouchEvent {
+        TouchEvent {
+            id,
+            phase,
+            pos,
+            force: None,
+            altitude: None,
+        }
+    }
+
+    pub fn down(&mut self, pos: Vec2<f32>) -> (u64, TouchEvent) {
+        let id = self.next_id;
+        self.next_id += 1;
+        (id, Self::event(id, TouchPhase::Started, pos))
+    }
+    pub fn pen_down(&mut self, pos: Vec2<f32>, force: f32, altitude: f32) -> (u64, TouchEvent) {
+        let (id, mut event) = self.down(pos);
+        event.force = Some(force);
+        event.altitude = Some(altitude);
+        (id, event)
+    }
+    pub fn move_to(&self, id: u64, pos: Vec2<f32>) -> TouchEvent {
+        Self::event(id, TouchPhase::Moved, pos)
+    }
+    pub fn up(&self, id: u64, pos: Vec2<f32>) -> TouchEvent {
+        Self::event(id, TouchPhase::Ended, pos)
+    }
+    pub fn cancel(&self, id: u64, pos: Vec2<f32>) -> TouchEvent {
+        Self::event(id, TouchPhase::Cancelled, pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::Input;
+    use crate::replay::InputEvent;
+
+    use winit::event::MouseButton;
+
+    fn input() -> Input {
+        let mut input = Input::new();
+        input.handle(InputEvent::Viewport(vec2(200.0, 100.0), 1.0));
+        input
+    }
+
+    // Touch events are applied between frames, as winit delivers them
+    fn frame(input: &mut Input, events: impl IntoIterator<Item = TouchEvent>) {
+        input.end_frame();
+        input.update(1.0 / 60.0);
+        for event in events {
+            input.handle(InputEvent::Touch(event));
+        }
+    }
+
+    #[test]
+    fn start_move_end() {
+        let mut touch = TouchState::new();
+        let mut inject = TouchInjector::new();
+
+        let (id, down) = inject.down(vec2(10.0, 20.0));
+        assert!(touch.apply(down));
+        assert_eq!(touch.primary().unwrap().phase, TouchPhase::Started);
+
+        touch.refresh();
+        assert_eq!(touch.get(id).unwrap().phase, TouchPhase::Moved);
+        touch.apply(inject.move_to(id, vec2(15.0, 20.0)));
+        touch.apply(inject.move_to(id, vec2(15.0, 30.0)));
+        let point = touch.get(id).unwrap();
+        assert_eq!(point.delta, vec2(5.0, 10.0));
+        assert_eq!(point.origin, vec2(10.0, 20.0));
+
+        touch.refresh();
+        assert_eq!(touch.get(id).unwrap().delta, vec2(0.0, 0.0));
+        assert!(touch.apply(inject.up(id, vec2(15.0, 30.0))));
+        // Still visible for the frame it ended in
+        assert!(touch.primary().is_none());
+        assert_eq!(touch.points().len(), 1);
+        assert_eq!(touch.active().count(), 0);
+
+        touch.refresh();
+        assert!(touch.points().is_empty());
+    }
+
+    #[test]
+    fn multi_touch() {
+        let mut touch = TouchState::new();
+        let mut inject = TouchInjector::new();
+
+        let (first, down) = inject.down(vec2(0.0, 0.0));
+        touch.apply(down);
+        let (second, down) = inject.down(vec2(50.0, 50.0));
+        assert_ne!(first, second);
+        assert!(!touch.apply(down));
+        assert_eq!(touch.active().count(), 2);
+
+        touch.apply(inject.move_to(second, vec2(60.0, 50.0)));
+        assert_eq!(touch.get(first).unwrap().delta, vec2(0.0, 0.0));
+        assert_eq!(touch.get(second).unwrap().delta, vec2(10.0, 0.0));
+
+        // The primary is not handed over to a touch that is already down
+        touch.apply(inject.cancel(first, vec2(0.0, 0.0)));
+        touch.refresh();
+        assert!(touch.primary().is_none());
+        assert_eq!(touch.active().count(), 1);
+
+        let (third, down) = inject.pen_down(vec2(5.0, 5.0), 0.5, 1.0);
+        assert!(touch.apply(down));
+        let pen = touch.primary().unwrap();
+        assert_eq!(pen.id, third);
+        assert_eq!(pen.force, Some(0.5));
+        assert!(down.is_pen());
+    }
+
+    #[test]
+    fn tap_promotes_to_click() {
+        let mut input = input();
+        let mut inject = TouchInjector::new();
+
+        let (id, down) = inject.down(vec2(150.0, 25.0));
+        frame(&mut input, [down]);
+        assert!(input.mouse.left.pressed());
+        assert_eq!(input.mouse.pos, vec2(0.5, 0.5));
+
+        frame(&mut input, [inject.up(id, vec2(150.0, 25.0))]);
+        assert!(input.mouse.left.released());
+        assert!(input.touch.primary().is_none());
+
+        // A second quick tap in the same spot
+        let (id, down) = inject.down(vec2(151.0, 25.0));
+        frame(&mut input, [down]);
+        assert!(input.mouse.double_clicked(MouseButton::Left));
+        frame(&mut input, [inject.up(id, vec2(151.0, 25.0))]);
+    }
+
+    #[test]
+    fn drag_with_primary_only() {
+        let mut input = input();
+        let mut inject = TouchInjector::new();
+
+        let (id, down) = inject.down(vec2(50.0, 50.0));
+        frame(&mut input, [down]);
+
+        // Another finger moving does not move the cursor
+        let (other, down) = inject.down(vec2(0.0, 0.0));
+        frame(&mut input, [down, inject.move_to(other, vec2(100.0, 0.0))]);
+        assert_eq!(input.mouse.pixels, vec2(50.0, 50.0));
+        assert!(input.mouse.dragging(MouseButton::Left).is_none());
+
+        frame(&mut input, [inject.move_to(id, vec2(80.0, 50.0))]);
+        assert!(input.mouse.drag_started);
+        let (origin, pos) = input.mouse.dragging(MouseButton::Left).unwrap();
+        assert_eq!(origin, vec2(-0.5, 0.0));
+        assert!((pos.x + 0.2).abs() < 1e-5);
+
+        frame(&mut input, [inject.up(id, vec2(80.0, 50.0))]);
+        assert!(input.mouse.drag_ended.is_some());
+        assert!(input.mouse.left.released());
+
+        // Lifting the other finger after the primary does not click again
+        frame(&mut input, [inject.up(other, vec2(100.0, 0.0))]);
+        assert!(!input.mouse.left.released() && !input.mouse.left.pressed());
+        frame(&mut input, []);
+        assert!(input.touch.points().is_empty());
+    }
+}
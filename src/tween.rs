@@ -0,0 +1,620 @@
+use std::cell::Cell;
+use std::f32::consts::PI;
+use std::rc::Rc;
+
+use crate::color::Color;
+use crate::nvec::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Ease {
+    Linear,
+    QuadIn,
+    QuadOut,
+    QuadInOut,
+    CubicIn,
+    CubicOut,
+    CubicInOut,
+    ElasticIn,
+    ElasticOut,
+    ElasticInOut,
+    BackIn,
+    BackOut,
+    BackInOut,
+    BounceIn,
+    BounceOut,
+    BounceInOut,
+}
+impl Ease {
+    // Maps t in [0, 1] to eased progress, with f(0) = 0 and f(1) = 1
+    pub fn apply(self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Ease::Linear => t,
+            Ease::QuadIn => t * t,
+            Ease::QuadOut => 1.0 - Ease::QuadIn.apply(1.0 - t),
+            Ease::QuadInOut => Self::in_out(Ease::QuadIn, t),
+            Ease::CubicIn => t * t * t,
+            Ease::CubicOut => 1.0 - Ease::CubicIn.apply(1.0 - t),
+            Ease::CubicInOut => Self::in_out(Ease::CubicIn, t),
+            Ease::ElasticIn => {
+                if t == 0.0 || t == 1.0 {
+                    return t;
+                }
+                let period = 2.0 * PI / 3.0;
+                -(2.0f32.powf(10.0 * t - 10.0)) * ((t * 10.0 - 10.75) * period).sin()
+            }
+            Ease::ElasticOut => 1.0 - Ease::ElasticIn.apply(1.0 - t),
+            Ease::ElasticInOut => Self::in_out(Ease::ElasticIn, t),
+            Ease::BackIn => {
+                let overshoot = 1.70158;
+                t * t * ((overshoot + 1.0) * t - overshoot)
+            }
+            Ease::BackOut => 1.0 - Ease::BackIn.apply(1.0 - t),
+            Ease::BackInOut => Self::in_out(Ease::BackIn, t),
+            Ease::BounceIn => 1.0 - Ease::BounceOut.apply(1.0 - t),
+            Ease::BounceOut => {
+                let (n, d) = (7.5625, 2.75);
+                if t < 1.0 / d {
+                    n * t * t
+                } else if t < 2.0 / d {
+                    let t = t - 1.5 / d;
+                    n * t * t + 0.75
+                } else if t < 2.5 / d {
+                    let t = t - 2.25 / d;
+                    n * t * t + 0.9375
+                } else {
+                    let t = t - 2.625 / d;
+                    n * t * t + 0.984375
+                }
+            }
+            Ease::BounceInOut => Self::in_out(Ease::BounceIn, t),
+        }
+    }
+
+    // Ease in for the first half, mirrored for the second
+    fn in_out(ease_in: Ease, t: f32) -> f32 {
+        if t < 0.5 {
+            0.5 * ease_in.apply(2.0 * t)
+        } else {
+            1.0 - 0.5 * ease_in.apply(2.0 - 2.0 * t)
+        }
+    }
+}
+
+pub trait Lerp: Copy {
+    fn lerp(self, to: Self, t: f32) -> Self;
+}
+impl Lerp for f32 {
+    fn lerp(self, to: Self, t: f32) -> Self {
+        self + (to - self) * t
+    }
+}
+impl Lerp for Vec2<f32> {
+    fn lerp(self, to: Self, t: f32) -> Self {
+        Vec2::<f32>::lerp(self, to, t)
+    }
+}
+impl Lerp for Vec3<f32> {
+    fn lerp(self, to: Self, t: f32) -> Self {
+        Vec3::<f32>::lerp(self, to, t)
+    }
+}
+impl Lerp for Vec4<f32> {
+    fn lerp(self, to: Self, t: f32) -> Self {
+        Vec4::<f32>::lerp(self, to, t)
+    }
+}
+impl Lerp for Color {
+    fn lerp(self, to: Self, t: f32) -> Self {
+        Color::lerp(self, to, t)
+    }
+}
+
+pub trait Animation {
+    // Advances by dt and returns the time left over once finished
+    fn advance(&mut self, dt: f32) -> f32;
+    fn is_finished(&self) -> bool;
+    fn reset(&mut self);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Repeat {
+    Times(u32),
+    Forever,
+}
+
+pub struct Tween<T: Lerp> {
+    pub from: T,
+    pub to: T,
+    pub duration: f32,
+    pub delay: f32,
+    pub ease: Ease,
+    pub repeat: Repeat,
+    // Play every other repetition backwards
+    pub yoyo: bool,
+
+    elapsed: f32,
+    value: T,
+    target: Option<Rc<Cell<T>>>,
+}
+impl<T: Lerp> Tween<T> {
+    pub fn new(from: T, to: T, duration: f32) -> Self {
+        Self {
+            from,
+            to,
+            duration,
+            delay: 0.0,
+            ease: Ease::Linear,
+            repeat: Repeat::Times(1),
+            yoyo: false,
+            elapsed: 0.0,
+            value: from,
+            target: None,
+        }
+    }
+
+    pub fn ease(mut self, ease: Ease) -> Self {
+        self.ease = ease;
+        self
+    }
+    pub fn delay(mut self, delay: f32) -> Self {
+        self.delay = delay;
+        self
+    }
+    pub fn repeat(mut self, repeat: Repeat) -> Self {
+        self.repeat = repeat;
+        self
+    }
+    pub fn yoyo(mut self, yoyo: bool) -> Self {
+        self.yoyo = yoyo;
+        self
+    }
+    // Also write every new value into a shared cell, for tweens owned by a group
+    pub fn target(mut self, target: &Rc<Cell<T>>) -> Self {
+        target.set(self.value);
+        self.target = Some(target.clone());
+        self
+    }
+
+    pub fn value(&self) -> T {
+        self.value
+    }
+    // Overall progress in [0, 1], ignoring the delay
+    pub fn progress(&self) -> f32 {
+        match self.total() {
+            Some(total) if total > 0.0 => ((self.elapsed - self.delay) / total).clamp(0.0, 1.0),
+            Some(_) => 1.0,
+            None => 0.0,
+        }
+    }
+
+    // Length of all repetitions, without the delay
+    fn total(&self) -> Option<f32> {
+        match self.repeat {
+            Repeat::Times(count) => Some(self.duration * count.max(1) as f32),
+            Repeat::Forever => None,
+        }
+    }
+
+    fn sample(&self) -> T {
+        let local = self.elapsed - self.delay;
+        if local <= 0.0 {
+            return self.from;
+        }
+
+        let (cycle, phase) = match self.total() {
+            Some(total) if local >= total => {
+                let count = match self.repeat {
+                    Repeat::Times(count) => count.max(1),
+                    Repeat::Forever => 1,
+                };
+                (count - 1, 1.0)
+            }
+            _ if self.duration <= 0.0 => (0, 1.0),
+            _ => (
+                (local / self.duration) as u32,
+                (local % self.duration) / self.duration,
+            ),
+        };
+
+        let phase = if self.yoyo && cycle % 2 == 1 {
+            1.0 - phase
+        } else {
+            phase
+        };
+        self.from.lerp(self.to, self.ease.apply(phase))
+    }
+
+    fn publish(&mut self) {
+        self.value = self.sample();
+        if let Some(target) = &self.target {
+            target.set(self.value);
+        }
+    }
+}
+impl<T: Lerp> Animation for Tween<T> {
+    fn advance(&mut self, dt: f32) -> f32 {
+        self.elapsed += dt;
+        self.publish();
+
+        match self.total() {
+            Some(total) => (self.elapsed - self.delay - total).max(0.0),
+            None => 0.0,
+        }
+    }
+    fn is_finished(&self) -> bool {
+        match self.total() {
+            Some(total) => self.elapsed >= self.delay + total,
+            None => false,
+        }
+    }
+    fn reset(&mut self) {
+        self.elapsed = 0.0;
+        self.publish();
+    }
+}
+
+pub struct Delay {
+    pub duration: f32,
+    elapsed: f32,
+}
+impl Delay {
+    pub fn new(duration: f32) -> Self {
+        Self {
+            duration,
+            elapsed: 0.0,
+        }
+    }
+}
+impl Animation for Delay {
+    fn advance(&mut self, dt: f32) -> f32 {
+        self.elapsed += dt;
+        (self.elapsed - self.duration).max(0.0)
+    }
+    fn is_finished(&self) -> bool {
+        self.elapsed >= self.duration
+    }
+    fn reset(&mut self) {
+        self.elapsed = 0.0;
+    }
+}
+
+// Plays its children one after another
+#[derive(Default)]
+pub struct Sequence {
+    steps: Vec<Box<dyn Animation>>,
+    current: usize,
+}
+impl Sequence {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn then(mut self, step: impl Animation + 'static) -> Self {
+        self.steps.push(Box::new(step));
+        self
+    }
+}
+impl Animation for Sequence {
+    fn advance(&mut self, mut dt: f32) -> f32 {
+        while let Some(step) = self.steps.get_mut(self.current) {
+            dt = step.advance(dt);
+            if !step.is_finished() {
+                return 0.0;
+            }
+            self.current += 1;
+        }
+        dt
+    }
+    fn is_finished(&self) -> bool {
+        self.current >= self.steps.len()
+    }
+    fn reset(&mut self) {
+        self.current = 0;
+        // Reset in reverse so the first step's start value is the one left
+        // in any shared targets
+        for step in self.steps.iter_mut().rev() {
+            step.reset();
+        }
+    }
+}
+
+// Plays its children at the same time, finishing with the longest
+#[derive(Default)]
+pub struct Parallel {
+    children: Vec<Box<dyn Animation>>,
+}
+impl Parallel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with(mut self, child: impl Animation + 'static) -> Self {
+        self.children.push(Box::new(child));
+        self
+    }
+}
+impl Animation for Parallel {
+    fn advance(&mut self, dt: f32) -> f32 {
+        let mut leftover = dt;
+        for child in self.children.iter_mut() {
+            if child.is_finished() {
+                continue;
+            }
+            leftover = leftover.min(child.advance(dt));
+        }
+        if self.is_finished() {
+            leftover
+        } else {
+            0.0
+        }
+    }
+    fn is_finished(&self) -> bool {
+        self.children.iter().all(|child| child.is_finished())
+    }
+    fn reset(&mut self) {
+        for child in self.children.iter_mut() {
+            child.reset();
+        }
+    }
+}
+
+// Restarts a finished animation
+pub struct Looped<A: Animation> {
+    inner: A,
+    repeat: Repeat,
+    played: u32,
+}
+impl<A: Animation> Looped<A> {
+    pub fn new(inner: A, repeat: Repeat) -> Self {
+        Self {
+            inner,
+            repeat,
+            played: 0,
+        }
+    }
+}
+impl<A: Animation> Animation for Looped<A> {
+    fn advance(&mut self, mut dt: f32) -> f32 {
+        loop {
+            let before = dt;
+            dt = self.inner.advance(dt);
+            if !self.inner.is_finished() {
+                return 0.0;
+            }
+
+            self.played += 1;
+            if self.is_finished() {
+                return dt;
+            }
+            self.inner.reset();
+
+            // Zero length loops would otherwise spin forever
+            if dt <= 0.0 || dt >= before {
+                return 0.0;
+            }
+        }
+    }
+    fn is_finished(&self) -> bool {
+        match self.repeat {
+            Repeat::Times(count) => self.played >= count.max(1),
+            Repeat::Forever => false,
+        }
+    }
+    fn reset(&mut self) {
+        self.played = 0;
+        self.inner.reset();
+    }
+}
+
+// Owns running animations and drops them when they finish
+#[derive(Default)]
+pub struct Tweener {
+    running: Vec<Box<dyn Animation>>,
+}
+impl Tweener {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, animation: impl Animation + 'static) {
+        self.running.push(Box::new(animation));
+    }
+    pub fn clear(&mut self) {
+        self.running.clear();
+    }
+    pub fn is_idle(&self) -> bool {
+        self.running.is_empty()
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        for animation in self.running.iter_mut() {
+            animation.advance(dt);
+        }
+        self.running.retain(|animation| !animation.is_finished());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EASES: [Ease; 16] = [
+        Ease::Linear,
+        Ease::QuadIn,
+        Ease::QuadOut,
+        Ease::QuadInOut,
+        Ease::CubicIn,
+        Ease::CubicOut,
+        Ease::CubicInOut,
+        Ease::ElasticIn,
+        Ease::ElasticOut,
+        Ease::ElasticInOut,
+        Ease::BackIn,
+        Ease::BackOut,
+        Ease::BackInOut,
+        Ease::BounceIn,
+        Ease::BounceOut,
+        Ease::BounceInOut,
+    ];
+
+    fn near(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn ease_endpoints() {
+        for ease in EASES {
+            assert!(near(ease.apply(0.0), 0.0), "{ease:?}");
+            assert!(near(ease.apply(1.0), 1.0), "{ease:?}");
+            assert!(near(ease.apply(-1.0), 0.0), "{ease:?}");
+            assert!(near(ease.apply(2.0), 1.0), "{ease:?}");
+        }
+        for ease in [Ease::QuadInOut, Ease::CubicInOut, Ease::BackInOut, Ease::BounceInOut] {
+            assert!(near(ease.apply(0.5), 0.5), "{ease:?}");
+        }
+        assert!(Ease::BackIn.apply(0.2) < 0.0);
+        assert!(Ease::BackOut.apply(0.8) > 1.0);
+    }
+
+    #[test]
+    fn tween_timing() {
+        let target = Rc::new(Cell::new(0.0));
+        let mut tween = Tween::new(10.0, 20.0, 2.0).delay(1.0).target(&target);
+        assert_eq!(target.get(), 10.0);
+
+        assert_eq!(tween.advance(0.5), 0.0);
+        assert_eq!(tween.value(), 10.0);
+        tween.advance(1.5);
+        assert!(near(tween.value(), 15.0));
+        assert!(near(tween.progress(), 0.5));
+        assert_eq!(target.get(), tween.value());
+
+        // Overshoot lands on the end value and hands back the rest
+        assert!(near(tween.advance(3.0), 2.0));
+        assert!(tween.is_finished());
+        assert_eq!(tween.value(), 20.0);
+
+        tween.reset();
+        assert_eq!(target.get(), 10.0);
+        assert!(!tween.is_finished());
+    }
+
+    #[test]
+    fn repeat_and_yoyo() {
+        let mut tween = Tween::new(0.0, 1.0, 1.0)
+            .repeat(Repeat::Times(3))
+            .yoyo(true);
+        tween.advance(1.25);
+        assert!(near(tween.value(), 0.75));
+        tween.advance(1.0);
+        assert!(near(tween.value(), 0.25));
+        assert!(!tween.is_finished());
+        // Ends on the forward pass
+        assert!(near(tween.advance(10.0), 9.25));
+        assert_eq!(tween.value(), 1.0);
+
+        let mut forever = Tween::new(0.0, 1.0, 1.0).repeat(Repeat::Forever);
+        assert_eq!(forever.advance(100.5), 0.0);
+        assert!(near(forever.value(), 0.5));
+        assert!(!forever.is_finished());
+        assert_eq!(forever.progress(), 0.0);
+    }
+
+    #[test]
+    fn delay() {
+        let mut delay = Delay::new(1.0);
+        assert_eq!(delay.advance(0.75), 0.0);
+        assert!(!delay.is_finished());
+        assert!(near(delay.advance(0.5), 0.25));
+        assert!(delay.is_finished());
+    }
+
+    #[test]
+    fn sequence_timing() {
+        let value = Rc::new(Cell::new(0.0));
+        let mut sequence = Sequence::new()
+            .then(Tween::new(0.0, 1.0, 1.0).target(&value))
+            .then(Delay::new(1.0))
+            .then(Tween::new(1.0, 3.0, 1.0).target(&value));
+
+        sequence.advance(0.5);
+        assert!(near(value.get(), 0.5));
+        // Leftover time from one step carries into the next
+        sequence.advance(1.0);
+        assert_eq!(value.get(), 1.0);
+        sequence.advance(1.0);
+        assert!(near(value.get(), 2.0));
+        assert!(!sequence.is_finished());
+
+        assert!(near(sequence.advance(2.0), 1.5));
+        assert!(sequence.is_finished());
+        assert_eq!(value.get(), 3.0);
+
+        sequence.reset();
+        assert_eq!(value.get(), 0.0);
+        // One large step runs straight through every child
+        assert!(near(sequence.advance(10.0), 7.0));
+        assert_eq!(value.get(), 3.0);
+    }
+
+    #[test]
+    fn parallel_timing() {
+        let short = Rc::new(Cell::new(0.0));
+        let long = Rc::new(Cell::new(0.0));
+        let mut parallel = Parallel::new()
+            .with(Tween::new(0.0, 1.0, 1.0).target(&short))
+            .with(Tween::new(0.0, 1.0, 4.0).target(&long));
+
+        assert_eq!(parallel.advance(2.0), 0.0);
+        assert_eq!(short.get(), 1.0);
+        assert!(near(long.get(), 0.5));
+        assert!(!parallel.is_finished());
+
+        // Only what is left after the longest child
+        assert!(near(parallel.advance(3.0), 1.0));
+        assert!(parallel.is_finished());
+        assert_eq!(long.get(), 1.0);
+    }
+
+    #[test]
+    fn looped_timing() {
+        let value = Rc::new(Cell::new(0.0));
+        let mut looped = Looped::new(Tween::new(0.0, 1.0, 1.0).target(&value), Repeat::Times(3));
+        looped.advance(1.5);
+        assert!(near(value.get(), 0.5));
+        assert!(!looped.is_finished());
+
+        // Skips whole loops within one step
+        assert!(near(looped.advance(5.0), 3.5));
+        assert!(looped.is_finished());
+        assert_eq!(value.get(), 1.0);
+
+        let mut forever = Looped::new(
+            Sequence::new().then(Delay::new(0.5)).then(Delay::new(0.5)),
+            Repeat::Forever,
+        );
+        assert_eq!(forever.advance(1000.25), 0.0);
+        assert!(!forever.is_finished());
+
+        // Zero length loops must not hang
+        let mut empty = Looped::new(Delay::new(0.0), Repeat::Forever);
+        assert_eq!(empty.advance(1.0), 0.0);
+    }
+
+    #[test]
+    fn tweener_drops_finished() {
+        let value = Rc::new(Cell::new(0.0));
+        let mut tweens = Tweener::new();
+        tweens.add(Tween::new(0.0, 1.0, 1.0).target(&value));
+        tweens.add(Delay::new(2.0));
+
+        tweens.update(1.5);
+        assert_eq!(value.get(), 1.0);
+        assert!(!tweens.is_idle());
+        tweens.update(1.0);
+        assert!(tweens.is_idle());
+
+        tweens.add(Delay::new(1.0));
+        tweens.clear();
+        assert!(tweens.is_idle());
+    }
+}